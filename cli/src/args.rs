@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+// options followed by a value, as `--price SOL=101.5` or `--price=SOL=101.5`
+const OPTIONS: &[&str] = &[
+    "file",
+    "registry",
+    "price",
+    "balance",
+    "prices",
+    "format",
+    "history",
+    "cash",
+    "fee-bps",
+    "slippage-bps",
+    "report",
+    "objective",
+    "search",
+    "samples",
+    "seed",
+    "threads",
+    "top",
+    "folds",
+    "model",
+    "paths",
+    "market",
+];
+const FLAGS: &[&str] = &["json", "pretty", "explain", "hex", "help", "anchored"];
+
+// The command line after the program name: a command, then options and positional
+// arguments in any order. options may repeat; `value` reads the last one
+#[derive(Debug, Default)]
+pub struct Args {
+    pub command: String,
+    pub positional: Vec<String>,
+    options: HashMap<String, Vec<String>>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let command = match args.next() {
+            None => "help".to_string(),
+            Some(arg) if arg == "--help" || arg == "-h" => "help".to_string(),
+            Some(arg) => arg,
+        };
+        let mut parsed = Args {
+            command,
+            ..Default::default()
+        };
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                parsed.positional.push(arg);
+                continue;
+            };
+            let (name, inline) = match name.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (name, None),
+            };
+            if OPTIONS.contains(&name) {
+                let value = match inline {
+                    Some(value) => value,
+                    None => args
+                        .next()
+                        .ok_or_else(|| format!("--{} needs a value", name))?,
+                };
+                parsed
+                    .options
+                    .entry(name.to_string())
+                    .or_default()
+                    .push(value);
+            } else if FLAGS.contains(&name) && inline.is_none() {
+                parsed.flags.push(name.to_string());
+            } else {
+                return Err(format!("unknown option `{}`", arg));
+            }
+        }
+        Ok(parsed)
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|f| f == name)
+    }
+
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values(name).last().map(String::as_str)
+    }
+
+    pub fn values(&self, name: &str) -> &[String] {
+        self.options.get(name).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, String> {
+        Args::parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_options_and_flags() {
+        let args =
+            parse("eval --price SOL=1 --price=USDC=2 --explain src --format compact").unwrap();
+        assert_eq!(args.command, "eval");
+        assert_eq!(args.positional, ["src"]);
+        assert_eq!(args.values("price"), ["SOL=1", "USDC=2"]);
+        assert_eq!(args.value("format"), Some("compact"));
+        assert_eq!(args.value("file"), None);
+        assert!(args.flag("explain") && !args.flag("json"));
+
+        assert!(parse("eval --price").is_err());
+        assert!(parse("fmt --colour").is_err());
+        assert!(parse("fmt --json=yes").is_err());
+        assert_eq!(parse("").unwrap().command, "help");
+        assert_eq!(parse("--help").unwrap().command, "help");
+    }
+}
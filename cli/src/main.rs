@@ -0,0 +1,1566 @@
+mod args;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::{AccountDeserialize, AnchorDeserialize, AnchorSerialize, Discriminator};
+use args::Args;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::collections::HashMap;
+use std::io::Read;
+use std::str::FromStr;
+use strategy_engine::logic::actions::{ActionTree, ActionType, AtomicAction};
+use strategy_engine::logic::backtest::{backtest, BacktestConfig, BacktestReport, PriceHistory};
+use strategy_engine::logic::conditions::{
+    ConditionTree, ConditionType, EvaluationContext, ExprStyle, NodeIndex,
+};
+use strategy_engine::logic::graph::GraphFormat;
+use strategy_engine::logic::metrics::Metrics;
+use strategy_engine::logic::parser::conditionParser::{
+    parse_condition_string_with, parse_price, parse_token,
+};
+use strategy_engine::logic::parser::context::ParseContext;
+use strategy_engine::logic::parser::diagnostics::parse_complete;
+use strategy_engine::logic::parser::lexer::{lex, skip_trivia};
+use strategy_engine::logic::parser::strategyParser::parse_strategy_string_with;
+use strategy_engine::logic::portfolio::{Market, Portfolio};
+use strategy_engine::logic::price::Price;
+use strategy_engine::logic::printer::PrintOptions;
+use strategy_engine::logic::registry::TokenRegistry;
+use strategy_engine::logic::report::{metric_rows, render_html, render_markdown};
+use strategy_engine::logic::simulation::{simulate, PathModel, SimulationConfig};
+use strategy_engine::logic::strategy::{format_duration, Strategy};
+use strategy_engine::logic::sweep::{
+    sweep, walk_forward, Candidate, Search, SweepConfig, SweepError, SweepTemplate, WalkForward,
+};
+use strategy_engine::VaultAccount;
+
+const USAGE: &str = "\
+usage: strategy-cli <command> [options] [source]
+
+commands:
+  parse     print the parsed tree node by node, or its JSON form with --json
+  fmt       print the canonical form; --pretty lays a condition out over several lines
+  validate  run the structural and semantic checks `create_vault` runs
+  eval      evaluate against --price TOKEN=PRICE, --balance TOKEN=AMOUNT and --prices FILE
+  encode    print a strategy as a vault stores it, in base64 (--hex for hex)
+  decode    read what `encode` printed, or a vault account's data, back into the DSL
+  backtest  replay the CSV or JSONL price history in --history FILE through a strategy
+  sweep     backtest a strategy with {80..120 step 5} ranges or {1, 2, 5} lists in place
+            of numbers for every combination of values, and rank them
+  simulate  run a strategy over random price paths from the model in --model FILE and
+            summarize the outcomes
+  graph     draw the tree as a Graphviz DOT or Mermaid graph, coloured by the value of
+            every condition when given --price, --balance or --prices
+
+The source is a condition, or a strategy starting with WHEN or with its LET bindings,
+written in the DSL or as strategy JSON. It is the argument, the contents of --file FILE, or stdin.
+
+options:
+  --registry FILE   token registry in TOML, so symbols can stand in for mints
+  --format FORMAT   for encode and decode: `compact` (the default, as a vault stores it) or
+                    `borsh`; for graph: `dot` (the default) or `mermaid`
+  --prices FILE     JSON of the form {\"prices\": {\"SOL\": 101.5}, \"balances\": {...}}
+  --explain         with eval, the tree with the result of every node
+  --cash AMOUNT     with backtest, the quote currency to start with (default 10000)
+  --fee-bps BPS     with backtest, the fee on every BUY and SELL
+  --slippage-bps BPS  with backtest, how far from the price BUY and SELL fill
+  --market FILE     with backtest, sweep and simulate, the market in TOML: fee_bps,
+                    slippage_bps and per token the depth for price impact, supply and
+                    borrow rates and the collateral factor
+  --report FILE     with backtest, also write the metrics, an equity chart and the trades
+                    to FILE, as HTML if it ends in .html and as markdown otherwise
+  --objective NAME  with sweep, what to rank by: sharpe (the default), sortino,
+                    total_return, cagr, max_drawdown or win_rate
+  --search KIND     with sweep, `grid` (the default) or `random`
+  --samples N       with --search random, how many combinations to try (default 100)
+  --seed N          with --search random and simulate, the seed (default 0)
+  --threads N       with sweep and simulate, backtests run at once (default one per core)
+  --top N           with sweep, how many of the ranked strategies to show (default 10)
+  --folds N         with sweep, walk forward: search on each of N windows of the history
+                    and test the best on the window after it; --anchored searches on
+                    everything before the test window instead
+  --model FILE      with simulate, the price model in TOML: assets with their price,
+                    drift and volatility, and optional correlation, jumps and regimes
+  --paths N         with simulate, how many paths to run (default 1000)
+";
+
+// what the source turned out to be
+enum Source {
+    Condition(ConditionTree),
+    Strategy(Strategy),
+}
+
+// how `encode` writes a strategy and `decode` reads it
+enum Format {
+    // `strategy_engine::Strategy` in Borsh
+    Borsh,
+    // `logic::encoding`, as `VaultAccount` stores it
+    Compact,
+}
+
+struct Cli<'a> {
+    args: &'a Args,
+    registry: TokenRegistry,
+}
+
+fn main() {
+    let result =
+        Args::parse(std::env::args().skip(1)).and_then(|args| run(&args, &mut std::io::stdin()));
+    match result {
+        Ok(output) => print!("{}", output),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+// everything but the printing, so the tests can drive it
+fn run(args: &Args, stdin: &mut dyn Read) -> Result<String, String> {
+    if args.flag("help") {
+        return Ok(USAGE.to_string());
+    }
+    let registry = match args.value("registry") {
+        Some(path) => TokenRegistry::from_toml(&read_file(path)?)
+            .map_err(|e| format!("{}: {}", path, message(e)))?,
+        None => TokenRegistry::default(),
+    };
+    let cli = Cli { args, registry };
+    match args.command.as_str() {
+        "parse" => cli.parse(&cli.source(stdin)?),
+        "fmt" => cli.fmt(&cli.source(stdin)?),
+        "validate" => cli.validate(&cli.source(stdin)?),
+        "eval" => cli.eval(&cli.source(stdin)?),
+        "encode" => cli.encode(&cli.source(stdin)?),
+        "decode" => cli.decode(&read_input(args, stdin)?),
+        "backtest" => cli.backtest(&cli.source(stdin)?),
+        "sweep" => cli.sweep(&read_input(args, stdin)?),
+        "simulate" => cli.simulate(&cli.source(stdin)?),
+        "graph" => cli.graph(&cli.source(stdin)?),
+        "help" => Ok(USAGE.to_string()),
+        other => Err(format!("unknown command `{}`\n\n{}", other, USAGE)),
+    }
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))
+}
+
+// the single positional argument, --file, or stdin when there is neither (or the argument is `-`)
+fn read_input(args: &Args, stdin: &mut dyn Read) -> Result<String, String> {
+    match (args.value("file"), args.positional.as_slice()) {
+        (Some(path), []) => read_file(path),
+        (None, [text]) if text != "-" => Ok(text.clone()),
+        (None, [] | [_]) => {
+            let mut text = String::new();
+            stdin
+                .read_to_string(&mut text)
+                .map_err(|e| format!("cannot read stdin: {}", e))?;
+            Ok(text)
+        }
+        _ => Err("expected one source: an argument, --file FILE or stdin".to_string()),
+    }
+}
+
+fn message(error: anchor_lang::error::Error) -> String {
+    match error {
+        anchor_lang::error::Error::AnchorError(e) => e.error_msg.clone(),
+        anchor_lang::error::Error::ProgramError(e) => e.program_error.to_string(),
+    }
+}
+
+fn parse_source(parsing: &ParseContext, text: &str) -> Result<Source, String> {
+    if text.trim_start().starts_with('{') {
+        return Strategy::from_json(text)
+            .map(Source::Strategy)
+            .map_err(|e| format!("error: {}", e));
+    }
+    // a strategy starts with WHEN or LET, after any comments; conditions have no bindings
+    let is_strategy =
+        lex(skip_trivia(text)).is_ok_and(|(_, token)| token.is("WHEN") || token.is("LET"));
+    if is_strategy {
+        parse_strategy_string_with(parsing, text)
+            .map(Source::Strategy)
+            .map_err(|d| d.render(text))
+    } else {
+        parse_condition_string_with(parsing, text)
+            .map(Source::Condition)
+            .map_err(|d| d.render(text))
+    }
+}
+
+fn parse_token_arg(parsing: &ParseContext, text: &str) -> Result<Pubkey, String> {
+    parse_complete(parsing, text, parse_token)
+        .map_err(|_| format!("`{}` is neither a pubkey nor a registered symbol", text))
+}
+
+fn parse_price_arg(parsing: &ParseContext, text: &str) -> Result<Price, String> {
+    parse_complete(parsing, text, parse_price).map_err(|_| format!("`{}` is not a price", text))
+}
+
+fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("invalid hex `{}`", text);
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return Err(invalid());
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn refs(children: &[NodeIndex]) -> String {
+    children
+        .iter()
+        .map(|c| format!("#{}", c))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// what `validate` found; any error makes the command fail
+#[derive(Default)]
+struct Report {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl Cli<'_> {
+    fn source(&self, stdin: &mut dyn Read) -> Result<Source, String> {
+        let text = read_input(self.args, stdin)?;
+        parse_source(&ParseContext::with_registry(&self.registry), &text)
+    }
+
+    fn format(&self) -> Result<Format, String> {
+        match self.args.value("format") {
+            None | Some("compact") => Ok(Format::Compact),
+            Some("borsh") => Ok(Format::Borsh),
+            Some(other) => Err(format!(
+                "unknown format `{}`, expected `compact` or `borsh`",
+                other
+            )),
+        }
+    }
+
+    fn number<T: FromStr>(&self, name: &str, default: T) -> Result<T, String> {
+        match self.args.value(name) {
+            Some(text) => text
+                .parse()
+                .map_err(|_| format!("--{} takes a number, got `{}`", name, text)),
+            None => Ok(default),
+        }
+    }
+
+    fn label(&self) -> impl Fn(&Pubkey) -> String + '_ {
+        |token| self.registry.label(token)
+    }
+
+    fn parse(&self, source: &Source) -> Result<String, String> {
+        if self.args.flag("json") {
+            let json = match source {
+                Source::Condition(tree) => {
+                    serde_json::to_string_pretty(tree).map_err(|e| e.to_string())?
+                }
+                Source::Strategy(strategy) => strategy.to_json(),
+            };
+            return Ok(json + "\n");
+        }
+        let strategy = match source {
+            Source::Condition(tree) => return Ok(self.condition_nodes(tree)),
+            Source::Strategy(strategy) => strategy,
+        };
+        let mut out = format!("when:\n{}", self.condition_nodes(&strategy.condition));
+        out += &format!("then:\n{}", self.action_nodes(&strategy.action));
+        out += &format!(
+            "every: {}\n",
+            format_duration(strategy.execute_every_seconds)
+        );
+        if let Some(until) = &strategy.until {
+            out += &format!("until:\n{}", self.condition_nodes(until));
+        }
+        if let Some(max_runs) = strategy.max_runs {
+            out += &format!("max_runs: {}\n", max_runs);
+        }
+        for (key, value) in &strategy.metadata {
+            out += &format!("meta: {} = {:?}\n", key, value);
+        }
+        Ok(out)
+    }
+
+    // one line per arena node, children referred to by index:
+    //
+    //   #0 PRICE_ABOVE(SOL, 100)
+    //   #1 PRICE_BELOW(SOL, 120)
+    //   #2 AND #0 #1 <- root
+    fn condition_nodes(&self, tree: &ConditionTree) -> String {
+        let label = self.label();
+        let mut out = String::new();
+        for (index, node) in tree.nodes.iter().enumerate() {
+            let text = match &node.condition_type {
+                ConditionType::Atomic(atomic) => {
+                    atomic.to_string_styled(ExprStyle::Keywords, &label)
+                }
+                ConditionType::And { left, right } => format!("AND {}", refs(&[*left, *right])),
+                ConditionType::Or { left, right } => format!("OR {}", refs(&[*left, *right])),
+                ConditionType::Xor { left, right } => format!("XOR {}", refs(&[*left, *right])),
+                ConditionType::Implies { left, right } => {
+                    format!("IMPLIES {}", refs(&[*left, *right]))
+                }
+                ConditionType::Not { child } => format!("NOT {}", refs(&[*child])),
+                ConditionType::Const(value) => value.to_string().to_uppercase(),
+                ConditionType::All { children } => format!("ALL {}", refs(children)),
+                ConditionType::Any { children } => format!("ANY {}", refs(children)),
+                ConditionType::AtLeast { k, children } => {
+                    format!("AT_LEAST {} OF {}", k, refs(children))
+                }
+            };
+            out += &self.node_line(index, tree.root_index, &text);
+        }
+        out
+    }
+
+    fn action_nodes(&self, tree: &ActionTree) -> String {
+        let label = self.label();
+        let mut out = String::new();
+        for (index, node) in tree.nodes.iter().enumerate() {
+            let text = match &node.action_type {
+                ActionType::Atomic(atomic) => atomic.to_string_with(&label),
+                ActionType::And { left, right } => format!("THEN {}", refs(&[*left, *right])),
+            };
+            out += &self.node_line(index, tree.root_index, &text);
+        }
+        out
+    }
+
+    fn node_line(&self, index: usize, root: NodeIndex, text: &str) -> String {
+        let marker = if index == root as usize {
+            " <- root"
+        } else {
+            ""
+        };
+        format!("  #{} {}{}\n", index, text, marker)
+    }
+
+    fn fmt(&self, source: &Source) -> Result<String, String> {
+        let text = match source {
+            Source::Condition(tree) => {
+                let options = PrintOptions {
+                    multiline: self.args.flag("pretty"),
+                    ..Default::default()
+                };
+                tree.to_string_pretty_with(options, &self.registry)
+            }
+            Source::Strategy(strategy) => strategy.to_string_expr_with(&self.registry),
+        };
+        Ok(text + "\n")
+    }
+
+    fn validate(&self, source: &Source) -> Result<String, String> {
+        let mut report = Report::default();
+        match source {
+            Source::Condition(tree) => self.check(tree, "condition", &mut report),
+            Source::Strategy(strategy) => {
+                self.check(&strategy.condition, "condition", &mut report);
+                if let Err(e) = strategy.action.validate() {
+                    report.errors.push(format!("actions: {}", message(e)));
+                }
+                if let Some(until) = &strategy.until {
+                    self.check(until, "UNTIL", &mut report);
+                }
+            }
+        }
+        let mut out = String::new();
+        for error in &report.errors {
+            out += &format!("error: {}\n", error);
+        }
+        for warning in &report.warnings {
+            out += &format!("warning: {}\n", warning);
+        }
+        if !report.errors.is_empty() {
+            return Err(out.trim_end().to_string());
+        }
+        Ok(out + "ok\n")
+    }
+
+    // the checks `create_vault` makes, plus what only a person reading the strategy cares about
+    fn check(&self, tree: &ConditionTree, name: &str, report: &mut Report) {
+        // the analysis indexes the tree, so it only runs on a well-formed one
+        if let Err(e) = tree.validate() {
+            report.errors.push(format!("{}: {}", name, message(e)));
+            return;
+        }
+        // errors follow the bounded check `create_vault` runs, which allows for missing prices;
+        // the analysis assumes every token has a price and only feeds the warnings
+        let satisfiable = tree.check_satisfiable();
+        let analysis = tree.analyze();
+        if analysis.is_none() {
+            report
+                .warnings
+                .push(format!("{}: analysis skipped: condition too large", name));
+        }
+        let always_true = analysis.as_ref().is_some_and(|a| a.always_true);
+        let is_until = name == "UNTIL";
+        match (satisfiable, always_true, is_until) {
+            (Some(false), _, false) => report
+                .errors
+                .push("condition can never be true, the actions would never run".to_string()),
+            (Some(false), _, true) => report
+                .warnings
+                .push("UNTIL can never be true, the strategy never stops on it".to_string()),
+            (_, true, false) => report
+                .warnings
+                .push("condition is always true, actions will run on every execution".to_string()),
+            (_, true, true) => report
+                .warnings
+                .push("UNTIL is always true, the strategy stops before it ever runs".to_string()),
+            _ => {}
+        }
+        match satisfiable {
+            None if !is_until => report.warnings.push(format!(
+                "{}: too large for create_vault to check whether it can ever be true",
+                name
+            )),
+            Some(true) if analysis.as_ref().is_some_and(|a| !a.satisfiable) => {
+                report.warnings.push(format!(
+                    "{}: can only be true while a token in it has no price",
+                    name
+                ))
+            }
+            _ => {}
+        }
+        let label = self.label();
+        for index in analysis.map(|a| a.redundant_atoms).unwrap_or_default() {
+            if let ConditionType::Atomic(atomic) = &tree.nodes[index as usize].condition_type {
+                report.warnings.push(format!(
+                    "{}: {} (node #{}) never changes the result",
+                    name,
+                    atomic.to_string_styled(ExprStyle::Keywords, &label),
+                    index
+                ));
+            }
+        }
+        if !self.registry.tokens.is_empty() {
+            for token in self.registry.unknown_tokens(tree) {
+                report
+                    .warnings
+                    .push(format!("{}: token {} is not in the registry", name, token));
+            }
+        }
+    }
+
+    fn eval(&self, source: &Source) -> Result<String, String> {
+        let ctx = self.context()?;
+        Ok(match source {
+            Source::Condition(tree) => self.evaluate(tree, &ctx, None),
+            Source::Strategy(strategy) => {
+                let mut out = self.evaluate(&strategy.condition, &ctx, Some("when"));
+                if let Some(until) = &strategy.until {
+                    out += &self.evaluate(until, &ctx, Some("until"));
+                }
+                out
+            }
+        })
+    }
+
+    fn evaluate(
+        &self,
+        tree: &ConditionTree,
+        ctx: &EvaluationContext,
+        name: Option<&str>,
+    ) -> String {
+        let result = tree.evaluate(ctx);
+        let mut out = match name {
+            Some(name) => format!("{}: {}\n", name, result),
+            None => format!("{}\n", result),
+        };
+        if self.args.flag("explain") {
+            out += &format!("  {}\n", tree.explain(&tree.evaluate_with_trace(ctx)));
+        }
+        out
+    }
+
+    // --prices first, so that --price and --balance can override single entries
+    fn context(&self) -> Result<EvaluationContext, String> {
+        let parsing = ParseContext::with_registry(&self.registry);
+        let mut ctx = EvaluationContext::default();
+        if let Some(path) = self.args.value("prices") {
+            let file: serde_json::Value =
+                serde_json::from_str(&read_file(path)?).map_err(|e| format!("{}: {}", path, e))?;
+            let sections = file
+                .as_object()
+                .ok_or_else(|| format!("{}: expected an object", path))?;
+            for (section, entries) in sections {
+                let map = match section.as_str() {
+                    "prices" => &mut ctx.token_prices,
+                    "balances" => &mut ctx.token_balances,
+                    _ => return Err(format!("{}: unknown section `{}`", path, section)),
+                };
+                read_values(&parsing, entries, map).map_err(|e| format!("{}: {}", path, e))?;
+            }
+        }
+        for (option, map) in [
+            ("price", &mut ctx.token_prices),
+            ("balance", &mut ctx.token_balances),
+        ] {
+            for arg in self.args.values(option) {
+                let (token, value) = arg
+                    .split_once('=')
+                    .ok_or_else(|| format!("--{} takes TOKEN=VALUE, got `{}`", option, arg))?;
+                map.insert(
+                    parse_token_arg(&parsing, token)?,
+                    parse_price_arg(&parsing, value)?,
+                );
+            }
+        }
+        Ok(ctx)
+    }
+
+    fn graph(&self, source: &Source) -> Result<String, String> {
+        let format = match self.args.value("format") {
+            None | Some("dot") => GraphFormat::Dot,
+            Some("mermaid") => GraphFormat::Mermaid,
+            Some(other) => {
+                return Err(format!(
+                    "unknown graph format `{}`, expected `dot` or `mermaid`",
+                    other
+                ))
+            }
+        };
+        // annotated only when there is something to evaluate against
+        let annotate = ["price", "balance", "prices"]
+            .iter()
+            .any(|option| !self.args.values(option).is_empty());
+        let ctx = match annotate {
+            true => Some(self.context()?),
+            false => None,
+        };
+        let label = self.label();
+        Ok(match source {
+            Source::Condition(tree) => tree.to_graph(format, &label, ctx.as_ref()),
+            Source::Strategy(strategy) => strategy.to_graph(format, &label, ctx.as_ref()),
+        })
+    }
+
+    fn encode(&self, source: &Source) -> Result<String, String> {
+        let Source::Strategy(strategy) = source else {
+            return Err("encode takes a strategy, starting with WHEN or LET".to_string());
+        };
+        if strategy.until.is_some() || strategy.max_runs.is_some() || !strategy.metadata.is_empty()
+        {
+            eprintln!("warning: a vault does not store UNTIL, MAX_RUNS or META, they are left out");
+        }
+        // what `create_vault` would store
+        let stored = strategy_engine::Strategy::new(
+            strategy.condition.clone(),
+            strategy.action.clone(),
+            strategy.execute_every_seconds,
+        );
+        let bytes = match self.format()? {
+            Format::Borsh => {
+                let mut buf = vec![];
+                stored.serialize(&mut buf).map_err(|e| e.to_string())?;
+                buf
+            }
+            Format::Compact => stored.encode().map_err(message)?,
+        };
+        let text = if self.args.flag("hex") {
+            to_hex(&bytes)
+        } else {
+            BASE64.encode(bytes)
+        };
+        Ok(text + "\n")
+    }
+
+    fn decode(&self, text: &str) -> Result<String, String> {
+        let text: String = text.split_whitespace().collect();
+        let bytes = if self.args.flag("hex") {
+            from_hex(&text)?
+        } else {
+            BASE64
+                .decode(&text)
+                .map_err(|e| format!("invalid base64: {}", e))?
+        };
+        let stored = match self.format()? {
+            Format::Compact if bytes.starts_with(VaultAccount::DISCRIMINATOR) => {
+                VaultAccount::try_deserialize(&mut bytes.as_slice())
+                    .and_then(|vault| vault.load_strategy())
+                    .map_err(message)?
+            }
+            Format::Borsh => strategy_engine::Strategy::try_from_slice(&bytes)
+                .map_err(|e| format!("not a Borsh-encoded strategy: {}", e))?,
+            Format::Compact => strategy_engine::Strategy::decode(&bytes).map_err(message)?,
+        };
+        // nothing after this point may index out of bounds
+        stored.condition_tree.validate().map_err(message)?;
+        stored.action_tree.validate().map_err(message)?;
+        let strategy = Strategy::new(
+            stored.condition_tree,
+            stored.action_tree,
+            stored.execute_every_seconds,
+        );
+        if self.args.flag("json") {
+            return Ok(strategy.to_json() + "\n");
+        }
+        Ok(strategy.to_string_expr_with(&self.registry) + "\n")
+    }
+
+    // --history FILE
+    fn history(&self) -> Result<PriceHistory, String> {
+        let path = self
+            .args
+            .value("history")
+            .ok_or_else(|| format!("{} needs --history FILE", self.args.command))?;
+        let text = read_file(path)?;
+        // JSONL by its extension or its first character, CSV otherwise
+        let is_jsonl = path.ends_with(".jsonl") || text.trim_start().starts_with('{');
+        if is_jsonl {
+            PriceHistory::from_jsonl(&text, &self.registry)
+        } else {
+            PriceHistory::from_csv(&text, &self.registry)
+        }
+        .map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // --market FILE, with --fee-bps and --slippage-bps in place of the file's
+    fn market(&self) -> Result<Market, String> {
+        let mut market = match self.args.value("market") {
+            Some(path) => {
+                let text = read_file(path)?;
+                Market::from_toml(&text, &self.registry).map_err(|e| format!("{}: {}", path, e))?
+            }
+            None => Market::default(),
+        };
+        market.fee_bps = self.number("fee-bps", market.fee_bps)?;
+        market.slippage_bps = self.number("slippage-bps", market.slippage_bps)?;
+        Ok(market)
+    }
+
+    fn backtest_config(&self) -> Result<BacktestConfig, String> {
+        Ok(BacktestConfig {
+            initial_cash: self.number("cash", 10_000.0)?,
+            market: self.market()?,
+            ..Default::default()
+        }
+        .with_registry(&self.registry))
+    }
+
+    fn backtest(&self, source: &Source) -> Result<String, String> {
+        let Source::Strategy(strategy) = source else {
+            return Err("backtest takes a strategy, starting with WHEN or LET".to_string());
+        };
+        let report = backtest(strategy, &self.history()?, &self.backtest_config()?);
+        let metrics = Metrics::from_report(&report);
+        if let Some(path) = self.args.value("report") {
+            let label = self.label();
+            let document = if path.ends_with(".html") || path.ends_with(".htm") {
+                render_html(strategy, &report, &metrics, &label)
+            } else {
+                render_markdown(strategy, &report, &metrics, &label)
+            };
+            std::fs::write(path, document).map_err(|e| format!("{}: {}", path, e))?;
+        }
+        if self.args.flag("json") {
+            return Ok(self.backtest_json(&report, &metrics) + "\n");
+        }
+
+        let mut out = String::from("trades:\n");
+        for trade in &report.trades {
+            let action = self.action_text(&trade.action);
+            out += &match (&trade.rejected, trade.price) {
+                (Some(reason), _) => {
+                    format!("  {} {} rejected: {}\n", trade.timestamp, action, reason)
+                }
+                (None, Some(price)) => format!(
+                    "  {} {} filled {} @ {:.4} fee {:.4}\n",
+                    trade.timestamp, action, trade.quantity, price, trade.fee
+                ),
+                (None, None) => format!(
+                    "  {} {} moved {}\n",
+                    trade.timestamp, action, trade.quantity
+                ),
+            };
+        }
+        out += "equity:\n";
+        for point in &report.equity_curve {
+            out += &format!("  {} {:.2}\n", point.timestamp, point.equity);
+        }
+        out += &format!("runs: {}\n", report.runs);
+        if let Some(finished_at) = report.finished_at {
+            out += &format!("finished at: {}\n", finished_at);
+        }
+        if let (Some(first), Some(last)) = (report.equity_curve.first(), report.equity_curve.last())
+        {
+            out += &format!(
+                "equity: {:.2} -> {:.2} ({:+.2}%)\n",
+                first.equity,
+                last.equity,
+                (last.equity / first.equity - 1.0) * 100.0
+            );
+        }
+        out += "metrics:\n";
+        for (name, value) in metric_rows(&metrics) {
+            out += &format!("  {:<15} {}\n", name.to_lowercase(), value);
+        }
+        out += &format!("portfolio:\n  cash {:.2}\n", report.portfolio.cash);
+        for (name, positions) in self.positions(&report.portfolio) {
+            for (token, units) in positions {
+                out += &format!("  {} {} {}\n", name, token, units);
+            }
+        }
+        Ok(out)
+    }
+
+    // (held, supplied, owed) positions of the portfolio that are not empty, by token label
+    fn positions(&self, portfolio: &Portfolio) -> [(&'static str, Vec<(String, u64)>); 3] {
+        let label = self.label();
+        let sorted = |units: &HashMap<Pubkey, u64>| {
+            let mut positions = units
+                .iter()
+                .filter(|(_, units)| **units > 0)
+                .map(|(token, units)| (label(token), *units))
+                .collect::<Vec<_>>();
+            positions.sort();
+            positions
+        };
+        [
+            ("held", sorted(&portfolio.holdings)),
+            ("supplied", sorted(&portfolio.supplied)),
+            ("owed", sorted(&portfolio.debt)),
+        ]
+    }
+
+    fn sweep(&self, text: &str) -> Result<String, String> {
+        let template = SweepTemplate::parse(text).map_err(|d| d.render(text))?;
+        let history = self.history()?;
+        let search = match self.args.value("search") {
+            None | Some("grid") => Search::Grid,
+            Some("random") => Search::Random {
+                samples: self.number("samples", 100)?,
+                seed: self.number("seed", 0)?,
+            },
+            Some(other) => {
+                return Err(format!(
+                    "unknown search `{}`, expected `grid` or `random`",
+                    other
+                ))
+            }
+        };
+        let config = SweepConfig {
+            search,
+            objective: self.args.value("objective").unwrap_or("sharpe").parse()?,
+            threads: self.number("threads", 0)?,
+            backtest: self.backtest_config()?,
+        };
+        let error = |e: SweepError| match e {
+            SweepError::Template(diagnostic) => diagnostic.render(text),
+            SweepError::Config(message) => message,
+        };
+        let objective = config.objective.name();
+
+        if self.args.value("folds").is_some() {
+            let walk = WalkForward {
+                folds: self.number("folds", 0)?,
+                anchored: self.args.flag("anchored"),
+            };
+            let folds =
+                walk_forward(&template, &self.registry, &history, &config, walk).map_err(error)?;
+            if self.args.flag("json") {
+                let folds = folds
+                    .iter()
+                    .map(|fold| {
+                        serde_json::json!({
+                            "train": [fold.train.0, fold.train.1],
+                            "test": [fold.test.0, fold.test.1],
+                            "parameters": parameters_json(&template, &fold.best),
+                            "strategy": fold.best.strategy.to_string_expr_with(&self.registry),
+                            "train_score": fold.best.score,
+                            "test_score": fold.test_score,
+                            "train_metrics": fold.best.metrics,
+                            "test_metrics": fold.test_metrics,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let json = serde_json::json!({"objective": objective, "folds": folds});
+                return Ok(
+                    serde_json::to_string_pretty(&json).expect("folds always serialize") + "\n",
+                );
+            }
+            let mut out = format!(
+                "walk forward over {} folds, by {}:\n",
+                folds.len(),
+                objective
+            );
+            for (i, fold) in folds.iter().enumerate() {
+                out += &format!(
+                    "  fold {}: train {}..{} test {}..{}: {}; {} in sample, {} out of sample\n",
+                    i + 1,
+                    fold.train.0,
+                    fold.train.1,
+                    fold.test.0,
+                    fold.test.1,
+                    parameters_text(&template, &fold.best),
+                    score_text(fold.best.score),
+                    score_text(fold.test_score)
+                );
+            }
+            return Ok(out);
+        }
+
+        let candidates = sweep(&template, &self.registry, &history, &config).map_err(error)?;
+        let top = &candidates[..candidates.len().min(self.number("top", 10)?)];
+        if self.args.flag("json") {
+            let ranked = top
+                .iter()
+                .map(|candidate| {
+                    serde_json::json!({
+                        "parameters": parameters_json(&template, candidate),
+                        "strategy": candidate.strategy.to_string_expr_with(&self.registry),
+                        "score": candidate.score,
+                        "metrics": candidate.metrics,
+                    })
+                })
+                .collect::<Vec<_>>();
+            let json = serde_json::json!({
+                "objective": objective,
+                "runs": candidates.len(),
+                "candidates": ranked,
+            });
+            return Ok(
+                serde_json::to_string_pretty(&json).expect("candidates always serialize") + "\n",
+            );
+        }
+        let mut out = format!("{} strategies ranked by {}:\n", candidates.len(), objective);
+        out += &format!(
+            "  {:>4}  {:>12}  {:>9}  {:>9}  parameters\n",
+            "rank", objective, "return", "drawdown"
+        );
+        for (i, candidate) in top.iter().enumerate() {
+            out += &format!(
+                "  {:>4}  {:>12}  {:>+8.2}%  {:>8.2}%  {}\n",
+                i + 1,
+                score_text(candidate.score),
+                candidate.metrics.total_return * 100.0,
+                candidate.metrics.max_drawdown * 100.0,
+                parameters_text(&template, candidate)
+            );
+        }
+        if let Some(best) = candidates.first() {
+            out += &format!(
+                "best: {}\n",
+                best.strategy.to_string_expr_with(&self.registry)
+            );
+        }
+        Ok(out)
+    }
+
+    fn simulate(&self, source: &Source) -> Result<String, String> {
+        let Source::Strategy(strategy) = source else {
+            return Err("simulate takes a strategy, starting with WHEN or LET".to_string());
+        };
+        let path = self
+            .args
+            .value("model")
+            .ok_or("simulate needs --model FILE")?;
+        let text = read_file(path)?;
+        let model =
+            PathModel::from_toml(&text, &self.registry).map_err(|e| format!("{}: {}", path, e))?;
+        let config = SimulationConfig {
+            paths: self.number("paths", 1_000)?,
+            seed: self.number("seed", 0)?,
+            threads: self.number("threads", 0)?,
+            backtest: self.backtest_config()?,
+        };
+        let report = simulate(strategy, &model, &config).map_err(|e| e.to_string())?;
+        if self.args.flag("json") {
+            return Ok(
+                serde_json::to_string_pretty(&report).expect("reports always serialize") + "\n",
+            );
+        }
+
+        let percent = |value: f64| format!("{:+.2}%", value * 100.0);
+        let r = &report.returns;
+        let d = &report.drawdowns;
+        let mut out = format!("{} paths of {} steps\n", report.paths, model.steps);
+        out += &format!(
+            "return:    mean {}  p1 {}  p5 {}  median {}  p95 {}  p99 {}\n",
+            percent(r.mean),
+            percent(r.p1),
+            percent(r.p5),
+            percent(r.median),
+            percent(r.p95),
+            percent(r.p99)
+        );
+        out += &format!(
+            "drawdown:  mean {:.2}%  median {:.2}%  p95 {:.2}%  max {:.2}%\n",
+            d.mean * 100.0,
+            d.median * 100.0,
+            d.p95 * 100.0,
+            d.max * 100.0
+        );
+        out += &format!(
+            "tail loss: value at risk {:.2}% (95%) {:.2}% (99%), expected shortfall {:.2}% (95%) {:.2}% (99%)\n",
+            report.value_at_risk_95 * 100.0,
+            report.value_at_risk_99 * 100.0,
+            report.expected_shortfall_95 * 100.0,
+            report.expected_shortfall_99 * 100.0
+        );
+        out += &format!("losing paths: {:.1}%\n", report.loss_probability * 100.0);
+        out += &format!(
+            "actions fired on {:.1}% of paths, {:.2} times on average, at most {}\n",
+            report.fired * 100.0,
+            report.triggers.mean,
+            report.triggers.max
+        );
+        if report.finished > 0.0 {
+            out += &format!(
+                "finished by UNTIL or MAX_RUNS on {:.1}% of paths\n",
+                report.finished * 100.0
+            );
+        }
+        Ok(out)
+    }
+
+    fn action_text(&self, action: &AtomicAction) -> String {
+        action.to_string_with(&self.label())
+    }
+
+    fn backtest_json(&self, report: &BacktestReport, metrics: &Metrics) -> String {
+        let trades = report
+            .trades
+            .iter()
+            .map(|trade| {
+                serde_json::json!({
+                    "timestamp": trade.timestamp,
+                    "action": self.action_text(&trade.action),
+                    "quantity": trade.quantity,
+                    "price": trade.price,
+                    "fee": trade.fee,
+                    "rejected": trade.rejected,
+                })
+            })
+            .collect::<Vec<_>>();
+        let equity_curve = report
+            .equity_curve
+            .iter()
+            .map(|point| serde_json::json!({"timestamp": point.timestamp, "equity": point.equity}))
+            .collect::<Vec<_>>();
+        let mut portfolio = serde_json::json!({"cash": report.portfolio.cash});
+        for (name, positions) in self.positions(&report.portfolio) {
+            let units = positions
+                .into_iter()
+                .map(|(token, units)| (token, units.into()))
+                .collect::<serde_json::Map<_, _>>();
+            portfolio[name] = units.into();
+        }
+        let json = serde_json::json!({
+            "runs": report.runs,
+            "finished_at": report.finished_at,
+            "trades": trades,
+            "equity_curve": equity_curve,
+            "metrics": metrics,
+            "portfolio": portfolio,
+        });
+        serde_json::to_string_pretty(&json).expect("reports always serialize")
+    }
+}
+
+// `name=value` for every parameter of the template
+fn parameters_text(template: &SweepTemplate, candidate: &Candidate) -> String {
+    template
+        .values(&candidate.choice)
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parameters_json(template: &SweepTemplate, candidate: &Candidate) -> serde_json::Value {
+    template
+        .values(&candidate.choice)
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), serde_json::Value::from(value)))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+fn score_text(score: Option<f64>) -> String {
+    score.map_or_else(|| "n/a".to_string(), |score| format!("{:.4}", score))
+}
+
+// `{"<token>": <price>, ...}`, prices as JSON numbers or decimal strings
+fn read_values(
+    parsing: &ParseContext,
+    entries: &serde_json::Value,
+    map: &mut HashMap<Pubkey, Price>,
+) -> Result<(), String> {
+    let entries = entries
+        .as_object()
+        .ok_or("expected an object of token to price")?;
+    for (token, value) in entries {
+        let value = match value {
+            serde_json::Value::String(text) => text.clone(),
+            serde_json::Value::Number(number) => number.to_string(),
+            _ => return Err(format!("value of `{}` must be a number or a string", token)),
+        };
+        map.insert(
+            parse_token_arg(parsing, token)?,
+            parse_price_arg(parsing, &value)?,
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strategy_engine::logic::actions::ActionNode;
+    use strategy_engine::logic::conditions::ConditionBuilder;
+
+    fn cli(args: &[&str], stdin: &str) -> Result<String, String> {
+        let args = Args::parse(args.iter().map(|a| a.to_string()))?;
+        run(&args, &mut stdin.as_bytes())
+    }
+
+    fn temp_file(name: &str, contents: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("strategy-cli-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_parse_and_fmt() {
+        let sol = Pubkey::new_unique();
+        let condition = format!("price_above({sol}, 100) and price_below({sol}, 120)");
+        let source = format!("{condition} // band");
+        assert_eq!(
+            cli(&["fmt", &source], "").unwrap(),
+            format!("PRICE_ABOVE({sol}, 100) AND PRICE_BELOW({sol}, 120)\n")
+        );
+        // stdin, when there is no argument
+        assert_eq!(cli(&["fmt"], &source), cli(&["fmt", "-"], &source));
+        assert_eq!(
+            cli(&["parse", &source], "").unwrap(),
+            format!(
+                "  #0 PRICE_ABOVE({sol}, 100)\n  #1 PRICE_BELOW({sol}, 120)\n  #2 AND #0 #1 <- root\n"
+            )
+        );
+        let json = cli(&["parse", "--json", &source], "").unwrap();
+        assert!(json.contains("\"price_above\""), "{}", json);
+
+        let strategy = format!("WHEN {condition} THEN BUY({sol}, 1) EVERY 90s MAX_RUNS 2");
+        let parsed = cli(&["parse", &strategy], "").unwrap();
+        assert!(
+            parsed.contains("then:\n  #0 BUY") && parsed.contains("every: 1m30s\nmax_runs: 2\n")
+        );
+        let json = cli(&["parse", "--json", &strategy], "").unwrap();
+        assert_eq!(cli(&["fmt", &json], ""), cli(&["fmt", &strategy], ""));
+
+        // bindings come before WHEN
+        let bound = format!(
+            "// dip\nLET dip = {condition}; WHEN dip OR NOT dip THEN BUY({sol}, 1) EVERY 1m"
+        );
+        assert_eq!(
+            cli(&["fmt", &bound], "").unwrap(),
+            format!(
+                "WHEN PRICE_ABOVE({sol}, 100) AND PRICE_BELOW({sol}, 120) OR \
+                 NOT(PRICE_ABOVE({sol}, 100) AND PRICE_BELOW({sol}, 120)) \
+                 THEN BUY({sol}, 1) EVERY 1m\n"
+            )
+        );
+
+        let error = cli(&["fmt", "PRICE_ABOVE(SOL, 1) ADN TRUE"], "").unwrap_err();
+        assert!(error.starts_with("error: expected"), "{}", error);
+        assert!(cli(&["frobnicate"], "").is_err());
+    }
+
+    #[test]
+    fn test_registry_symbols() {
+        let registry = temp_file(
+            "registry.toml",
+            "[tokens.SOL]\nmint = \"So11111111111111111111111111111111111111112\"\n\
+             decimals = 9\noracle = \"H6ARHf6YXhGYeQfUzQNGk6rDNnLBQKrenN712K4AQJEG\"\n",
+        );
+        let source = "PRICE_ABOVE(SOL, 100) OR PRICE_BELOW(SOL, 50)";
+        assert_eq!(
+            cli(&["fmt", "--registry", &registry, "--pretty", source], "").unwrap(),
+            "PRICE_ABOVE(SOL, 100)\nOR PRICE_BELOW(SOL, 50)\n"
+        );
+        assert_eq!(
+            cli(
+                &[
+                    "eval",
+                    "--registry",
+                    &registry,
+                    "--price",
+                    "SOL=42.5",
+                    source
+                ],
+                ""
+            )
+            .unwrap(),
+            "true\n"
+        );
+        assert!(cli(&["fmt", source], "").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let sol = Pubkey::new_unique();
+        assert_eq!(
+            cli(&["validate", &format!("PRICE_ABOVE({sol}, 100)")], "").unwrap(),
+            "ok\n"
+        );
+        let never = cli(
+            &[
+                "validate",
+                &format!("PRICE_ABOVE({sol}, 100) AND PRICE_BELOW({sol}, 50)"),
+            ],
+            "",
+        )
+        .unwrap_err();
+        assert!(
+            never.contains("error: condition can never be true"),
+            "{}",
+            never
+        );
+        let redundant = cli(
+            &[
+                "validate",
+                &format!("PRICE_ABOVE({sol}, 100) OR PRICE_ABOVE({sol}, 200)"),
+            ],
+            "",
+        )
+        .unwrap();
+        assert!(
+            redundant.contains("(node #1) never changes the result"),
+            "{}",
+            redundant
+        );
+        assert!(redundant.ends_with("ok\n"));
+
+        // create_vault accepts this: both atoms are false while SOL has no price
+        let unpriced = cli(
+            &[
+                "validate",
+                &format!("NOT PRICE_ABOVE({sol}, 100) AND NOT PRICE_AT_OR_BELOW({sol}, 100)"),
+            ],
+            "",
+        )
+        .unwrap();
+        assert!(
+            unpriced.contains("can only be true while a token in it has no price"),
+            "{}",
+            unpriced
+        );
+
+        // too large to analyze: a warning instead of a hang
+        let atoms: Vec<_> = (0..20)
+            .map(|i| format!("PRICE_ABOVE({sol}, {i})"))
+            .collect();
+        let wide = cli(
+            &[
+                "validate",
+                &format!("AT_LEAST 10 OF ({})", atoms.join(", ")),
+            ],
+            "",
+        )
+        .unwrap();
+        assert!(
+            wide.contains("warning: condition: analysis skipped: condition too large"),
+            "{}",
+            wide
+        );
+        assert!(wide.ends_with("ok\n"));
+    }
+
+    #[test]
+    fn test_eval() {
+        let sol = Pubkey::new_unique();
+        let usdc = Pubkey::new_unique();
+        let source = format!(
+            "WHEN PRICE_ABOVE({sol}, 100) AND balance({usdc}) >= 10 THEN BUY({sol}, 1) EVERY 1m \
+             UNTIL PRICE_ABOVE({sol}, 150)"
+        );
+        let prices = temp_file(
+            "prices.json",
+            &format!(r#"{{"prices": {{"{sol}": 120.5}}, "balances": {{"{usdc}": "10"}}}}"#),
+        );
+        assert_eq!(
+            cli(&["eval", "--prices", &prices, &source], "").unwrap(),
+            "when: true\nuntil: false\n"
+        );
+        // single flags override the file
+        let out = cli(
+            &[
+                "eval",
+                "--prices",
+                &prices,
+                "--balance",
+                &format!("{usdc}=9"),
+                "--explain",
+                &source,
+            ],
+            "",
+        )
+        .unwrap();
+        assert!(out.starts_with("when: false\n  ("), "{}", out);
+        assert!(out.contains("[120.5 => true]"), "{}", out);
+
+        assert!(cli(&["eval", "--price", "120", &source], "").is_err());
+        assert!(cli(&["eval", "--price", &format!("{sol}=lots"), &source], "").is_err());
+        let bad = temp_file("bad.json", r#"{"quotes": {}}"#);
+        assert!(cli(&["eval", "--prices", &bad, &source], "").is_err());
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let sol = Pubkey::new_unique();
+        let strategy = format!(
+            "WHEN PRICE_ABOVE({sol}, 100) OR PRICE_BELOW({sol}, 50.25) THEN SELL({sol}, 50%) EVERY 5m"
+        );
+        for format in [
+            &["--format", "borsh"][..],
+            &["--format", "compact"],
+            &["--hex"],
+        ] {
+            let encode = [&["encode"], format, &[strategy.as_str()]].concat();
+            let encoded = cli(&encode, "").unwrap();
+            let decode = [&["decode"], format].concat();
+            assert_eq!(cli(&decode, &encoded).unwrap(), format!("{strategy}\n"));
+        }
+        let borsh = cli(&["encode", "--format", "borsh", &strategy], "").unwrap();
+        let compact = cli(&["encode", &strategy], "").unwrap();
+        assert!(compact.len() < borsh.len());
+
+        // the data of a vault account, as fetched from a node
+        let vault = VaultAccount {
+            authority: Pubkey::new_unique(),
+            strategy: BASE64.decode(compact.trim()).unwrap(),
+            balance: 7,
+            last_executed: 0,
+        };
+        let mut data = vec![];
+        anchor_lang::AccountSerialize::try_serialize(&vault, &mut data).unwrap();
+        assert_eq!(
+            cli(&["decode", &BASE64.encode(data)], "").unwrap(),
+            format!("{strategy}\n")
+        );
+
+        assert!(cli(&["encode", &format!("PRICE_ABOVE({sol}, 100)")], "").is_err());
+        assert!(cli(&["decode", "not base64!"], "").is_err());
+        assert!(cli(&["decode", "--format", "compact", "AQ=="], "").is_err());
+        // parses, but the action tree points past its only node
+        assert!(cli(&["decode", "--format", "compact", "AQAAAQAJAQEABgUF"], "").is_err());
+        assert!(cli(&["decode", "--format", "json", &borsh], "").is_err());
+        assert!(cli(&["decode", "--format", "borsh", &compact], "").is_err());
+
+        // the same action tree in Borsh, which takes any indices
+        let stored = strategy_engine::Strategy {
+            condition_tree: ConditionBuilder::price_above(sol, Price::from(1))
+                .build()
+                .unwrap(),
+            action_tree: ActionTree {
+                nodes: vec![ActionNode {
+                    action_type: ActionType::And { left: 5, right: 5 },
+                }],
+                root_index: 0,
+            },
+            execute_every_seconds: 60,
+        };
+        let mut data = vec![];
+        stored.serialize(&mut data).unwrap();
+        assert!(cli(&["decode", "--format", "borsh", &BASE64.encode(data)], "").is_err());
+
+        // a vault whose strategy does not decode
+        let mut vault = vault;
+        vault.strategy = BASE64.decode("AQAAAQAJAQEABgUF").unwrap();
+        let mut data = vec![];
+        anchor_lang::AccountSerialize::try_serialize(&vault, &mut data).unwrap();
+        assert!(cli(&["decode", &BASE64.encode(data)], "").is_err());
+    }
+
+    #[test]
+    fn test_backtest() {
+        let sol = Pubkey::new_unique();
+        let strategy = format!(
+            "WHEN PRICE_BELOW({sol}, 95) THEN BUY({sol}, 10) THEN SELL({sol}, 20) EVERY 1m"
+        );
+        let csv = temp_file(
+            "history.csv",
+            &format!("timestamp,{sol}\n0,100\n60,90\n120,110\n"),
+        );
+        let out = cli(
+            &["backtest", "--history", &csv, "--fee-bps", "10", &strategy],
+            "",
+        )
+        .unwrap();
+        assert!(
+            out.starts_with(&format!(
+                "trades:\n  60 BUY({sol}, 10) filled 10 @ 90.0000 fee 0.9000\n  \
+                 60 SELL({sol}, 20) rejected: sells 20 units, holds 10\n"
+            )),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains("runs: 1\nequity: 10000.00 -> 10199.10 (+1.99%)\nmetrics:\n"),
+            "{}",
+            out
+        );
+        assert!(out.contains("\n  total return    +1.99%\n"), "{}", out);
+        assert!(
+            out.contains("\n  trades          2 (1 rejected)\n"),
+            "{}",
+            out
+        );
+
+        let output = |name: &str| {
+            std::env::temp_dir().join(format!("strategy-cli-{}-{}", std::process::id(), name))
+        };
+        let (markdown, html) = (output("report.md"), output("report.html"));
+        for path in [&markdown, &html] {
+            let path = path.to_str().unwrap();
+            cli(
+                &["backtest", "--history", &csv, "--report", path, &strategy],
+                "",
+            )
+            .unwrap();
+        }
+        let markdown = std::fs::read_to_string(markdown).unwrap();
+        assert!(markdown.starts_with("# Backtest report\n"));
+        assert!(markdown.contains("| Triggers | 1 |\n"));
+        let html = std::fs::read_to_string(html).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+
+        let jsonl = temp_file(
+            "history.jsonl",
+            &format!("{{\"timestamp\": 0, \"prices\": {{\"{sol}\": 100}}}}\n"),
+        );
+        let json = cli(&["backtest", "--history", &jsonl, "--json", &strategy], "").unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["equity_curve"][0]["equity"], 10_000.0);
+        assert_eq!(value["trades"].as_array().unwrap().len(), 0);
+        assert_eq!(value["metrics"]["total_return"], 0.0);
+        assert_eq!(value["metrics"]["cagr"], serde_json::Value::Null);
+
+        assert!(cli(&["backtest", &strategy], "").is_err());
+        assert!(cli(
+            &["backtest", "--history", &csv, "--cash", "lots", &strategy],
+            ""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_sweep() {
+        let sol = Pubkey::new_unique();
+        let template = format!(
+            "WHEN PRICE_BELOW({sol}, {{80..120 step 10}}) THEN BUY({sol}, {{size = 1, 2}}) EVERY 1m"
+        );
+        let csv = temp_file(
+            "sweep.csv",
+            &format!("timestamp,{sol}\n0,100\n60,90\n120,130\n"),
+        );
+        let sweep = |extra: &[&str]| {
+            let mut args = vec!["sweep", "--history", &csv, "--objective", "total_return"];
+            args.extend(extra);
+            args.push(&template);
+            cli(&args, "")
+        };
+        let out = sweep(&["--top", "2"]).unwrap();
+        assert_eq!(
+            out,
+            format!(
+                "10 strategies ranked by total_return:\n  \
+                 rank  total_return     return   drawdown  parameters\n     \
+                 1        0.0080     +0.80%      0.00%  1=100 size=2\n     \
+                 2        0.0080     +0.80%      0.00%  1=110 size=2\n\
+                 best: WHEN PRICE_BELOW({sol}, 100) THEN BUY({sol}, 2) EVERY 1m\n"
+            )
+        );
+
+        let json = sweep(&[
+            "--json",
+            "--search",
+            "random",
+            "--samples",
+            "4",
+            "--seed",
+            "3",
+        ])
+        .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["runs"], 4);
+        assert_eq!(value["candidates"].as_array().unwrap().len(), 4);
+        assert!(value["candidates"][0]["parameters"]["size"].is_string());
+        assert!(value["candidates"][0]["metrics"]["total_return"].is_number());
+
+        let csv = temp_file(
+            "folds.csv",
+            &format!("timestamp,{sol}\n0,100\n60,90\n120,130\n180,100\n240,90\n300,130\n"),
+        );
+        let out = cli(
+            &[
+                "sweep",
+                "--history",
+                &csv,
+                "--objective",
+                "total_return",
+                "--folds",
+                "2",
+                &template,
+            ],
+            "",
+        )
+        .unwrap();
+        assert!(
+            out.starts_with("walk forward over 2 folds, by total_return:\n"),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains("  fold 2: train 120..180 test 240..300: "),
+            "{}",
+            out
+        );
+
+        assert!(sweep(&["--objective", "profit"])
+            .unwrap_err()
+            .contains("unknown objective"));
+        assert!(sweep(&["--search", "exhaustive"]).is_err());
+        let error = cli(
+            &["sweep", "--history", &csv, "WHEN PRICE_BELOW(SOL, {1.."],
+            "",
+        );
+        assert!(error.unwrap_err().contains("`{` is never closed"));
+    }
+
+    #[test]
+    fn test_graph() {
+        let registry = temp_file(
+            "graph-registry.toml",
+            "[tokens.SOL]\nmint = \"So11111111111111111111111111111111111111112\"\n\
+             decimals = 9\noracle = \"H6ARHf6YXhGYeQfUzQNGk6rDNnLBQKrenN712K4AQJEG\"\n",
+        );
+        let condition = "PRICE_BELOW(SOL, 100) OR PRICE_ABOVE(SOL, 200)";
+        let graph = |extra: &[&str], source: &str| {
+            let mut args = vec!["graph", "--registry", &registry];
+            args.extend(extra);
+            args.push(source);
+            cli(&args, "")
+        };
+        assert_eq!(
+            graph(&[], condition).unwrap(),
+            "digraph condition {\n  node [shape=box, fontname=\"monospace\"];\n  \
+             c2 [label=\"OR\"];\n  c0 [label=\"PRICE_BELOW(SOL, 100)\"];\n  \
+             c1 [label=\"PRICE_ABOVE(SOL, 200)\"];\n  c2 -> c0;\n  c2 -> c1;\n}\n"
+        );
+        let mermaid = graph(&["--format", "mermaid", "--price", "SOL=90"], condition).unwrap();
+        assert!(mermaid.contains("  c0[\"PRICE_BELOW(SOL, 100)<br>90 =#gt; true\"]\n"));
+        assert!(mermaid.ends_with("  class c1 skipped\n"), "{}", mermaid);
+
+        let strategy = format!("WHEN {condition} THEN BUY(SOL, 1) EVERY 5m");
+        assert!(graph(&[], &strategy)
+            .unwrap()
+            .contains("  s -> a0 [label=\"then\"];\n"));
+        assert!(graph(&["--format", "svg"], condition)
+            .unwrap_err()
+            .starts_with("unknown graph format `svg`"));
+    }
+
+    #[test]
+    fn test_market() {
+        let (sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let strategy = format!(
+            "WHEN TRUE THEN BUY({sol}, 10) THEN LEND({sol}, 100%) THEN BORROW({usdc}, 50%) \
+             EVERY 1m MAX_RUNS 1"
+        );
+        let csv = temp_file(
+            "lending.csv",
+            &format!("timestamp,{sol},{usdc}\n0,100,1\n60,100,1\n"),
+        );
+        let market = temp_file(
+            "market.toml",
+            &format!("fee_bps = 10\n\n[[tokens]]\ntoken = \"{sol}\"\ncollateral_factor = 0.5\n"),
+        );
+        let run = |extra: &[&str]| {
+            let mut args = vec!["backtest", "--history", &csv, "--market", &market];
+            args.extend(extra);
+            args.push(&strategy);
+            cli(&args, "")
+        };
+        // 1000 of SOL supplied at a factor of one half, and half of the 500 it allows borrowed
+        let out = run(&[]).unwrap();
+        assert!(
+            out.ends_with(&format!(
+                "portfolio:\n  cash 8999.00\n  held {usdc} 250\n  supplied {sol} 10\n  \
+                 owed {usdc} 250\n"
+            )),
+            "{}",
+            out
+        );
+        // the option wins over the file
+        assert!(run(&["--fee-bps", "0"])
+            .unwrap()
+            .contains("portfolio:\n  cash 9000.00\n"));
+        let json = run(&["--json"]).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["portfolio"]["owed"][usdc.to_string()], 250);
+
+        let bad = temp_file("bad-market.toml", "[[tokens]]\ntoken = \"NOT_A_TOKEN\"\n");
+        assert!(cli(
+            &["backtest", "--history", &csv, "--market", &bad, &strategy],
+            ""
+        )
+        .unwrap_err()
+        .ends_with("`NOT_A_TOKEN` is neither a pubkey nor a registered symbol"));
+    }
+
+    #[test]
+    fn test_simulate() {
+        let sol = Pubkey::new_unique();
+        let strategy =
+            format!("WHEN PRICE_BELOW({sol}, 95) THEN BUY({sol}, 10) EVERY 1h MAX_RUNS 1");
+        let model = temp_file(
+            "model.toml",
+            &format!(
+                "step = \"1h\"\nsteps = 48\n\n[[assets]]\ntoken = \"{sol}\"\n\
+                 price = 100\nvolatility = 0.8\n\n[jumps]\nintensity = 50\nmean = -0.1\n"
+            ),
+        );
+        let run = |extra: &[&str]| {
+            let mut args = vec![
+                "simulate", "--model", &model, "--paths", "50", "--seed", "4",
+            ];
+            args.extend(extra);
+            args.push(&strategy);
+            cli(&args, "")
+        };
+        let out = run(&[]).unwrap();
+        assert!(
+            out.starts_with("50 paths of 48 steps\nreturn:    mean "),
+            "{}",
+            out
+        );
+        assert!(out.contains("\nactions fired on "), "{}", out);
+        assert_eq!(run(&["--threads", "3"]).unwrap(), out);
+
+        let json = run(&["--json"]).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["paths"], 50);
+        assert_eq!(value["outcomes"].as_array().unwrap().len(), 50);
+        assert!(value["returns"]["p5"].is_number());
+
+        assert!(cli(&["simulate", &strategy], "")
+            .unwrap_err()
+            .contains("--model"));
+        let bad = temp_file("bad.toml", "step = \"1h\"\nsteps = 1\nassets = []\n");
+        assert!(cli(&["simulate", "--model", &bad, &strategy], "")
+            .unwrap_err()
+            .ends_with("the model has no assets"));
+    }
+}
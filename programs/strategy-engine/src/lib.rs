@@ -1,10 +1,10 @@
 use crate::logic::actions::ActionTree;
 use crate::logic::conditions::ConditionTree;
 use crate::logic::conditions::EvaluationContext;
-use crate::logic::parser::tokens::*;
+use crate::logic::legacy::{ActionTreeV1, ConditionTreeV1};
+use crate::logic::registry::{TokenInfo, TokenRegistry};
 // use crate::logic::parser::actionParser::translate_action_string;
 // use crate::logic::parser::conditionParser::translate_condition_string;
-// use crate::logic::parser::tokens::*;
 use anchor_lang::prelude::*;
 
 pub mod logic;
@@ -20,24 +20,77 @@ pub mod strategy_engine {
         Ok(())
     }
 
-    // pub fn create_vault(
-    //     ctx: Context<CreateVault>,
-    //     condition_str: String,
-    //     action_str: String,
-    //     execute_every_seconds: u64,
-    // ) -> Result<()> {
-    //     let vault = &mut ctx.accounts.vault;
+    pub fn create_vault(
+        ctx: Context<CreateVault>,
+        condition_tree: ConditionTree,
+        action_tree: ActionTree,
+        execute_every_seconds: u64,
+    ) -> Result<()> {
+        condition_tree.validate()?;
+        // a condition that can never be true would lock the vault's strategy forever. the check
+        // is bounded, so a larger tree goes through unchecked; `strategy-cli validate` runs the
+        // full analysis off-chain
+        match condition_tree.check_satisfiable() {
+            Some(satisfiable) => require!(satisfiable, ErrorCode::UnsatisfiableCondition),
+            None => msg!("Warning: condition is too large to check whether it can ever be true"),
+        }
 
-    //     let condition_tree = translate_condition_string(&condition_str)?;
-    //     let strategy = Strategy::new(condition_tree, action_tree, execute_every_seconds);
+        let vault = &mut ctx.accounts.vault;
+        vault.authority = *ctx.accounts.authority.key;
+        vault.strategy =
+            Strategy::new(condition_tree, action_tree, execute_every_seconds).encode()?;
 
-    //     vault.authority = *ctx.accounts.authority.key;
-    //     vault.strategy = strategy;
+        vault.balance = 0;
+        vault.last_executed = Clock::get()?.unix_timestamp as u64;
+        Ok(())
+    }
 
-    //     vault.balance = 0;
-    //     vault.last_executed = Clock::get()?.unix_timestamp as u64;
-    //     Ok(())
-    // }
+    // publishes the symbols clients may use in place of mints when writing strategies
+    pub fn create_token_registry(
+        ctx: Context<CreateTokenRegistry>,
+        tokens: Vec<TokenInfo>,
+    ) -> Result<()> {
+        let account = &mut ctx.accounts.token_registry;
+        account.authority = *ctx.accounts.authority.key;
+        account.registry = TokenRegistry::new(tokens)?;
+        Ok(())
+    }
+
+    // rewrites a vault created before tree indices were widened to `NodeIndex`.
+    // a vault that is already in the current layout is refused
+    pub fn migrate_vault(ctx: Context<MigrateVault>) -> Result<()> {
+        let info = ctx.accounts.vault.to_account_info();
+        let vault = VaultAccount::migrate(&info.try_borrow_data()?)?;
+        require_keys_eq!(vault.authority, ctx.accounts.authority.key());
+
+        let mut buf = VaultAccount::DISCRIMINATOR.to_vec();
+        vault.serialize(&mut buf)?;
+
+        // the compact form is usually smaller, but a strategy with many distinct tokens can take
+        // more space: top up rent and grow the account if needed
+        if buf.len() > info.data_len() {
+            let required = Rent::get()?.minimum_balance(buf.len());
+            let missing = required.saturating_sub(info.lamports());
+            if missing > 0 {
+                anchor_lang::system_program::transfer(
+                    CpiContext::new(
+                        ctx.accounts.system_program.to_account_info(),
+                        anchor_lang::system_program::Transfer {
+                            from: ctx.accounts.authority.to_account_info(),
+                            to: info.clone(),
+                        },
+                    ),
+                    missing,
+                )?;
+            }
+            info.realloc(buf.len(), false)?;
+        }
+        // zero what the old layout leaves behind, so the vault reads as migrated
+        let mut data = info.try_borrow_mut_data()?;
+        data[..buf.len()].copy_from_slice(&buf);
+        data[buf.len()..].fill(0);
+        Ok(())
+    }
 }
 
 pub fn deposit(ctx: Context<DepositVault>, amount: u64) -> Result<()> {
@@ -60,12 +113,13 @@ pub fn withdraw(ctx: Context<WithdrawVault>, amount: u64) -> Result<()> {
 
 pub fn execute_strategy(ctx: Context<ExecuteVault>, ctx_eval: EvaluationContext) -> Result<()> {
     let vault = &mut ctx.accounts.vault;
+    let strategy = vault.load_strategy()?;
     let now = Clock::get()?.unix_timestamp as u64;
-    if now - vault.last_executed < vault.strategy.execute_every_seconds {
+    if now - vault.last_executed < strategy.execute_every_seconds {
         return Ok(());
     }
-    if vault.strategy.condition_tree.evaluate(&ctx_eval) {
-        vault.strategy.action_tree.execute();
+    if strategy.condition_tree.evaluate(&ctx_eval) {
+        strategy.action_tree.execute();
         vault.last_executed = now;
     }
     Ok(())
@@ -74,11 +128,86 @@ pub fn execute_strategy(ctx: Context<ExecuteVault>, ctx_eval: EvaluationContext)
 #[account]
 pub struct VaultAccount {
     pub authority: Pubkey,
-    pub strategy: Strategy,
+    // `Strategy::encode`, read back with `load_strategy`
+    pub strategy: Vec<u8>,
     pub balance: u64,
     pub last_executed: u64,
 }
 
+#[account]
+pub struct TokenRegistryAccount {
+    pub authority: Pubkey,
+    pub registry: TokenRegistry,
+}
+
+// vault layout before `NodeIndex` and the compact encoding; only read by `migrate_vault`
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct VaultAccountV1 {
+    pub authority: Pubkey,
+    pub strategy: StrategyV1,
+    pub balance: u64,
+    pub last_executed: u64,
+}
+
+impl VaultAccount {
+    // account space for a vault storing `strategy` as `create_vault` would: simplified, then
+    // encoded. a strategy that cannot be encoded gets the bare minimum, and `create_vault`
+    // then fails on the same error
+    pub fn space(
+        condition_tree: &ConditionTree,
+        action_tree: &ActionTree,
+        execute_every_seconds: u64,
+    ) -> usize {
+        let strategy = Strategy::new(
+            condition_tree.clone(),
+            action_tree.clone(),
+            execute_every_seconds,
+        );
+        8 + 32 + 4 + strategy.encode().map_or(0, |bytes| bytes.len()) + 8 + 8
+    }
+
+    pub fn load_strategy(&self) -> Result<Strategy> {
+        Strategy::decode(&self.strategy)
+    }
+
+    // the vault stored in `data`, an account in the `VaultAccountV1` layout. both layouts share
+    // the discriminator, so the data is first read as a current vault: if that uses every byte
+    // but trailing zeros and holds a strategy that decodes, the vault was already migrated and
+    // reading it as V1 again would scramble it
+    pub fn migrate(data: &[u8]) -> Result<VaultAccount> {
+        require!(
+            data.len() >= 8 && &data[..8] == VaultAccount::DISCRIMINATOR,
+            ErrorCode::NotAVault
+        );
+        let mut rest = &data[8..];
+        if let Ok(vault) = VaultAccount::deserialize(&mut rest) {
+            if rest.iter().all(|b| *b == 0) && vault.load_strategy().is_ok() {
+                return err!(ErrorCode::AlreadyMigrated);
+            }
+        }
+        VaultAccountV1::deserialize(&mut &data[8..])?.try_into()
+    }
+}
+
+impl TryFrom<VaultAccountV1> for VaultAccount {
+    type Error = Error;
+
+    // the trees are stored as they were, not simplified
+    fn try_from(old: VaultAccountV1) -> Result<Self> {
+        let strategy = Strategy {
+            condition_tree: old.strategy.condition_tree.into(),
+            action_tree: old.strategy.action_tree.into(),
+            execute_every_seconds: old.strategy.execute_every_seconds,
+        };
+        Ok(Self {
+            authority: old.authority,
+            strategy: strategy.encode()?,
+            balance: old.balance,
+            last_executed: old.last_executed,
+        })
+    }
+}
+
 #[derive(Accounts)]
 pub struct Initialize {}
 
@@ -87,13 +216,34 @@ pub struct Initialize {}
 #[derive(Accounts)]
 #[instruction(condition_tree: ConditionTree, action_tree: ActionTree, execute_every_seconds: u64)]
 pub struct CreateVault<'info> {
-    #[account(init, payer = authority, space = 8+32+condition_tree.size() + action_tree.size()+8+8+8, seeds = [b"vault", authority.key().as_ref()], bump)]
+    #[account(init, payer = authority, space = VaultAccount::space(&condition_tree, &action_tree, execute_every_seconds), seeds = [b"vault", authority.key().as_ref()], bump)]
     pub vault: Account<'info, VaultAccount>,
     #[account(mut)]
     pub authority: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+#[instruction(tokens: Vec<TokenInfo>)]
+pub struct CreateTokenRegistry<'info> {
+    #[account(init, payer = authority, space = 8 + 32 + 4 + tokens.len() * TokenInfo::MAX_SIZE, seeds = [b"token_registry", authority.key().as_ref()], bump)]
+    pub token_registry: Account<'info, TokenRegistryAccount>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateVault<'info> {
+    /// CHECK: still in the `VaultAccountV1` layout, so it cannot be loaded as `Account<VaultAccount>`.
+    /// `migrate_vault` checks the discriminator and the authority by hand.
+    #[account(mut, seeds = [b"vault", authority.key().as_ref()], bump, owner = crate::ID)]
+    pub vault: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
 /// Deposit into vault (just updates balance)
 #[derive(Accounts)]
 pub struct DepositVault<'info> {
@@ -124,18 +274,47 @@ pub struct Strategy {
     pub execute_every_seconds: u64,
 }
 
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct StrategyV1 {
+    pub condition_tree: ConditionTreeV1,
+    pub action_tree: ActionTreeV1,
+    pub execute_every_seconds: u64,
+}
+
 impl Strategy {
     pub fn new(
         condition_tree: ConditionTree,
         action_tree: ActionTree,
         execute_every_seconds: u64,
     ) -> Self {
+        // stored trees are simplified first: fewer nodes means less account space and compute
         Self {
-            condition_tree,
+            condition_tree: condition_tree.simplify(),
             action_tree,
             execute_every_seconds,
         }
     }
+
+    // the compact form from `logic::encoding`, a fraction of the Borsh size. vaults store
+    // this form
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        logic::encoding::encode(
+            &self.condition_tree,
+            &self.action_tree,
+            self.execute_every_seconds,
+        )
+    }
+
+    // trees come back exactly as they were encoded, so they are not simplified again.
+    // they are structurally valid, but custom conditions are not checked against the registry
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let decoded = logic::encoding::decode(bytes)?;
+        Ok(Self {
+            condition_tree: decoded.condition,
+            action_tree: decoded.action,
+            execute_every_seconds: decoded.execute_every_seconds,
+        })
+    }
 }
 // #[cfg(test)]
 // mod test_smoke {
@@ -154,4 +333,38 @@ pub enum ErrorCode {
     Overflow,
     #[msg("Underflow when subtracting from vault balance")]
     Underflow,
+    #[msg("Condition can never be true")]
+    UnsatisfiableCondition,
+    #[msg("Tree has more nodes than a NodeIndex can address")]
+    TooManyNodes,
+    #[msg("Account is not a vault")]
+    NotAVault,
+    #[msg("Tree references a node that does not precede it")]
+    InvalidTree,
+    #[msg("Condition kind is not registered")]
+    UnknownConditionKind,
+    #[msg("Condition arguments are invalid for their kind")]
+    InvalidConditionData,
+    #[msg("Condition kind id or keyword is already registered")]
+    ConditionKindConflict,
+    #[msg("Price must not be negative")]
+    NegativePrice,
+    #[msg("Token symbols must start with a letter and be at most 10 letters, digits or _")]
+    InvalidTokenSymbol,
+    #[msg("Token symbol or mint is already registered")]
+    TokenConflict,
+    #[msg("Token registry file is invalid")]
+    InvalidRegistryFile,
+    #[msg("Encoded strategy is malformed")]
+    InvalidEncoding,
+    #[msg("Encoded strategy has an unknown format version")]
+    UnsupportedEncodingVersion,
+    #[msg("Strategy refers to more tokens than the encoding can index")]
+    TooManyTokens,
+    #[msg("Vault is already in the current layout")]
+    AlreadyMigrated,
+    #[msg("Price exponent is out of range")]
+    PriceOutOfRange,
+    #[msg("Failed to parse strategy source")]
+    ParseError,
 }
@@ -1,20 +1,95 @@
+use crate::logic::conditions::{NodeIndex, MAX_NODES};
+use crate::ErrorCode;
 use anchor_lang::prelude::*;
 
+// How much of a token an action moves
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Amount {
+    // raw token units
+    Units(u64),
+    // share of what the vault holds when the action runs, in basis points (5000 = 50%)
+    Percent(u16),
+}
+
+impl Amount {
+    pub const MAX_PERCENT_BPS: u16 = 10_000;
+}
+
+impl From<u64> for Amount {
+    fn from(units: u64) -> Self {
+        Amount::Units(units)
+    }
+}
+
+// "100" or "50%", "12.5%"; `parse_amount` reads it back
+impl std::fmt::Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Amount::Units(units) => write!(f, "{}", units),
+            Amount::Percent(bps) => match bps % 100 {
+                0 => write!(f, "{}%", bps / 100),
+                frac if frac % 10 == 0 => write!(f, "{}.{}%", bps / 100, frac / 10),
+                frac => write!(f, "{}.{:02}%", bps / 100, frac),
+            },
+        }
+    }
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
 pub enum AtomicAction {
-    Buy { token: Pubkey, amount: u64 },
-    Sell { token: Pubkey, amount: u64 },
-    Borrow { token: Pubkey, amount: u64 },
-    Repay { token: Pubkey, amount: u64 },
-    Lend { token: Pubkey, amount: u64 },
-    Redeem { token: Pubkey, amount: u64 },
+    Buy { token: Pubkey, amount: Amount },
+    Sell { token: Pubkey, amount: Amount },
+    Borrow { token: Pubkey, amount: Amount },
+    Repay { token: Pubkey, amount: Amount },
+    Lend { token: Pubkey, amount: Amount },
+    Redeem { token: Pubkey, amount: Amount },
+}
+
+impl std::fmt::Display for AtomicAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_string_with(&|token| token.to_string()))
+    }
+}
+
+impl AtomicAction {
+    pub fn token(&self) -> Pubkey {
+        self.parts().0
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.parts().1
+    }
+
+    fn parts(&self) -> (Pubkey, Amount) {
+        match self {
+            AtomicAction::Buy { token, amount }
+            | AtomicAction::Sell { token, amount }
+            | AtomicAction::Borrow { token, amount }
+            | AtomicAction::Repay { token, amount }
+            | AtomicAction::Lend { token, amount }
+            | AtomicAction::Redeem { token, amount } => (*token, *amount),
+        }
+    }
+
+    // printed form, with tokens written by `label` (e.g. as registry symbols)
+    pub fn to_string_with(&self, label: &dyn Fn(&Pubkey) -> String) -> String {
+        let (keyword, token, amount) = match self {
+            AtomicAction::Buy { token, amount } => ("BUY", token, amount),
+            AtomicAction::Sell { token, amount } => ("SELL", token, amount),
+            AtomicAction::Borrow { token, amount } => ("BORROW", token, amount),
+            AtomicAction::Repay { token, amount } => ("REPAY", token, amount),
+            AtomicAction::Lend { token, amount } => ("LEND", token, amount),
+            AtomicAction::Redeem { token, amount } => ("REDEEM", token, amount),
+        };
+        format!("{}({}, {})", keyword, label(token), amount)
+    }
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
 pub enum ActionType {
     Atomic(AtomicAction),
     // And(Box<Action>, Box<Action>),
-    And { left: u8, right: u8 },
+    And { left: u16, right: u16 },
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
@@ -25,10 +100,12 @@ pub struct ActionNode {
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
 pub struct ActionTree {
     pub nodes: Vec<ActionNode>,
-    pub root_index: u8,
+    pub root_index: u16,
 }
 
 impl ActionTree {
+    // only logs the actions; off-chain, `Portfolio::execute` in `logic::portfolio` models what
+    // they do
     pub fn execute(&self) -> bool {
         self.execute_node(self.root_index)
     }
@@ -42,7 +119,53 @@ impl ActionTree {
         buf.len()
     }
 
-    pub fn execute_node(&self, index: u8) -> bool {
+    // every child index points at an earlier node (which also rules out cycles) and
+    // percentages are at most 100%
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            (self.root_index as usize) < self.nodes.len(),
+            ErrorCode::InvalidTree
+        );
+        for (index, node) in self.nodes.iter().enumerate() {
+            match node.action_type {
+                ActionType::And { left, right } => require!(
+                    (left as usize) < index && (right as usize) < index,
+                    ErrorCode::InvalidTree
+                ),
+                ActionType::Atomic(ref atomic) => require!(
+                    !matches!(atomic.amount(), Amount::Percent(bps) if bps > Amount::MAX_PERCENT_BPS),
+                    ErrorCode::InvalidTree
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    // `translate_action_string` parses the result back into the same tree
+    pub fn to_string_expr(&self) -> String {
+        self.string_node_with(self.root_index, &|token| token.to_string())
+    }
+
+    pub(crate) fn string_node_with(
+        &self,
+        index: NodeIndex,
+        label: &dyn Fn(&Pubkey) -> String,
+    ) -> String {
+        match &self.nodes[index as usize].action_type {
+            ActionType::Atomic(atomic) => atomic.to_string_with(label),
+            // THEN groups to the left, so only a sequence on the right needs parentheses
+            ActionType::And { left, right } => {
+                let l = self.string_node_with(*left, label);
+                let r = self.string_node_with(*right, label);
+                match self.nodes[*right as usize].action_type {
+                    ActionType::And { .. } => format!("{} THEN ({})", l, r),
+                    ActionType::Atomic(_) => format!("{} THEN {}", l, r),
+                }
+            }
+        }
+    }
+
+    pub fn execute_node(&self, index: NodeIndex) -> bool {
         let node = &self.nodes[index as usize];
 
         match &node.action_type {
@@ -80,9 +203,16 @@ impl ActionTree {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct ActionBuilder {
     nodes: Vec<ActionNode>,
-    root_index: u8,
+    root_index: NodeIndex,
+}
+
+impl Default for ActionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ActionBuilder {
@@ -94,66 +224,71 @@ impl ActionBuilder {
     }
 
     fn with_node(mut self, node: ActionNode) -> Self {
-        let index: u8 = self.nodes.len() as u8;
+        let index: NodeIndex = self.nodes.len() as NodeIndex;
         self.nodes.push(node);
         self.root_index = index;
         self
     }
 
-    fn buy(token: Pubkey, amount: u64) -> Self {
+    pub fn buy(token: Pubkey, amount: impl Into<Amount>) -> Self {
+        let amount = amount.into();
         Self::new().with_node(ActionNode {
             action_type: ActionType::Atomic(AtomicAction::Buy { token, amount }),
         })
     }
 
-    pub fn sell(token: Pubkey, amount: u64) -> Self {
+    pub fn sell(token: Pubkey, amount: impl Into<Amount>) -> Self {
+        let amount = amount.into();
         Self::new().with_node(ActionNode {
             action_type: ActionType::Atomic(AtomicAction::Sell { token, amount }),
         })
     }
 
-    pub fn borrow(token: Pubkey, amount: u64) -> Self {
+    pub fn borrow(token: Pubkey, amount: impl Into<Amount>) -> Self {
+        let amount = amount.into();
         Self::new().with_node(ActionNode {
             action_type: ActionType::Atomic(AtomicAction::Borrow { token, amount }),
         })
     }
 
-    pub fn repay(token: Pubkey, amount: u64) -> Self {
+    pub fn repay(token: Pubkey, amount: impl Into<Amount>) -> Self {
+        let amount = amount.into();
         Self::new().with_node(ActionNode {
-            action_type: ActionType::Atomic(AtomicAction::Repay {
-                token: token,
-                amount,
-            }),
+            action_type: ActionType::Atomic(AtomicAction::Repay { token, amount }),
         })
     }
 
-    pub fn lend(token: Pubkey, amount: u64) -> Self {
+    pub fn lend(token: Pubkey, amount: impl Into<Amount>) -> Self {
+        let amount = amount.into();
         Self::new().with_node(ActionNode {
-            action_type: ActionType::Atomic(AtomicAction::Lend {
-                token: token,
-                amount,
-            }),
+            action_type: ActionType::Atomic(AtomicAction::Lend { token, amount }),
         })
     }
 
-    pub fn redeem(token: Pubkey, amount: u64) -> Self {
+    pub fn redeem(token: Pubkey, amount: impl Into<Amount>) -> Self {
+        let amount = amount.into();
         Self::new().with_node(ActionNode {
-            action_type: ActionType::Atomic(AtomicAction::Redeem {
-                token: token,
-                amount,
-            }),
+            action_type: ActionType::Atomic(AtomicAction::Redeem { token, amount }),
         })
     }
 
     pub fn and(mut self, mut child: Self) -> Self {
-        let left: u8 = self.root_index;
-        let right: u8 = child.root_index;
+        // `child`'s indices are relative to its own arena; shift them past ours
+        let offset = self.nodes.len();
+        for node in &mut child.nodes {
+            if let ActionType::And { left, right } = &mut node.action_type {
+                *left = (*left as usize + offset) as NodeIndex;
+                *right = (*right as usize + offset) as NodeIndex;
+            }
+        }
+        let left: NodeIndex = self.root_index;
+        let right: NodeIndex = (child.root_index as usize + offset) as NodeIndex;
 
         let mut nodes = vec![];
         nodes.append(&mut self.nodes);
         nodes.append(&mut child.nodes);
 
-        let root = nodes.len() as u8;
+        let root = nodes.len() as NodeIndex;
         nodes.push(ActionNode {
             action_type: ActionType::And { left, right },
         });
@@ -164,11 +299,13 @@ impl ActionBuilder {
         }
     }
 
-    pub fn build(self) -> ActionTree {
-        ActionTree {
+    // see `ConditionBuilder::build` for why checking the final length is enough
+    pub fn build(self) -> Result<ActionTree> {
+        require!(self.nodes.len() <= MAX_NODES, ErrorCode::TooManyNodes);
+        Ok(ActionTree {
             nodes: self.nodes,
             root_index: self.root_index,
-        }
+        })
     }
 }
 
@@ -193,8 +330,31 @@ mod tests {
             .and(action_4_prebuilt)
             .and(action_5_prebuilt)
             .and(action_6_prebuilt)
-            .build();
+            .build()
+            .unwrap();
+
+        assert!(action.execute());
+    }
+
+    #[test]
+    fn test_action_builder_nested() {
+        let token = Pubkey::new_unique();
+
+        // BUY AND (SELL AND LEND): the right-hand subtree has its own And node
+        let action = ActionBuilder::buy(token, 1)
+            .and(ActionBuilder::sell(token, 2).and(ActionBuilder::lend(token, 3)))
+            .build()
+            .unwrap();
 
-        assert_eq!(action.execute(), true);
+        assert_eq!(action.nodes.len(), 5);
+        assert_eq!(
+            action.nodes[3].action_type,
+            ActionType::And { left: 1, right: 2 }
+        );
+        assert_eq!(
+            action.nodes[4].action_type,
+            ActionType::And { left: 0, right: 3 }
+        );
+        assert!(action.execute());
     }
 }
@@ -0,0 +1,340 @@
+use crate::logic::conditions::{ConditionTree, ConditionType, NodeIndex};
+use crate::logic::price::Price;
+pub use crate::logic::satisfiability::PriceInterval;
+use crate::logic::satisfiability::{MAX_CHECKED_ATOMS, MAX_CHECKED_NODES, MAX_CHECKED_TERMS};
+use crate::logic::simplify::{Expr, MAX_SIMPLIFIED_NODES};
+use anchor_lang::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ops::Bound;
+
+// The full analysis behind `strategy-cli validate`. it only exists off-chain; `create_vault`
+// runs the cheaper `ConditionTree::check_satisfiable` instead. it can take exponential time, so
+// it has the same caps, and gives up with `None` past them
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SatisfiabilityReport {
+    // false if no combination of prices can make the tree true
+    pub satisfiable: bool,
+    // true if every combination of prices makes the tree true
+    pub always_true: bool,
+    // for every token in the tree, the prices at which the tree can still be true
+    pub token_ranges: HashMap<Pubkey, Vec<PriceInterval>>,
+    // atomic nodes whose result never changes the result of the tree
+    pub redundant_atoms: Vec<NodeIndex>,
+}
+
+// The analysis works on the DNF of the tree: every term is a conjunction of atoms, which
+// bounds each token to a single interval. the tree is true exactly on the union of those boxes.
+// unlike the simplifier, it assumes every token in the tree has a price in the context, so a
+// negated atom is read as its complementary comparison.
+fn satisfiable_boxes(expr: Expr) -> Option<Vec<HashMap<Pubkey, PriceInterval>>> {
+    let terms = expr
+        .nnf(false)
+        .fold()
+        .normal_form(false, MAX_CHECKED_TERMS)?;
+    let boxes = terms
+        .into_iter()
+        .filter_map(|term| {
+            let mut bounds: HashMap<Pubkey, PriceInterval> = HashMap::new();
+            for literal in term {
+                let literal = match literal {
+                    Expr::Not(inner) => match *inner {
+                        Expr::Atom(atomic) => match atomic.negate() {
+                            Some(complement) => Expr::Atom(complement),
+                            None => Expr::Not(Box::new(Expr::Atom(atomic))),
+                        },
+                        other => Expr::Not(Box::new(other)),
+                    },
+                    other => other,
+                };
+                match literal {
+                    Expr::Const(true) => {}
+                    Expr::Atom(atomic) => {
+                        // custom conditions are opaque: assume they can go either way
+                        let Some(token) = atomic.token() else {
+                            continue;
+                        };
+                        let current = bounds.get(&token).unwrap_or(&PriceInterval::FULL);
+                        let narrowed = current.intersect(&PriceInterval::of_atom(&atomic)?)?;
+                        bounds.insert(token, narrowed);
+                    }
+                    // what is left of NOT after NNF only wraps custom conditions
+                    Expr::Not(_) => {}
+                    _ => return None,
+                }
+            }
+            Some(bounds)
+        })
+        .collect();
+    Some(boxes)
+}
+
+fn is_satisfiable(expr: Expr) -> Option<bool> {
+    Some(!satisfiable_boxes(expr)?.is_empty())
+}
+
+fn equivalent(a: &Expr, b: &Expr) -> Option<bool> {
+    let a_not_b = Expr::And(vec![a.clone(), Expr::Not(Box::new(b.clone()))]);
+    let b_not_a = Expr::And(vec![b.clone(), Expr::Not(Box::new(a.clone()))]);
+    Some(!is_satisfiable(a_not_b)? && !is_satisfiable(b_not_a)?)
+}
+
+// lower bounds in ascending order; at the same price an included bound comes first
+fn lower_key(bound: &Bound<Price>) -> (Price, bool) {
+    match *bound {
+        Bound::Included(p) => (p, false),
+        Bound::Excluded(p) => (p, true),
+        Bound::Unbounded => (Price::ZERO, false),
+    }
+}
+
+fn merge(mut intervals: Vec<PriceInterval>) -> Vec<PriceInterval> {
+    intervals.sort_by_key(|i| lower_key(&i.lo));
+    let mut merged: Vec<PriceInterval> = vec![];
+    for interval in intervals {
+        // `interval` starts at or after `last`, so they join if it starts before `last`
+        // ends, or right where it ends and one of them covers that price
+        let joins = |last: &PriceInterval| match (last.hi, interval.lo) {
+            (Bound::Unbounded, _) | (_, Bound::Unbounded) => true,
+            (Bound::Excluded(hi), Bound::Excluded(lo)) => lo < hi,
+            (
+                Bound::Included(hi) | Bound::Excluded(hi),
+                Bound::Included(lo) | Bound::Excluded(lo),
+            ) => lo <= hi,
+        };
+        match merged.last_mut() {
+            Some(last) if joins(last) => {
+                last.hi = loosest_upper(last.hi, interval.hi);
+            }
+            _ => merged.push(interval),
+        }
+    }
+    merged
+}
+
+fn loosest_upper(a: Bound<Price>, b: Bound<Price>) -> Bound<Price> {
+    match (a, b) {
+        (Bound::Unbounded, _) | (_, Bound::Unbounded) => Bound::Unbounded,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            match x.cmp(&y) {
+                Ordering::Equal if matches!(a, Bound::Included(_)) => a,
+                Ordering::Equal | Ordering::Less => b,
+                Ordering::Greater => a,
+            }
+        }
+    }
+}
+
+impl ConditionTree {
+    // `None` past the caps of `check_satisfiable`
+    pub fn is_satisfiable(&self) -> Option<bool> {
+        is_satisfiable(self.to_checked_expr()?)
+    }
+
+    // `None` past the caps of `check_satisfiable`
+    pub fn analyze(&self) -> Option<SatisfiabilityReport> {
+        let expr = self.to_checked_expr()?;
+        let boxes = satisfiable_boxes(expr.clone())?;
+        let always_true = !is_satisfiable(Expr::Not(Box::new(expr.clone())))?;
+
+        let mut token_ranges = HashMap::new();
+        for node in &self.nodes {
+            if let ConditionType::Atomic(atomic) = &node.condition_type {
+                let Some(token) = atomic.token() else {
+                    continue;
+                };
+                let ranges = boxes
+                    .iter()
+                    .map(|b| *b.get(&token).unwrap_or(&PriceInterval::FULL))
+                    .collect();
+                token_ranges.insert(token, merge(ranges));
+            }
+        }
+
+        // an atom is redundant if pinning it to either constant leaves the tree unchanged
+        let mut redundant_atoms = vec![];
+        for (index, node) in self.nodes.iter().enumerate() {
+            if !matches!(node.condition_type, ConditionType::Atomic(_)) {
+                continue;
+            }
+            // pinning only shrinks the tree, so it stays under the caps
+            let pinned = |value: bool| {
+                let mut tree = self.clone();
+                tree.nodes[index].condition_type = ConditionType::Const(value);
+                tree.to_checked_expr()
+            };
+            if equivalent(&expr, &pinned(true)?)? || equivalent(&expr, &pinned(false)?)? {
+                redundant_atoms.push(index as NodeIndex);
+            }
+        }
+
+        Some(SatisfiabilityReport {
+            satisfiable: !boxes.is_empty(),
+            always_true,
+            token_ranges,
+            redundant_atoms,
+        })
+    }
+
+    fn to_checked_expr(&self) -> Option<Expr> {
+        let atoms = self
+            .nodes
+            .iter()
+            .filter(|n| matches!(n.condition_type, ConditionType::Atomic(_)))
+            .count();
+        if self.nodes.len() > MAX_CHECKED_NODES || atoms > MAX_CHECKED_ATOMS {
+            return None;
+        }
+        Expr::from_tree(self, MAX_SIMPLIFIED_NODES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::conditions::ConditionBuilder;
+
+    #[test]
+    fn test_contradiction() {
+        let token = Pubkey::new_unique();
+        // the tree from conditions.rs that can never be true
+        let tree = ConditionBuilder::price_above(token, Price::from(400))
+            .and(ConditionBuilder::price_below(token, Price::from(10)))
+            .build()
+            .unwrap();
+
+        let report = tree.analyze().unwrap();
+        assert!(!report.satisfiable);
+        assert!(!report.always_true);
+        assert_eq!(report.token_ranges[&token], vec![]);
+    }
+
+    #[test]
+    fn test_token_ranges() {
+        let t1 = Pubkey::new_unique();
+        let t2 = Pubkey::new_unique();
+        // (100 < t1 < 200 OR t1 >= 500) AND t2 <= 50
+        let tree = ConditionBuilder::price_above(t1, Price::from(100))
+            .and(ConditionBuilder::price_below(t1, Price::from(200)))
+            .or(ConditionBuilder::price_at_or_above(t1, Price::from(500)))
+            .and(ConditionBuilder::price_at_or_below(t2, Price::from(50)))
+            .build()
+            .unwrap();
+
+        let report = tree.analyze().unwrap();
+        assert!(report.satisfiable);
+        assert!(!report.always_true);
+        assert_eq!(
+            report.token_ranges[&t1],
+            vec![
+                PriceInterval {
+                    lo: Bound::Excluded(Price::from(100)),
+                    hi: Bound::Excluded(Price::from(200)),
+                },
+                PriceInterval {
+                    lo: Bound::Included(Price::from(500)),
+                    hi: Bound::Unbounded,
+                }
+            ]
+        );
+        assert_eq!(
+            report.token_ranges[&t2],
+            vec![PriceInterval {
+                lo: Bound::Included(Price::ZERO),
+                hi: Bound::Included(Price::from(50)),
+            }]
+        );
+        let ranges: Vec<String> = report.token_ranges[&t1]
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        assert_eq!(ranges, vec!["(100, 200)", "[500, inf)"]);
+        assert!(report.redundant_atoms.is_empty());
+    }
+
+    #[test]
+    fn test_always_true_and_redundant() {
+        let token = Pubkey::new_unique();
+        // price > 100 OR price < 200 covers every price
+        let tree = ConditionBuilder::price_above(token, Price::from(100))
+            .or(ConditionBuilder::price_below(token, Price::from(200)))
+            .build()
+            .unwrap();
+        let report = tree.analyze().unwrap();
+        assert!(report.always_true);
+
+        // price > 300 is implied by price > 400
+        let tree = ConditionBuilder::price_above(token, Price::from(300))
+            .and(ConditionBuilder::price_above(token, Price::from(400)))
+            .build()
+            .unwrap();
+        let report = tree.analyze().unwrap();
+        assert_eq!(report.redundant_atoms, vec![0]);
+    }
+
+    #[test]
+    fn test_gives_up_past_the_caps() {
+        let token = Pubkey::new_unique();
+        let above = |p: u64| ConditionBuilder::price_above(token, Price::from(p));
+
+        let wide = ConditionBuilder::at_least(10, (0..20).map(above).collect())
+            .build()
+            .unwrap();
+        assert_eq!(wide.analyze(), None);
+
+        // few nodes, but 2^9 copies of the first atom unrolled
+        let mut chain = above(0);
+        for i in 1..=9 {
+            chain = chain.clone().xor(chain.and(above(i)));
+        }
+        let deep = chain.build().unwrap();
+        assert!(deep.nodes.len() <= MAX_CHECKED_NODES);
+        assert_eq!(deep.analyze(), None);
+        assert_eq!(deep.is_satisfiable(), None);
+
+        // under the caps, but the negation for `always_true` has 3^5 terms
+        let terms = (0..5).map(|i| above(3 * i).and(above(3 * i + 1)).and(above(3 * i + 2)));
+        let wide_dnf = ConditionBuilder::any(terms.collect()).build().unwrap();
+        assert_eq!(wide_dnf.is_satisfiable(), Some(true));
+        assert_eq!(wide_dnf.analyze(), None);
+    }
+
+    #[test]
+    fn test_exact_bounds() {
+        let token = Pubkey::new_unique();
+        // there are prices strictly between 100 and 100.5
+        let tree = ConditionBuilder::price_above(token, Price::from(100))
+            .and(ConditionBuilder::price_below(token, Price::new(1005, -1)))
+            .build()
+            .unwrap();
+        assert_eq!(tree.is_satisfiable(), Some(true));
+
+        // but none both above and at or below 100, whatever the exponent
+        let tree = ConditionBuilder::price_above(token, Price::from(100))
+            .and(ConditionBuilder::price_at_or_below(
+                token,
+                Price::new(10000, -2),
+            ))
+            .build()
+            .unwrap();
+        assert_eq!(tree.is_satisfiable(), Some(false));
+
+        // price < 100 OR price >= 100 covers every price, price < 100 OR price > 100 misses 100
+        let tree = ConditionBuilder::price_below(token, Price::from(100))
+            .or(ConditionBuilder::price_at_or_above(token, Price::from(100)))
+            .build()
+            .unwrap();
+        assert!(tree.analyze().unwrap().always_true);
+        let tree = ConditionBuilder::price_below(token, Price::from(100))
+            .or(ConditionBuilder::price_above(token, Price::from(100)))
+            .build()
+            .unwrap();
+        let report = tree.analyze().unwrap();
+        assert!(!report.always_true);
+        assert_eq!(report.token_ranges[&token].len(), 2);
+        assert!(!report.token_ranges[&token]
+            .iter()
+            .any(|r| r.contains(Price::from(100))));
+    }
+}
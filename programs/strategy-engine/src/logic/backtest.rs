@@ -0,0 +1,530 @@
+use crate::logic::parser::conditionParser::{parse_price, parse_token};
+use crate::logic::parser::context::ParseContext;
+use crate::logic::parser::diagnostics::parse_complete;
+use crate::logic::portfolio::{Market, Portfolio, Trade};
+use crate::logic::price::Price;
+use crate::logic::registry::TokenRegistry;
+use crate::logic::strategy::Strategy;
+use anchor_lang::prelude::*;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Replays a price history through a `Strategy` the way a keeper drives a vault: at every tick the
+// timing of `execute_strategy` applies (the condition is only checked once
+// `execute_every_seconds` have passed since the actions last ran), and when the condition holds
+// the actions are applied to a `Portfolio`, which accrues interest from tick to tick. The vault is
+// taken to be created at the first tick, so nothing runs before `execute_every_seconds` have
+// passed.
+
+// Prices at one point in time. only the tokens whose price changed are listed; the others keep
+// their last price
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tick {
+    pub timestamp: u64,
+    pub prices: HashMap<Pubkey, Price>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PriceHistory {
+    pub ticks: Vec<Tick>,
+}
+
+// a problem with a price history file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryError {
+    // 1-based
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn history_error(line: usize, message: impl Into<String>) -> HistoryError {
+    HistoryError {
+        line,
+        message: message.into(),
+    }
+}
+
+// a pubkey, or a symbol of the context's token registry
+fn token_key(
+    ctx: &ParseContext,
+    text: &str,
+    line: usize,
+) -> std::result::Result<Pubkey, HistoryError> {
+    parse_complete(ctx, text, parse_token).map_err(|_| {
+        history_error(
+            line,
+            format!("`{}` is neither a pubkey nor a registered symbol", text),
+        )
+    })
+}
+
+fn price_value(
+    ctx: &ParseContext,
+    text: &str,
+    line: usize,
+) -> std::result::Result<Price, HistoryError> {
+    parse_complete(ctx, text, parse_price)
+        .map_err(|_| history_error(line, format!("`{}` is not a price", text)))
+}
+
+impl PriceHistory {
+    // a header of `timestamp` and the tokens, then one row per tick:
+    //
+    //   timestamp,SOL,USDC
+    //   1700000000,101.5,1
+    //   1700000060,102,
+    //
+    // timestamps are unix seconds and must increase; an empty cell keeps the previous price.
+    // blank lines and lines starting with `#` are skipped. tokens are pubkeys or symbols of
+    // `registry`
+    pub fn from_csv(
+        input: &str,
+        registry: &TokenRegistry,
+    ) -> std::result::Result<Self, HistoryError> {
+        let ctx = ParseContext::with_registry(registry);
+        let mut rows = input
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        let (line, header) = rows
+            .next()
+            .ok_or_else(|| history_error(1, "missing header"))?;
+        let mut columns = header.split(',').map(str::trim);
+        if columns.next() != Some("timestamp") {
+            return Err(history_error(line, "the first column must be `timestamp`"));
+        }
+        let tokens = columns
+            .map(|column| token_key(&ctx, column, line))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let mut history = Self::default();
+        for (line, row) in rows {
+            let cells = row.split(',').map(str::trim).collect::<Vec<_>>();
+            if cells.len() != tokens.len() + 1 {
+                return Err(history_error(
+                    line,
+                    format!(
+                        "expected {} columns, found {}",
+                        tokens.len() + 1,
+                        cells.len()
+                    ),
+                ));
+            }
+            let timestamp = cells[0].parse().map_err(|_| {
+                history_error(line, format!("`{}` is not a unix timestamp", cells[0]))
+            })?;
+            let mut prices = HashMap::new();
+            for (token, cell) in tokens.iter().zip(&cells[1..]) {
+                if !cell.is_empty() {
+                    prices.insert(*token, price_value(&ctx, cell, line)?);
+                }
+            }
+            history.push(line, Tick { timestamp, prices })?;
+        }
+        Ok(history)
+    }
+
+    // one JSON object per line, prices as numbers or decimal strings:
+    //
+    //   {"timestamp": 1700000000, "prices": {"SOL": 101.5, "USDC": "1"}}
+    #[cfg(feature = "serde")]
+    pub fn from_jsonl(
+        input: &str,
+        registry: &TokenRegistry,
+    ) -> std::result::Result<Self, HistoryError> {
+        use std::collections::BTreeMap;
+
+        let ctx = ParseContext::with_registry(registry);
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Row {
+            timestamp: u64,
+            prices: BTreeMap<String, serde_json::Value>,
+        }
+
+        let mut history = Self::default();
+        for (i, text) in input.lines().enumerate() {
+            let line = i + 1;
+            if text.trim().is_empty() {
+                continue;
+            }
+            let row: Row =
+                serde_json::from_str(text).map_err(|e| history_error(line, e.to_string()))?;
+            let mut prices = HashMap::new();
+            for (token, value) in row.prices {
+                let text = match value {
+                    serde_json::Value::String(text) => text,
+                    serde_json::Value::Number(number) => number.to_string(),
+                    _ => {
+                        return Err(history_error(
+                            line,
+                            format!("price of `{}` must be a number or a string", token),
+                        ))
+                    }
+                };
+                prices.insert(
+                    token_key(&ctx, &token, line)?,
+                    price_value(&ctx, &text, line)?,
+                );
+            }
+            history.push(
+                line,
+                Tick {
+                    timestamp: row.timestamp,
+                    prices,
+                },
+            )?;
+        }
+        Ok(history)
+    }
+
+    // the ticks in `range`, the first of them carrying every price known by then, so that the
+    // window can be replayed on its own
+    pub fn window(&self, range: std::ops::Range<usize>) -> Self {
+        let mut ticks = self.ticks[range.clone()].to_vec();
+        if let Some(first) = ticks.first_mut() {
+            for tick in self.ticks[..range.start].iter().rev() {
+                for (token, price) in &tick.prices {
+                    first.prices.entry(*token).or_insert(*price);
+                }
+            }
+        }
+        Self { ticks }
+    }
+
+    fn push(&mut self, line: usize, tick: Tick) -> std::result::Result<(), HistoryError> {
+        if let Some(last) = self.ticks.last() {
+            if tick.timestamp <= last.timestamp {
+                return Err(history_error(
+                    line,
+                    format!(
+                        "timestamp {} does not come after {}",
+                        tick.timestamp, last.timestamp
+                    ),
+                ));
+            }
+        }
+        self.ticks.push(tick);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BacktestConfig {
+    // quote currency (e.g. USD) the portfolio starts with; BUY pays and SELL receives it
+    pub initial_cash: f64,
+    // raw token units held at the start
+    pub initial_balances: HashMap<Pubkey, u64>,
+    // fees, price impact and lending terms
+    pub market: Market,
+}
+
+impl Default for BacktestConfig {
+    fn default() -> Self {
+        Self {
+            initial_cash: 10_000.0,
+            initial_balances: HashMap::new(),
+            market: Market::default(),
+        }
+    }
+}
+
+impl BacktestConfig {
+    // decimals of every token in `registry`
+    pub fn with_registry(mut self, registry: &TokenRegistry) -> Self {
+        self.market = self.market.with_registry(registry);
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EquityPoint {
+    pub timestamp: u64,
+    // cash plus holdings and supplied tokens less debt, at the last known prices
+    pub equity: f64,
+    // quote value of the holdings and supplied tokens alone, not net of debt
+    pub positions: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BacktestReport {
+    pub trades: Vec<Trade>,
+    // one point per tick
+    pub equity_curve: Vec<EquityPoint>,
+    // times the condition held and the actions ran
+    pub runs: u64,
+    // the tick at which UNTIL or MAX_RUNS ended the strategy
+    pub finished_at: Option<u64>,
+    pub portfolio: Portfolio,
+}
+
+pub fn backtest(
+    strategy: &Strategy,
+    history: &PriceHistory,
+    config: &BacktestConfig,
+) -> BacktestReport {
+    let market = &config.market;
+    let mut portfolio = Portfolio::new(config.initial_cash, config.initial_balances.clone());
+    // last known price of every token
+    let mut prices = HashMap::new();
+    let mut trades = vec![];
+    let mut last_executed = history.ticks.first().map_or(0, |tick| tick.timestamp);
+    let mut runs = 0;
+    let mut finished_at = None;
+    let mut equity_curve = Vec::with_capacity(history.ticks.len());
+    for tick in &history.ticks {
+        prices.extend(&tick.prices);
+        portfolio.accrue(market, tick.timestamp);
+        let due = tick.timestamp - last_executed >= strategy.execute_every_seconds;
+        if finished_at.is_none() && due {
+            let ctx = portfolio.context(market, &prices);
+            if strategy.is_finished(&ctx, runs) {
+                finished_at = Some(tick.timestamp);
+            } else if strategy.condition.evaluate(&ctx) {
+                trades.extend(portfolio.execute(&strategy.action, market, &prices, tick.timestamp));
+                runs += 1;
+                last_executed = tick.timestamp;
+            }
+        }
+        let (equity, positions) = portfolio.valuation(market, &prices);
+        equity_curve.push(EquityPoint {
+            timestamp: tick.timestamp,
+            equity,
+            positions,
+        });
+    }
+    BacktestReport {
+        trades,
+        equity_curve,
+        runs,
+        finished_at,
+        portfolio,
+    }
+}
+
+// `job(0)` to `job(count - 1)` in order, run on up to `threads` threads at once; 0 runs one per
+// available core
+pub(crate) fn in_parallel<R: Send>(
+    count: usize,
+    threads: usize,
+    job: impl Fn(usize) -> R + Sync,
+) -> Vec<R> {
+    let threads = match threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+    .clamp(1, count.max(1));
+    let next = AtomicUsize::new(0);
+    let mut results = (0..count).map(|_| None).collect::<Vec<_>>();
+    std::thread::scope(|scope| {
+        let workers = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= count {
+                            return done;
+                        }
+                        done.push((i, job(i)));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            for (i, result) in worker.join().expect("a job panicked") {
+                results[i] = Some(result);
+            }
+        }
+    });
+    results
+        .into_iter()
+        .map(|r| r.expect("every job runs"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::parser::strategyParser::translate_strategy_string;
+
+    fn history(token: Pubkey, prices: &[(u64, u64)]) -> PriceHistory {
+        let mut csv = format!("timestamp,{}\n", token);
+        for (timestamp, price) in prices {
+            csv += &format!("{},{}\n", timestamp, price);
+        }
+        PriceHistory::from_csv(&csv, &TokenRegistry::default()).unwrap()
+    }
+
+    #[test]
+    fn test_csv_history() {
+        let sol = Pubkey::new_unique();
+        let usdc = Pubkey::new_unique();
+        let csv = format!(
+            "# minute bars\ntimestamp, {sol}, {usdc}\n\n100,101.5,1\n160,,0.99\n# gap\n220,102,\n"
+        );
+        let history = PriceHistory::from_csv(&csv, &TokenRegistry::default()).unwrap();
+        assert_eq!(history.ticks.len(), 3);
+        assert_eq!(history.ticks[0].prices[&sol], Price::new(1015, -1));
+        assert_eq!(history.ticks[1].prices.get(&sol), None);
+        assert_eq!(history.ticks[2].prices.len(), 1);
+
+        let error = |csv: &str| PriceHistory::from_csv(csv, &TokenRegistry::default()).unwrap_err();
+        assert_eq!(error(&format!("time,{sol}\n")).line, 1);
+        assert_eq!(error(&format!("timestamp,{sol}\n1,2,3\n")).line, 2);
+        assert_eq!(error(&format!("timestamp,{sol}\n1,2\n\n1,3\n")).line, 4);
+        assert_eq!(
+            error(&format!("timestamp,{sol}\n1,lots\n")).to_string(),
+            "line 2: `lots` is not a price"
+        );
+        assert!(
+            PriceHistory::from_csv("timestamp,NOT_A_TOKEN\n", &TokenRegistry::default()).is_err()
+        );
+
+        // the window starts with the prices carried over from before it
+        let window = history.window(2..3);
+        assert_eq!(window.ticks.len(), 1);
+        assert_eq!(window.ticks[0].timestamp, 220);
+        assert_eq!(window.ticks[0].prices[&sol], Price::from(102));
+        assert_eq!(window.ticks[0].prices[&usdc], Price::new(99, -2));
+        assert_eq!(history.window(0..3), history);
+        assert!(history.window(3..3).ticks.is_empty());
+    }
+
+    #[test]
+    fn test_buy_the_dip_once() {
+        let sol = Pubkey::new_unique();
+        let strategy = translate_strategy_string(&format!(
+            "WHEN PRICE_BELOW({sol}, 95) THEN BUY({sol}, 10) EVERY 1m MAX_RUNS 1"
+        ))
+        .unwrap();
+        let history = history(sol, &[(0, 100), (60, 90), (120, 80), (180, 110)]);
+        let config = BacktestConfig {
+            market: Market {
+                fee_bps: 10,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = backtest(&strategy, &history, &config);
+
+        assert_eq!(report.runs, 1);
+        assert_eq!(report.finished_at, Some(120));
+        assert_eq!(report.trades.len(), 1);
+        let trade = &report.trades[0];
+        assert_eq!((trade.timestamp, trade.quantity), (60, 10));
+        assert_eq!(trade.price, Some(90.0));
+        assert!((trade.fee - 0.9).abs() < 1e-9);
+        assert!((report.portfolio.cash - 9099.1).abs() < 1e-9);
+        let equity = report
+            .equity_curve
+            .iter()
+            .map(|p| (p.timestamp, (p.equity * 10.0).round() / 10.0))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            equity,
+            [(0, 10_000.0), (60, 9999.1), (120, 9899.1), (180, 10_199.1)]
+        );
+    }
+
+    #[test]
+    fn test_execute_every_seconds() {
+        let sol = Pubkey::new_unique();
+        let strategy = translate_strategy_string(&format!(
+            "WHEN PRICE_BELOW({sol}, 1000) THEN BUY({sol}, 1) EVERY 2m"
+        ))
+        .unwrap();
+        let ticks = (0..10).map(|i| (i * 60, 10)).collect::<Vec<_>>();
+        let report = backtest(&strategy, &history(sol, &ticks), &Default::default());
+        let times = report
+            .trades
+            .iter()
+            .map(|t| t.timestamp)
+            .collect::<Vec<_>>();
+        assert_eq!(times, [120, 240, 360, 480]);
+        assert_eq!(report.portfolio.holdings[&sol], 4);
+    }
+
+    #[test]
+    fn test_sequences_stop_at_rejection() {
+        let sol = Pubkey::new_unique();
+        let strategy = translate_strategy_string(&format!(
+            "WHEN TRUE THEN SELL({sol}, 5) THEN BUY({sol}, 1) EVERY 1m MAX_RUNS 1"
+        ))
+        .unwrap();
+        let report = backtest(
+            &strategy,
+            &history(sol, &[(0, 10), (60, 10)]),
+            &Default::default(),
+        );
+        assert_eq!(report.trades.len(), 1);
+        assert_eq!(
+            report.trades[0].rejected.as_deref(),
+            Some("sells 5 units, holds 0")
+        );
+        assert_eq!(report.portfolio.cash, 10_000.0);
+    }
+
+    #[test]
+    fn test_percent_amounts_slippage_and_decimals() {
+        let sol = Pubkey::new_unique();
+        let strategy = translate_strategy_string(&format!(
+            "WHEN TRUE THEN BUY({sol}, 50%) THEN LEND({sol}, 50%) EVERY 1m MAX_RUNS 1"
+        ))
+        .unwrap();
+        let config = BacktestConfig {
+            market: Market {
+                decimals: HashMap::from([(sol, 9)]),
+                slippage_bps: 100,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = backtest(&strategy, &history(sol, &[(0, 100), (60, 100)]), &config);
+        let buy = &report.trades[0];
+        // 5000 of cash at 101 per SOL
+        assert_eq!(buy.price, Some(101.0));
+        assert_eq!(buy.quantity, 49_504_950_495);
+        assert!((report.portfolio.cash - 5000.0).abs() < 1e-6);
+        assert_eq!(report.trades[1].quantity, 24_752_475_247);
+        assert_eq!(
+            report.portfolio.holdings[&sol] + report.portfolio.supplied[&sol],
+            buy.quantity
+        );
+        // marked at 100, so the slippage shows as a loss
+        let last = report.equity_curve.last().unwrap().equity;
+        assert!((last - 9950.495).abs() < 1e-3, "{}", last);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_jsonl_history() {
+        let sol = Pubkey::new_unique();
+        let jsonl = format!(
+            "{{\"timestamp\": 1, \"prices\": {{\"{sol}\": 101.5}}}}\n\n\
+             {{\"timestamp\": 2, \"prices\": {{\"{sol}\": \"102.25\"}}}}\n"
+        );
+        let history = PriceHistory::from_jsonl(&jsonl, &TokenRegistry::default()).unwrap();
+        assert_eq!(history.ticks[1].prices[&sol], Price::new(10225, -2));
+
+        let bad = format!("{{\"timestamp\": 1, \"prices\": {{\"{sol}\": true}}}}");
+        assert_eq!(
+            PriceHistory::from_jsonl(&bad, &TokenRegistry::default())
+                .unwrap_err()
+                .line,
+            1
+        );
+        assert!(PriceHistory::from_jsonl(
+            "{\"timestamp\": 1, \"volume\": 3}",
+            &TokenRegistry::default()
+        )
+        .is_err());
+    }
+}
@@ -1,39 +1,293 @@
+use crate::logic::kinds::condition_kind;
+use crate::logic::price::Price;
+use crate::logic::printer::PrintOptions;
+use crate::logic::value::ValueExpr;
+use crate::ErrorCode;
 use anchor_lang::prelude::*;
 use std::collections::HashMap;
 
+// Position of a node in a tree's `nodes` arena. Trees used to be indexed with `u8`, which
+// wrapped silently past 255 nodes; see `logic::legacy` for reading trees stored that way.
+// The types stored on chain spell out `u16` instead: Anchor's IDL generation only resolves
+// aliases when built with `procmacro2_semver_exempt`, and takes `NodeIndex` for a type of this
+// crate otherwise, which breaks `--features idl-build`
+pub type NodeIndex = u16;
+
+pub const MAX_NODES: usize = NodeIndex::MAX as usize + 1;
+
+#[derive(Default)]
 pub struct EvaluationContext {
-    pub token_prices: HashMap<Pubkey, u64>,
+    pub token_prices: HashMap<Pubkey, Price>,
+    // the vault's holdings in whole tokens, read by `balance(..)` in value expressions
+    pub token_balances: HashMap<Pubkey, Price>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum AtomicCondition {
     // Price-based conditions
-    PriceAbove { token: Pubkey, price: u64 },
-    PriceBelow { token: Pubkey, price: u64 },
+    PriceAbove {
+        token: Pubkey,
+        price: Price,
+    },
+    PriceBelow {
+        token: Pubkey,
+        price: Price,
+    },
+    PriceAtOrAbove {
+        token: Pubkey,
+        price: Price,
+    },
+    PriceAtOrBelow {
+        token: Pubkey,
+        price: Price,
+    },
+    // a condition implemented outside this crate, see `logic::kinds`.
+    // `kind` is the id of a registered `ConditionKind`, `data` its encoded arguments
+    Custom {
+        kind: u16,
+        data: Vec<u8>,
+    },
+    // two arithmetic expressions, e.g. `price(ETH) / price(BTC) < 0.05`
+    Compare {
+        left: ValueExpr,
+        op: CompareOp,
+        right: ValueExpr,
+    },
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CompareOp {
+    Above,
+    Below,
+    AtOrAbove,
+    AtOrBelow,
+}
+
+impl CompareOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            CompareOp::Above => ">",
+            CompareOp::Below => "<",
+            CompareOp::AtOrAbove => ">=",
+            CompareOp::AtOrBelow => "<=",
+        }
+    }
+
+    // the same comparison with its operands swapped: `a < b` is `b > a`
+    pub fn flip(&self) -> Self {
+        match self {
+            CompareOp::Above => CompareOp::Below,
+            CompareOp::Below => CompareOp::Above,
+            CompareOp::AtOrAbove => CompareOp::AtOrBelow,
+            CompareOp::AtOrBelow => CompareOp::AtOrAbove,
+        }
+    }
+
+    // the complementary comparison: `a > b` is `NOT(a <= b)`
+    pub fn negate(&self) -> Self {
+        match self {
+            CompareOp::Above => CompareOp::AtOrBelow,
+            CompareOp::Below => CompareOp::AtOrAbove,
+            CompareOp::AtOrAbove => CompareOp::Below,
+            CompareOp::AtOrBelow => CompareOp::Above,
+        }
+    }
+
+    pub fn holds<T: Ord>(&self, left: T, right: T) -> bool {
+        match self {
+            CompareOp::Above => left > right,
+            CompareOp::Below => left < right,
+            CompareOp::AtOrAbove => left >= right,
+            CompareOp::AtOrBelow => left <= right,
+        }
+    }
+}
+
+// How `to_string_expr_styled` writes operators and price comparisons
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExprStyle {
+    // AND, OR, NOT and PRICE_ABOVE(t, 100)
+    #[default]
+    Keywords,
+    // &&, || , ! and price(t) > 100
+    Symbols,
 }
 
 impl std::fmt::Display for AtomicCondition {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_string_with(&|token| token.to_string()))
+    }
+}
+
+impl AtomicCondition {
+    // printed form, with tokens written by `label` (e.g. as registry symbols)
+    pub fn to_string_with(&self, label: &dyn Fn(&Pubkey) -> String) -> String {
+        self.to_string_styled(ExprStyle::Keywords, label)
+    }
+
+    pub fn to_string_styled(&self, style: ExprStyle, label: &dyn Fn(&Pubkey) -> String) -> String {
+        if style == ExprStyle::Symbols {
+            let infix = |token, op, price| format!("price({}) {} {}", label(token), op, price);
+            match self {
+                AtomicCondition::PriceAbove { token, price } => return infix(token, ">", price),
+                AtomicCondition::PriceBelow { token, price } => return infix(token, "<", price),
+                AtomicCondition::PriceAtOrAbove { token, price } => {
+                    return infix(token, ">=", price)
+                }
+                AtomicCondition::PriceAtOrBelow { token, price } => {
+                    return infix(token, "<=", price)
+                }
+                AtomicCondition::Custom { .. } | AtomicCondition::Compare { .. } => {}
+            }
+        }
         match self {
             AtomicCondition::PriceAbove { token, price } => {
-                write!(f, "PRICE_ABOVE({}, {})", token, price)
+                format!("PRICE_ABOVE({}, {})", label(token), price)
             }
             AtomicCondition::PriceBelow { token, price } => {
-                write!(f, "PRICE_BELOW({}, {})", token, price)
+                format!("PRICE_BELOW({}, {})", label(token), price)
+            }
+            AtomicCondition::PriceAtOrAbove { token, price } => {
+                format!("PRICE_AT_OR_ABOVE({}, {})", label(token), price)
+            }
+            AtomicCondition::PriceAtOrBelow { token, price } => {
+                format!("PRICE_AT_OR_BELOW({}, {})", label(token), price)
+            }
+            AtomicCondition::Custom { kind, data } => match condition_kind(*kind) {
+                Some(k) => format!("{}{}", k.keyword(), k.display(data)),
+                // not registered in this process: still printable, but not parseable
+                None => format!("CUSTOM_{}({:?})", kind, data),
+            },
+            // infix in either style, there is no keyword form
+            AtomicCondition::Compare { left, op, right } => format!(
+                "{} {} {}",
+                left.to_string_with(label),
+                op.symbol(),
+                right.to_string_with(label)
+            ),
+        }
+    }
+
+    // the token whose price this condition compares; `None` for custom conditions and
+    // comparisons of value expressions, which may read several tokens
+    pub fn token(&self) -> Option<Pubkey> {
+        match self {
+            AtomicCondition::PriceAbove { token, .. }
+            | AtomicCondition::PriceBelow { token, .. }
+            | AtomicCondition::PriceAtOrAbove { token, .. }
+            | AtomicCondition::PriceAtOrBelow { token, .. } => Some(*token),
+            AtomicCondition::Custom { .. } | AtomicCondition::Compare { .. } => None,
+        }
+    }
+
+    // the complementary comparison, e.g. PRICE_ABOVE(t, 100) -> PRICE_AT_OR_BELOW(t, 100).
+    // only a true complement when a price for the token is present: a missing price makes both false.
+    // custom conditions have no known complement
+    pub fn negate(&self) -> Option<Self> {
+        match self.clone() {
+            AtomicCondition::PriceAbove { token, price } => {
+                Some(AtomicCondition::PriceAtOrBelow { token, price })
+            }
+            AtomicCondition::PriceBelow { token, price } => {
+                Some(AtomicCondition::PriceAtOrAbove { token, price })
+            }
+            AtomicCondition::PriceAtOrAbove { token, price } => {
+                Some(AtomicCondition::PriceBelow { token, price })
+            }
+            AtomicCondition::PriceAtOrBelow { token, price } => {
+                Some(AtomicCondition::PriceAbove { token, price })
+            }
+            AtomicCondition::Compare { left, op, right } => Some(AtomicCondition::Compare {
+                left,
+                op: op.negate(),
+                right,
+            }),
+            AtomicCondition::Custom { .. } => None,
+        }
+    }
+
+    // evaluates the condition against an observed price; a missing price is never true
+    pub fn evaluate_price(&self, observed: Option<Price>) -> bool {
+        match self {
+            AtomicCondition::PriceAbove { price, .. } => observed.is_some_and(|p| p > *price),
+            AtomicCondition::PriceBelow { price, .. } => observed.is_some_and(|p| p < *price),
+            AtomicCondition::PriceAtOrAbove { price, .. } => observed.is_some_and(|p| p >= *price),
+            AtomicCondition::PriceAtOrBelow { price, .. } => observed.is_some_and(|p| p <= *price),
+            AtomicCondition::Custom { .. } | AtomicCondition::Compare { .. } => false,
+        }
+    }
+
+    pub fn evaluate(&self, ctx: &EvaluationContext) -> bool {
+        match (self, self.token()) {
+            // an unregistered kind can't be evaluated; `ConditionTree::validate` reports it
+            (AtomicCondition::Custom { kind, data }, _) => {
+                condition_kind(*kind).is_some_and(|k| k.evaluate(data, ctx))
+            }
+            (AtomicCondition::Compare { left, op, right }, _) => {
+                match (left.evaluate(ctx), right.evaluate(ctx)) {
+                    (Some(l), Some(r)) => op.holds(l, r),
+                    _ => false,
+                }
+            }
+            (_, token) => {
+                self.evaluate_price(token.and_then(|t| ctx.token_prices.get(&t).copied()))
             }
         }
     }
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum ConditionType {
     Atomic(AtomicCondition),
-    And { left: u8, right: u8 }, // And(Box<Condition>, Box<Condition>),
-    Or { left: u8, right: u8 },  // Or(Box<Condition>, Box<Condition>),
-    Not { child: u8 },           // Not(Box<Condition>),
+    And { left: u16, right: u16 }, // And(Box<Condition>, Box<Condition>),
+    Or { left: u16, right: u16 },  // Or(Box<Condition>, Box<Condition>),
+    Not { child: u16 },            // Not(Box<Condition>),
+    Const(bool),                   // TRUE / FALSE, mostly produced by the simplifier
+    All { children: Vec<u16> },    // every child is true
+    Any { children: Vec<u16> },    // at least one child is true
+    AtLeast { k: u16, children: Vec<u16> }, // at least `k` children are true
+    Xor { left: u16, right: u16 },
+    Implies { left: u16, right: u16 }, // NOT left OR right
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+impl ConditionType {
+    // indices of the direct children of this node, in order
+    pub fn children(&self) -> Vec<NodeIndex> {
+        match self {
+            ConditionType::Atomic(_) | ConditionType::Const(_) => vec![],
+            ConditionType::And { left, right }
+            | ConditionType::Or { left, right }
+            | ConditionType::Xor { left, right }
+            | ConditionType::Implies { left, right } => vec![*left, *right],
+            ConditionType::Not { child } => vec![*child],
+            ConditionType::All { children }
+            | ConditionType::Any { children }
+            | ConditionType::AtLeast { children, .. } => children.clone(),
+        }
+    }
+
+    // applies `f` to every child index, e.g. to shift a subtree when grafting it into another arena
+    pub fn map_children<F: FnMut(NodeIndex) -> NodeIndex>(&mut self, mut f: F) {
+        match self {
+            ConditionType::Atomic(_) | ConditionType::Const(_) => {}
+            ConditionType::And { left, right }
+            | ConditionType::Or { left, right }
+            | ConditionType::Xor { left, right }
+            | ConditionType::Implies { left, right } => {
+                *left = f(*left);
+                *right = f(*right);
+            }
+            ConditionType::Not { child } => *child = f(*child),
+            ConditionType::All { children }
+            | ConditionType::Any { children }
+            | ConditionType::AtLeast { children, .. } => {
+                children.iter_mut().for_each(|c| *c = f(*c))
+            }
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ConditionNode {
     pub condition_type: ConditionType,
 }
@@ -41,7 +295,7 @@ pub struct ConditionNode {
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
 pub struct ConditionTree {
     pub nodes: Vec<ConditionNode>,
-    pub root_index: u8,
+    pub root_index: u16,
 }
 
 impl ConditionTree {
@@ -49,6 +303,52 @@ impl ConditionTree {
         self.evaluate_node(self.root_index, ctx)
     }
 
+    // structural checks, plus custom conditions being registered and well-formed
+    pub fn validate(&self) -> Result<()> {
+        self.validate_structure()?;
+        for node in &self.nodes {
+            if let ConditionType::Atomic(AtomicCondition::Custom { kind, data }) =
+                &node.condition_type
+            {
+                let kind = condition_kind(*kind).ok_or(ErrorCode::UnknownConditionKind)?;
+                require!(
+                    data.len() == kind.data_len(),
+                    ErrorCode::InvalidConditionData
+                );
+                kind.validate(data)?;
+            }
+        }
+        Ok(())
+    }
+
+    // every child index points at an earlier node (which also rules out cycles), the same
+    // holds inside comparisons, and AT_LEAST can be satisfied. enough for the tree to be
+    // walked without indexing out of bounds, whatever kinds are registered
+    pub fn validate_structure(&self) -> Result<()> {
+        require!(
+            (self.root_index as usize) < self.nodes.len(),
+            ErrorCode::InvalidTree
+        );
+        for (index, node) in self.nodes.iter().enumerate() {
+            let children = node.condition_type.children();
+            require!(
+                children.iter().all(|c| (*c as usize) < index),
+                ErrorCode::InvalidTree
+            );
+            match &node.condition_type {
+                ConditionType::AtLeast { k, children } => {
+                    require!(*k as usize <= children.len(), ErrorCode::InvalidTree)
+                }
+                ConditionType::Atomic(AtomicCondition::Compare { left, right, .. }) => {
+                    left.validate()?;
+                    right.validate()?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
     pub fn size(&self) -> usize {
         // // 8 bytes for discriminator + 1 byte for root_index + nodes size
         // 8 + 1 + (self.nodes.len() * std::mem::size_of::<ConditionNode>())
@@ -57,58 +357,164 @@ impl ConditionTree {
         buf.len()
     }
 
-    pub fn evaluate_node(&self, index: u8, ctx: &EvaluationContext) -> bool {
-        // evaluate this node at index number `index`
-        let node = &self.nodes[index as usize];
-        match &node.condition_type {
-            ConditionType::Atomic(atomic) => match atomic {
-                AtomicCondition::PriceAbove { token, price } => {
-                    ctx.token_prices.get(token).map_or(false, |p| p > price)
-                }
-                AtomicCondition::PriceBelow { token, price } => {
-                    ctx.token_prices.get(token).map_or(false, |p| p < price)
+    pub fn evaluate_node(&self, index: NodeIndex, ctx: &EvaluationContext) -> bool {
+        self.evaluate_shared(index, ctx, &mut vec![None; self.nodes.len()])
+    }
+
+    // `memo` keeps the value of every node evaluated so far, by index, so a node several
+    // parents share (e.g. a `LET` binding) is evaluated once instead of once per path to it
+    fn evaluate_shared(
+        &self,
+        index: NodeIndex,
+        ctx: &EvaluationContext,
+        memo: &mut [Option<bool>],
+    ) -> bool {
+        if let Some(value) = memo[index as usize] {
+            return value;
+        }
+        let mut eval = |child: NodeIndex| self.evaluate_shared(child, ctx, memo);
+        let value = match &self.nodes[index as usize].condition_type {
+            ConditionType::Atomic(atomic) => atomic.evaluate(ctx),
+            ConditionType::And { left, right } => eval(*left) && eval(*right),
+            ConditionType::Or { left, right } => eval(*left) || eval(*right),
+            ConditionType::Not { child } => !eval(*child),
+            ConditionType::Const(value) => *value,
+            ConditionType::All { children } => children.iter().all(|c| eval(*c)),
+            ConditionType::Any { children } => children.iter().any(|c| eval(*c)),
+            ConditionType::AtLeast { k, children } => {
+                // stops as soon as `k` children have been found true
+                let mut count = 0;
+                for c in children {
+                    if count >= *k {
+                        break;
+                    }
+                    if eval(*c) {
+                        count += 1;
+                    }
                 }
-            },
-            ConditionType::And { left, right } => {
-                self.evaluate_node(*left, ctx) && self.evaluate_node(*right, ctx)
+                count >= *k
             }
-            ConditionType::Or { left, right } => {
-                self.evaluate_node(*left, ctx) || self.evaluate_node(*right, ctx)
-            }
-            ConditionType::Not { child } => !self.evaluate_node(*child, ctx),
-        }
+            ConditionType::Xor { left, right } => eval(*left) != eval(*right),
+            ConditionType::Implies { left, right } => !eval(*left) || eval(*right),
+        };
+        memo[index as usize] = Some(value);
+        value
     }
 
     pub fn to_string_expr(&self) -> String {
-        self.string_node(self.root_index)
+        self.to_string_pretty(PrintOptions::default())
+    }
+
+    pub fn to_string_expr_styled(&self, style: ExprStyle) -> String {
+        self.to_string_pretty(PrintOptions {
+            style,
+            multiline: false,
+        })
     }
 
-    fn string_node(&self, index: u8) -> String {
+    // every binary operator in parentheses, e.g. for `explain`; `annotate` receives the index of every node along with its rendered text and may decorate it
+    pub(crate) fn string_node_with<F>(
+        &self,
+        index: NodeIndex,
+        style: ExprStyle,
+        annotate: &F,
+    ) -> String
+    where
+        F: Fn(NodeIndex, String) -> String,
+    {
         let node = &self.nodes[index as usize];
-        match &node.condition_type {
-            ConditionType::Atomic(atomic) => format!("{}", atomic),
+        let s = match &node.condition_type {
+            ConditionType::Atomic(atomic) => atomic.to_string_styled(style, &|t| t.to_string()),
             ConditionType::And { left, right } => {
-                let l = self.string_node(*left);
-                let r = self.string_node(*right);
-                format!("({} AND {})", l, r)
+                let l = self.string_node_with(*left, style, annotate);
+                let r = self.string_node_with(*right, style, annotate);
+                match style {
+                    ExprStyle::Keywords => format!("({} AND {})", l, r),
+                    ExprStyle::Symbols => format!("({} && {})", l, r),
+                }
             }
             ConditionType::Or { left, right } => {
-                let l = self.string_node(*left);
-                let r = self.string_node(*right);
-                format!("({} OR {})", l, r)
+                let l = self.string_node_with(*left, style, annotate);
+                let r = self.string_node_with(*right, style, annotate);
+                match style {
+                    ExprStyle::Keywords => format!("({} OR {})", l, r),
+                    ExprStyle::Symbols => format!("({} || {})", l, r),
+                }
             }
             ConditionType::Not { child } => {
-                let c = self.string_node(*child);
-                format!("NOT{}", c)
+                let c = self.string_node_with(*child, style, annotate);
+                // infix atoms need parentheses: `!price(t) > 1` would negate `price(t)`
+                let infix = match &self.nodes[*child as usize].condition_type {
+                    ConditionType::Atomic(AtomicCondition::Compare { .. }) => true,
+                    ConditionType::Atomic(_) => style == ExprStyle::Symbols,
+                    _ => false,
+                };
+                let c = if infix { format!("({})", c) } else { c };
+                match style {
+                    ExprStyle::Keywords => format!("NOT{}", c),
+                    ExprStyle::Symbols => format!("!{}", c),
+                }
             }
-        }
+            ConditionType::Const(true) => "TRUE".to_string(),
+            ConditionType::Const(false) => "FALSE".to_string(),
+            ConditionType::All { children } => {
+                format!(
+                    "ALL({})",
+                    self.string_children_with(children, style, annotate)
+                )
+            }
+            ConditionType::Any { children } => {
+                format!(
+                    "ANY({})",
+                    self.string_children_with(children, style, annotate)
+                )
+            }
+            ConditionType::AtLeast { k, children } => format!(
+                "AT_LEAST {} OF ({})",
+                k,
+                self.string_children_with(children, style, annotate)
+            ),
+            ConditionType::Xor { left, right } => {
+                let l = self.string_node_with(*left, style, annotate);
+                let r = self.string_node_with(*right, style, annotate);
+                format!("({} XOR {})", l, r)
+            }
+            ConditionType::Implies { left, right } => {
+                let l = self.string_node_with(*left, style, annotate);
+                let r = self.string_node_with(*right, style, annotate);
+                format!("({} IMPLIES {})", l, r)
+            }
+        };
+        annotate(index, s)
+    }
+
+    fn string_children_with<F>(
+        &self,
+        children: &[NodeIndex],
+        style: ExprStyle,
+        annotate: &F,
+    ) -> String
+    where
+        F: Fn(NodeIndex, String) -> String,
+    {
+        children
+            .iter()
+            .map(|c| self.string_node_with(*c, style, annotate))
+            .collect::<Vec<_>>()
+            .join(", ")
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct ConditionBuilder {
     nodes: Vec<ConditionNode>,
-    root_index: u8,
+    root_index: NodeIndex,
+}
+
+impl Default for ConditionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ConditionBuilder {
@@ -120,51 +526,117 @@ impl ConditionBuilder {
     }
 
     fn with_node(mut self, node: ConditionNode) -> Self {
-        let index = self.nodes.len() as u8;
+        let index = self.nodes.len() as NodeIndex;
         self.nodes.push(node);
         self.root_index = index;
         self
     }
 
-    pub fn price_above(token: Pubkey, price: u64) -> Self {
+    pub fn price_above(token: Pubkey, price: Price) -> Self {
         Self::new().with_node(ConditionNode {
             condition_type: ConditionType::Atomic(AtomicCondition::PriceAbove { token, price }),
         })
     }
 
-    pub fn price_below(token: Pubkey, price: u64) -> Self {
+    pub fn price_below(token: Pubkey, price: Price) -> Self {
         Self::new().with_node(ConditionNode {
             condition_type: ConditionType::Atomic(AtomicCondition::PriceBelow { token, price }),
         })
     }
 
-    pub fn and(mut self, mut other: Self) -> Self {
-        let offset = self.nodes.len() as u8;
+    pub fn price_at_or_above(token: Pubkey, price: Price) -> Self {
+        Self::new().with_node(ConditionNode {
+            condition_type: ConditionType::Atomic(AtomicCondition::PriceAtOrAbove { token, price }),
+        })
+    }
+
+    pub fn price_at_or_below(token: Pubkey, price: Price) -> Self {
+        Self::new().with_node(ConditionNode {
+            condition_type: ConditionType::Atomic(AtomicCondition::PriceAtOrBelow { token, price }),
+        })
+    }
 
-        // shift other’s node indices
-        for node in &mut other.nodes {
-            match &mut node.condition_type {
-                ConditionType::And { left, right } | ConditionType::Or { left, right } => {
-                    *left += offset;
-                    *right += offset;
-                }
-                ConditionType::Not { child } => {
-                    *child += offset;
+    // `condition` as it is, without the lowering `compare` does
+    pub fn atom(condition: AtomicCondition) -> Self {
+        Self::new().with_node(ConditionNode {
+            condition_type: ConditionType::Atomic(condition),
+        })
+    }
+
+    // `data` is the payload of a registered `ConditionKind`
+    pub fn custom(kind: u16, data: Vec<u8>) -> Self {
+        Self::new().with_node(ConditionNode {
+            condition_type: ConditionType::Atomic(AtomicCondition::Custom { kind, data }),
+        })
+    }
+
+    // `left op right` over value expressions. a price against a constant becomes the
+    // matching price atom, so there is one tree for `price(t) > 100`
+    pub fn compare(left: ValueExpr, op: CompareOp, right: ValueExpr) -> Self {
+        let (token, op, price) = match (left.as_price(), right.as_constant()) {
+            (Some(token), Some(price)) => (token, op, price),
+            _ => match (left.as_constant(), right.as_price()) {
+                (Some(price), Some(token)) => (token, op.flip(), price),
+                _ => {
+                    return Self::new().with_node(ConditionNode {
+                        condition_type: ConditionType::Atomic(AtomicCondition::Compare {
+                            left,
+                            op,
+                            right,
+                        }),
+                    })
                 }
-                _ => {}
-            }
+            },
+        };
+        match op {
+            CompareOp::Above => Self::price_above(token, price),
+            CompareOp::Below => Self::price_below(token, price),
+            CompareOp::AtOrAbove => Self::price_at_or_above(token, price),
+            CompareOp::AtOrBelow => Self::price_at_or_below(token, price),
         }
+    }
 
-        let left = self.root_index;
-        let right = other.root_index + offset;
+    pub fn constant(value: bool) -> Self {
+        Self::new().with_node(ConditionNode {
+            condition_type: ConditionType::Const(value),
+        })
+    }
 
-        let mut nodes = vec![];
-        nodes.append(&mut self.nodes);
-        nodes.append(&mut other.nodes);
+    // merges the arenas of `parts` and returns the merged nodes together with the root of every
+    // part. a node equal to one already merged is not copied: its parents point at the existing
+    // one instead, so a subtree used twice (e.g. a `LET` binding) is stored once
+    fn graft(parts: Vec<Self>) -> (Vec<ConditionNode>, Vec<NodeIndex>) {
+        let mut nodes: Vec<ConditionNode> = vec![];
+        // where each node of `nodes` is, to find an equal one without comparing against all
+        let mut merged: HashMap<ConditionNode, NodeIndex> = HashMap::new();
+        let mut roots = vec![];
+        for part in parts {
+            // where each node of `part` ended up in `nodes`
+            let mut moved = Vec::with_capacity(part.nodes.len());
+            for mut node in part.nodes {
+                node.condition_type.map_children(|c| moved[c as usize]);
+                let index = *merged.entry(node).or_insert_with_key(|node| {
+                    nodes.push(node.clone());
+                    (nodes.len() - 1) as NodeIndex
+                });
+                moved.push(index);
+            }
+            // an empty part keeps pointing just past what came before it
+            roots.push(
+                moved
+                    .get(part.root_index as usize)
+                    .copied()
+                    .unwrap_or(nodes.len() as NodeIndex),
+            );
+        }
+        (nodes, roots)
+    }
 
-        let root = nodes.len() as u8;
+    fn combine(parts: Vec<Self>, make: impl FnOnce(Vec<NodeIndex>) -> ConditionType) -> Self {
+        let (mut nodes, roots) = Self::graft(parts);
+        let root = nodes.len() as NodeIndex;
         nodes.push(ConditionNode {
-            condition_type: ConditionType::And { left, right },
+            condition_type: make(roots),
         });
 
         Self {
@@ -172,6 +644,13 @@ impl ConditionBuilder {
             root_index: root,
         }
     }
+
+    pub fn and(self, other: Self) -> Self {
+        Self::combine(vec![self, other], |roots| ConditionType::And {
+            left: roots[0],
+            right: roots[1],
+        })
+    }
     // pub fn and(mut self, mut other: Self) -> Self {
     //     /*  store the root indexes of the two subtrees self and other
     //      * These indces point to the "top" node of each tree, and they will
@@ -201,37 +680,39 @@ impl ConditionBuilder {
     //     }
     // }
 
-    pub fn or(mut self, mut other: Self) -> Self {
-        let offset = self.nodes.len() as u8;
-        // shift other's node indices
-        for node in &mut other.nodes {
-            match &mut node.condition_type {
-                ConditionType::And { left, right } | ConditionType::Or { left, right } => {
-                    *left += offset;
-                    *right += offset;
-                }
-                ConditionType::Not { child } => {
-                    *child += offset;
-                }
-                _ => {}
-            }
-        }
-        let left = self.root_index;
-        let right = other.root_index + offset;
+    pub fn or(self, other: Self) -> Self {
+        Self::combine(vec![self, other], |roots| ConditionType::Or {
+            left: roots[0],
+            right: roots[1],
+        })
+    }
 
-        let mut nodes = vec![];
-        nodes.append(&mut self.nodes);
-        nodes.append(&mut other.nodes);
+    pub fn xor(self, other: Self) -> Self {
+        Self::combine(vec![self, other], |roots| ConditionType::Xor {
+            left: roots[0],
+            right: roots[1],
+        })
+    }
 
-        let root = nodes.len() as u8;
-        nodes.push(ConditionNode {
-            condition_type: ConditionType::Or { left, right },
-        });
+    // self => other
+    pub fn implies(self, other: Self) -> Self {
+        Self::combine(vec![self, other], |roots| ConditionType::Implies {
+            left: roots[0],
+            right: roots[1],
+        })
+    }
 
-        Self {
-            nodes,
-            root_index: root,
-        }
+    pub fn all(children: Vec<Self>) -> Self {
+        Self::combine(children, |children| ConditionType::All { children })
+    }
+
+    pub fn any(children: Vec<Self>) -> Self {
+        Self::combine(children, |children| ConditionType::Any { children })
+    }
+
+    // true when at least `k` of `children` are true, e.g. "2 of 3 majors dumping"
+    pub fn at_least(k: NodeIndex, children: Vec<Self>) -> Self {
+        Self::combine(children, |children| ConditionType::AtLeast { k, children })
     }
 
     // pub fn or(mut self, mut other: Self) -> Self {
@@ -253,12 +734,13 @@ impl ConditionBuilder {
     //     }
     // }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn not(mut self) -> Self {
         let child = self.root_index;
         let mut nodes = vec![];
         nodes.append(&mut self.nodes); // no offset needed; all indices are local
 
-        let root = nodes.len() as u8;
+        let root = nodes.len() as NodeIndex;
         nodes.push(ConditionNode {
             condition_type: ConditionType::Not { child },
         });
@@ -281,11 +763,14 @@ impl ConditionBuilder {
         // }
     }
 
-    pub fn build(self) -> ConditionTree {
-        ConditionTree {
+    // the arena only ever grows, so every index handed out while building is below the final
+    // length: if that length fits in a `NodeIndex`, none of the `as NodeIndex` casts above truncated
+    pub fn build(self) -> Result<ConditionTree> {
+        require!(self.nodes.len() <= MAX_NODES, ErrorCode::TooManyNodes);
+        Ok(ConditionTree {
             nodes: self.nodes,
             root_index: self.root_index,
-        }
+        })
     }
 }
 
@@ -299,14 +784,14 @@ mod tests {
         let token = Pubkey::default();
 
         let strategy_1 = ConditionBuilder::not(
-            ConditionBuilder::price_above(token, 100)
-                .and(ConditionBuilder::price_below(token, 200)),
+            ConditionBuilder::price_above(token, Price::from(100))
+                .and(ConditionBuilder::price_below(token, Price::from(200))),
         );
 
-        let strategy_2 =
-            ConditionBuilder::price_above(token, 400).and(ConditionBuilder::price_below(token, 10));
+        let strategy_2 = ConditionBuilder::price_above(token, Price::from(400))
+            .and(ConditionBuilder::price_below(token, Price::from(10)));
 
-        let strategy_3 = strategy_1.or(strategy_2).build();
+        let _strategy_3 = strategy_1.or(strategy_2).build().unwrap();
     }
 
     #[test]
@@ -314,103 +799,156 @@ mod tests {
         let token = Pubkey::new_unique();
 
         // NOT (price > 100)
-        let condition = ConditionBuilder::not(ConditionBuilder::price_above(token, 100)).build();
+        let condition =
+            ConditionBuilder::not(ConditionBuilder::price_above(token, Price::from(100)))
+                .build()
+                .unwrap();
 
         let mut prices = HashMap::new();
-        prices.insert(token, 150);
+        prices.insert(token, Price::from(150));
 
         let mut context = EvaluationContext {
             token_prices: prices.clone(),
+            ..Default::default()
         };
-        assert_eq!(condition.evaluate(&context), false);
+        assert!(!condition.evaluate(&context));
         // now change the price to 50
-        prices.insert(token, 50);
+        prices.insert(token, Price::from(50));
         context.token_prices = prices;
-        assert_eq!(condition.evaluate(&context), true);
+        assert!(condition.evaluate(&context));
     }
 
     #[test]
     fn test_evaluate_and() {
         let token = Pubkey::new_unique();
         // Condition (price > 100) AND (price < 400)
-        let condition_1_prebuilt = ConditionBuilder::price_above(token, 100)
-            .and(ConditionBuilder::price_below(token, 400));
+        let condition_1_prebuilt = ConditionBuilder::price_above(token, Price::from(100))
+            .and(ConditionBuilder::price_below(token, Price::from(400)));
 
         let mut prices = HashMap::new();
-        prices.insert(token, 150);
+        prices.insert(token, Price::from(150));
 
-        let mut context = EvaluationContext {
+        let context = EvaluationContext {
             token_prices: prices.clone(),
+            ..Default::default()
         };
 
-        let condition_1 = condition_1_prebuilt.clone().build();
-        assert_eq!(condition_1.evaluate(&context), true);
+        let condition_1 = condition_1_prebuilt.clone().build().unwrap();
+        assert!(condition_1.evaluate(&context));
 
-        let condition_2 = ConditionBuilder::not(condition_1_prebuilt).build();
-        assert_eq!(condition_2.evaluate(&context), false);
+        let condition_2 = ConditionBuilder::not(condition_1_prebuilt).build().unwrap();
+        assert!(!condition_2.evaluate(&context));
     }
 
     #[test]
     fn test_evaluate_or() {
         let token = Pubkey::new_unique();
         // Condition (price < 100) OR (price > 400)
-        let condition_1_prebuilt =
-            ConditionBuilder::price_below(token, 100).or(ConditionBuilder::price_above(token, 400));
+        let condition_1_prebuilt = ConditionBuilder::price_below(token, Price::from(100))
+            .or(ConditionBuilder::price_above(token, Price::from(400)));
 
         let mut prices = HashMap::new();
-        prices.insert(token, 150);
+        prices.insert(token, Price::from(150));
 
-        let mut context = EvaluationContext {
+        let context = EvaluationContext {
             token_prices: prices.clone(),
+            ..Default::default()
         };
 
-        let condition_1 = condition_1_prebuilt.clone().build();
-        assert_eq!(condition_1.evaluate(&context), false);
+        let condition_1 = condition_1_prebuilt.clone().build().unwrap();
+        assert!(!condition_1.evaluate(&context));
 
-        let condition_2 = ConditionBuilder::not(condition_1_prebuilt).build();
-        assert_eq!(condition_2.evaluate(&context), true);
+        let condition_2 = ConditionBuilder::not(condition_1_prebuilt).build().unwrap();
+        assert!(condition_2.evaluate(&context));
     }
 
     #[test]
     fn test_evaluate_condition_tree_2() {
         let token = Pubkey::default();
         let mut token_prices = HashMap::new();
-        token_prices.insert(token, 150); // Set current price to 150
+        token_prices.insert(token, Price::from(150)); // Set current price to 150
 
-        let context = EvaluationContext { token_prices };
+        let context = EvaluationContext {
+            token_prices,
+            ..Default::default()
+        };
 
         // Test case 1: NOT(price > 100 AND price < 200)
         let condition = ConditionBuilder::not(
-            ConditionBuilder::price_above(token, 100)
-                .and(ConditionBuilder::price_below(token, 200)),
+            ConditionBuilder::price_above(token, Price::from(100))
+                .and(ConditionBuilder::price_below(token, Price::from(200))),
         )
-        .build();
+        .build()
+        .unwrap();
 
         assert!(!condition.evaluate(&context));
 
         // Test case 2: price > 400 AND price < 10
-        let condition2 = ConditionBuilder::price_above(token, 400)
-            .and(ConditionBuilder::price_below(token, 10))
-            .build();
+        let condition2 = ConditionBuilder::price_above(token, Price::from(400))
+            .and(ConditionBuilder::price_below(token, Price::from(10)))
+            .build()
+            .unwrap();
 
         assert!(!condition2.evaluate(&context));
 
         // Test case 3: price > 100
-        let condition3 = ConditionBuilder::price_above(token, 100).build();
+        let condition3 = ConditionBuilder::price_above(token, Price::from(100))
+            .build()
+            .unwrap();
 
         assert!(condition3.evaluate(&context));
     }
 
+    #[test]
+    fn test_more_than_255_nodes() {
+        let token = Pubkey::new_unique();
+        // 200 ORed thresholds: 399 nodes, which used to wrap around a u8 index
+        let condition = (1..200)
+            .fold(
+                ConditionBuilder::price_above(token, Price::from(0)),
+                |acc, i| acc.or(ConditionBuilder::price_above(token, Price::from(i * 10))),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(condition.nodes.len(), 399);
+        assert_eq!(condition.root_index, 398);
+
+        let context = EvaluationContext {
+            token_prices: HashMap::from([(token, Price::from(5))]),
+            ..Default::default()
+        };
+        assert!(condition.evaluate(&context));
+    }
+
+    #[test]
+    fn test_evaluate_shared_chain() {
+        let token = Pubkey::new_unique();
+        // each level uses the one below twice, and XOR needs both sides: 2^40 visits unshared
+        let mut condition = ConditionBuilder::price_above(token, Price::from(100));
+        for _ in 0..40 {
+            condition = condition.clone().xor(condition.not());
+        }
+        let condition = condition.build().unwrap();
+        assert_eq!(condition.nodes.len(), 81);
+
+        let context = EvaluationContext {
+            token_prices: HashMap::from([(token, Price::from(150))]),
+            ..Default::default()
+        };
+        assert!(condition.evaluate(&context));
+    }
+
     #[test]
     fn test_to_string_expr() {
         let token = Pubkey::new_unique();
 
         let tree = ConditionBuilder::not(
-            ConditionBuilder::price_above(token, 100)
-                .and(ConditionBuilder::price_below(token, 200)),
+            ConditionBuilder::price_above(token, Price::from(100))
+                .and(ConditionBuilder::price_below(token, Price::from(200))),
         )
-        .or(ConditionBuilder::price_above(token, 400))
-        .build();
+        .or(ConditionBuilder::price_above(token, Price::from(400)))
+        .build()
+        .unwrap();
 
         let expr = tree.to_string_expr();
         println!("{}", expr);
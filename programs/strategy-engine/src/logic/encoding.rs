@@ -0,0 +1,742 @@
+use crate::logic::actions::{ActionNode, ActionTree, ActionType, Amount, AtomicAction};
+use crate::logic::conditions::{
+    AtomicCondition, CompareOp, ConditionNode, ConditionTree, ConditionType, NodeIndex, MAX_NODES,
+};
+use crate::logic::price::Price;
+use crate::logic::value::{ValueExpr, ValueNode};
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+
+// Compact, versioned encoding of a strategy's trees. Plain Borsh writes a 32-byte pubkey into
+// every atom and fixed-width integers everywhere; this writes
+//
+//   version: u8
+//   tokens:  varint count, at most 256, then each 32-byte pubkey once
+//   every:   varint seconds
+//   condition tree, then action tree: varint node count, varint root, the nodes in order
+//
+// where atoms refer to tokens by their 1-byte position in the table, and integers are LEB128
+// varints (exponents zigzag-encoded first). Each node is a tag byte followed by its fields.
+//
+// `VaultAccount` stores strategies in this form, and so can clients that store or send them
+// themselves (`strategy-cli encode`).
+//
+// `encode` always writes `FORMAT_VERSION`. `decode` reads every version ever written: when the
+// format changes, the current reader is frozen as the decoder of its version, tags are only
+// ever added, and a new version number is taken.
+
+pub const FORMAT_VERSION: u8 = 1;
+
+// distinct tokens one encoded strategy can refer to
+pub const MAX_TOKENS: usize = u8::MAX as usize + 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EncodedStrategy {
+    pub condition: ConditionTree,
+    pub action: ActionTree,
+    pub execute_every_seconds: u64,
+}
+
+pub fn encode(
+    condition: &ConditionTree,
+    action: &ActionTree,
+    execute_every_seconds: u64,
+) -> Result<Vec<u8>> {
+    let mut body = Writer::default();
+    body.varint(execute_every_seconds);
+    body.condition_tree(condition)?;
+    body.action_tree(action)?;
+
+    let mut header = Writer::default();
+    header.byte(FORMAT_VERSION);
+    header.varint(body.tokens.len() as u64);
+    let mut out = header.bytes;
+    for token in &body.tokens {
+        out.extend_from_slice(token.as_ref());
+    }
+    out.extend_from_slice(&body.bytes);
+    Ok(out)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<EncodedStrategy> {
+    let (&version, rest) = bytes
+        .split_first()
+        .ok_or_else(|| error!(ErrorCode::InvalidEncoding))?;
+    match version {
+        1 => v1::decode(rest),
+        _ => err!(ErrorCode::UnsupportedEncodingVersion),
+    }
+}
+
+// node tags of version 1
+mod tag {
+    pub const PRICE_ABOVE: u8 = 0;
+    pub const PRICE_BELOW: u8 = 1;
+    pub const PRICE_AT_OR_ABOVE: u8 = 2;
+    pub const PRICE_AT_OR_BELOW: u8 = 3;
+    pub const CUSTOM: u8 = 4;
+    pub const COMPARE: u8 = 5;
+    pub const AND: u8 = 6;
+    pub const OR: u8 = 7;
+    pub const NOT: u8 = 8;
+    pub const CONST: u8 = 9;
+    pub const ALL: u8 = 10;
+    pub const ANY: u8 = 11;
+    pub const AT_LEAST: u8 = 12;
+    pub const XOR: u8 = 13;
+    pub const IMPLIES: u8 = 14;
+
+    pub const VALUE_CONST: u8 = 0;
+    pub const VALUE_PRICE: u8 = 1;
+    pub const VALUE_BALANCE: u8 = 2;
+    pub const VALUE_NEG: u8 = 3;
+    pub const VALUE_ABS: u8 = 4;
+    pub const VALUE_ADD: u8 = 5;
+    pub const VALUE_SUB: u8 = 6;
+    pub const VALUE_MUL: u8 = 7;
+    pub const VALUE_DIV: u8 = 8;
+    pub const VALUE_MIN: u8 = 9;
+    pub const VALUE_MAX: u8 = 10;
+
+    pub const BUY: u8 = 0;
+    pub const SELL: u8 = 1;
+    pub const BORROW: u8 = 2;
+    pub const REPAY: u8 = 3;
+    pub const LEND: u8 = 4;
+    pub const REDEEM: u8 = 5;
+    pub const THEN: u8 = 6;
+
+    pub const UNITS: u8 = 0;
+    pub const PERCENT: u8 = 1;
+}
+
+fn op_byte(op: CompareOp) -> u8 {
+    match op {
+        CompareOp::Above => 0,
+        CompareOp::Below => 1,
+        CompareOp::AtOrAbove => 2,
+        CompareOp::AtOrBelow => 3,
+    }
+}
+
+fn zigzag(n: i32) -> u64 {
+    ((n << 1) ^ (n >> 31)) as u32 as u64
+}
+
+fn unzigzag(n: u32) -> i32 {
+    ((n >> 1) as i32) ^ -((n & 1) as i32)
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+    tokens: Vec<Pubkey>,
+}
+
+impl Writer {
+    fn byte(&mut self, b: u8) {
+        self.bytes.push(b);
+    }
+
+    fn varint(&mut self, mut n: u64) {
+        while n >= 0x80 {
+            self.bytes.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        self.bytes.push(n as u8);
+    }
+
+    fn index(&mut self, i: NodeIndex) {
+        self.varint(i as u64);
+    }
+
+    fn indices(&mut self, indices: &[NodeIndex]) {
+        self.varint(indices.len() as u64);
+        indices.iter().for_each(|i| self.index(*i));
+    }
+
+    // position of `token` in the table, adding it on first use
+    fn token(&mut self, token: &Pubkey) -> Result<()> {
+        let position = match self.tokens.iter().position(|t| t == token) {
+            Some(position) => position,
+            None => {
+                require!(self.tokens.len() < MAX_TOKENS, ErrorCode::TooManyTokens);
+                self.tokens.push(*token);
+                self.tokens.len() - 1
+            }
+        };
+        self.byte(position as u8);
+        Ok(())
+    }
+
+    fn price(&mut self, price: &Price) {
+        self.varint(price.mantissa);
+        self.varint(zigzag(price.expo));
+    }
+
+    fn condition_tree(&mut self, tree: &ConditionTree) -> Result<()> {
+        self.varint(tree.nodes.len() as u64);
+        self.index(tree.root_index);
+        for node in &tree.nodes {
+            self.condition(&node.condition_type)?;
+        }
+        Ok(())
+    }
+
+    fn condition(&mut self, condition: &ConditionType) -> Result<()> {
+        let binary = |w: &mut Self, tag: u8, left: &NodeIndex, right: &NodeIndex| {
+            w.byte(tag);
+            w.index(*left);
+            w.index(*right);
+        };
+        match condition {
+            ConditionType::Atomic(atom) => self.atom(atom)?,
+            ConditionType::And { left, right } => binary(self, tag::AND, left, right),
+            ConditionType::Or { left, right } => binary(self, tag::OR, left, right),
+            ConditionType::Xor { left, right } => binary(self, tag::XOR, left, right),
+            ConditionType::Implies { left, right } => binary(self, tag::IMPLIES, left, right),
+            ConditionType::Not { child } => {
+                self.byte(tag::NOT);
+                self.index(*child);
+            }
+            ConditionType::Const(value) => {
+                self.byte(tag::CONST);
+                self.byte(*value as u8);
+            }
+            ConditionType::All { children } => {
+                self.byte(tag::ALL);
+                self.indices(children);
+            }
+            ConditionType::Any { children } => {
+                self.byte(tag::ANY);
+                self.indices(children);
+            }
+            ConditionType::AtLeast { k, children } => {
+                self.byte(tag::AT_LEAST);
+                self.index(*k);
+                self.indices(children);
+            }
+        }
+        Ok(())
+    }
+
+    fn atom(&mut self, atom: &AtomicCondition) -> Result<()> {
+        let (tag, token, price) = match atom {
+            AtomicCondition::PriceAbove { token, price } => (tag::PRICE_ABOVE, token, price),
+            AtomicCondition::PriceBelow { token, price } => (tag::PRICE_BELOW, token, price),
+            AtomicCondition::PriceAtOrAbove { token, price } => {
+                (tag::PRICE_AT_OR_ABOVE, token, price)
+            }
+            AtomicCondition::PriceAtOrBelow { token, price } => {
+                (tag::PRICE_AT_OR_BELOW, token, price)
+            }
+            AtomicCondition::Custom { kind, data } => {
+                self.byte(tag::CUSTOM);
+                self.varint(*kind as u64);
+                self.varint(data.len() as u64);
+                self.bytes.extend_from_slice(data);
+                return Ok(());
+            }
+            AtomicCondition::Compare { left, op, right } => {
+                self.byte(tag::COMPARE);
+                self.value(left)?;
+                self.byte(op_byte(*op));
+                return self.value(right);
+            }
+        };
+        self.byte(tag);
+        self.token(token)?;
+        self.price(price);
+        Ok(())
+    }
+
+    fn value(&mut self, expr: &ValueExpr) -> Result<()> {
+        self.varint(expr.nodes.len() as u64);
+        self.index(expr.root);
+        for node in &expr.nodes {
+            let (tag, left, right) = match node {
+                ValueNode::Const(price) => {
+                    self.byte(tag::VALUE_CONST);
+                    self.price(price);
+                    continue;
+                }
+                ValueNode::Price(token) | ValueNode::Balance(token) => {
+                    let is_price = matches!(node, ValueNode::Price(_));
+                    self.byte(if is_price {
+                        tag::VALUE_PRICE
+                    } else {
+                        tag::VALUE_BALANCE
+                    });
+                    self.token(token)?;
+                    continue;
+                }
+                ValueNode::Neg { child } | ValueNode::Abs { child } => {
+                    let is_neg = matches!(node, ValueNode::Neg { .. });
+                    self.byte(if is_neg {
+                        tag::VALUE_NEG
+                    } else {
+                        tag::VALUE_ABS
+                    });
+                    self.index(*child);
+                    continue;
+                }
+                ValueNode::Add { left, right } => (tag::VALUE_ADD, left, right),
+                ValueNode::Sub { left, right } => (tag::VALUE_SUB, left, right),
+                ValueNode::Mul { left, right } => (tag::VALUE_MUL, left, right),
+                ValueNode::Div { left, right } => (tag::VALUE_DIV, left, right),
+                ValueNode::Min { left, right } => (tag::VALUE_MIN, left, right),
+                ValueNode::Max { left, right } => (tag::VALUE_MAX, left, right),
+            };
+            self.byte(tag);
+            self.index(*left);
+            self.index(*right);
+        }
+        Ok(())
+    }
+
+    fn action_tree(&mut self, tree: &ActionTree) -> Result<()> {
+        self.varint(tree.nodes.len() as u64);
+        self.index(tree.root_index);
+        for node in &tree.nodes {
+            let (tag, token, amount) = match &node.action_type {
+                ActionType::And { left, right } => {
+                    self.byte(tag::THEN);
+                    self.index(*left);
+                    self.index(*right);
+                    continue;
+                }
+                ActionType::Atomic(AtomicAction::Buy { token, amount }) => {
+                    (tag::BUY, token, amount)
+                }
+                ActionType::Atomic(AtomicAction::Sell { token, amount }) => {
+                    (tag::SELL, token, amount)
+                }
+                ActionType::Atomic(AtomicAction::Borrow { token, amount }) => {
+                    (tag::BORROW, token, amount)
+                }
+                ActionType::Atomic(AtomicAction::Repay { token, amount }) => {
+                    (tag::REPAY, token, amount)
+                }
+                ActionType::Atomic(AtomicAction::Lend { token, amount }) => {
+                    (tag::LEND, token, amount)
+                }
+                ActionType::Atomic(AtomicAction::Redeem { token, amount }) => {
+                    (tag::REDEEM, token, amount)
+                }
+            };
+            self.byte(tag);
+            self.token(token)?;
+            match amount {
+                Amount::Units(units) => {
+                    self.byte(tag::UNITS);
+                    self.varint(*units);
+                }
+                Amount::Percent(bps) => {
+                    self.byte(tag::PERCENT);
+                    self.varint(*bps as u64);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    tokens: Vec<Pubkey>,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        require!(n <= self.bytes.len(), ErrorCode::InvalidEncoding);
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            let bits = (b & 0x7f) as u64;
+            // the 10th byte may only carry the top bit of a u64
+            require!(shift < 63 || bits <= 1, ErrorCode::InvalidEncoding);
+            n |= bits << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        err!(ErrorCode::InvalidEncoding)
+    }
+
+    fn narrow<T: TryFrom<u64>>(&mut self) -> Result<T> {
+        T::try_from(self.varint()?).map_err(|_| error!(ErrorCode::InvalidEncoding))
+    }
+
+    fn index(&mut self) -> Result<NodeIndex> {
+        self.narrow()
+    }
+
+    // a count of items that take at least one byte each, so a corrupt count can't make us
+    // allocate more than the input could hold
+    fn count(&mut self, limit: usize) -> Result<usize> {
+        let n: usize = self.narrow()?;
+        require!(
+            n <= limit && n <= self.bytes.len(),
+            ErrorCode::InvalidEncoding
+        );
+        Ok(n)
+    }
+
+    fn indices(&mut self) -> Result<Vec<NodeIndex>> {
+        let n = self.count(MAX_NODES)?;
+        (0..n).map(|_| self.index()).collect()
+    }
+
+    fn token(&mut self) -> Result<Pubkey> {
+        let position = self.byte()? as usize;
+        self.tokens
+            .get(position)
+            .copied()
+            .ok_or_else(|| error!(ErrorCode::InvalidEncoding))
+    }
+
+    fn price(&mut self) -> Result<Price> {
+        let mantissa = self.varint()?;
+        let expo = unzigzag(self.narrow()?);
+        Price::try_new(mantissa, expo).map_err(|_| error!(ErrorCode::InvalidEncoding))
+    }
+}
+
+mod v1 {
+    use super::*;
+
+    pub fn decode(bytes: &[u8]) -> Result<EncodedStrategy> {
+        let mut r = Reader {
+            bytes,
+            tokens: vec![],
+        };
+        let count = r.count(MAX_TOKENS)?;
+        for _ in 0..count {
+            let key: [u8; 32] = r.take(32)?.try_into().unwrap();
+            r.tokens.push(Pubkey::new_from_array(key));
+        }
+        let execute_every_seconds = r.varint()?;
+        let condition = condition_tree(&mut r)?;
+        let action = action_tree(&mut r)?;
+        require!(r.bytes.is_empty(), ErrorCode::InvalidEncoding);
+        // well-formed bytes can still describe a tree with out-of-range or cyclic indices
+        condition.validate_structure()?;
+        action.validate()?;
+        Ok(EncodedStrategy {
+            condition,
+            action,
+            execute_every_seconds,
+        })
+    }
+
+    fn condition_tree(r: &mut Reader) -> Result<ConditionTree> {
+        let n = r.count(MAX_NODES)?;
+        let root_index = r.index()?;
+        let nodes = (0..n)
+            .map(|_| {
+                Ok(ConditionNode {
+                    condition_type: condition(r)?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(ConditionTree { nodes, root_index })
+    }
+
+    fn condition(r: &mut Reader) -> Result<ConditionType> {
+        let tag = r.byte()?;
+        Ok(match tag {
+            tag::PRICE_ABOVE..=tag::PRICE_AT_OR_BELOW => {
+                let token = r.token()?;
+                let price = r.price()?;
+                ConditionType::Atomic(match tag {
+                    tag::PRICE_ABOVE => AtomicCondition::PriceAbove { token, price },
+                    tag::PRICE_BELOW => AtomicCondition::PriceBelow { token, price },
+                    tag::PRICE_AT_OR_ABOVE => AtomicCondition::PriceAtOrAbove { token, price },
+                    _ => AtomicCondition::PriceAtOrBelow { token, price },
+                })
+            }
+            tag::CUSTOM => {
+                let kind = r.narrow()?;
+                let len = r.count(usize::MAX)?;
+                let data = r.take(len)?.to_vec();
+                ConditionType::Atomic(AtomicCondition::Custom { kind, data })
+            }
+            tag::COMPARE => {
+                let left = value(r)?;
+                let op = match r.byte()? {
+                    0 => CompareOp::Above,
+                    1 => CompareOp::Below,
+                    2 => CompareOp::AtOrAbove,
+                    3 => CompareOp::AtOrBelow,
+                    _ => return err!(ErrorCode::InvalidEncoding),
+                };
+                let right = value(r)?;
+                ConditionType::Atomic(AtomicCondition::Compare { left, op, right })
+            }
+            tag::AND | tag::OR | tag::XOR | tag::IMPLIES => {
+                let (left, right) = (r.index()?, r.index()?);
+                match tag {
+                    tag::AND => ConditionType::And { left, right },
+                    tag::OR => ConditionType::Or { left, right },
+                    tag::XOR => ConditionType::Xor { left, right },
+                    _ => ConditionType::Implies { left, right },
+                }
+            }
+            tag::NOT => ConditionType::Not { child: r.index()? },
+            tag::CONST => match r.byte()? {
+                0 => ConditionType::Const(false),
+                1 => ConditionType::Const(true),
+                _ => return err!(ErrorCode::InvalidEncoding),
+            },
+            tag::ALL => ConditionType::All {
+                children: r.indices()?,
+            },
+            tag::ANY => ConditionType::Any {
+                children: r.indices()?,
+            },
+            tag::AT_LEAST => ConditionType::AtLeast {
+                k: r.index()?,
+                children: r.indices()?,
+            },
+            _ => return err!(ErrorCode::InvalidEncoding),
+        })
+    }
+
+    fn value(r: &mut Reader) -> Result<ValueExpr> {
+        let n = r.count(MAX_NODES)?;
+        let root = r.index()?;
+        let nodes = (0..n)
+            .map(|_| {
+                let tag = r.byte()?;
+                Ok(match tag {
+                    tag::VALUE_CONST => ValueNode::Const(r.price()?),
+                    tag::VALUE_PRICE => ValueNode::Price(r.token()?),
+                    tag::VALUE_BALANCE => ValueNode::Balance(r.token()?),
+                    tag::VALUE_NEG => ValueNode::Neg { child: r.index()? },
+                    tag::VALUE_ABS => ValueNode::Abs { child: r.index()? },
+                    tag::VALUE_ADD..=tag::VALUE_MAX => {
+                        let (left, right) = (r.index()?, r.index()?);
+                        match tag {
+                            tag::VALUE_ADD => ValueNode::Add { left, right },
+                            tag::VALUE_SUB => ValueNode::Sub { left, right },
+                            tag::VALUE_MUL => ValueNode::Mul { left, right },
+                            tag::VALUE_DIV => ValueNode::Div { left, right },
+                            tag::VALUE_MIN => ValueNode::Min { left, right },
+                            _ => ValueNode::Max { left, right },
+                        }
+                    }
+                    _ => return err!(ErrorCode::InvalidEncoding),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(ValueExpr { nodes, root })
+    }
+
+    fn action_tree(r: &mut Reader) -> Result<ActionTree> {
+        let n = r.count(MAX_NODES)?;
+        let root_index = r.index()?;
+        let nodes = (0..n)
+            .map(|_| {
+                let tag = r.byte()?;
+                if tag == tag::THEN {
+                    let (left, right) = (r.index()?, r.index()?);
+                    return Ok(ActionNode {
+                        action_type: ActionType::And { left, right },
+                    });
+                }
+                let token = r.token()?;
+                let amount = match r.byte()? {
+                    tag::UNITS => Amount::Units(r.varint()?),
+                    tag::PERCENT => Amount::Percent(r.narrow()?),
+                    _ => return err!(ErrorCode::InvalidEncoding),
+                };
+                let atomic = match tag {
+                    tag::BUY => AtomicAction::Buy { token, amount },
+                    tag::SELL => AtomicAction::Sell { token, amount },
+                    tag::BORROW => AtomicAction::Borrow { token, amount },
+                    tag::REPAY => AtomicAction::Repay { token, amount },
+                    tag::LEND => AtomicAction::Lend { token, amount },
+                    tag::REDEEM => AtomicAction::Redeem { token, amount },
+                    _ => return err!(ErrorCode::InvalidEncoding),
+                };
+                Ok(ActionNode {
+                    action_type: ActionType::Atomic(atomic),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(ActionTree { nodes, root_index })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::actions::ActionBuilder;
+    use crate::logic::conditions::ConditionBuilder;
+    use crate::logic::parser::strategyParser::translate_strategy_string;
+
+    fn round_trip(condition: &ConditionTree, action: &ActionTree, every: u64) -> Vec<u8> {
+        let bytes = encode(condition, action, every).unwrap();
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(&decoded.condition, condition);
+        assert_eq!(&decoded.action, action);
+        assert_eq!(decoded.execute_every_seconds, every);
+        bytes
+    }
+
+    #[test]
+    fn test_every_node_round_trips() {
+        let sol = Pubkey::new_unique();
+        let usdc = Pubkey::new_unique();
+        let source = format!(
+            "WHEN PRICE_BELOW({sol}, 90.50) AND NOT(price({sol}) / price({usdc}) >= 2) \
+             OR AT_LEAST 1 OF (TRUE, balance({usdc}) < 10 XOR FALSE) \
+             THEN BUY({sol}, 100) THEN (SELL({sol}, 12.5%) THEN LEND({usdc}, 1)) EVERY 1h"
+        );
+        let strategy = translate_strategy_string(&source).unwrap();
+        round_trip(&strategy.condition, &strategy.action, 3600);
+
+        let value = ValueExpr::price(sol)
+            .sub(ValueExpr::balance(usdc).neg())
+            .abs()
+            .mul(ValueExpr::constant(Price::new(5, 3)))
+            .min(ValueExpr::constant(Price::new(1, -9)).max(ValueExpr::price(usdc)))
+            .add(ValueExpr::price(sol).div(ValueExpr::balance(sol)));
+        let condition = ConditionBuilder::all(vec![
+            ConditionBuilder::price_at_or_above(sol, Price::from(1)),
+            ConditionBuilder::price_at_or_below(usdc, Price::from(u64::MAX)),
+            ConditionBuilder::price_above(sol, Price::from(7)),
+        ])
+        .implies(ConditionBuilder::any(vec![
+            ConditionBuilder::custom(3, vec![0xde, 0xad, 0xbe, 0xef]),
+            ConditionBuilder::custom(u16::MAX, vec![]),
+            ConditionBuilder::compare(value, CompareOp::AtOrBelow, ValueExpr::balance(sol)),
+        ]))
+        .build()
+        .unwrap();
+        let action = ActionBuilder::borrow(usdc, u64::MAX)
+            .and(ActionBuilder::repay(usdc, Amount::Percent(10_000)))
+            .and(ActionBuilder::redeem(sol, 0))
+            .build()
+            .unwrap();
+        round_trip(&condition, &action, u64::MAX);
+    }
+
+    #[test]
+    fn test_smaller_than_borsh() {
+        let sol = Pubkey::new_unique();
+        let usdc = Pubkey::new_unique();
+        let source = format!(
+            "WHEN PRICE_ABOVE({sol}, 100) AND PRICE_BELOW({sol}, 150) OR PRICE_BELOW({usdc}, 0.99) \
+             THEN SELL({sol}, 50%) THEN BUY({usdc}, 1000000) EVERY 5m"
+        );
+        let strategy = translate_strategy_string(&source).unwrap();
+        let bytes = round_trip(&strategy.condition, &strategy.action, 300);
+        let borsh = strategy.condition.size() + strategy.action.size() + 8;
+        // two 32-byte keys are written once instead of five times
+        assert!(bytes.len() * 2 < borsh, "{} vs {}", bytes.len(), borsh);
+    }
+
+    #[test]
+    fn test_version_1_bytes_are_stable() {
+        // accounts written by version 1 must keep decoding; this is the exact layout
+        let token = Pubkey::new_from_array([1; 32]);
+        let condition = ConditionBuilder::atom(AtomicCondition::PriceAbove {
+            token,
+            price: Price::new(10050, -2),
+        })
+        .build()
+        .unwrap();
+        let action = ActionBuilder::buy(token, 300).build().unwrap();
+
+        let mut expected = vec![1, 1];
+        expected.extend([1; 32]);
+        expected.extend([0xac, 0x02]);
+        expected.extend([1, 0, tag::PRICE_ABOVE, 0, 0xc2, 0x4e, 3]);
+        expected.extend([1, 0, tag::BUY, 0, tag::UNITS, 0xac, 0x02]);
+        assert_eq!(round_trip(&condition, &action, 300), expected);
+
+        let strategy = crate::Strategy::decode(&expected).unwrap();
+        assert_eq!(strategy.encode().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        let token = Pubkey::new_from_array([1; 32]);
+        let condition = ConditionBuilder::price_above(token, Price::from(1))
+            .build()
+            .unwrap();
+        let action = ActionBuilder::buy(token, 1).build().unwrap();
+        let bytes = encode(&condition, &action, 60).unwrap();
+        let is = |bytes: &[u8], code: ErrorCode| decode(bytes).unwrap_err() == error!(code);
+
+        assert!(is(&[], ErrorCode::InvalidEncoding));
+        let mut future = bytes.clone();
+        future[0] = FORMAT_VERSION + 1;
+        assert!(is(&future, ErrorCode::UnsupportedEncodingVersion));
+        for end in 1..bytes.len() {
+            assert!(is(&bytes[..end], ErrorCode::InvalidEncoding), "{}", end);
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(is(&trailing, ErrorCode::InvalidEncoding));
+
+        // the atom's token index, past the one-entry table
+        let mut bad_token = bytes.clone();
+        bad_token[2 + 32 + 1 + 3] = 1;
+        assert!(is(&bad_token, ErrorCode::InvalidEncoding));
+
+        // the atom's price at exponent `i32::MAX`
+        let mut expo = Writer::default();
+        expo.varint(zigzag(i32::MAX));
+        let huge = [
+            &bytes[..2 + 32 + 1 + 5],
+            &expo.bytes,
+            &bytes[2 + 32 + 1 + 6..],
+        ]
+        .concat();
+        assert!(is(&huge, ErrorCode::InvalidEncoding));
+
+        // `every` as an eleven-byte varint
+        let mut overflow = bytes[..2 + 32].to_vec();
+        overflow.extend([0xff; 10]);
+        overflow.push(0);
+        assert!(is(&overflow, ErrorCode::InvalidEncoding));
+
+        // parses, but the action is `THEN` over node 5 of a one-node tree
+        let out_of_range = [1, 0, 0, 1, 0, tag::CONST, 1, 1, 0, tag::THEN, 5, 5];
+        assert!(is(&out_of_range, ErrorCode::InvalidTree));
+        // a `NOT` that is its own child, and a root past the end
+        let cyclic = [1, 0, 0, 1, 0, tag::NOT, 0, 1, 0, tag::THEN, 0, 0];
+        assert!(is(&cyclic, ErrorCode::InvalidTree));
+        let mut no_root = vec![1, 1];
+        no_root.extend([0; 32]);
+        no_root.extend([0, 1, 1, tag::CONST, 1, 1, 0, tag::BUY, 0, tag::UNITS, 1]);
+        assert!(is(&no_root, ErrorCode::InvalidTree));
+        no_root[2 + 32 + 2] = 0;
+        assert!(decode(&no_root).is_ok());
+    }
+
+    #[test]
+    fn test_too_many_tokens() {
+        let children = (0..=MAX_TOKENS)
+            .map(|_| ConditionBuilder::price_above(Pubkey::new_unique(), Price::from(1)))
+            .collect();
+        let condition = ConditionBuilder::any(children).build().unwrap();
+        let action = ActionBuilder::buy(Pubkey::new_unique(), 1).build().unwrap();
+        assert_eq!(
+            encode(&condition, &action, 60).unwrap_err(),
+            error!(ErrorCode::TooManyTokens)
+        );
+    }
+}
@@ -0,0 +1,449 @@
+use crate::logic::actions::{ActionTree, ActionType};
+use crate::logic::conditions::{ConditionTree, ConditionType, EvaluationContext, NodeIndex};
+use crate::logic::strategy::{format_duration, Strategy};
+use crate::logic::trace::EvaluationTrace;
+use anchor_lang::prelude::*;
+
+// Trees as graphs for Graphviz (`dot -Tsvg`) or Mermaid, for reviewing trees too large to read as
+// one expression. Every node of the arena reachable from the root is drawn once, so a subtree
+// the simplifier shares between several parents shows up as a DAG with several edges into it.
+// Given an `EvaluationContext`, condition nodes are coloured by their value and the ones
+// short-circuiting skipped are drawn dashed
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mark {
+    Plain,
+    Holds,
+    Fails,
+    Skipped,
+}
+
+struct GraphNode {
+    id: String,
+    // one entry per line
+    lines: Vec<String>,
+    mark: Mark,
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<GraphNode>,
+    edges: Vec<(String, String, Option<&'static str>)>,
+}
+
+fn dot_text(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Mermaid reads a few characters inside quoted labels as markup; its entity codes keep them
+fn mermaid_text(text: &str) -> String {
+    text.replace('#', "#35;")
+        .replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+}
+
+impl Graph {
+    fn node(&mut self, id: String, lines: Vec<String>, mark: Mark) {
+        self.nodes.push(GraphNode { id, lines, mark });
+    }
+
+    fn edge(&mut self, from: &str, to: &str, label: Option<&'static str>) {
+        self.edges.push((from.to_string(), to.to_string(), label));
+    }
+
+    //   digraph condition {
+    //     node [shape=box, fontname="monospace"];
+    //     c2 [label="OR"];
+    //     c0 [label="PRICE_BELOW(SOL, 100)\n90 => true", style=filled, fillcolor="#d4edda"];
+    //     c2 -> c0;
+    //   }
+    fn to_dot(&self, name: &str) -> String {
+        let mut out = format!(
+            "digraph {} {{\n  node [shape=box, fontname=\"monospace\"];\n",
+            name
+        );
+        for node in &self.nodes {
+            let label = node
+                .lines
+                .iter()
+                .map(|line| dot_text(line))
+                .collect::<Vec<_>>()
+                .join("\\n");
+            let style = match node.mark {
+                Mark::Plain => "",
+                Mark::Holds => ", style=filled, fillcolor=\"#d4edda\"",
+                Mark::Fails => ", style=filled, fillcolor=\"#f8d7da\"",
+                Mark::Skipped => ", style=dashed, fontcolor=\"#888888\"",
+            };
+            out += &format!("  {} [label=\"{}\"{}];\n", node.id, label, style);
+        }
+        for (from, to, label) in &self.edges {
+            match label {
+                Some(label) => out += &format!("  {} -> {} [label=\"{}\"];\n", from, to, label),
+                None => out += &format!("  {} -> {};\n", from, to),
+            }
+        }
+        out + "}\n"
+    }
+
+    //   flowchart TD
+    //     c2["OR"]
+    //     c0["PRICE_BELOW(SOL, 100)<br>90 =#gt; true"]
+    //     c2 --> c0
+    //     class c0 holds
+    fn to_mermaid(&self) -> String {
+        let mut out = String::from("flowchart TD\n");
+        for node in &self.nodes {
+            let label = node
+                .lines
+                .iter()
+                .map(|line| mermaid_text(line))
+                .collect::<Vec<_>>()
+                .join("<br>");
+            out += &format!("  {}[\"{}\"]\n", node.id, label);
+        }
+        for (from, to, label) in &self.edges {
+            match label {
+                Some(label) => out += &format!("  {} -->|{}| {}\n", from, label, to),
+                None => out += &format!("  {} --> {}\n", from, to),
+            }
+        }
+        let marked = [
+            (Mark::Holds, "holds", "fill:#d4edda"),
+            (Mark::Fails, "fails", "fill:#f8d7da"),
+            (
+                Mark::Skipped,
+                "skipped",
+                "stroke-dasharray:4 4,color:#888888",
+            ),
+        ];
+        for (mark, class, style) in marked {
+            let ids = self
+                .nodes
+                .iter()
+                .filter(|node| node.mark == mark)
+                .map(|node| node.id.as_str())
+                .collect::<Vec<_>>();
+            if !ids.is_empty() {
+                out += &format!(
+                    "  classDef {} {}\n  class {} {}\n",
+                    class,
+                    style,
+                    ids.join(","),
+                    class
+                );
+            }
+        }
+        out
+    }
+
+    fn render(&self, format: GraphFormat, name: &str) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(name),
+            GraphFormat::Mermaid => self.to_mermaid(),
+        }
+    }
+}
+
+// edge labels for the children of a node whose operands play different parts
+fn condition_edge(node: &ConditionType, position: usize) -> Option<&'static str> {
+    match node {
+        ConditionType::Implies { .. } => Some(["if", "then"][position]),
+        _ => None,
+    }
+}
+
+fn operator(node: &ConditionType) -> String {
+    match node {
+        ConditionType::Atomic(_) => unreachable!("atoms are labelled by their text"),
+        ConditionType::And { .. } => "AND".to_string(),
+        ConditionType::Or { .. } => "OR".to_string(),
+        ConditionType::Not { .. } => "NOT".to_string(),
+        ConditionType::Const(true) => "TRUE".to_string(),
+        ConditionType::Const(false) => "FALSE".to_string(),
+        ConditionType::All { .. } => "ALL".to_string(),
+        ConditionType::Any { .. } => "ANY".to_string(),
+        ConditionType::AtLeast { k, .. } => format!("AT_LEAST {}", k),
+        ConditionType::Xor { .. } => "XOR".to_string(),
+        ConditionType::Implies { .. } => "IMPLIES".to_string(),
+    }
+}
+
+impl ConditionTree {
+    pub fn to_dot(&self) -> String {
+        self.to_graph(GraphFormat::Dot, &|token| token.to_string(), None)
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        self.to_graph(GraphFormat::Mermaid, &|token| token.to_string(), None)
+    }
+
+    // with tokens named by `label`, and annotated with the value of every node under `ctx`
+    pub fn to_graph(
+        &self,
+        format: GraphFormat,
+        label: &dyn Fn(&Pubkey) -> String,
+        ctx: Option<&EvaluationContext>,
+    ) -> String {
+        let mut graph = Graph::default();
+        let trace = ctx.map(|ctx| self.evaluate_with_trace(ctx));
+        self.add_to_graph(&mut graph, "c", label, trace.as_ref());
+        graph.render(format, "condition")
+    }
+
+    // adds the nodes reachable from the root, each with the id `prefix` and its index, and
+    // returns the id of the root
+    fn add_to_graph(
+        &self,
+        graph: &mut Graph,
+        prefix: &str,
+        label: &dyn Fn(&Pubkey) -> String,
+        trace: Option<&EvaluationTrace>,
+    ) -> String {
+        let mut visited = vec![false; self.nodes.len()];
+        self.add_node(graph, self.root_index, prefix, label, trace, &mut visited);
+        format!("{}{}", prefix, self.root_index)
+    }
+
+    fn add_node(
+        &self,
+        graph: &mut Graph,
+        index: NodeIndex,
+        prefix: &str,
+        label: &dyn Fn(&Pubkey) -> String,
+        trace: Option<&EvaluationTrace>,
+        visited: &mut [bool],
+    ) {
+        if std::mem::replace(&mut visited[index as usize], true) {
+            return;
+        }
+        let node = &self.nodes[index as usize].condition_type;
+        let mut lines = vec![match node {
+            ConditionType::Atomic(atomic) => atomic.to_string_with(label),
+            other => operator(other),
+        }];
+        let mut mark = Mark::Plain;
+        if let Some(trace) = trace {
+            let traced = &trace.nodes[index as usize];
+            mark = match traced.value {
+                None => Mark::Skipped,
+                Some(true) => Mark::Holds,
+                Some(false) => Mark::Fails,
+            };
+            lines.push(match (traced.value, traced.price_read) {
+                (None, _) => "skipped".to_string(),
+                (Some(v), Some((_, Some(price)))) => format!("{} => {}", price, v),
+                (Some(v), Some((_, None))) => format!("no price => {}", v),
+                (Some(v), None) => v.to_string(),
+            });
+        }
+        let id = format!("{}{}", prefix, index);
+        graph.node(id.clone(), lines, mark);
+        for (position, child) in node.children().into_iter().enumerate() {
+            graph.edge(
+                &id,
+                &format!("{}{}", prefix, child),
+                condition_edge(node, position),
+            );
+            self.add_node(graph, child, prefix, label, trace, visited);
+        }
+    }
+}
+
+impl ActionTree {
+    pub fn to_dot(&self) -> String {
+        self.to_graph(GraphFormat::Dot, &|token| token.to_string())
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        self.to_graph(GraphFormat::Mermaid, &|token| token.to_string())
+    }
+
+    pub fn to_graph(&self, format: GraphFormat, label: &dyn Fn(&Pubkey) -> String) -> String {
+        let mut graph = Graph::default();
+        self.add_to_graph(&mut graph, "a", label);
+        graph.render(format, "action")
+    }
+
+    // like `ConditionTree::add_to_graph`; THEN runs the edge labelled 1 before the one labelled 2
+    fn add_to_graph(
+        &self,
+        graph: &mut Graph,
+        prefix: &str,
+        label: &dyn Fn(&Pubkey) -> String,
+    ) -> String {
+        let mut visited = vec![false; self.nodes.len()];
+        let mut pending = vec![self.root_index];
+        while let Some(index) = pending.pop() {
+            if std::mem::replace(&mut visited[index as usize], true) {
+                continue;
+            }
+            let id = format!("{}{}", prefix, index);
+            match &self.nodes[index as usize].action_type {
+                ActionType::Atomic(action) => {
+                    graph.node(id, vec![action.to_string_with(label)], Mark::Plain)
+                }
+                ActionType::And { left, right } => {
+                    graph.node(id.clone(), vec!["THEN".to_string()], Mark::Plain);
+                    graph.edge(&id, &format!("{}{}", prefix, left), Some("1"));
+                    graph.edge(&id, &format!("{}{}", prefix, right), Some("2"));
+                    // the left operand comes off the stack first
+                    pending.extend([*right, *left]);
+                }
+            }
+        }
+        format!("{}{}", prefix, self.root_index)
+    }
+}
+
+impl Strategy {
+    pub fn to_dot(&self) -> String {
+        self.to_graph(GraphFormat::Dot, &|token| token.to_string(), None)
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        self.to_graph(GraphFormat::Mermaid, &|token| token.to_string(), None)
+    }
+
+    // the strategy's timing as the root, with edges to the condition, the actions and UNTIL.
+    // `ctx` annotates both conditions
+    pub fn to_graph(
+        &self,
+        format: GraphFormat,
+        label: &dyn Fn(&Pubkey) -> String,
+        ctx: Option<&EvaluationContext>,
+    ) -> String {
+        let mut graph = Graph::default();
+        let mut lines = vec![
+            "WHEN".to_string(),
+            format!("EVERY {}", format_duration(self.execute_every_seconds)),
+        ];
+        if let Some(max_runs) = self.max_runs {
+            lines.push(format!("MAX_RUNS {}", max_runs));
+        }
+        graph.node("s".to_string(), lines, Mark::Plain);
+
+        let trace = ctx.map(|ctx| self.condition.evaluate_with_trace(ctx));
+        let root = self
+            .condition
+            .add_to_graph(&mut graph, "c", label, trace.as_ref());
+        graph.edge("s", &root, Some("if"));
+        let root = self.action.add_to_graph(&mut graph, "a", label);
+        graph.edge("s", &root, Some("then"));
+        if let Some(until) = &self.until {
+            let trace = ctx.map(|ctx| until.evaluate_with_trace(ctx));
+            let root = until.add_to_graph(&mut graph, "u", label, trace.as_ref());
+            graph.edge("s", &root, Some("until"));
+        }
+        graph.render(format, "strategy")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::conditions::ConditionBuilder;
+    use crate::logic::parser::actionParser::translate_action_string;
+    use crate::logic::parser::strategyParser::translate_strategy_string;
+    use crate::logic::price::Price;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_condition_graph() {
+        let sol = Pubkey::new_unique();
+        // PRICE_BELOW(SOL, 100) IMPLIES NOT PRICE_BELOW(SOL, 50)
+        let tree = ConditionBuilder::price_below(sol, Price::from(100))
+            .implies(ConditionBuilder::price_below(sol, Price::from(50)).not())
+            .build()
+            .unwrap();
+        let label = |token: &Pubkey| {
+            if *token == sol {
+                "SOL".to_string()
+            } else {
+                token.to_string()
+            }
+        };
+        assert_eq!(
+            tree.to_graph(GraphFormat::Dot, &label, None),
+            "digraph condition {\n  node [shape=box, fontname=\"monospace\"];\n  \
+             c3 [label=\"IMPLIES\"];\n  c0 [label=\"PRICE_BELOW(SOL, 100)\"];\n  \
+             c2 [label=\"NOT\"];\n  c1 [label=\"PRICE_BELOW(SOL, 50)\"];\n  \
+             c3 -> c0 [label=\"if\"];\n  c3 -> c2 [label=\"then\"];\n  c2 -> c1;\n}\n"
+        );
+
+        let ctx = EvaluationContext {
+            token_prices: HashMap::from([(sol, Price::from(120))]),
+            ..Default::default()
+        };
+        let mermaid = tree.to_graph(GraphFormat::Mermaid, &label, Some(&ctx));
+        assert!(mermaid.starts_with("flowchart TD\n  c3[\"IMPLIES<br>true\"]\n"));
+        assert!(mermaid.contains("  c0[\"PRICE_BELOW(SOL, 100)<br>120 =#gt; false\"]\n"));
+        assert!(mermaid.contains("  c3 -->|then| c2\n"));
+        // the consequent is never looked at once the premise fails
+        assert!(mermaid.contains("  class c3 holds\n"));
+        assert!(mermaid.contains("  class c0 fails\n"));
+        assert!(mermaid.ends_with("  class c2,c1 skipped\n"), "{}", mermaid);
+    }
+
+    #[test]
+    fn test_shared_subtrees() {
+        let (t1, t2) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let shared = || {
+            ConditionBuilder::price_above(t1, Price::from(100))
+                .or(ConditionBuilder::price_below(t2, Price::from(50)))
+        };
+        let tree = shared()
+            .and(ConditionBuilder::price_below(t1, Price::from(400)))
+            .or(shared().and(ConditionBuilder::price_above(t2, Price::from(10))))
+            .build()
+            .unwrap()
+            .simplify();
+        let dot = tree.to_dot();
+        let nodes = dot.lines().filter(|line| line.contains("[label=")).count();
+        let edges = dot.lines().filter(|line| line.contains(" -> ")).count();
+        // one node per arena entry, and one more edge than a tree would have
+        assert_eq!(nodes, tree.nodes.len());
+        assert_eq!(edges, nodes);
+        assert_eq!(dot.matches("label=\"OR\"").count(), 2);
+    }
+
+    #[test]
+    fn test_action_and_strategy_graphs() {
+        let sol = Pubkey::new_unique();
+        let tree = translate_action_string(&format!(
+            "BUY({sol}, 1) THEN LEND({sol}, 1) THEN SELL({sol}, 1)"
+        ))
+        .unwrap();
+        let mermaid = tree.to_mermaid();
+        let root = format!("a{}", tree.root_index);
+        assert!(mermaid.starts_with(&format!("flowchart TD\n  {}[\"THEN\"]\n", root)));
+        assert_eq!(mermaid.matches("[\"THEN\"]").count(), 2);
+        assert!(mermaid.contains(&format!("  {} -->|2| a", root)));
+        assert!(!mermaid.contains("classDef"));
+
+        let strategy = translate_strategy_string(&format!(
+            "WHEN PRICE_BELOW({sol}, 100) THEN BUY({sol}, 1) EVERY 1h \
+             UNTIL PRICE_ABOVE({sol}, 200) MAX_RUNS 3"
+        ))
+        .unwrap();
+        let dot = strategy.to_dot();
+        assert!(dot.starts_with(
+            "digraph strategy {\n  node [shape=box, fontname=\"monospace\"];\n  \
+             s [label=\"WHEN\\nEVERY 1h\\nMAX_RUNS 3\"];\n"
+        ));
+        for edge in [
+            "s -> c0 [label=\"if\"]",
+            "s -> a0 [label=\"then\"]",
+            "s -> u0 [label=\"until\"]",
+        ] {
+            assert!(dot.contains(edge), "{}", dot);
+        }
+    }
+}
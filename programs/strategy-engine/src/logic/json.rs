@@ -0,0 +1,928 @@
+use crate::logic::actions::{ActionBuilder, ActionTree, ActionType, Amount, AtomicAction};
+use crate::logic::conditions::{
+    AtomicCondition, CompareOp, ConditionBuilder, ConditionTree, ConditionType, NodeIndex,
+};
+use crate::logic::price::Price;
+use crate::logic::strategy::Strategy;
+use crate::logic::value::{ValueExpr, ValueNode};
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+
+// Nested form of conditions, actions and strategies for JSON and YAML files, e.g.
+//
+//   {"and": [{"price_above": {"token": "So11...", "price": "100.5"}}, {"not": {"const": false}}]}
+//
+// instead of the flat arena stored on chain. A subtree used more than once, as `simplify` and
+// `LET` leave them, is written once under a name and referred to by it:
+//
+//   {"let": {"bind": [{"name": "s0", "is": {"or": [..]}}], "in": {"and": [{"ref": "s0"}, ..]}}}
+//
+// Reading a tree back stores equal subtrees once (see `ConditionBuilder::graft`), so a tree
+// made by the builders and parsers comes back node for node, and any other comes back as the
+// tree the builder would have made. `ConditionTree`, `ActionTree` and `Strategy`
+// (de)serialize through it, and `STRATEGY_JSON_SCHEMA` describes it.
+//
+// Binary operators take exactly two operands, so `and` is not `all`. Prices are decimal
+// strings carrying their precision ("100.50"); positive exponents are written "5e3".
+// serde_json refuses documents nested deeper than 128 levels.
+
+pub const STRATEGY_JSON_SCHEMA: &str = include_str!("../../schema/strategy.schema.json");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConditionJson {
+    PriceAbove(PriceArgs),
+    PriceBelow(PriceArgs),
+    PriceAtOrAbove(PriceArgs),
+    PriceAtOrBelow(PriceArgs),
+    Compare(Box<CompareArgs>),
+    Custom(CustomArgs),
+    Const(bool),
+    Not(Box<ConditionJson>),
+    And(Box<(ConditionJson, ConditionJson)>),
+    Or(Box<(ConditionJson, ConditionJson)>),
+    Xor(Box<(ConditionJson, ConditionJson)>),
+    Implies(Box<(ConditionJson, ConditionJson)>),
+    All(Vec<ConditionJson>),
+    Any(Vec<ConditionJson>),
+    AtLeast(AtLeastArgs),
+    Let(Box<LetArgs>),
+    // a subtree named by an enclosing `let`
+    Ref(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PriceArgs {
+    #[serde(with = "key")]
+    pub token: Pubkey,
+    #[serde(with = "decimal")]
+    pub price: Price,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CompareArgs {
+    pub left: ValueJson,
+    // ">", "<", ">=" or "<="
+    #[serde(with = "comparison")]
+    pub op: CompareOp,
+    pub right: ValueJson,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CustomArgs {
+    pub kind: u16,
+    // the encoded arguments as lowercase hex
+    #[serde(with = "hex")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AtLeastArgs {
+    pub k: NodeIndex,
+    pub of: Vec<ConditionJson>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LetArgs {
+    // each binding may refer to the ones before it
+    pub bind: Vec<BindingJson>,
+    #[serde(rename = "in")]
+    pub body: ConditionJson,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BindingJson {
+    pub name: String,
+    pub is: ConditionJson,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueJson {
+    Const(#[serde(with = "decimal")] Price),
+    Price(#[serde(with = "key")] Pubkey),
+    Balance(#[serde(with = "key")] Pubkey),
+    Neg(Box<ValueJson>),
+    Abs(Box<ValueJson>),
+    Add(Box<(ValueJson, ValueJson)>),
+    Sub(Box<(ValueJson, ValueJson)>),
+    Mul(Box<(ValueJson, ValueJson)>),
+    Div(Box<(ValueJson, ValueJson)>),
+    Min(Box<(ValueJson, ValueJson)>),
+    Max(Box<(ValueJson, ValueJson)>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionJson {
+    Buy(ActionArgs),
+    Sell(ActionArgs),
+    Borrow(ActionArgs),
+    Repay(ActionArgs),
+    Lend(ActionArgs),
+    Redeem(ActionArgs),
+    // the first action, then the second
+    Then(Box<(ActionJson, ActionJson)>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ActionArgs {
+    #[serde(with = "key")]
+    pub token: Pubkey,
+    // raw units as a number, or a share of the holding as a string like "12.5%"
+    #[serde(with = "amount")]
+    pub amount: Amount,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StrategyJson {
+    pub when: ConditionJson,
+    pub then: ActionJson,
+    // "5m", "1h30m", ...
+    #[serde(with = "duration")]
+    pub every: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<ConditionJson>,
+    // 0 never runs, as for `Strategy::max_runs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_runs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub meta: Vec<MetaJson>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MetaJson {
+    pub key: String,
+    pub value: String,
+}
+
+impl ConditionJson {
+    pub fn from_tree(tree: &ConditionTree) -> Self {
+        // the nodes reached from the root, children first, and how many parents point at each
+        fn visit(
+            tree: &ConditionTree,
+            index: NodeIndex,
+            uses: &mut [usize],
+            seen: &mut [bool],
+            order: &mut Vec<NodeIndex>,
+        ) {
+            if std::mem::replace(&mut seen[index as usize], true) {
+                return;
+            }
+            for child in tree.nodes[index as usize].condition_type.children() {
+                uses[child as usize] += 1;
+                visit(tree, child, uses, seen, order);
+            }
+            order.push(index);
+        }
+        let mut uses = vec![0; tree.nodes.len()];
+        let mut seen = vec![false; tree.nodes.len()];
+        let mut order = vec![];
+        visit(tree, tree.root_index, &mut uses, &mut seen, &mut order);
+
+        // an atom written out at every use only costs its own size, so only operators get a name
+        let shared: Vec<_> = order
+            .into_iter()
+            .filter(|i| {
+                uses[*i as usize] > 1
+                    && !tree.nodes[*i as usize].condition_type.children().is_empty()
+            })
+            .collect();
+        let names: HashMap<_, _> = shared
+            .iter()
+            .enumerate()
+            .map(|(n, i)| (*i, format!("s{}", n)))
+            .collect();
+        let bind: Vec<_> = shared
+            .iter()
+            .map(|i| BindingJson {
+                name: names[i].clone(),
+                is: Self::from_operands(tree, *i, &names),
+            })
+            .collect();
+        let body = Self::from_node(tree, tree.root_index, &names);
+        if bind.is_empty() {
+            body
+        } else {
+            Self::Let(Box::new(LetArgs { bind, body }))
+        }
+    }
+
+    fn from_node(
+        tree: &ConditionTree,
+        index: NodeIndex,
+        names: &HashMap<NodeIndex, String>,
+    ) -> Self {
+        match names.get(&index) {
+            Some(name) => Self::Ref(name.clone()),
+            None => Self::from_operands(tree, index, names),
+        }
+    }
+
+    // `index` itself written out, with its operands referred to by name where they have one
+    fn from_operands(
+        tree: &ConditionTree,
+        index: NodeIndex,
+        names: &HashMap<NodeIndex, String>,
+    ) -> Self {
+        let node = |i: &NodeIndex| Self::from_node(tree, *i, names);
+        let pair = |l: &NodeIndex, r: &NodeIndex| Box::new((node(l), node(r)));
+        match &tree.nodes[index as usize].condition_type {
+            ConditionType::Atomic(atom) => Self::from_atom(atom),
+            ConditionType::Const(value) => Self::Const(*value),
+            ConditionType::Not { child } => Self::Not(Box::new(node(child))),
+            ConditionType::And { left, right } => Self::And(pair(left, right)),
+            ConditionType::Or { left, right } => Self::Or(pair(left, right)),
+            ConditionType::Xor { left, right } => Self::Xor(pair(left, right)),
+            ConditionType::Implies { left, right } => Self::Implies(pair(left, right)),
+            ConditionType::All { children } => Self::All(children.iter().map(node).collect()),
+            ConditionType::Any { children } => Self::Any(children.iter().map(node).collect()),
+            ConditionType::AtLeast { k, children } => Self::AtLeast(AtLeastArgs {
+                k: *k,
+                of: children.iter().map(node).collect(),
+            }),
+        }
+    }
+
+    fn from_atom(atom: &AtomicCondition) -> Self {
+        let args = |token: &Pubkey, price: &Price| PriceArgs {
+            token: *token,
+            price: *price,
+        };
+        match atom {
+            AtomicCondition::PriceAbove { token, price } => Self::PriceAbove(args(token, price)),
+            AtomicCondition::PriceBelow { token, price } => Self::PriceBelow(args(token, price)),
+            AtomicCondition::PriceAtOrAbove { token, price } => {
+                Self::PriceAtOrAbove(args(token, price))
+            }
+            AtomicCondition::PriceAtOrBelow { token, price } => {
+                Self::PriceAtOrBelow(args(token, price))
+            }
+            AtomicCondition::Custom { kind, data } => Self::Custom(CustomArgs {
+                kind: *kind,
+                data: data.clone(),
+            }),
+            AtomicCondition::Compare { left, op, right } => Self::Compare(Box::new(CompareArgs {
+                left: ValueJson::from_expr(left),
+                op: *op,
+                right: ValueJson::from_expr(right),
+            })),
+        }
+    }
+
+    pub fn to_builder(&self) -> Result<ConditionBuilder> {
+        self.builder(&HashMap::new())
+    }
+
+    fn builder<'a>(
+        &'a self,
+        bound: &HashMap<&'a str, ConditionBuilder>,
+    ) -> Result<ConditionBuilder> {
+        let pair =
+            |p: &'a (Self, Self)| -> Result<_> { Ok((p.0.builder(bound)?, p.1.builder(bound)?)) };
+        let list = |c: &'a [Self]| {
+            c.iter()
+                .map(|c| c.builder(bound))
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(match self {
+            Self::PriceAbove(a) => ConditionBuilder::price_above(a.token, a.price),
+            Self::PriceBelow(a) => ConditionBuilder::price_below(a.token, a.price),
+            Self::PriceAtOrAbove(a) => ConditionBuilder::price_at_or_above(a.token, a.price),
+            Self::PriceAtOrBelow(a) => ConditionBuilder::price_at_or_below(a.token, a.price),
+            // not `ConditionBuilder::compare`, which would turn `price(t) > 1` into PRICE_ABOVE
+            Self::Compare(c) => ConditionBuilder::atom(AtomicCondition::Compare {
+                left: c.left.to_expr(),
+                op: c.op,
+                right: c.right.to_expr(),
+            }),
+            Self::Custom(c) => ConditionBuilder::custom(c.kind, c.data.clone()),
+            Self::Const(value) => ConditionBuilder::constant(*value),
+            Self::Not(child) => child.builder(bound)?.not(),
+            Self::And(p) => {
+                let (l, r) = pair(p)?;
+                l.and(r)
+            }
+            Self::Or(p) => {
+                let (l, r) = pair(p)?;
+                l.or(r)
+            }
+            Self::Xor(p) => {
+                let (l, r) = pair(p)?;
+                l.xor(r)
+            }
+            Self::Implies(p) => {
+                let (l, r) = pair(p)?;
+                l.implies(r)
+            }
+            Self::All(children) => ConditionBuilder::all(list(children)?),
+            Self::Any(children) => ConditionBuilder::any(list(children)?),
+            Self::AtLeast(a) => ConditionBuilder::at_least(a.k, list(&a.of)?),
+            Self::Let(l) => {
+                let mut bound = bound.clone();
+                for binding in &l.bind {
+                    let condition = binding.is.builder(&bound)?;
+                    bound.insert(&binding.name, condition);
+                }
+                l.body.builder(&bound)?
+            }
+            // a clone, which `graft` stores once again wherever it ends up
+            Self::Ref(name) => bound.get(name.as_str()).cloned().ok_or_else(|| {
+                msg!("no subtree named `{}`", name);
+                error!(ErrorCode::InvalidTree)
+            })?,
+        })
+    }
+
+    pub fn to_tree(&self) -> Result<ConditionTree> {
+        self.to_builder()?.build()
+    }
+}
+
+impl ValueJson {
+    pub fn from_expr(expr: &ValueExpr) -> Self {
+        Self::from_node(expr, expr.root)
+    }
+
+    fn from_node(expr: &ValueExpr, index: NodeIndex) -> Self {
+        let node = |i: &NodeIndex| Self::from_node(expr, *i);
+        let pair = |l: &NodeIndex, r: &NodeIndex| Box::new((node(l), node(r)));
+        match &expr.nodes[index as usize] {
+            ValueNode::Const(price) => Self::Const(*price),
+            ValueNode::Price(token) => Self::Price(*token),
+            ValueNode::Balance(token) => Self::Balance(*token),
+            ValueNode::Neg { child } => Self::Neg(Box::new(node(child))),
+            ValueNode::Abs { child } => Self::Abs(Box::new(node(child))),
+            ValueNode::Add { left, right } => Self::Add(pair(left, right)),
+            ValueNode::Sub { left, right } => Self::Sub(pair(left, right)),
+            ValueNode::Mul { left, right } => Self::Mul(pair(left, right)),
+            ValueNode::Div { left, right } => Self::Div(pair(left, right)),
+            ValueNode::Min { left, right } => Self::Min(pair(left, right)),
+            ValueNode::Max { left, right } => Self::Max(pair(left, right)),
+        }
+    }
+
+    pub fn to_expr(&self) -> ValueExpr {
+        let pair = |p: &(Self, Self)| (p.0.to_expr(), p.1.to_expr());
+        match self {
+            Self::Const(price) => ValueExpr::constant(*price),
+            Self::Price(token) => ValueExpr::price(*token),
+            Self::Balance(token) => ValueExpr::balance(*token),
+            Self::Neg(child) => child.to_expr().neg(),
+            Self::Abs(child) => child.to_expr().abs(),
+            Self::Add(p) => {
+                let (l, r) = pair(p);
+                l.add(r)
+            }
+            Self::Sub(p) => {
+                let (l, r) = pair(p);
+                l.sub(r)
+            }
+            Self::Mul(p) => {
+                let (l, r) = pair(p);
+                l.mul(r)
+            }
+            Self::Div(p) => {
+                let (l, r) = pair(p);
+                l.div(r)
+            }
+            Self::Min(p) => {
+                let (l, r) = pair(p);
+                l.min(r)
+            }
+            Self::Max(p) => {
+                let (l, r) = pair(p);
+                l.max(r)
+            }
+        }
+    }
+}
+
+impl ActionJson {
+    pub fn from_tree(tree: &ActionTree) -> Self {
+        Self::from_node(tree, tree.root_index)
+    }
+
+    fn from_node(tree: &ActionTree, index: NodeIndex) -> Self {
+        match &tree.nodes[index as usize].action_type {
+            ActionType::And { left, right } => Self::Then(Box::new((
+                Self::from_node(tree, *left),
+                Self::from_node(tree, *right),
+            ))),
+            ActionType::Atomic(atomic) => {
+                let (make, token, amount): (fn(ActionArgs) -> Self, _, _) = match atomic {
+                    AtomicAction::Buy { token, amount } => (Self::Buy, token, amount),
+                    AtomicAction::Sell { token, amount } => (Self::Sell, token, amount),
+                    AtomicAction::Borrow { token, amount } => (Self::Borrow, token, amount),
+                    AtomicAction::Repay { token, amount } => (Self::Repay, token, amount),
+                    AtomicAction::Lend { token, amount } => (Self::Lend, token, amount),
+                    AtomicAction::Redeem { token, amount } => (Self::Redeem, token, amount),
+                };
+                make(ActionArgs {
+                    token: *token,
+                    amount: *amount,
+                })
+            }
+        }
+    }
+
+    pub fn to_builder(&self) -> ActionBuilder {
+        match self {
+            Self::Buy(a) => ActionBuilder::buy(a.token, a.amount),
+            Self::Sell(a) => ActionBuilder::sell(a.token, a.amount),
+            Self::Borrow(a) => ActionBuilder::borrow(a.token, a.amount),
+            Self::Repay(a) => ActionBuilder::repay(a.token, a.amount),
+            Self::Lend(a) => ActionBuilder::lend(a.token, a.amount),
+            Self::Redeem(a) => ActionBuilder::redeem(a.token, a.amount),
+            Self::Then(p) => p.0.to_builder().and(p.1.to_builder()),
+        }
+    }
+
+    pub fn to_tree(&self) -> Result<ActionTree> {
+        self.to_builder().build()
+    }
+}
+
+impl StrategyJson {
+    pub fn from_strategy(strategy: &Strategy) -> Self {
+        Self {
+            when: ConditionJson::from_tree(&strategy.condition),
+            then: ActionJson::from_tree(&strategy.action),
+            every: strategy.execute_every_seconds,
+            until: strategy.until.as_ref().map(ConditionJson::from_tree),
+            max_runs: strategy.max_runs,
+            meta: strategy
+                .metadata
+                .iter()
+                .map(|(key, value)| MetaJson {
+                    key: key.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_strategy(&self) -> Result<Strategy> {
+        let mut strategy = Strategy::new(self.when.to_tree()?, self.then.to_tree()?, self.every);
+        strategy.until = self
+            .until
+            .as_ref()
+            .map(ConditionJson::to_tree)
+            .transpose()?;
+        strategy.max_runs = self.max_runs;
+        strategy.metadata = self
+            .meta
+            .iter()
+            .map(|m| (m.key.clone(), m.value.clone()))
+            .collect();
+        Ok(strategy)
+    }
+}
+
+impl Strategy {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("strategies always serialize")
+    }
+
+    pub fn from_json(input: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(input)
+    }
+
+    // both go through a JSON value, since serde_yaml maps enums to YAML tags (`!and`) and
+    // can't nest them; this way the YAML reads the same as the JSON form
+    pub fn to_yaml(&self) -> String {
+        let value = serde_json::to_value(self).expect("strategies always serialize");
+        serde_yaml::to_string(&value).expect("JSON values always serialize")
+    }
+
+    pub fn from_yaml(input: &str) -> serde_yaml::Result<Self> {
+        let value: serde_json::Value = serde_yaml::from_str(input)?;
+        serde_json::from_value(value).map_err(serde::de::Error::custom)
+    }
+}
+
+// the trees themselves (de)serialize in the nested form; the arena is only for Borsh
+
+impl Serialize for ConditionTree {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        ConditionJson::from_tree(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ConditionTree {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        ConditionJson::deserialize(deserializer)?
+            .to_tree()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for ActionTree {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        ActionJson::from_tree(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ActionTree {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        ActionJson::deserialize(deserializer)?
+            .to_tree()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Strategy {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        StrategyJson::from_strategy(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Strategy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        StrategyJson::deserialize(deserializer)?
+            .to_strategy()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+// --- field formats, shared with the DSL where there is one ---
+
+mod key {
+    use super::*;
+    use std::str::FromStr;
+
+    pub fn serialize<S: Serializer>(key: &Pubkey, s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.collect_str(key)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Pubkey, D::Error> {
+        let text = <String as Deserialize>::deserialize(d)?;
+        Pubkey::from_str(&text)
+            .map_err(|_| serde::de::Error::custom(format!("`{}` is not a pubkey", text)))
+    }
+}
+
+mod decimal {
+    use super::*;
+    use crate::logic::parser::conditionParser::parse_price;
+    use crate::logic::parser::context::ParseContext;
+    use crate::logic::parser::diagnostics::parse_complete;
+
+    // `Display` for exponents up to 0, "5e3" above, so the exponent survives as well
+    pub fn serialize<S: Serializer>(price: &Price, s: S) -> std::result::Result<S::Ok, S::Error> {
+        match price.expo {
+            expo if expo > 0 => s.collect_str(&format_args!("{}e{}", price.mantissa, expo)),
+            _ => s.collect_str(price),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Price, D::Error> {
+        let text = <String as Deserialize>::deserialize(d)?;
+        let invalid = || serde::de::Error::custom(format!("`{}` is not a decimal", text));
+        let (digits, expo) = match text.split_once(['e', 'E']) {
+            Some((digits, expo)) => (digits, expo.parse::<i32>().map_err(|_| invalid())?),
+            None => (text.as_str(), 0),
+        };
+        let price =
+            parse_complete(&ParseContext::new(), digits, parse_price).map_err(|_| invalid())?;
+        let expo = price.expo.checked_add(expo).ok_or_else(invalid)?;
+        Ok(Price::new(price.mantissa, expo))
+    }
+}
+
+mod comparison {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(op: &CompareOp, s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.serialize_str(op.symbol())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> std::result::Result<CompareOp, D::Error> {
+        let text = <String as Deserialize>::deserialize(d)?;
+        [
+            CompareOp::Above,
+            CompareOp::Below,
+            CompareOp::AtOrAbove,
+            CompareOp::AtOrBelow,
+        ]
+        .into_iter()
+        .find(|op| op.symbol() == text)
+        .ok_or_else(|| serde::de::Error::custom(format!("`{}` is not >, <, >= or <=", text)))
+    }
+}
+
+mod hex {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(data: &[u8], s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.serialize_str(
+            &data
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>(),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Vec<u8>, D::Error> {
+        let text = <String as Deserialize>::deserialize(d)?;
+        let invalid = || serde::de::Error::custom(format!("`{}` is not hex", text));
+        if text.len() % 2 != 0 || !text.is_ascii() {
+            return Err(invalid());
+        }
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| invalid()))
+            .collect()
+    }
+}
+
+mod amount {
+    use super::*;
+    use crate::logic::parser::actionParser::parse_amount;
+    use crate::logic::parser::context::ParseContext;
+    use crate::logic::parser::diagnostics::parse_complete;
+
+    pub fn serialize<S: Serializer>(amount: &Amount, s: S) -> std::result::Result<S::Ok, S::Error> {
+        match amount {
+            Amount::Units(units) => s.serialize_u64(*units),
+            Amount::Percent(_) => s.collect_str(amount),
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Written {
+        Units(u64),
+        Text(String),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Amount, D::Error> {
+        match Written::deserialize(d)? {
+            Written::Units(units) => Ok(Amount::Units(units)),
+            Written::Text(text) => parse_complete(&ParseContext::new(), &text, parse_amount)
+                .map_err(|_| {
+                    serde::de::Error::custom(format!("`{}` is not an amount or a percentage", text))
+                }),
+        }
+    }
+}
+
+mod duration {
+    use super::*;
+    use crate::logic::parser::context::ParseContext;
+    use crate::logic::parser::diagnostics::parse_complete;
+    use crate::logic::parser::strategyParser::parse_duration;
+    use crate::logic::strategy::format_duration;
+
+    pub fn serialize<S: Serializer>(seconds: &u64, s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.serialize_str(&format_duration(*seconds))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<u64, D::Error> {
+        let text = <String as Deserialize>::deserialize(d)?;
+        match parse_complete(&ParseContext::new(), &text, parse_duration) {
+            Ok(seconds) if seconds > 0 => Ok(seconds),
+            _ => Err(serde::de::Error::custom(format!(
+                "`{}` is not a positive duration like 5m or 1h30m",
+                text
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::parser::strategyParser::translate_strategy_string;
+
+    #[test]
+    fn test_json_round_trip() {
+        let sol = Pubkey::new_unique();
+        let usdc = Pubkey::new_unique();
+        let source = format!(
+            "WHEN PRICE_BELOW({sol}, 90.50) AND NOT(price({sol}) / price({usdc}) >= 2) \
+             OR AT_LEAST 1 OF (TRUE, balance({usdc}) < 10 XOR FALSE) \
+             THEN BUY({sol}, 100) THEN (SELL({sol}, 12.5%) THEN LEND({usdc}, 1)) \
+             EVERY 1h30m UNTIL PRICE_ABOVE({sol}, 200) MAX_RUNS 3 META(name, \"dip\")"
+        );
+        let strategy = translate_strategy_string(&source).unwrap();
+
+        let json = strategy.to_json();
+        let back = Strategy::from_json(&json).unwrap();
+        assert_eq!(back, strategy);
+        // node for node, not only equal prices
+        assert_eq!(back.to_string_expr(), strategy.to_string_expr());
+        let yaml = strategy.to_yaml();
+        assert!(yaml.contains("every: 1h30m") && yaml.contains("- price_below:"));
+        assert_eq!(Strategy::from_yaml(&yaml).unwrap(), strategy);
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["every"], "1h30m");
+        assert_eq!(
+            value["when"]["or"][0]["and"][0]["price_below"]["price"],
+            "90.50"
+        );
+        assert_eq!(
+            value["then"]["then"][1]["then"][0]["sell"]["amount"],
+            "12.5%"
+        );
+        assert_eq!(value["then"]["then"][0]["buy"]["amount"], 100);
+        assert_eq!(value["meta"][0]["key"], "name");
+    }
+
+    #[test]
+    fn test_hand_written_json() {
+        let sol = Pubkey::new_unique();
+        let json = format!(
+            r#"{{
+                "when": {{"and": [
+                    {{"price_above": {{"token": "{sol}", "price": "5e3"}}}},
+                    {{"compare": {{"left": {{"price": "{sol}"}}, "op": "<", "right": {{"const": "6000"}}}}}}
+                ]}},
+                "then": {{"buy": {{"token": "{sol}", "amount": "50%"}}}},
+                "every": "5m"
+            }}"#
+        );
+        let strategy = Strategy::from_json(&json).unwrap();
+        assert_eq!(strategy.execute_every_seconds, 300);
+        // a compare written out stays a compare
+        assert_eq!(
+            strategy.to_string_expr(),
+            format!("WHEN PRICE_ABOVE({sol}, 5000) AND price({sol}) < 6000 THEN BUY({sol}, 50%) EVERY 5m")
+        );
+        let value: serde_json::Value = serde_json::from_str(&strategy.to_json()).unwrap();
+        assert_eq!(value["when"]["and"][0]["price_above"]["price"], "5e3");
+
+        for bad in [
+            json.replace("\"5m\"", "\"0s\""),
+            json.replace("\"<\"", "\"<>\""),
+            json.replace("\"50%\"", "\"150%\""),
+            json.replace("\"5e3\"", "\"lots\""),
+            json.replace("\"price\": \"5e3\"", "\"price\": \"5e3\", \"extra\": 1"),
+        ] {
+            assert!(Strategy::from_json(&bad).is_err(), "{}", bad);
+        }
+        // `and` takes exactly two operands
+        let three = r#"{"and": [{"const": true}, {"const": true}, {"const": false}]}"#;
+        assert!(serde_json::from_str::<ConditionTree>(three).is_err());
+    }
+
+    #[test]
+    fn test_shared_subtrees() {
+        let sol = Pubkey::new_unique();
+        // 2^40 operators unshared, 80 or so as stored
+        let mut condition = ConditionBuilder::price_above(sol, Price::from(1));
+        for _ in 0..40 {
+            condition = condition.clone().and(condition.not());
+        }
+        let tree = condition.build().unwrap();
+        let json = serde_json::to_string(&tree).unwrap();
+        assert!(json.len() < 100 * tree.nodes.len(), "{}", json);
+        assert_eq!(serde_json::from_str::<ConditionTree>(&json).unwrap(), tree);
+
+        // what `simplify` stores comes back as an equivalent tree the builder would have made
+        let source = format!(
+            "LET low = PRICE_BELOW({sol}, 90) OR price({sol}) < 80; \
+             WHEN low AND NOT(low XOR PRICE_ABOVE({sol}, 1)) THEN BUY({sol}, 1) EVERY 1m \
+             MAX_RUNS 2"
+        );
+        let mut strategy = translate_strategy_string(&source).unwrap();
+        strategy.condition = strategy.condition.simplify();
+        assert!(strategy.to_json().contains("\"ref\": \"s0\""));
+        let back = Strategy::from_json(&strategy.to_json()).unwrap();
+        assert_eq!(back.to_string_expr(), strategy.to_string_expr());
+        assert!(back.condition.nodes.len() <= strategy.condition.nodes.len());
+
+        // a MAX_RUNS of 0 the DSL can't write survives as well
+        strategy.max_runs = Some(0);
+        let back = Strategy::from_json(&strategy.to_json()).unwrap();
+        assert_eq!(back.max_runs, Some(0));
+
+        let json = r#"{"let": {"bind": [{"name": "a", "is": {"not": {"const": true}}},
+                                        {"name": "b", "is": {"or": [{"ref": "a"}, {"const": true}]}}],
+                                "in": {"and": [{"ref": "a"}, {"ref": "b"}]}}}"#;
+        let tree = serde_json::from_str::<ConditionTree>(json).unwrap();
+        assert_eq!(tree.nodes.len(), 4);
+        assert!(serde_json::from_str::<ConditionTree>(
+            &json.replace("\"ref\": \"b\"", "\"ref\": \"c\"")
+        )
+        .is_err());
+        // names are scoped to their `let`
+        let escaped = r#"{"and": [{"let": {"bind": [{"name": "a", "is": {"const": true}}], "in": {"ref": "a"}}}, {"ref": "a"}]}"#;
+        assert!(serde_json::from_str::<ConditionTree>(escaped).is_err());
+    }
+
+    #[test]
+    fn test_schema_covers_every_variant() {
+        let schema: serde_json::Value = serde_json::from_str(STRATEGY_JSON_SCHEMA).unwrap();
+        // the single key of each object form a definition allows
+        let keys = |definition: &str| -> Vec<String> {
+            schema["$defs"][definition]["oneOf"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|form| form["required"][0].as_str().unwrap().to_string())
+                .collect()
+        };
+        let key_of = |value: serde_json::Value| value.as_object().unwrap().keys().next().cloned();
+
+        let token = Pubkey::new_unique();
+        let price = PriceArgs {
+            token,
+            price: Price::from(1),
+        };
+        let t = || ConditionJson::Const(true);
+        let conditions = [
+            ConditionJson::PriceAbove(price.clone()),
+            ConditionJson::PriceBelow(price.clone()),
+            ConditionJson::PriceAtOrAbove(price.clone()),
+            ConditionJson::PriceAtOrBelow(price),
+            ConditionJson::Compare(Box::new(CompareArgs {
+                left: ValueJson::Price(token),
+                op: CompareOp::Above,
+                right: ValueJson::Const(Price::from(1)),
+            })),
+            ConditionJson::Custom(CustomArgs {
+                kind: 1,
+                data: vec![],
+            }),
+            t(),
+            ConditionJson::Not(Box::new(t())),
+            ConditionJson::And(Box::new((t(), t()))),
+            ConditionJson::Or(Box::new((t(), t()))),
+            ConditionJson::Xor(Box::new((t(), t()))),
+            ConditionJson::Implies(Box::new((t(), t()))),
+            ConditionJson::All(vec![t()]),
+            ConditionJson::Any(vec![t()]),
+            ConditionJson::AtLeast(AtLeastArgs {
+                k: 1,
+                of: vec![t()],
+            }),
+            ConditionJson::Let(Box::new(LetArgs {
+                bind: vec![],
+                body: t(),
+            })),
+            ConditionJson::Ref("s0".to_string()),
+        ];
+        let written: Vec<_> = conditions
+            .into_iter()
+            .filter_map(|c| key_of(serde_json::to_value(c).unwrap()))
+            .collect();
+        assert_eq!(written, keys("condition"));
+
+        let v = || ValueJson::Price(token);
+        let values = [
+            ValueJson::Const(Price::from(1)),
+            v(),
+            ValueJson::Balance(token),
+            ValueJson::Neg(Box::new(v())),
+            ValueJson::Abs(Box::new(v())),
+            ValueJson::Add(Box::new((v(), v()))),
+            ValueJson::Sub(Box::new((v(), v()))),
+            ValueJson::Mul(Box::new((v(), v()))),
+            ValueJson::Div(Box::new((v(), v()))),
+            ValueJson::Min(Box::new((v(), v()))),
+            ValueJson::Max(Box::new((v(), v()))),
+        ];
+        let written: Vec<_> = values
+            .into_iter()
+            .filter_map(|v| key_of(serde_json::to_value(v).unwrap()))
+            .collect();
+        assert_eq!(written, keys("value"));
+
+        let args = || ActionArgs {
+            token,
+            amount: Amount::Units(1),
+        };
+        let actions = [
+            ActionJson::Buy(args()),
+            ActionJson::Sell(args()),
+            ActionJson::Borrow(args()),
+            ActionJson::Repay(args()),
+            ActionJson::Lend(args()),
+            ActionJson::Redeem(args()),
+            ActionJson::Then(Box::new((
+                ActionJson::Buy(args()),
+                ActionJson::Sell(args()),
+            ))),
+        ];
+        let written: Vec<_> = actions
+            .into_iter()
+            .filter_map(|a| key_of(serde_json::to_value(a).unwrap()))
+            .collect();
+        assert_eq!(written, keys("action"));
+    }
+}
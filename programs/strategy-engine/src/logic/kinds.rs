@@ -0,0 +1,222 @@
+use crate::logic::conditions::EvaluationContext;
+use crate::logic::parser::lexer::is_keyword;
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use nom::IResult;
+#[cfg(not(target_os = "solana"))]
+use once_cell::sync::Lazy;
+#[cfg(not(target_os = "solana"))]
+use std::collections::HashMap;
+#[cfg(not(target_os = "solana"))]
+use std::sync::RwLock;
+
+// A condition kind defined outside `AtomicCondition`. Everything the engine needs to know
+// about it lives here, so adding one is a single entry in `BUILTIN_CONDITION_KINDS` (or, off-chain,
+// a `register_condition_kind` call) instead of touching the enum, the parser, the printer and
+// the evaluator.
+//
+// Trees store such conditions as `AtomicCondition::Custom { kind: id(), data }`, where
+// `data` is whatever `parse` produced.
+pub trait ConditionKind: Send + Sync {
+    // stable id written into stored trees; never reuse one for a different kind
+    fn id(&self) -> u16;
+
+    // the DSL keyword, e.g. "FUNDING_RATE_ABOVE"
+    fn keyword(&self) -> &'static str;
+
+    // parses the arguments following the keyword and encodes them into `data`
+    fn parse<'a>(&self, input: &'a str) -> IResult<&'a str, Vec<u8>>;
+
+    // prints the arguments following the keyword; must be accepted by `parse`
+    fn display(&self, data: &[u8]) -> String;
+
+    fn evaluate(&self, data: &[u8], ctx: &EvaluationContext) -> bool;
+
+    // semantic checks on decoded arguments; the length is already checked against `data_len`
+    fn validate(&self, _data: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    // number of bytes `parse` encodes arguments into, used to validate stored trees
+    // and to size accounts
+    fn data_len(&self) -> usize;
+}
+
+// Kinds compiled into the program. `register_condition_kind` only lasts as long as the process
+// that calls it, and every instruction runs in a fresh one, so this list is the only way for
+// `create_vault` and `execute_strategy` to know a kind: list it here to accept it on-chain.
+// off-chain tools see these as well and can register more of their own
+pub static BUILTIN_CONDITION_KINDS: &[&dyn ConditionKind] = &[];
+
+#[cfg(not(target_os = "solana"))]
+static CONDITION_KINDS: Lazy<RwLock<HashMap<u16, &'static dyn ConditionKind>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+// the built-in kinds, then the ones registered at runtime
+fn condition_kinds() -> Vec<&'static dyn ConditionKind> {
+    let mut kinds = BUILTIN_CONDITION_KINDS.to_vec();
+    #[cfg(not(target_os = "solana"))]
+    kinds.extend(CONDITION_KINDS.read().unwrap().values());
+    kinds
+}
+
+// off-chain only; see `BUILTIN_CONDITION_KINDS`
+#[cfg(not(target_os = "solana"))]
+pub fn register_condition_kind(kind: &'static dyn ConditionKind) -> Result<()> {
+    let mut kinds = CONDITION_KINDS.write().unwrap();
+    let taken = |k: &dyn ConditionKind| k.id() == kind.id() || k.keyword() == kind.keyword();
+    let conflict = is_keyword(kind.keyword())
+        || BUILTIN_CONDITION_KINDS.iter().any(|k| taken(*k))
+        || kinds.values().any(|k| taken(*k));
+    require!(!conflict, ErrorCode::ConditionKindConflict);
+    kinds.insert(kind.id(), kind);
+    Ok(())
+}
+
+pub fn condition_kind(id: u16) -> Option<&'static dyn ConditionKind> {
+    condition_kinds().into_iter().find(|k| k.id() == id)
+}
+
+pub fn condition_kind_by_keyword(keyword: &str) -> Option<&'static dyn ConditionKind> {
+    condition_kinds()
+        .into_iter()
+        .find(|k| k.keyword() == keyword)
+}
+
+pub fn condition_kind_keywords() -> Vec<&'static str> {
+    condition_kinds().iter().map(|k| k.keyword()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::conditions::{AtomicCondition, ConditionBuilder};
+    use crate::logic::parser::conditionParser::{
+        parse_number, parse_pubkey, translate_condition_string, ws,
+    };
+    use crate::logic::price::Price;
+    use nom::character::complete::char;
+    use nom::Parser;
+
+    // PRICE_BETWEEN(token, lo, hi): lo < price < hi
+    struct PriceBetween;
+
+    impl ConditionKind for PriceBetween {
+        fn id(&self) -> u16 {
+            1000
+        }
+
+        fn keyword(&self) -> &'static str {
+            "PRICE_BETWEEN"
+        }
+
+        fn parse<'a>(&self, input: &'a str) -> IResult<&'a str, Vec<u8>> {
+            let (input, _) = ws(char('(')).parse(input)?;
+            let (input, token) = ws(parse_pubkey).parse(input)?;
+            let (input, _) = ws(char(',')).parse(input)?;
+            let (input, lo) = ws(parse_number).parse(input)?;
+            let (input, _) = ws(char(',')).parse(input)?;
+            let (input, hi) = ws(parse_number).parse(input)?;
+            let (input, _) = ws(char(')')).parse(input)?;
+            Ok((input, (token, lo, hi).try_to_vec().unwrap()))
+        }
+
+        fn display(&self, data: &[u8]) -> String {
+            let (token, lo, hi) = <(Pubkey, u64, u64)>::try_from_slice(data).unwrap();
+            format!("({}, {}, {})", token, lo, hi)
+        }
+
+        fn evaluate(&self, data: &[u8], ctx: &EvaluationContext) -> bool {
+            let (token, lo, hi) = <(Pubkey, u64, u64)>::try_from_slice(data).unwrap();
+            ctx.token_prices
+                .get(&token)
+                .is_some_and(|p| Price::from(lo) < *p && *p < Price::from(hi))
+        }
+
+        fn validate(&self, data: &[u8]) -> Result<()> {
+            let (_, lo, hi) = <(Pubkey, u64, u64)>::try_from_slice(data)?;
+            require!(lo < hi, ErrorCode::InvalidConditionData);
+            Ok(())
+        }
+
+        fn data_len(&self) -> usize {
+            32 + 8 + 8
+        }
+    }
+
+    fn register() {
+        // tests share the registry; whichever runs first registers the kind
+        if condition_kind(1000).is_none() {
+            let _ = register_condition_kind(&PriceBetween);
+        }
+    }
+
+    #[test]
+    fn test_custom_condition_round_trip() {
+        register();
+        let token = Pubkey::new_unique();
+        let input = format!(
+            "PRICE_BETWEEN({}, 100, 200) AND PRICE_ABOVE({}, 150)",
+            token, token
+        );
+
+        let tree = translate_condition_string(&input).unwrap();
+        assert!(matches!(
+            tree.nodes[0].condition_type,
+            crate::logic::conditions::ConditionType::Atomic(AtomicCondition::Custom {
+                kind: 1000,
+                ..
+            })
+        ));
+        assert_eq!(tree.to_string_expr(), input);
+        tree.validate().unwrap();
+
+        let ctx = EvaluationContext {
+            token_prices: HashMap::from([(token, Price::from(170))]),
+            ..Default::default()
+        };
+        assert!(tree.evaluate(&ctx));
+        let ctx = EvaluationContext {
+            token_prices: HashMap::from([(token, Price::from(250))]),
+            ..Default::default()
+        };
+        assert!(!tree.evaluate(&ctx));
+    }
+
+    #[test]
+    fn test_custom_condition_validation() {
+        register();
+        let token = Pubkey::new_unique();
+
+        let inverted = (token, 200u64, 100u64).try_to_vec().unwrap();
+        let tree = ConditionBuilder::custom(1000, inverted).build().unwrap();
+        assert!(tree.validate().is_err());
+
+        let unknown = ConditionBuilder::custom(4242, vec![]).build().unwrap();
+        assert!(unknown.validate().is_err());
+
+        // keywords can't shadow built-in ones
+        struct Shadow;
+        impl ConditionKind for Shadow {
+            fn id(&self) -> u16 {
+                1001
+            }
+            fn keyword(&self) -> &'static str {
+                "PRICE_ABOVE"
+            }
+            fn parse<'a>(&self, input: &'a str) -> IResult<&'a str, Vec<u8>> {
+                Ok((input, vec![]))
+            }
+            fn display(&self, _data: &[u8]) -> String {
+                String::new()
+            }
+            fn evaluate(&self, _data: &[u8], _ctx: &EvaluationContext) -> bool {
+                false
+            }
+            fn data_len(&self) -> usize {
+                0
+            }
+        }
+        assert!(register_condition_kind(&Shadow).is_err());
+    }
+}
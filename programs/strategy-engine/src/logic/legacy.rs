@@ -0,0 +1,313 @@
+use crate::logic::actions::{ActionNode, ActionTree, ActionType, Amount, AtomicAction};
+use crate::logic::conditions::{
+    AtomicCondition, ConditionNode, ConditionTree, ConditionType, NodeIndex,
+};
+use crate::logic::price::Price;
+use anchor_lang::prelude::*;
+
+// Layouts of trees stored before node indices were widened from `u8` to `NodeIndex`
+// and prices became fixed-point `Price`s, and before action amounts could be percentages.
+// They only exist so `migrate_vault` can read accounts created with the old layout;
+// nothing should build new trees with them.
+
+// prices were whole units
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub enum AtomicConditionV1 {
+    PriceAbove { token: Pubkey, price: u64 },
+    PriceBelow { token: Pubkey, price: u64 },
+    PriceAtOrAbove { token: Pubkey, price: u64 },
+    PriceAtOrBelow { token: Pubkey, price: u64 },
+    Custom { kind: u16, data: Vec<u8> },
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub enum ConditionTypeV1 {
+    Atomic(AtomicConditionV1),
+    And { left: u8, right: u8 },
+    Or { left: u8, right: u8 },
+    Not { child: u8 },
+    Const(bool),
+    All { children: Vec<u8> },
+    Any { children: Vec<u8> },
+    AtLeast { k: u8, children: Vec<u8> },
+    Xor { left: u8, right: u8 },
+    Implies { left: u8, right: u8 },
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct ConditionNodeV1 {
+    pub condition_type: ConditionTypeV1,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct ConditionTreeV1 {
+    pub nodes: Vec<ConditionNodeV1>,
+    pub root_index: u8,
+}
+
+// amounts were raw token units
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub enum AtomicActionV1 {
+    Buy { token: Pubkey, amount: u64 },
+    Sell { token: Pubkey, amount: u64 },
+    Borrow { token: Pubkey, amount: u64 },
+    Repay { token: Pubkey, amount: u64 },
+    Lend { token: Pubkey, amount: u64 },
+    Redeem { token: Pubkey, amount: u64 },
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub enum ActionTypeV1 {
+    Atomic(AtomicActionV1),
+    And { left: u8, right: u8 },
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct ActionNodeV1 {
+    pub action_type: ActionTypeV1,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct ActionTreeV1 {
+    pub nodes: Vec<ActionNodeV1>,
+    pub root_index: u8,
+}
+
+impl From<AtomicConditionV1> for AtomicCondition {
+    fn from(old: AtomicConditionV1) -> Self {
+        match old {
+            AtomicConditionV1::PriceAbove { token, price } => AtomicCondition::PriceAbove {
+                token,
+                price: Price::from(price),
+            },
+            AtomicConditionV1::PriceBelow { token, price } => AtomicCondition::PriceBelow {
+                token,
+                price: Price::from(price),
+            },
+            AtomicConditionV1::PriceAtOrAbove { token, price } => AtomicCondition::PriceAtOrAbove {
+                token,
+                price: Price::from(price),
+            },
+            AtomicConditionV1::PriceAtOrBelow { token, price } => AtomicCondition::PriceAtOrBelow {
+                token,
+                price: Price::from(price),
+            },
+            AtomicConditionV1::Custom { kind, data } => AtomicCondition::Custom { kind, data },
+        }
+    }
+}
+
+fn widen(indices: Vec<u8>) -> Vec<NodeIndex> {
+    indices.into_iter().map(NodeIndex::from).collect()
+}
+
+impl From<ConditionTypeV1> for ConditionType {
+    fn from(old: ConditionTypeV1) -> Self {
+        match old {
+            ConditionTypeV1::Atomic(atomic) => ConditionType::Atomic(atomic.into()),
+            ConditionTypeV1::And { left, right } => ConditionType::And {
+                left: left.into(),
+                right: right.into(),
+            },
+            ConditionTypeV1::Or { left, right } => ConditionType::Or {
+                left: left.into(),
+                right: right.into(),
+            },
+            ConditionTypeV1::Not { child } => ConditionType::Not {
+                child: child.into(),
+            },
+            ConditionTypeV1::Const(value) => ConditionType::Const(value),
+            ConditionTypeV1::All { children } => ConditionType::All {
+                children: widen(children),
+            },
+            ConditionTypeV1::Any { children } => ConditionType::Any {
+                children: widen(children),
+            },
+            ConditionTypeV1::AtLeast { k, children } => ConditionType::AtLeast {
+                k: k.into(),
+                children: widen(children),
+            },
+            ConditionTypeV1::Xor { left, right } => ConditionType::Xor {
+                left: left.into(),
+                right: right.into(),
+            },
+            ConditionTypeV1::Implies { left, right } => ConditionType::Implies {
+                left: left.into(),
+                right: right.into(),
+            },
+        }
+    }
+}
+
+impl From<ConditionTreeV1> for ConditionTree {
+    fn from(old: ConditionTreeV1) -> Self {
+        ConditionTree {
+            nodes: old
+                .nodes
+                .into_iter()
+                .map(|n| ConditionNode {
+                    condition_type: n.condition_type.into(),
+                })
+                .collect(),
+            root_index: old.root_index.into(),
+        }
+    }
+}
+
+impl From<AtomicActionV1> for AtomicAction {
+    fn from(old: AtomicActionV1) -> Self {
+        let units = Amount::Units;
+        match old {
+            AtomicActionV1::Buy { token, amount } => AtomicAction::Buy {
+                token,
+                amount: units(amount),
+            },
+            AtomicActionV1::Sell { token, amount } => AtomicAction::Sell {
+                token,
+                amount: units(amount),
+            },
+            AtomicActionV1::Borrow { token, amount } => AtomicAction::Borrow {
+                token,
+                amount: units(amount),
+            },
+            AtomicActionV1::Repay { token, amount } => AtomicAction::Repay {
+                token,
+                amount: units(amount),
+            },
+            AtomicActionV1::Lend { token, amount } => AtomicAction::Lend {
+                token,
+                amount: units(amount),
+            },
+            AtomicActionV1::Redeem { token, amount } => AtomicAction::Redeem {
+                token,
+                amount: units(amount),
+            },
+        }
+    }
+}
+
+impl From<ActionTreeV1> for ActionTree {
+    fn from(old: ActionTreeV1) -> Self {
+        ActionTree {
+            nodes: old
+                .nodes
+                .into_iter()
+                .map(|n| ActionNode {
+                    action_type: match n.action_type {
+                        ActionTypeV1::Atomic(atomic) => ActionType::Atomic(atomic.into()),
+                        ActionTypeV1::And { left, right } => ActionType::And {
+                            left: left.into(),
+                            right: right.into(),
+                        },
+                    },
+                })
+                .collect(),
+            root_index: old.root_index.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::actions::ActionBuilder;
+    use crate::logic::conditions::ConditionBuilder;
+    use crate::{ErrorCode, StrategyV1, VaultAccount, VaultAccountV1};
+
+    #[test]
+    fn test_condition_tree_v1_migration() {
+        let token = Pubkey::new_unique();
+        // PRICE_ABOVE(t, 100) AND NOT PRICE_BELOW(t, 50), as the old program stored it
+        let old = ConditionTreeV1 {
+            nodes: vec![
+                ConditionNodeV1 {
+                    condition_type: ConditionTypeV1::Atomic(AtomicConditionV1::PriceAbove {
+                        token,
+                        price: 100,
+                    }),
+                },
+                ConditionNodeV1 {
+                    condition_type: ConditionTypeV1::Atomic(AtomicConditionV1::PriceBelow {
+                        token,
+                        price: 50,
+                    }),
+                },
+                ConditionNodeV1 {
+                    condition_type: ConditionTypeV1::Not { child: 1 },
+                },
+                ConditionNodeV1 {
+                    condition_type: ConditionTypeV1::And { left: 0, right: 2 },
+                },
+            ],
+            root_index: 3,
+        };
+        let bytes = old.try_to_vec().unwrap();
+
+        let decoded = ConditionTreeV1::deserialize(&mut bytes.as_slice()).unwrap();
+        let migrated = ConditionTree::from(decoded);
+
+        let expected = ConditionBuilder::price_above(token, Price::from(100))
+            .and(ConditionBuilder::price_below(token, Price::from(50)).not())
+            .build()
+            .unwrap();
+        assert_eq!(migrated, expected);
+    }
+
+    #[test]
+    fn test_vault_migrates_once() {
+        let authority = Pubkey::new_unique();
+        let token = Pubkey::new_unique();
+        let old = VaultAccountV1 {
+            authority,
+            strategy: StrategyV1 {
+                condition_tree: ConditionTreeV1 {
+                    nodes: vec![ConditionNodeV1 {
+                        condition_type: ConditionTypeV1::Atomic(AtomicConditionV1::PriceAbove {
+                            token,
+                            price: 100,
+                        }),
+                    }],
+                    root_index: 0,
+                },
+                action_tree: ActionTreeV1 {
+                    nodes: vec![ActionNodeV1 {
+                        action_type: ActionTypeV1::Atomic(AtomicActionV1::Buy { token, amount: 5 }),
+                    }],
+                    root_index: 0,
+                },
+                execute_every_seconds: 60,
+            },
+            balance: 7,
+            last_executed: 1_700_000_000,
+        };
+        let mut data = VaultAccount::DISCRIMINATOR.to_vec();
+        old.serialize(&mut data).unwrap();
+
+        let vault = VaultAccount::migrate(&data).unwrap();
+        assert_eq!(vault.authority, authority);
+        assert_eq!(vault.balance, 7);
+        let strategy = vault.load_strategy().unwrap();
+        assert_eq!(
+            strategy.condition_tree,
+            ConditionBuilder::price_above(token, Price::from(100))
+                .build()
+                .unwrap()
+        );
+        assert_eq!(
+            strategy.action_tree,
+            ActionBuilder::buy(token, 5).build().unwrap()
+        );
+        assert_eq!(strategy.execute_every_seconds, 60);
+
+        // what `migrate_vault` writes back, with and without spare space after it
+        let mut migrated = VaultAccount::DISCRIMINATOR.to_vec();
+        vault.serialize(&mut migrated).unwrap();
+        assert!(migrated.len() < data.len());
+        let refused = |data: &[u8]| {
+            VaultAccount::migrate(data).err() == Some(error!(ErrorCode::AlreadyMigrated))
+        };
+        assert!(refused(&migrated));
+        migrated.extend([0; 16]);
+        assert!(refused(&migrated));
+    }
+}
@@ -0,0 +1,258 @@
+use crate::logic::actions::AtomicAction;
+use crate::logic::backtest::BacktestReport;
+use anchor_lang::prelude::*;
+use std::collections::HashMap;
+
+pub const SECONDS_PER_YEAR: f64 = 365.25 * 86_400.0;
+
+// Performance of a backtest, as risk reviewers ask for it. returns and ratios are fractions
+// (0.05 is 5%); the ones a run is too short or too flat to define are `None`
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct Metrics {
+    pub initial_equity: f64,
+    pub final_equity: f64,
+    pub total_return: f64,
+    // the total return as a yearly rate
+    pub cagr: Option<f64>,
+    // largest fall from an earlier peak, as a fraction of that peak
+    pub max_drawdown: f64,
+    // annualized mean over standard deviation of the tick-to-tick returns, at a zero risk-free
+    // rate
+    pub sharpe: Option<f64>,
+    // like `sharpe`, with only the falls counted as risk
+    pub sortino: Option<f64>,
+    // share of SELLs that got more than the tokens cost, fees included
+    pub win_rate: Option<f64>,
+    // BUY and SELL value over the average equity
+    pub turnover: f64,
+    // share of the time some of the equity was in tokens rather than cash
+    pub exposure: f64,
+    // times the condition held and the actions ran
+    pub triggers: u64,
+    pub trades: usize,
+    pub rejected: usize,
+    pub fees: f64,
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+// share of SELLs that made money against the average cost of the units bought before them.
+// units held from the start have no cost, so selling them does not count
+fn win_rate(report: &BacktestReport) -> Option<f64> {
+    // raw units bought and not yet sold, and what they cost
+    let mut positions: HashMap<Pubkey, (u64, f64)> = HashMap::new();
+    let (mut wins, mut closed) = (0, 0);
+    for trade in report.trades.iter().filter(|t| t.rejected.is_none()) {
+        let position = positions.entry(trade.action.token()).or_default();
+        match trade.action {
+            AtomicAction::Buy { .. } => {
+                position.0 += trade.quantity;
+                position.1 += trade.value + trade.fee;
+            }
+            AtomicAction::Sell { .. } => {
+                let matched = trade.quantity.min(position.0);
+                if matched == 0 {
+                    continue;
+                }
+                let cost = position.1 * matched as f64 / position.0 as f64;
+                let proceeds = (trade.value - trade.fee) * matched as f64 / trade.quantity as f64;
+                position.0 -= matched;
+                position.1 -= cost;
+                closed += 1;
+                if proceeds > cost {
+                    wins += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    (closed > 0).then(|| wins as f64 / closed as f64)
+}
+
+impl Metrics {
+    pub fn from_report(report: &BacktestReport) -> Self {
+        let curve = &report.equity_curve;
+        let initial_equity = curve.first().map_or(0.0, |p| p.equity);
+        let final_equity = curve.last().map_or(0.0, |p| p.equity);
+        let span = match (curve.first(), curve.last()) {
+            (Some(first), Some(last)) => (last.timestamp - first.timestamp) as f64,
+            _ => 0.0,
+        };
+        let total_return = if initial_equity > 0.0 {
+            final_equity / initial_equity - 1.0
+        } else {
+            0.0
+        };
+        // compounding a short run over a year easily overflows
+        let cagr = (span > 0.0 && initial_equity > 0.0 && final_equity >= 0.0)
+            .then(|| (final_equity / initial_equity).powf(SECONDS_PER_YEAR / span) - 1.0)
+            .filter(|cagr| cagr.is_finite());
+
+        let mut peak = f64::MIN;
+        let mut max_drawdown: f64 = 0.0;
+        for point in curve {
+            peak = peak.max(point.equity);
+            if peak > 0.0 {
+                max_drawdown = max_drawdown.max((peak - point.equity) / peak);
+            }
+        }
+
+        let returns = curve
+            .windows(2)
+            .filter(|w| w[0].equity > 0.0)
+            .map(|w| w[1].equity / w[0].equity - 1.0)
+            .collect::<Vec<_>>();
+        // the ticks are taken as evenly spaced, at their average interval
+        let periods_per_year = SECONDS_PER_YEAR / (span / (curve.len().max(2) - 1) as f64);
+        let (sharpe, sortino) = if returns.len() >= 2 && span > 0.0 {
+            let average = mean(&returns);
+            let variance = returns.iter().map(|r| (r - average).powi(2)).sum::<f64>()
+                / (returns.len() - 1) as f64;
+            let downside = mean(
+                &returns
+                    .iter()
+                    .map(|r| r.min(0.0).powi(2))
+                    .collect::<Vec<_>>(),
+            );
+            let annualize = periods_per_year.sqrt();
+            (
+                (variance > 0.0).then(|| average / variance.sqrt() * annualize),
+                (downside > 0.0).then(|| average / downside.sqrt() * annualize),
+            )
+        } else {
+            (None, None)
+        };
+
+        let traded: f64 = report.trades.iter().map(|t| t.value).sum();
+        let average_equity = if curve.is_empty() {
+            0.0
+        } else {
+            mean(&curve.iter().map(|p| p.equity).collect::<Vec<_>>())
+        };
+        let exposed: u64 = curve
+            .windows(2)
+            .filter(|w| w[0].positions > 0.0)
+            .map(|w| w[1].timestamp - w[0].timestamp)
+            .sum();
+
+        Self {
+            initial_equity,
+            final_equity,
+            total_return,
+            cagr,
+            max_drawdown,
+            sharpe,
+            sortino,
+            win_rate: win_rate(report),
+            turnover: if average_equity > 0.0 {
+                traded / average_equity
+            } else {
+                0.0
+            },
+            exposure: if span > 0.0 {
+                exposed as f64 / span
+            } else {
+                0.0
+            },
+            triggers: report.runs,
+            trades: report.trades.len(),
+            rejected: report
+                .trades
+                .iter()
+                .filter(|t| t.rejected.is_some())
+                .count(),
+            fees: report.trades.iter().map(|t| t.fee).sum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::backtest::{backtest, BacktestConfig, PriceHistory};
+    use crate::logic::parser::strategyParser::translate_strategy_string;
+    use crate::logic::portfolio::Market;
+    use crate::logic::registry::TokenRegistry;
+
+    fn run(strategy: &str, token: Pubkey, prices: &[(u64, u64)]) -> Metrics {
+        let mut csv = format!("timestamp,{}\n", token);
+        for (timestamp, price) in prices {
+            csv += &format!("{},{}\n", timestamp, price);
+        }
+        let history = PriceHistory::from_csv(&csv, &TokenRegistry::default()).unwrap();
+        let strategy = translate_strategy_string(strategy).unwrap();
+        let config = BacktestConfig {
+            market: Market {
+                fee_bps: 100,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        Metrics::from_report(&backtest(&strategy, &history, &config))
+    }
+
+    #[test]
+    fn test_buy_and_hold() {
+        let sol = Pubkey::new_unique();
+        let strategy = format!("WHEN PRICE_BELOW({sol}, 100) THEN BUY({sol}, 10) EVERY 1d");
+        let day = 86_400;
+        let metrics = run(
+            &strategy,
+            sol,
+            &[(0, 100), (day, 90), (2 * day, 120), (3 * day, 120)],
+        );
+        assert_eq!(
+            (metrics.triggers, metrics.trades, metrics.rejected),
+            (1, 1, 0)
+        );
+        assert_eq!(metrics.win_rate, None);
+        // 900 of SOL and 9 of fees
+        assert!((metrics.fees - 9.0).abs() < 1e-9);
+        assert!((metrics.final_equity - 10_291.0).abs() < 1e-9);
+        assert!((metrics.total_return - 0.0291).abs() < 1e-9);
+        // the fee is the only fall
+        assert!((metrics.max_drawdown - 0.0009).abs() < 1e-9);
+        // in SOL from day 1 to the end
+        assert!((metrics.exposure - 2.0 / 3.0).abs() < 1e-9);
+        assert!((metrics.turnover - 900.0 / 10_143.25).abs() < 1e-9);
+        assert!(metrics.cagr.unwrap() > metrics.total_return);
+        assert!(metrics.sharpe.unwrap() > 0.0 && metrics.sortino.unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_win_rate() {
+        let sol = Pubkey::new_unique();
+        // sells what it bought on the previous trigger, then buys again
+        let strategy = format!(
+            "WHEN PRICE_BELOW({sol}, 100) OR PRICE_ABOVE({sol}, 110) \
+             THEN SELL({sol}, 100%) THEN BUY({sol}, 10) EVERY 1d"
+        );
+        let day = 86_400;
+        let metrics = run(
+            &strategy,
+            sol,
+            &[(0, 100), (day, 90), (2 * day, 120), (3 * day, 120)],
+        );
+        assert_eq!(metrics.triggers, 3);
+        // bought at 90 and sold at 120 wins; bought and sold at 120 loses the fees
+        assert_eq!(metrics.win_rate, Some(0.5));
+    }
+
+    #[test]
+    fn test_flat_and_short_runs() {
+        let sol = Pubkey::new_unique();
+        let never = format!("WHEN PRICE_ABOVE({sol}, 1000) THEN BUY({sol}, 1) EVERY 1m");
+        let metrics = run(&never, sol, &[(0, 1), (60, 2), (120, 3)]);
+        assert_eq!(metrics.total_return, 0.0);
+        assert_eq!(metrics.max_drawdown, 0.0);
+        assert_eq!((metrics.sharpe, metrics.sortino), (None, None));
+        assert_eq!((metrics.exposure, metrics.turnover), (0.0, 0.0));
+        assert_eq!(metrics.cagr, Some(0.0));
+
+        let metrics = run(&never, sol, &[(0, 1)]);
+        assert_eq!(metrics.cagr, None);
+        assert_eq!(metrics.final_equity, 10_000.0);
+    }
+}
@@ -1,5 +1,34 @@
 pub mod actions;
+#[cfg(not(target_os = "solana"))]
+pub mod analysis;
+#[cfg(not(target_os = "solana"))]
+pub mod backtest;
 pub mod conditions;
+pub mod encoding;
+pub mod graph;
+#[cfg(all(feature = "serde", not(target_os = "solana")))]
+pub mod json;
+pub mod kinds;
+pub mod legacy;
+#[cfg(not(target_os = "solana"))]
+pub mod metrics;
 
 pub mod parser;
+#[cfg(not(target_os = "solana"))]
+pub mod portfolio;
+pub mod price;
+pub mod printer;
+#[cfg(not(target_os = "solana"))]
+pub mod random;
+pub mod registry;
+#[cfg(not(target_os = "solana"))]
+pub mod report;
+pub mod satisfiability;
+pub mod simplify;
+#[cfg(not(target_os = "solana"))]
+pub mod simulation;
 pub mod strategy;
+#[cfg(not(target_os = "solana"))]
+pub mod sweep;
+pub mod trace;
+pub mod value;
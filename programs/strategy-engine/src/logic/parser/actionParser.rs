@@ -1,82 +1,209 @@
-// use nom::{
-//     branch::alt,
-//     bytes::complete::tag,
-//     character::complete::{char, multispace0},
-//     combinator::map_res,
-//     multi::fold_many0,
-//     sequence::{delimited, preceded, tuple},
-//     IResult, Parser,
-// };
-
-// use super::common::{parse_number, parse_pubkey};
-// use super::tokens::{ActionToken, ACTION_KEYWORDS};
-// use crate::logic::actions::{ActionBuilder, ActionTree};
-// use anchor_lang::prelude::*;
-
-// type ParseResult<'a, T> = IResult<&'a str, T>;
-
-// fn parse_keyword(input: &str) -> ParseResult<ActionToken> {
-//     map_res(tag, |s: &str| {
-//         ActionToken::from_str(s).ok_or_else(|| "Invalid token")
-//     })
-//     .parse(input)
-// }
-
-// fn parse_swap(input: &str) -> ParseResult<ActionBuilder> {
-//     let (input, (_, token_in, _, token_out, _, amount, _)) = tuple((
-//         parse_keyword,
-//         delimited(char('('), parse_pubkey, char(',')),
-//         multispace0,
-//         delimited(multispace0, parse_pubkey, char(',')),
-//         multispace0,
-//         parse_number,
-//         char(')'),
-//     ))
-//     .parse(input)?;
-
-//     Ok((input, ActionBuilder::swap(token_in, token_out, amount)))
-// }
-
-// fn parse_send(input: &str) -> ParseResult<ActionBuilder> {
-//     let (input, (_, token, _, recipient, _, amount, _)) = tuple((
-//         parse_keyword,
-//         delimited(char('('), parse_pubkey, char(',')),
-//         multispace0,
-//         delimited(multispace0, parse_pubkey, char(',')),
-//         multispace0,
-//         parse_number,
-//         char(')'),
-//     ))
-//     .parse(input)?;
-
-//     Ok((input, ActionBuilder::send(token, recipient, amount)))
-// }
-
-// fn parse_atomic_action(input: &str) -> ParseResult<ActionBuilder> {
-//     alt((parse_swap, parse_send)).parse(input)
-// }
-
-// fn parse_sequence(input: &str) -> ParseResult<ActionBuilder> {
-//     let (input, first) = parse_atomic_action(input)?;
-
-//     fold_many0(
-//         tuple((
-//             preceded(multispace0, parse_keyword),
-//             preceded(multispace0, parse_atomic_action),
-//         )),
-//         move || first.clone(),
-//         |acc, (_, next)| acc.then(next),
-//     )
-//     .parse(input)
-// }
-
-// pub fn translate_action_string(input: &str) -> Result<ActionTree> {
-//     let (_, builder) = parse_sequence(input).map_err(|_| error!(ErrorCode::ParseError))?;
-//     Ok(builder.build())
-// }
-
-// #[error_code]
-// pub enum ErrorCode {
-//     #[msg("Failed to parse action string")]
-//     ParseError,
-// }
+use super::conditionParser::{parse_number, parse_token, ws};
+use super::context::ParseContext;
+use super::diagnostics::{expect, expect_one_of, kw, parse_complete, sym, Diagnostic};
+use super::lexer::{lex, word, Token};
+use super::template::or_parameter;
+use crate::logic::actions::{ActionBuilder, ActionTree, Amount};
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use nom::{
+    branch::alt,
+    combinator::map_opt,
+    multi::fold_many0,
+    sequence::{delimited, preceded},
+    IResult, Parser,
+};
+
+type MakeAction = fn(Pubkey, Amount) -> ActionBuilder;
+
+// "BUY", "SELL", ... in any case, resolved to the matching builder
+fn parse_action_keyword<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, MakeAction> {
+    let keywords = &["BUY", "SELL", "BORROW", "REPAY", "LEND", "REDEEM"];
+    expect_one_of(
+        ctx,
+        keywords,
+        map_opt(word, |keyword: &str| -> Option<MakeAction> {
+            match keyword.to_ascii_uppercase().as_str() {
+                "BUY" => Some(ActionBuilder::buy),
+                "SELL" => Some(ActionBuilder::sell),
+                "BORROW" => Some(ActionBuilder::borrow),
+                "REPAY" => Some(ActionBuilder::repay),
+                "LEND" => Some(ActionBuilder::lend),
+                "REDEEM" => Some(ActionBuilder::redeem),
+                _ => None,
+            }
+        }),
+    )
+    .parse(input)
+}
+
+// "100" raw units, or "50%" / "12.5%" / "0.25%" of the holding, at most 100%, or a
+// `$name` template placeholder
+pub fn parse_amount<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, Amount> {
+    or_parameter(ctx, parse_amount_literal).parse(input)
+}
+
+fn parse_amount_literal(input: &str) -> IResult<&str, Amount> {
+    let percent = map_opt(lex, |token| {
+        let Token::Percent(text) = token else {
+            return None;
+        };
+        let text = text.replace('_', "");
+        let (int, frac) = text.split_once('.').unwrap_or((&text, ""));
+        if frac.len() > 2 {
+            return None;
+        }
+        // in u32 and checked, so a percentage past 655.35% is rejected rather than overflowing
+        let bps = int
+            .parse::<u32>()
+            .ok()?
+            .checked_mul(100)?
+            .checked_add(format!("{:0<2}", frac).parse::<u32>().ok()?)?;
+        let bps = u16::try_from(bps).ok()?;
+        (bps <= Amount::MAX_PERCENT_BPS).then_some(Amount::Percent(bps))
+    });
+    alt((percent, parse_number.map(Amount::Units))).parse(input)
+}
+
+// "BUY(<token>, <amount>)"
+pub fn parse_atomic_action<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ActionBuilder> {
+    let (input, make) = parse_action_keyword(ctx, input)?;
+    let (input, _) = sym(ctx, '(').parse(input)?;
+    let (input, token) = ws(expect(ctx, "token", |i| parse_token(ctx, i))).parse(input)?;
+    let (input, _) = sym(ctx, ',').parse(input)?;
+    let (input, amount) = ws(expect(ctx, "amount", |i| parse_amount(ctx, i))).parse(input)?;
+    let (input, _) = sym(ctx, ')').parse(input)?;
+    Ok((input, make(token, amount)))
+}
+
+fn parse_action_term<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, ActionBuilder> {
+    if let Ok(result) = parse_atomic_action(ctx, input) {
+        return Ok(result);
+    }
+    delimited(
+        sym(ctx, '('),
+        |i| parse_action_sequence(ctx, i),
+        sym(ctx, ')'),
+    )
+    .parse(input)
+}
+
+// actions joined by THEN run left to right, stopping at the first one that fails
+pub fn parse_action_sequence<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ActionBuilder> {
+    let (input, first) = parse_action_term(ctx, input)?;
+    fold_many0(
+        preceded(kw(ctx, "THEN"), |i| parse_action_term(ctx, i)),
+        move || first.clone(),
+        |acc, next| acc.and(next),
+    )
+    .parse(input)
+}
+
+pub fn parse_action_string_with(
+    ctx: &ParseContext,
+    input: &str,
+) -> std::result::Result<ActionTree, Diagnostic> {
+    let builder = parse_complete(ctx, input, parse_action_sequence)?;
+    builder
+        .build()
+        .map_err(|e| Diagnostic::spanning(input, 0..input.len(), e.to_string()))
+}
+
+// tokens as raw pubkeys only
+pub fn parse_action_string(input: &str) -> std::result::Result<ActionTree, Diagnostic> {
+    parse_action_string_with(&ParseContext::new(), input)
+}
+
+pub fn translate_action_string(input: &str) -> Result<ActionTree> {
+    parse_action_string(input).map_err(|_| error!(ErrorCode::ParseError))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_action_string() {
+        let sol = Pubkey::new_unique();
+        let usdc = Pubkey::new_unique();
+        let input = format!("BUY({}, 100) THEN SELL({}, 50)", sol, usdc);
+
+        let tree = translate_action_string(&input).unwrap();
+        let expected = ActionBuilder::buy(sol, 100)
+            .and(ActionBuilder::sell(usdc, 50))
+            .build()
+            .unwrap();
+        assert_eq!(tree, expected);
+        assert_eq!(tree.to_string_expr(), input);
+        assert!(tree.execute());
+    }
+
+    #[test]
+    fn test_every_action_round_trips() {
+        let token = Pubkey::new_unique();
+        let input = format!(
+            "BUY({t}, 1) THEN SELL({t}, 2) THEN BORROW({t}, 3) THEN REPAY({t}, 4) THEN LEND({t}, 5) THEN REDEEM({t}, 6)",
+            t = token
+        );
+        let tree = translate_action_string(&input).unwrap();
+        assert_eq!(tree.nodes.len(), 11);
+        assert_eq!(tree.to_string_expr(), input);
+
+        // a sequence on the right keeps its parentheses, so the tree comes back unchanged
+        let nested = ActionBuilder::buy(token, 1)
+            .and(ActionBuilder::sell(token, 2).and(ActionBuilder::lend(token, 3)))
+            .build()
+            .unwrap();
+        let printed = nested.to_string_expr();
+        assert_eq!(
+            printed,
+            format!(
+                "BUY({t}, 1) THEN (SELL({t}, 2) THEN LEND({t}, 3))",
+                t = token
+            )
+        );
+        assert_eq!(translate_action_string(&printed).unwrap(), nested);
+    }
+
+    #[test]
+    fn test_percent_amounts() {
+        let token = Pubkey::new_unique();
+        for amount in ["50%", "12.5%", "0.25%", "100%", "0%"] {
+            let input = format!("SELL({}, {})", token, amount);
+            let tree = translate_action_string(&input).unwrap();
+            assert_eq!(tree.to_string_expr(), input);
+        }
+        let tree = translate_action_string(&format!("SELL({}, 12.5%)", token)).unwrap();
+        assert_eq!(
+            tree,
+            ActionBuilder::sell(token, Amount::Percent(1250))
+                .build()
+                .unwrap()
+        );
+        assert!(translate_action_string(&format!("SELL({}, 100.01%)", token)).is_err());
+        assert!(translate_action_string(&format!("SELL({}, 1.125%)", token)).is_err());
+        // past what fits in basis points
+        for amount in ["655.99%", "655.36%", "42949673%", "99999999999%"] {
+            let input = format!("SELL({}, {})", token, amount);
+            assert!(translate_action_string(&input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_invalid_action_strings() {
+        let token = Pubkey::new_unique();
+        assert_eq!(
+            translate_action_string(&format!("SWAP({}, 1)", token)).unwrap_err(),
+            error!(ErrorCode::ParseError)
+        );
+        assert!(translate_action_string(&format!("BUY({}, 1) THEN", token)).is_err());
+        assert!(translate_action_string(&format!("BUY({}, -1)", token)).is_err());
+        assert!(translate_action_string("BUY(not_a_key, 1)").is_err());
+    }
+}
@@ -1,13 +1,19 @@
-use crate::logic::conditions::{ConditionBuilder, ConditionTree};
+use super::context::ParseContext;
+use super::diagnostics::{expect, expect_one_of, kw, parse_complete, sym, Diagnostic};
+use super::lexer::{identifier, lex, trivia, word, Token};
+use super::template::or_parameter;
+use crate::logic::conditions::{CompareOp, ConditionBuilder, ConditionTree, NodeIndex};
+use crate::logic::kinds::{condition_kind_by_keyword, condition_kind_keywords};
+use crate::logic::price::Price;
+use crate::logic::value::ValueExpr;
+use crate::ErrorCode;
 use anchor_lang::prelude::*;
 use nom::{
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{alphanumeric1, char, digit1, multispace0},
-    combinator::map_res,
+    combinator::map_opt,
     error::ParseError,
-    multi::fold_many0,
-    sequence::{delimited, preceded, tuple},
+    multi::{fold_many0, separated_list1},
+    sequence::{delimited, preceded},
     IResult, Parser,
 };
 use std::str::FromStr;
@@ -17,136 +23,443 @@ use std::str::FromStr;
 * F is anything that implements Parser<&'a str>
 * The return type is some parser that takes a &'a str as input.
 * where F: Parser<&'a str> says that F is a parser that takes a &'a str as input.
-* delimited(multispace0, inner, multispace0) returns a new parser.
+* delimited(trivia, inner, trivia) returns a new parser, skipping whitespace and comments.
 */
-
 pub fn ws<'a, O, E: ParseError<&'a str>, F>(inner: F) -> impl Parser<&'a str, Output = O, Error = E>
 where
     F: Parser<&'a str, Output = O, Error = E>,
 {
-    delimited(multispace0, inner, multispace0)
+    delimited(trivia, inner, trivia)
 }
 
 // --- Parsing utilities ---
-// fn parse_pubkey(input: &str) -> IResult<&str, Pubkey> {
-//     map_res(nom::character::complete::alphanumeric1, Pubkey::from_str).parse(input)
-// }
-fn parse_pubkey(input: &str) -> IResult<&str, Pubkey> {
-    // This is a parser that matches 1 or more alphanumeric ASCII characters ([a-zA-Z0-9]).
-    let base_parser = alphanumeric1;
-    // apply map_res (https://docs.rs/nom/latest/nom/combinator/fn.map_res.html)
-    let mut pubkey_parser = map_res(base_parser, Pubkey::from_str);
-    pubkey_parser.parse(input)
-}
-
-// fn parse_number(input: &str) -> IResult<&str, u64> {
-//     map_res(digit1, |s: &str| s.parse::<u64>()).parse(input)
-// }
-
-fn parse_number(input: &str) -> IResult<&str, u64> {
-    // Step 1: Base parser that matches 1+ digits as a string slice
-    let base_parser = digit1;
-    // Step 2: Apply map_res to convert the string slice to a u64
-    let mut number_parser = map_res(base_parser, |s: &str| s.parse::<u64>());
-    // Step 3: Parse the input string
-    number_parser.parse(input)
+pub fn parse_pubkey(input: &str) -> IResult<&str, Pubkey> {
+    // base58 may be all digits, or look like a duration, so any of those tokens will do
+    map_opt(lex, |token| match token {
+        Token::Word(text) | Token::Number(text) | Token::Duration(text) => {
+            Pubkey::from_str(text).ok()
+        }
+        _ => None,
+    })
+    .parse(input)
+}
+
+// a raw pubkey, or a symbol from the context's token registry
+pub fn parse_token<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, Pubkey> {
+    alt((parse_pubkey, map_opt(word, |w| ctx.resolve_symbol(w)))).parse(input)
+}
+
+// a whole number, "100" or "1_000"
+pub fn parse_number(input: &str) -> IResult<&str, u64> {
+    map_opt(lex, |token| match token {
+        Token::Number(text) if !text.contains('.') => text.replace('_', "").parse().ok(),
+        _ => None,
+    })
+    .parse(input)
+}
+
+// decimal price literal, "100" or "100.25"; keeps the precision as written, so "100.50"
+// parses to 10050e-2 and prints back the same way. a `$name` template placeholder is
+// read from its value
+pub fn parse_price<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, Price> {
+    or_parameter(ctx, parse_price_literal).parse(input)
+}
+
+fn parse_price_literal(input: &str) -> IResult<&str, Price> {
+    map_opt(lex, |token| {
+        let Token::Number(text) = token else {
+            return None;
+        };
+        let (int, frac) = text.split_once('.').unwrap_or((text, ""));
+        let frac = frac.replace('_', "");
+        let mantissa = format!("{}{}", int.replace('_', ""), frac)
+            .parse::<u64>()
+            .ok()?;
+        Some(Price::new(mantissa, -(frac.len() as i32)))
+    })
+    .parse(input)
 }
 
 // --- Atomic Conditions ---
-pub fn parse_price_above(input: &str) -> IResult<&str, ConditionBuilder> {
-    let (input, _) = ws(tag("PRICE_ABOVE")).parse(input)?;
-    println!("input 1: {:?}", input);
-    let (input, _) = ws(char('(')).parse(input)?;
-    println!("input 2: {:?}", input);
-    let (input, token) = ws(parse_pubkey).parse(input)?;
-    println!("input 3: {:?}, token: {:?}", input, token);
-    let (input, _) = ws(char(',')).parse(input)?;
-    println!("input 4: {:?}", input);
-    let (input, price) = ws(parse_number).parse(input)?;
-    println!("input 5: {:?}, price: {:?}", input, price);
-    let (input, _) = ws(char(')')).parse(input)?;
-    println!("input 6: {:?}", input);
+pub fn parse_price_above<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ConditionBuilder> {
+    let (input, _) = kw(ctx, "PRICE_ABOVE").parse(input)?;
+    let (input, (token, price)) = parse_price_args(ctx, input)?;
     Ok((input, ConditionBuilder::price_above(token, price)))
 }
 
-pub fn parse_price_below(input: &str) -> IResult<&str, ConditionBuilder> {
-    let (input, _) = ws(tag("PRICE_BELOW")).parse(input)?;
-    println!("input 1: {:?}", input);
-    let (input, _) = ws(char('(')).parse(input)?;
-    println!("input 2: {:?}", input);
-    let (input, token) = ws(parse_pubkey).parse(input)?;
-    println!("input 3: {:?}, token: {:?}", input, token);
-    let (input, _) = ws(char(',')).parse(input)?;
-    println!("input 4: {:?}", input);
-    let (input, price) = ws(parse_number).parse(input)?;
-    println!("input 5: {:?}, price: {:?}", input, price);
-    let (input, _) = ws(char(')')).parse(input)?;
-    println!("input 6: {:?}", input);
-    // todo!()
+pub fn parse_price_below<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ConditionBuilder> {
+    let (input, _) = kw(ctx, "PRICE_BELOW").parse(input)?;
+    let (input, (token, price)) = parse_price_args(ctx, input)?;
     Ok((input, ConditionBuilder::price_below(token, price)))
 }
 
-pub fn parse_atomic_condition(input: &str) -> IResult<&str, ConditionBuilder> {
-    alt((parse_price_above, parse_price_below)).parse(input)
+// "(<pubkey>, <price>)"
+fn parse_price_args<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, (Pubkey, Price)> {
+    let (input, _) = sym(ctx, '(').parse(input)?;
+    let (input, token) = ws(expect(ctx, "token", |i| parse_token(ctx, i))).parse(input)?;
+    let (input, _) = sym(ctx, ',').parse(input)?;
+    let (input, price) = ws(expect(ctx, "price", |i| parse_price(ctx, i))).parse(input)?;
+    let (input, _) = sym(ctx, ')').parse(input)?;
+    Ok((input, (token, price)))
+}
+
+pub fn parse_price_at_or_above<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ConditionBuilder> {
+    let (input, _) = kw(ctx, "PRICE_AT_OR_ABOVE").parse(input)?;
+    let (input, (token, price)) = parse_price_args(ctx, input)?;
+    Ok((input, ConditionBuilder::price_at_or_above(token, price)))
+}
+
+pub fn parse_price_at_or_below<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ConditionBuilder> {
+    let (input, _) = kw(ctx, "PRICE_AT_OR_BELOW").parse(input)?;
+    let (input, (token, price)) = parse_price_args(ctx, input)?;
+    Ok((input, ConditionBuilder::price_at_or_below(token, price)))
+}
+
+fn parse_constant<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, ConditionBuilder> {
+    alt((
+        kw(ctx, "TRUE").map(|_| ConditionBuilder::constant(true)),
+        kw(ctx, "FALSE").map(|_| ConditionBuilder::constant(false)),
+    ))
+    .parse(input)
+}
+
+// a keyword registered through `logic::kinds`, followed by whatever that kind parses
+fn parse_custom_condition<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ConditionBuilder> {
+    let (rest, keyword) = word(input)?;
+    let Some(kind) = condition_kind_by_keyword(&keyword.to_ascii_uppercase()) else {
+        ctx.record(input, &condition_kind_keywords());
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Tag,
+        )));
+    };
+    let (rest, data) = ws(|i| kind.parse(i)).parse(rest)?;
+    Ok((rest, ConditionBuilder::custom(kind.id(), data)))
+}
+
+// --- Value expressions: price(A) - price(B), min(balance(A), 10) / 2, ... ---
+// `name(`, with `name` in any case
+fn function<'a, 'c>(
+    ctx: &'c ParseContext,
+    name: &'static str,
+) -> impl Parser<&'a str, Output = char, Error = nom::error::Error<&'a str>> + use<'a, 'c> {
+    preceded(kw(ctx, name), sym(ctx, '('))
+}
+
+// "price(<token>)"
+fn parse_price_of<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, Pubkey> {
+    delimited(
+        function(ctx, "PRICE"),
+        ws(expect(ctx, "token", |i| parse_token(ctx, i))),
+        sym(ctx, ')'),
+    )
+    .parse(input)
+}
+
+fn parse_balance_of<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, Pubkey> {
+    delimited(
+        function(ctx, "BALANCE"),
+        ws(expect(ctx, "token", |i| parse_token(ctx, i))),
+        sym(ctx, ')'),
+    )
+    .parse(input)
+}
+
+// "(<value>, <value>)" after min/max
+fn parse_value_pair<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, (ValueExpr, ValueExpr)> {
+    let (input, left) = parse_value_expr(ctx, input)?;
+    let (input, _) = sym(ctx, ',').parse(input)?;
+    let (input, right) = parse_value_expr(ctx, input)?;
+    let (input, _) = sym(ctx, ')').parse(input)?;
+    Ok((input, (left, right)))
+}
+
+fn parse_value_factor<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, ValueExpr> {
+    let value = |i| parse_value_expr(ctx, i);
+    alt((
+        preceded(sym(ctx, '-'), |i| parse_value_factor(ctx, i)).map(ValueExpr::neg),
+        (|i| parse_price_of(ctx, i)).map(ValueExpr::price),
+        (|i| parse_balance_of(ctx, i)).map(ValueExpr::balance),
+        preceded(function(ctx, "MIN"), |i| parse_value_pair(ctx, i)).map(|(l, r)| l.min(r)),
+        preceded(function(ctx, "MAX"), |i| parse_value_pair(ctx, i)).map(|(l, r)| l.max(r)),
+        delimited(function(ctx, "ABS"), value, sym(ctx, ')')).map(ValueExpr::abs),
+        ws(expect(ctx, "number", |i| parse_price(ctx, i))).map(ValueExpr::constant),
+        delimited(sym(ctx, '('), value, sym(ctx, ')')),
+    ))
+    .parse(input)
+}
+
+// * and /, left-associative
+fn parse_value_term<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, ValueExpr> {
+    let (input, init) = parse_value_factor(ctx, input)?;
+    fold_many0(
+        (alt((sym(ctx, '*'), sym(ctx, '/'))), |i| {
+            parse_value_factor(ctx, i)
+        }),
+        move || init.clone(),
+        |acc, (op, next)| match op {
+            '*' => acc.mul(next),
+            _ => acc.div(next),
+        },
+    )
+    .parse(input)
+}
+
+// + and -, left-associative
+pub fn parse_value_expr<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, ValueExpr> {
+    let (input, init) = parse_value_term(ctx, input)?;
+    fold_many0(
+        (alt((sym(ctx, '+'), sym(ctx, '-'))), |i| {
+            parse_value_term(ctx, i)
+        }),
+        move || init.clone(),
+        |acc, (op, next)| match op {
+            '+' => acc.add(next),
+            _ => acc.sub(next),
+        },
+    )
+    .parse(input)
+}
+
+// --- Infix comparisons: price(SOL) > 100, 90 < price(SOL) < 110, price(A) / price(B) < 0.05 ---
+fn parse_comparison_op<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, CompareOp> {
+    expect_one_of(
+        ctx,
+        &[">=", "<=", ">", "<"],
+        map_opt(lex, |token| match token {
+            Token::Punct(">=") => Some(CompareOp::AtOrAbove),
+            Token::Punct("<=") => Some(CompareOp::AtOrBelow),
+            Token::Punct(">") => Some(CompareOp::Above),
+            Token::Punct("<") => Some(CompareOp::Below),
+            _ => None,
+        }),
+    )
+    .parse(input)
+}
+
+// `a op b`, or the chain `a op b op c` as `a op b AND b op c`
+fn parse_comparison<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, ConditionBuilder> {
+    let (input, first) = parse_value_expr(ctx, input)?;
+    let (input, op) = parse_comparison_op(ctx, input)?;
+    let (input, second) = parse_value_expr(ctx, input)?;
+    match (
+        |i| parse_comparison_op(ctx, i),
+        |i| parse_value_expr(ctx, i),
+    )
+        .parse(input)
+    {
+        Ok((input, (op2, third))) => Ok((
+            input,
+            ConditionBuilder::compare(first, op, second.clone())
+                .and(ConditionBuilder::compare(second, op2, third)),
+        )),
+        Err(nom::Err::Error(_)) => Ok((input, ConditionBuilder::compare(first, op, second))),
+        Err(e) => Err(e),
+    }
+}
+
+// a name bound earlier with `LET name = ...;`
+fn parse_binding<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, ConditionBuilder> {
+    map_opt(identifier, |name| ctx.binding(name)).parse(input)
+}
+
+pub fn parse_atomic_condition<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ConditionBuilder> {
+    alt((
+        |i| parse_price_above(ctx, i),
+        |i| parse_price_below(ctx, i),
+        |i| parse_price_at_or_above(ctx, i),
+        |i| parse_price_at_or_below(ctx, i),
+        |i| parse_comparison(ctx, i),
+        |i| parse_constant(ctx, i),
+        |i| parse_binding(ctx, i),
+        |i| parse_custom_condition(ctx, i),
+    ))
+    .parse(input)
 }
 
 // --- Parentheses and NOT ---
-fn parse_parenthesized_condition(input: &str) -> IResult<&str, ConditionBuilder> {
-    delimited(ws(char('(')), parse_condition_expr, ws(char(')'))).parse(input)
+fn parse_parenthesized_condition<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ConditionBuilder> {
+    delimited(
+        sym(ctx, '('),
+        |i| parse_condition_expr(ctx, i),
+        sym(ctx, ')'),
+    )
+    .parse(input)
 }
 
-fn parse_not(input: &str) -> IResult<&str, ConditionBuilder> {
-    let (input, _) = ws(tag("NOT")).parse(input)?;
-    let (input, inner) = parse_condition_term(input)?;
+fn parse_not<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, ConditionBuilder> {
+    let (input, _) = alt((kw(ctx, "NOT"), kw(ctx, "!"))).parse(input)?;
+    let (input, inner) = parse_condition_term(ctx, input)?;
     Ok((input, inner.not()))
 }
 
-// --- Term: Not, Atomic, Parentheses ---
-fn parse_condition_term(input: &str) -> IResult<&str, ConditionBuilder> {
+// --- N-ary: ALL(a, b, ...), ANY(a, b, ...), AT_LEAST k OF (a, b, ...) ---
+fn parse_condition_list<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, Vec<ConditionBuilder>> {
+    delimited(
+        sym(ctx, '('),
+        separated_list1(sym(ctx, ','), |i| parse_condition_expr(ctx, i)),
+        sym(ctx, ')'),
+    )
+    .parse(input)
+}
+
+fn parse_all<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, ConditionBuilder> {
+    let (input, _) = kw(ctx, "ALL").parse(input)?;
+    let (input, children) = parse_condition_list(ctx, input)?;
+    Ok((input, ConditionBuilder::all(children)))
+}
+
+fn parse_any<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, ConditionBuilder> {
+    let (input, _) = kw(ctx, "ANY").parse(input)?;
+    let (input, children) = parse_condition_list(ctx, input)?;
+    Ok((input, ConditionBuilder::any(children)))
+}
+
+fn parse_at_least<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, ConditionBuilder> {
+    let (input, _) = kw(ctx, "AT_LEAST").parse(input)?;
+    let (input, k) = expect(
+        ctx,
+        "number",
+        map_opt(parse_number, |n| NodeIndex::try_from(n).ok()),
+    )
+    .parse(input)?;
+    let (input, _) = kw(ctx, "OF").parse(input)?;
+    let (input, children) = parse_condition_list(ctx, input)?;
+    Ok((input, ConditionBuilder::at_least(k, children)))
+}
+
+// --- Term: Not, N-ary, Atomic, Parentheses ---
+fn parse_condition_term<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ConditionBuilder> {
     alt((
-        parse_not,
-        parse_atomic_condition,
-        parse_parenthesized_condition,
+        |i| parse_not(ctx, i),
+        |i| parse_at_least(ctx, i),
+        |i| parse_all(ctx, i),
+        |i| parse_any(ctx, i),
+        |i| parse_atomic_condition(ctx, i),
+        |i| parse_parenthesized_condition(ctx, i),
     ))
     .parse(input)
 }
 
 // --- AND precedence level ---
-fn parse_condition_and(input: &str) -> IResult<&str, ConditionBuilder> {
-    let (input, init) = parse_condition_term(input)?;
+fn parse_condition_and<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ConditionBuilder> {
+    let (input, init) = parse_condition_term(ctx, input)?;
     fold_many0(
-        preceded(ws(tag("AND")), parse_condition_term),
+        preceded(alt((kw(ctx, "AND"), kw(ctx, "&&"))), |i| {
+            parse_condition_term(ctx, i)
+        }),
         move || init.clone(),
         |acc, next| acc.and(next),
     )
     .parse(input)
 }
 
+// --- XOR precedence level ---
+fn parse_condition_xor<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ConditionBuilder> {
+    let (input, init) = parse_condition_and(ctx, input)?;
+    fold_many0(
+        preceded(kw(ctx, "XOR"), |i| parse_condition_and(ctx, i)),
+        move || init.clone(),
+        |acc, next| acc.xor(next),
+    )
+    .parse(input)
+}
+
 // --- OR precedence level ---
-fn parse_condition_expr(input: &str) -> IResult<&str, ConditionBuilder> {
-    let (input, init) = parse_condition_and(input)?;
+fn parse_condition_or<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ConditionBuilder> {
+    let (input, init) = parse_condition_xor(ctx, input)?;
     fold_many0(
-        preceded(ws(tag("OR")), parse_condition_and),
+        preceded(alt((kw(ctx, "OR"), kw(ctx, "||"))), |i| {
+            parse_condition_xor(ctx, i)
+        }),
         move || init.clone(),
         |acc, next| acc.or(next),
     )
     .parse(input)
 }
 
-// --- Final wrapper ---
+// --- IMPLIES precedence level (lowest, right-associative) ---
+pub fn parse_condition_expr<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, ConditionBuilder> {
+    let (input, premise) = parse_condition_or(ctx, input)?;
+    match preceded(kw(ctx, "IMPLIES"), |i| parse_condition_expr(ctx, i)).parse(input) {
+        Ok((input, conclusion)) => Ok((input, premise.implies(conclusion))),
+        Err(nom::Err::Error(_)) => Ok((input, premise)),
+        Err(e) => Err(e),
+    }
+}
+
+// --- Final wrappers ---
+// the whole of `input` as a condition, or an explanation of where and why it isn't one
+pub fn parse_condition_string_with(
+    ctx: &ParseContext,
+    input: &str,
+) -> std::result::Result<ConditionTree, Diagnostic> {
+    let builder = parse_complete(ctx, input, parse_condition_expr)?;
+    builder
+        .build()
+        .map_err(|e| Diagnostic::spanning(input, 0..input.len(), e.to_string()))
+}
+
+// tokens as raw pubkeys only
+pub fn parse_condition_string(input: &str) -> std::result::Result<ConditionTree, Diagnostic> {
+    parse_condition_string_with(&ParseContext::new(), input)
+}
+
 pub fn translate_condition_string(input: &str) -> Result<ConditionTree> {
-    let (_, builder) = parse_condition_expr(input).map_err(|_| error!(ErrorCode::ParseError))?;
-    Ok(builder.build())
+    parse_condition_string(input).map_err(|_| error!(ErrorCode::ParseError))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::logic::conditions::ExprStyle;
     #[test]
     fn test_parse_price_above() {
         let token = Pubkey::new_unique();
         let input = format!("PRICE_ABOVE({}, 300)", token);
-        let result = parse_price_above(&input);
+        let result = parse_price_above(&ParseContext::new(), &input);
         println!("result: {:?}", result);
     }
 
@@ -154,7 +467,7 @@ mod tests {
     fn test_parse_price_below() {
         let token = Pubkey::new_unique();
         let input = format!("PRICE_BELOW({}, 400)", token);
-        let result = parse_price_below(&input);
+        let result = parse_price_below(&ParseContext::new(), &input);
         println!("result: {:?}", result);
     }
 
@@ -165,7 +478,7 @@ mod tests {
             "PRICE_ABOVE({}, 300) AND PRICE_BELOW({}, 400)",
             token, token
         );
-        let result = parse_condition_expr(&input);
+        let result = parse_condition_expr(&ParseContext::new(), &input);
         println!(
             "\n
         result: {:?}",
@@ -173,6 +486,152 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_translate_n_ary_operators() {
+        let sol = Pubkey::new_unique();
+        let eth = Pubkey::new_unique();
+        let btc = Pubkey::new_unique();
+        let input = format!(
+            "AT_LEAST 2 OF (PRICE_BELOW({}, 100), PRICE_BELOW({}, 2000), PRICE_BELOW({}, 50000)) IMPLIES ALL(PRICE_ABOVE({}, 10), PRICE_ABOVE({}, 10)) XOR ANY(PRICE_ABOVE({}, 10))",
+            sol, eth, btc, sol, eth, btc
+        );
+        let tree = translate_condition_string(&input).unwrap();
+        assert_eq!(
+            tree.to_string_expr(),
+            format!(
+                "AT_LEAST 2 OF (PRICE_BELOW({}, 100), PRICE_BELOW({}, 2000), PRICE_BELOW({}, 50000)) IMPLIES ALL(PRICE_ABOVE({}, 10), PRICE_ABOVE({}, 10)) XOR ANY(PRICE_ABOVE({}, 10))",
+                sol, eth, btc, sol, eth, btc
+            )
+        );
+
+        // 2 of 3 dumping: SOL and ETH below threshold, BTC above
+        let ctx = crate::logic::conditions::EvaluationContext {
+            token_prices: std::collections::HashMap::from([
+                (sol, Price::from(90)),
+                (eth, Price::from(1500)),
+                (btc, Price::from(60000)),
+            ]),
+            ..Default::default()
+        };
+        let two_of_three = translate_condition_string(&format!(
+            "AT_LEAST 2 OF (PRICE_BELOW({}, 100), PRICE_BELOW({}, 2000), PRICE_BELOW({}, 50000))",
+            sol, eth, btc
+        ))
+        .unwrap();
+        assert!(two_of_three.evaluate(&ctx));
+    }
+
+    #[test]
+    fn test_translate_decimal_prices() {
+        let token = Pubkey::new_unique();
+        let input = format!(
+            "PRICE_ABOVE({}, 100.50) AND PRICE_AT_OR_BELOW({}, 0.0025)",
+            token, token
+        );
+        let tree = translate_condition_string(&input).unwrap();
+        assert_eq!(tree.to_string_expr(), input);
+
+        let ctx = ParseContext::new();
+        let (_, price) = parse_price(&ctx, "100.50").unwrap();
+        assert_eq!(price, Price::new(1005, -1));
+        assert_eq!(price.expo, -2);
+        assert!(parse_price(&ctx, "99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn test_translate_symbolic_and_infix_forms() {
+        let sol = Pubkey::new_unique();
+        let eth = Pubkey::new_unique();
+        let keywords = translate_condition_string(&format!(
+            "NOT(PRICE_ABOVE({sol}, 300)) OR (PRICE_AT_OR_ABOVE({eth}, 80) AND PRICE_AT_OR_BELOW({eth}, 90))"
+        ))
+        .unwrap();
+        for input in [
+            format!("!(price({sol}) > 300) || (price({eth}) >= 80 && price({eth}) <= 90)"),
+            format!("not 300 < price({sol}) or 80 <= price({eth}) <= 90"),
+            format!("Not(Price_Above({sol}, 300)) Or (80 <= PRICE({eth}) And 90 >= price({eth}))"),
+        ] {
+            let tree = translate_condition_string(&input).unwrap();
+            assert_eq!(
+                tree.to_string_expr(),
+                keywords.to_string_expr(),
+                "{}",
+                input
+            );
+        }
+
+        let symbols = keywords.to_string_expr_styled(ExprStyle::Symbols);
+        assert_eq!(
+            symbols,
+            format!("!(price({sol}) > 300) || price({eth}) >= 80 && price({eth}) <= 90")
+        );
+        assert_eq!(translate_condition_string(&symbols).unwrap(), keywords);
+
+        for bad in [
+            format!("price({sol}) = 300"),
+            format!("price({sol}) >"),
+            format!("90 < price({sol}) < "),
+        ] {
+            assert!(translate_condition_string(&bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_translate_value_expressions() {
+        let eth = Pubkey::new_unique();
+        let btc = Pubkey::new_unique();
+        let input = format!(
+            "(price({eth}) - price({btc})) / price({btc}) > 0.02 OR price({eth})/price({btc}) < 0.05"
+        );
+        let tree = translate_condition_string(&input).unwrap();
+        tree.validate().unwrap();
+        assert_eq!(
+            tree.to_string_expr(),
+            format!(
+                "(price({eth}) - price({btc})) / price({btc}) > 0.02 OR price({eth}) / price({btc}) < 0.05"
+            )
+        );
+        assert_eq!(
+            translate_condition_string(&tree.to_string_expr()).unwrap(),
+            tree
+        );
+
+        let prices = |e: u64, b: u64| crate::logic::conditions::EvaluationContext {
+            token_prices: std::collections::HashMap::from([
+                (eth, Price::from(e)),
+                (btc, Price::from(b)),
+            ]),
+            ..Default::default()
+        };
+        assert!(tree.evaluate(&prices(4_000, 100_000)));
+        assert!(!tree.evaluate(&prices(6_000, 100_000)));
+        // a zero price divides by zero, which is never true
+        assert!(!tree.evaluate(&prices(6_000, 0)));
+
+        let input = format!(
+            "NOT(abs(-balance({eth}) + 1) >= max(2, min(balance({btc}), 3)) * 1.5) AND price({eth}) > 10"
+        );
+        let tree = translate_condition_string(&input).unwrap();
+        assert_eq!(
+            tree.to_string_expr(),
+            format!(
+                "NOT(abs(-balance({eth}) + 1) >= max(2, min(balance({btc}), 3)) * 1.5) AND PRICE_ABOVE({eth}, 10)"
+            )
+        );
+        assert_eq!(
+            translate_condition_string(&tree.to_string_expr()).unwrap(),
+            tree
+        );
+
+        for bad in [
+            format!("price({eth}) + > 1"),
+            format!("min(price({eth})) > 1"),
+            format!("(price({eth}) > 1"),
+        ] {
+            assert!(translate_condition_string(&bad).is_err(), "{}", bad);
+        }
+    }
+
     #[test]
     fn test_translate_condition_string_1() {
         let token = Pubkey::new_unique();
@@ -184,9 +643,6 @@ mod tests {
         println!("result: {:?}", result);
 
         let expr = result.unwrap().to_string_expr();
-        expr.strip_prefix('(')
-            .and_then(|s| s.strip_suffix(')'))
-            .unwrap_or(&expr);
 
         println!("expr: {}", expr);
         assert_eq!(expr, input);
@@ -200,9 +656,6 @@ mod tests {
         println!("result: {:?}", result);
 
         let expr = result.unwrap().to_string_expr();
-        expr.strip_prefix('(')
-            .and_then(|s| s.strip_suffix(')'))
-            .unwrap_or(&expr);
 
         println!("expr: {}", expr);
         assert_eq!(expr, input);
@@ -219,19 +672,10 @@ mod tests {
         println!("result: {:?}", result);
 
         let expr = result.unwrap().to_string_expr();
-        expr.strip_prefix('(')
-            .and_then(|s| s.strip_suffix(')'))
-            .unwrap_or(&expr);
 
         println!("expr: {}", expr);
         assert_eq!(expr, input);
     }
 }
 
-#[error_code]
-pub enum ErrorCode {
-    #[msg("Failed to parse condition string")]
-    ParseError,
-}
-
 // You can add tests here using #[cfg(test)] mod tests {}
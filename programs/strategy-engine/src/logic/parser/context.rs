@@ -0,0 +1,150 @@
+use super::lexer::skip_trivia;
+use crate::logic::conditions::ConditionBuilder;
+use crate::logic::registry::TokenRegistry;
+use anchor_lang::prelude::Pubkey;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+// Everything a parse depends on besides the input: the registry symbols resolve through and
+// the values of `$name` placeholders, plus what it collects along the way, the `LET` bindings
+// seen so far and what was expected at the furthest point any branch reached. Parsers take it
+// as their first argument; make one per parse.
+#[derive(Default)]
+pub struct ParseContext {
+    registry: TokenRegistry,
+    parameters: HashMap<String, String>,
+    bindings: RefCell<HashMap<String, ConditionBuilder>>,
+    // (bytes of input left at that point, labels)
+    furthest: RefCell<Option<(usize, Vec<&'static str>)>>,
+}
+
+impl ParseContext {
+    // raw pubkeys only; no symbols
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_registry(registry: &TokenRegistry) -> Self {
+        Self {
+            registry: registry.clone(),
+            ..Self::default()
+        }
+    }
+
+    // `$name` placeholders read their values from `parameters`
+    pub fn with_parameters(mut self, parameters: HashMap<String, String>) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    pub fn registry(&self) -> &TokenRegistry {
+        &self.registry
+    }
+
+    pub fn resolve_symbol(&self, symbol: &str) -> Option<Pubkey> {
+        self.registry.by_symbol(symbol).map(|t| t.mint)
+    }
+
+    // e.g. to suggest one for a mistyped symbol
+    pub fn symbols(&self) -> Vec<String> {
+        self.registry
+            .tokens
+            .iter()
+            .map(|t| t.symbol.clone())
+            .collect()
+    }
+
+    pub(crate) fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters.get(name).map(String::as_str)
+    }
+
+    pub(crate) fn clear_bindings(&self) {
+        self.bindings.borrow_mut().clear();
+    }
+
+    // false if `name` is already bound
+    pub(crate) fn bind(&self, name: &str, condition: ConditionBuilder) -> bool {
+        let mut bindings = self.bindings.borrow_mut();
+        if bindings.contains_key(name) {
+            return false;
+        }
+        bindings.insert(name.to_string(), condition);
+        true
+    }
+
+    pub(crate) fn binding(&self, name: &str) -> Option<ConditionBuilder> {
+        self.bindings.borrow().get(name).cloned()
+    }
+
+    // notes `labels` as expected where `input` starts, if no branch got further
+    pub(crate) fn record(&self, input: &str, labels: &[&'static str]) {
+        let left = skip_trivia(input).len();
+        let mut furthest = self.furthest.borrow_mut();
+        match &mut *furthest {
+            Some((at, expected)) if *at == left => {
+                for label in labels {
+                    if !expected.contains(label) {
+                        expected.push(label);
+                    }
+                }
+            }
+            Some((at, _)) if *at < left => {}
+            _ => *furthest = Some((left, labels.to_vec())),
+        }
+    }
+
+    pub(crate) fn take_furthest(&self) -> Option<(usize, Vec<&'static str>)> {
+        self.furthest.borrow_mut().take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::parser::strategyParser::parse_strategy_string_with;
+    use crate::logic::registry::TokenInfo;
+
+    fn registry(symbol: &str, mint: Pubkey) -> TokenRegistry {
+        TokenRegistry::new(vec![TokenInfo {
+            symbol: symbol.to_string(),
+            mint,
+            decimals: 9,
+            oracle: Pubkey::new_unique(),
+        }])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_contexts_are_independent() {
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (first, second) = (registry("X", a), registry("X", b));
+        let source = "LET low = PRICE_BELOW(X, 1); WHEN low THEN BUY(X, 1) EVERY 1m";
+
+        // the same source on other threads, each with its own registry
+        let [on_first, on_second] = std::thread::scope(|s| {
+            [&first, &second]
+                .map(|registry| {
+                    s.spawn(|| {
+                        parse_strategy_string_with(&ParseContext::with_registry(registry), source)
+                            .unwrap()
+                    })
+                })
+                .map(|handle| handle.join().unwrap())
+        });
+        assert_eq!(on_first.action.nodes.len(), 1);
+        assert_eq!(
+            on_first.to_string_expr_with(&first),
+            on_second.to_string_expr_with(&second)
+        );
+        assert_ne!(on_first, on_second);
+
+        // bindings and placeholders stay with the context that saw them
+        let ctx = ParseContext::with_registry(&first);
+        parse_strategy_string_with(&ctx, source).unwrap();
+        assert!(ctx.binding("low").is_none());
+        assert!(ParseContext::new().resolve_symbol("X").is_none());
+        let ctx = ctx.with_parameters(HashMap::from([("n".to_string(), "5".to_string())]));
+        assert_eq!(ctx.parameter("n"), Some("5"));
+        assert_eq!(ParseContext::with_registry(&first).parameter("n"), None);
+    }
+}
@@ -0,0 +1,344 @@
+use super::context::ParseContext;
+use super::lexer::{lex, skip_trivia, trivia};
+use nom::{
+    combinator::{all_consuming, verify},
+    sequence::terminated,
+    Parser,
+};
+use std::ops::Range;
+
+type Error<'a> = nom::error::Error<&'a str>;
+
+// A parse failure explained for the person who wrote the input: where it happened, what
+// the parser would have accepted there, and what it found instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    // byte range of the offending input
+    pub span: Range<usize>,
+    // 1-based, columns count characters
+    pub line: u32,
+    pub column: u32,
+    pub expected: Vec<String>,
+    // the offending token, or "end of input"
+    pub found: String,
+    pub suggestion: Option<String>,
+}
+
+impl Diagnostic {
+    // failure at byte `offset` of `source`, where one of `expected` should have been.
+    // `symbols` are the registry symbols to suggest where a token was expected
+    pub fn expected(
+        source: &str,
+        offset: usize,
+        expected: Vec<String>,
+        symbols: &[String],
+    ) -> Self {
+        let rest = &source[offset..];
+        // the whole token there, or a single character the lexer doesn't know
+        let len = match lex(rest) {
+            Ok((after, _)) => rest.len() - after.len(),
+            Err(_) => rest.chars().next().map_or(0, char::len_utf8),
+        };
+        let found = match len {
+            0 => "end of input".to_string(),
+            _ => rest[..len].to_string(),
+        };
+        let listed = expected.iter().map(|e| quote_label(e)).collect::<Vec<_>>();
+        let message = match listed.len() {
+            1 => format!("expected {}", listed[0]),
+            _ => format!("expected one of {}", listed.join(", ")),
+        };
+        let shown = if len == 0 {
+            found.clone()
+        } else {
+            format!("`{}`", found)
+        };
+        let suggestion = suggest(&found, &expected, symbols);
+        let mut diagnostic = Self::spanning(source, offset..offset + len, message);
+        diagnostic.message += &format!(", found {}", shown);
+        diagnostic.expected = expected;
+        diagnostic.found = found;
+        diagnostic.suggestion = suggestion;
+        diagnostic
+    }
+
+    // any other problem with the input in `span`
+    pub fn spanning(source: &str, span: Range<usize>, message: impl Into<String>) -> Self {
+        let before = &source[..span.start];
+        let line = before.matches('\n').count() as u32 + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let column = source[line_start..span.start].chars().count() as u32 + 1;
+        Self {
+            message: message.into(),
+            found: source[span.clone()].to_string(),
+            span,
+            line,
+            column,
+            expected: vec![],
+            suggestion: None,
+        }
+    }
+
+    // the message followed by the offending line with a caret under the span:
+    //
+    //   error: expected one of `AND`, `OR`, found `ADN`
+    //     --> 1:21
+    //     |
+    //   1 | PRICE_ABOVE(SOL, 1) ADN PRICE_BELOW(SOL, 2)
+    //     |                     ^^^ did you mean `AND`?
+    pub fn render(&self, source: &str) -> String {
+        let line_start = source[..self.span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[line_start..]
+            .find('\n')
+            .map_or(source.len(), |i| line_start + i);
+        let text = &source[line_start..line_end];
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        let end = self.span.end.min(line_end);
+        let width = source[self.span.start..end].chars().count().max(1);
+        let mut out = format!(
+            "error: {}\n{} --> {}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self.message,
+            gutter,
+            self.line,
+            self.column,
+            gutter,
+            number,
+            text,
+            gutter,
+            " ".repeat(self.column as usize - 1),
+            "^".repeat(width)
+        );
+        if let Some(suggestion) = &self.suggestion {
+            out += &format!(" did you mean `{}`?", suggestion);
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (did you mean `{}`?)", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+// keywords and punctuation are quoted, descriptions like "token" or "price" are not
+fn quote_label(label: &str) -> String {
+    if label.chars().all(|c| c.is_ascii_lowercase() || c == ' ') {
+        label.to_string()
+    } else {
+        format!("`{}`", label)
+    }
+}
+
+// closest expected keyword (or registry symbol, where a token was expected) to a misspelled word
+fn suggest(found: &str, expected: &[String], symbols: &[String]) -> Option<String> {
+    let mut candidates: Vec<String> = expected
+        .iter()
+        .filter(|e| e.chars().all(|c| c.is_ascii_uppercase() || c == '_'))
+        .cloned()
+        .collect();
+    if expected.iter().any(|e| e == "token") {
+        candidates.extend_from_slice(symbols);
+    }
+    let word = found.to_ascii_uppercase();
+    candidates
+        .into_iter()
+        .filter(|c| c != found)
+        .map(|c| (edit_distance(&word, &c), c))
+        .filter(|(d, c)| *d <= (c.len() / 3).max(1))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+// edits (insert, delete, substitute, swap two neighbours) to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitute = d[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = substitute.min(d[i - 1][j] + 1).min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+// `inner`, noting `labels` as expected here when it fails
+pub fn expect_one_of<'a, 'c, O, F>(
+    ctx: &'c ParseContext,
+    labels: &'static [&'static str],
+    mut inner: F,
+) -> impl Parser<&'a str, Output = O, Error = Error<'a>> + use<'a, 'c, O, F>
+where
+    F: Parser<&'a str, Output = O, Error = Error<'a>>,
+{
+    move |input: &'a str| {
+        let result = inner.parse(input);
+        if let Err(nom::Err::Error(_)) = result {
+            ctx.record(input, labels);
+        }
+        result
+    }
+}
+
+pub fn expect<'a, 'c, O, F>(
+    ctx: &'c ParseContext,
+    label: &'static str,
+    mut inner: F,
+) -> impl Parser<&'a str, Output = O, Error = Error<'a>> + use<'a, 'c, O, F>
+where
+    F: Parser<&'a str, Output = O, Error = Error<'a>>,
+{
+    move |input: &'a str| {
+        let result = inner.parse(input);
+        if let Err(nom::Err::Error(_)) = result {
+            ctx.record(input, &[label]);
+        }
+        result
+    }
+}
+
+// a keyword in any case, or a punctuation token like `&&`
+pub fn kw<'a, 'c>(
+    ctx: &'c ParseContext,
+    keyword: &'static str,
+) -> impl Parser<&'a str, Output = &'a str, Error = Error<'a>> + use<'a, 'c> {
+    let token = verify(lex, move |token| token.is(keyword));
+    expect(ctx, keyword, token.map(move |_| keyword))
+}
+
+// a single punctuation token
+pub fn sym<'a, 'c>(
+    ctx: &'c ParseContext,
+    c: char,
+) -> impl Parser<&'a str, Output = char, Error = Error<'a>> + use<'a, 'c> {
+    let label = match c {
+        '(' => "(",
+        ')' => ")",
+        ',' => ",",
+        '=' => "=",
+        ';' => ";",
+        '+' => "+",
+        '-' => "-",
+        '*' => "*",
+        '/' => "/",
+        _ => "symbol",
+    };
+    expect(
+        ctx,
+        label,
+        verify(lex, move |token| token.is(label)).map(move |_| c),
+    )
+}
+
+// runs `parser` over all of `source` (surrounding whitespace and comments allowed), explaining a failure
+pub fn parse_complete<'a, O, P>(
+    ctx: &ParseContext,
+    source: &'a str,
+    mut parser: P,
+) -> Result<O, Diagnostic>
+where
+    P: FnMut(&ParseContext, &'a str) -> nom::IResult<&'a str, O>,
+{
+    ctx.take_furthest();
+    let result = all_consuming(terminated(|i| parser(ctx, i), trivia)).parse(source);
+    let furthest = ctx.take_furthest();
+    let stopped = match result {
+        Ok((_, output)) => return Ok(output),
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => skip_trivia(e.input).len(),
+        Err(nom::Err::Incomplete(_)) => 0,
+    };
+    let (left, expected) = match furthest {
+        Some((left, labels)) if left <= stopped => {
+            (left, labels.into_iter().map(String::from).collect())
+        }
+        _ => (stopped, vec!["end of input".to_string()]),
+    };
+    Err(Diagnostic::expected(
+        source,
+        source.len() - left,
+        expected,
+        &ctx.symbols(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logic::parser::conditionParser::parse_condition_string;
+    use crate::logic::parser::context::ParseContext;
+    use crate::logic::parser::strategyParser::parse_strategy_string_with;
+    use crate::logic::registry::{TokenInfo, TokenRegistry};
+    use anchor_lang::prelude::Pubkey;
+
+    #[test]
+    fn test_misspelled_keyword() {
+        let token = Pubkey::new_unique();
+        let source = format!("PRICE_ABOVE({t}, 1)\n  AND PRICE_ABOV({t}, 2)", t = token);
+        let diagnostic = parse_condition_string(&source).unwrap_err();
+
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 7));
+        assert_eq!(diagnostic.found, "PRICE_ABOV");
+        assert_eq!(&source[diagnostic.span.clone()], "PRICE_ABOV");
+        assert!(diagnostic.expected.contains(&"PRICE_ABOVE".to_string()));
+        assert_eq!(diagnostic.suggestion.as_deref(), Some("PRICE_ABOVE"));
+        assert_eq!(
+            diagnostic.render(&source),
+            format!(
+                "error: {}\n  --> 2:7\n  |\n2 |   AND PRICE_ABOV({}, 2)\n  |       ^^^^^^^^^^ did you mean `PRICE_ABOVE`?",
+                diagnostic.message, token
+            )
+        );
+    }
+
+    #[test]
+    fn test_trailing_input_and_operators() {
+        let token = Pubkey::new_unique();
+        let source = format!("PRICE_ABOVE({}, 1) ADN TRUE", token);
+        let diagnostic = parse_condition_string(&source).unwrap_err();
+        assert_eq!(diagnostic.found, "ADN");
+        assert_eq!(diagnostic.suggestion.as_deref(), Some("AND"));
+        assert!(diagnostic.message.starts_with("expected one of `AND`"));
+
+        let source = format!("PRICE_ABOVE({}, 1", token);
+        let diagnostic = parse_condition_string(&source).unwrap_err();
+        assert_eq!(diagnostic.found, "end of input");
+        assert!(diagnostic.expected.contains(&")".to_string()));
+        assert_eq!(diagnostic.span, source.len()..source.len());
+    }
+
+    #[test]
+    fn test_unknown_symbol_suggestion() {
+        let registry = TokenRegistry::new(vec![TokenInfo {
+            symbol: "USDC".to_string(),
+            mint: Pubkey::new_unique(),
+            decimals: 6,
+            oracle: Pubkey::new_unique(),
+        }])
+        .unwrap();
+        let source = "WHEN PRICE_BELOW(USCD, 1) THEN BUY(USDC, 1) EVERY 1h";
+        let diagnostic =
+            parse_strategy_string_with(&ParseContext::with_registry(&registry), source)
+                .unwrap_err();
+        assert_eq!(diagnostic.expected, vec!["token".to_string()]);
+        assert_eq!(diagnostic.suggestion.as_deref(), Some("USDC"));
+        assert_eq!(
+            diagnostic.to_string(),
+            "1:18: expected token, found `USCD` (did you mean `USDC`?)"
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use nom::{
+    combinator::map_opt,
+    error::{ErrorKind, ParseError},
+    IResult, Parser,
+};
+use std::ops::Range;
+
+// Words the language reserves, in any case. Everything else that looks like a word is a
+// pubkey, a registry symbol, a `LET` name or the keyword of a registered condition kind.
+pub const CONDITION_KEYWORDS: &[&str] = &[
+    "AND",
+    "OR",
+    "NOT",
+    "XOR",
+    "IMPLIES",
+    "ALL",
+    "ANY",
+    "AT_LEAST",
+    "OF",
+    "PRICE_ABOVE",
+    "PRICE_BELOW",
+    "PRICE_AT_OR_ABOVE",
+    "PRICE_AT_OR_BELOW",
+    "TRUE",
+    "FALSE",
+    "PRICE",
+    "BALANCE",
+    "MIN",
+    "MAX",
+    "ABS",
+];
+
+pub const ACTION_KEYWORDS: &[&str] = &["BUY", "SELL", "BORROW", "REPAY", "LEND", "REDEEM", "THEN"];
+
+pub const STRATEGY_KEYWORDS: &[&str] = &["LET", "WHEN", "EVERY", "UNTIL", "MAX_RUNS", "META"];
+
+pub fn is_keyword(word: &str) -> bool {
+    [CONDITION_KEYWORDS, ACTION_KEYWORDS, STRATEGY_KEYWORDS]
+        .iter()
+        .any(|keywords| keywords.iter().any(|k| k.eq_ignore_ascii_case(word)))
+}
+
+// longest first, so `<=` isn't read as `<`
+const PUNCTUATION: &[&str] = &[
+    "&&", "||", "<=", ">=", "(", ")", ",", ";", "=", "+", "-", "*", "/", "<", ">", "!",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Token<'a> {
+    // keywords, names, symbols and pubkeys
+    Word(&'a str),
+    // "100", "1_000", "0.25"; as written, underscores included
+    Number(&'a str),
+    // "12.5%", without the `%`
+    Percent(&'a str),
+    // "5m", "1h30m"
+    Duration(&'a str),
+    // a double-quoted string with its escapes resolved
+    Str(String),
+    // "$entry", without the `$`
+    Placeholder(&'a str),
+    Punct(&'static str),
+}
+
+impl Token<'_> {
+    // the keyword or punctuation this token spells, if any
+    pub fn is(&self, text: &str) -> bool {
+        match self {
+            Token::Word(w) => w.eq_ignore_ascii_case(text),
+            Token::Punct(p) => *p == text,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Spanned<T> {
+    pub token: T,
+    // byte range in the source
+    pub span: Range<usize>,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// whitespace, `// ...` and `# ...` comments
+pub fn skip_trivia(mut input: &str) -> &str {
+    loop {
+        let trimmed = input.trim_start();
+        let comment = trimmed.starts_with("//") || trimmed.starts_with('#');
+        if !comment {
+            return trimmed;
+        }
+        input = trimmed.find('\n').map_or("", |i| &trimmed[i..]);
+    }
+}
+
+// `skip_trivia` as a parser, for `ws`
+pub fn trivia<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, (), E> {
+    Ok((skip_trivia(input), ()))
+}
+
+fn word_len(input: &str) -> usize {
+    input.find(|c| !is_word_char(c)).unwrap_or(input.len())
+}
+
+// digits with single underscores between them
+fn is_digits(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_digit())
+        && !s.ends_with('_')
+        && !s.contains("__")
+        && s.chars().all(|c| c.is_ascii_digit() || c == '_')
+}
+
+// "1d2h3m4s": one or more numbers, each followed by a unit
+fn is_duration(s: &str) -> bool {
+    let mut digits = 0;
+    for c in s.chars() {
+        match c {
+            '0'..='9' => digits += 1,
+            's' | 'm' | 'h' | 'd' if digits > 0 => digits = 0,
+            _ => return false,
+        }
+    }
+    digits == 0 && !s.is_empty()
+}
+
+fn error(input: &str) -> nom::Err<nom::error::Error<&str>> {
+    nom::Err::Error(nom::error::Error::new(input, ErrorKind::Char))
+}
+
+fn lex_string(input: &str) -> IResult<&str, Token<'_>> {
+    let mut value = String::new();
+    let mut chars = input.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((&input[i + 1..], Token::Str(value))),
+            '\\' => match chars.next() {
+                Some((_, '"')) => value.push('"'),
+                Some((_, '\\')) => value.push('\\'),
+                Some((_, 'n')) => value.push('\n'),
+                _ => return Err(error(input)),
+            },
+            c => value.push(c),
+        }
+    }
+    Err(error(input))
+}
+
+// the next token after any trivia
+pub fn lex(input: &str) -> IResult<&str, Token<'_>> {
+    let input = skip_trivia(input);
+    let Some(first) = input.chars().next() else {
+        return Err(error(input));
+    };
+    if first == '"' {
+        return lex_string(input);
+    }
+    if first == '$' {
+        let len = word_len(&input[1..]);
+        if len == 0 {
+            return Err(error(input));
+        }
+        return Ok((&input[1 + len..], Token::Placeholder(&input[1..1 + len])));
+    }
+    if is_word_char(first) {
+        let len = word_len(input);
+        let (word, rest) = input.split_at(len);
+        if !is_digits(word) {
+            let token = if is_duration(word) {
+                Token::Duration(word)
+            } else {
+                Token::Word(word)
+            };
+            return Ok((rest, token));
+        }
+        // a fractional part, then maybe a percent sign
+        let mut end = len;
+        if let Some(frac) = rest.strip_prefix('.') {
+            let frac_len = word_len(frac);
+            if frac_len > 0 && is_digits(&frac[..frac_len]) {
+                end += 1 + frac_len;
+            }
+        }
+        let (number, rest) = input.split_at(end);
+        return Ok(match rest.strip_prefix('%') {
+            Some(rest) => (rest, Token::Percent(number)),
+            None => (rest, Token::Number(number)),
+        });
+    }
+    match PUNCTUATION.iter().find(|p| input.starts_with(**p)) {
+        Some(p) => Ok((&input[p.len()..], Token::Punct(p))),
+        None => Err(error(input)),
+    }
+}
+
+// any word, keyword or not
+pub fn word(input: &str) -> IResult<&str, &str> {
+    map_opt(lex, |token| match token {
+        Token::Word(w) => Some(w),
+        _ => None,
+    })
+    .parse(input)
+}
+
+// a word that isn't a keyword: a `LET` name, a registry symbol, ...
+pub fn identifier(input: &str) -> IResult<&str, &str> {
+    map_opt(word, |w| (!is_keyword(w)).then_some(w)).parse(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lex_in_sequence() {
+        let source = "WHEN price(SOL) >= 1_000.50 // entry\n  && !TRUE # no\nTHEN BUY(SOL, 12.5%) EVERY 1h30m META(k, \"a \\\"b\\\"\") $size";
+        let mut kinds = vec![];
+        let mut input = source;
+        while !skip_trivia(input).is_empty() {
+            let (rest, token) = lex(input).unwrap();
+            kinds.push(token);
+            input = rest;
+        }
+        assert_eq!(
+            kinds,
+            vec![
+                Token::Word("WHEN"),
+                Token::Word("price"),
+                Token::Punct("("),
+                Token::Word("SOL"),
+                Token::Punct(")"),
+                Token::Punct(">="),
+                Token::Number("1_000.50"),
+                Token::Punct("&&"),
+                Token::Punct("!"),
+                Token::Word("TRUE"),
+                Token::Word("THEN"),
+                Token::Word("BUY"),
+                Token::Punct("("),
+                Token::Word("SOL"),
+                Token::Punct(","),
+                Token::Percent("12.5"),
+                Token::Punct(")"),
+                Token::Word("EVERY"),
+                Token::Duration("1h30m"),
+                Token::Word("META"),
+                Token::Punct("("),
+                Token::Word("k"),
+                Token::Punct(","),
+                Token::Str("a \"b\"".to_string()),
+                Token::Punct(")"),
+                Token::Placeholder("size"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_words_numbers_and_errors() {
+        // pubkeys may start with digits, and are still words
+        let usdc = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+        assert_eq!(lex(usdc).unwrap().1, Token::Word(usdc));
+        assert_eq!(
+            lex("1111111Af7Udc9v").unwrap().1,
+            Token::Word("1111111Af7Udc9v")
+        );
+        assert_eq!(lex("1__0").unwrap().1, Token::Word("1__0"));
+        assert_eq!(lex("100.").unwrap(), (".", Token::Number("100")));
+        assert!(is_keyword("price_above") && !is_keyword("SOL"));
+
+        assert!(lex(" @").is_err());
+        assert!(lex("\"unterminated)").is_err());
+    }
+}
@@ -1,4 +1,10 @@
+#[allow(non_snake_case)]
 pub mod actionParser;
-pub mod common;
+#[allow(non_snake_case)]
 pub mod conditionParser;
-pub mod tokens;
+pub mod context;
+pub mod diagnostics;
+pub mod lexer;
+#[allow(non_snake_case)]
+pub mod strategyParser;
+pub mod template;
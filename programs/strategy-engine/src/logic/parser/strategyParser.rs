@@ -0,0 +1,321 @@
+use super::actionParser::parse_action_sequence;
+use super::conditionParser::{parse_condition_expr, parse_number, ws};
+use super::context::ParseContext;
+use super::diagnostics::{expect, kw, parse_complete, sym, Diagnostic};
+use super::lexer::{identifier, lex, skip_trivia, word, Token};
+use super::template::or_parameter;
+use crate::logic::actions::ActionBuilder;
+use crate::logic::conditions::ConditionBuilder;
+use crate::logic::strategy::Strategy;
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use nom::{
+    branch::alt,
+    combinator::{map_opt, verify},
+    multi::many0,
+    sequence::{delimited, preceded},
+    IResult, Parser,
+};
+
+// "5m", "1h30m", "45s", "1d", or a `$name` template placeholder
+pub fn parse_duration<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, u64> {
+    or_parameter(ctx, parse_duration_literal).parse(input)
+}
+
+fn parse_duration_literal(input: &str) -> IResult<&str, u64> {
+    map_opt(lex, |token| {
+        let Token::Duration(text) = token else {
+            return None;
+        };
+        let mut total = 0u64;
+        let mut n = 0u64;
+        for c in text.chars() {
+            let size = match c {
+                's' => 1,
+                'm' => 60,
+                'h' => 3_600,
+                'd' => 86_400,
+                digit => {
+                    n = n.checked_mul(10)?.checked_add(digit.to_digit(10)? as u64)?;
+                    continue;
+                }
+            };
+            total = total.checked_add(n.checked_mul(size)?)?;
+            n = 0;
+        }
+        Some(total)
+    })
+    .parse(input)
+}
+
+// double-quoted, with \" \\ and \n escapes
+pub fn parse_string(input: &str) -> IResult<&str, String> {
+    map_opt(lex, |token| match token {
+        Token::Str(value) => Some(value),
+        _ => None,
+    })
+    .parse(input)
+}
+
+enum Clause {
+    Until(ConditionBuilder),
+    MaxRuns(u64),
+    Meta(String, String),
+}
+
+fn parse_clause<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, Clause> {
+    let key = word;
+    let meta = delimited(
+        (kw(ctx, "META"), sym(ctx, '(')),
+        (
+            ws(expect(ctx, "key", key)),
+            preceded(sym(ctx, ','), ws(expect(ctx, "string", parse_string))),
+        ),
+        sym(ctx, ')'),
+    );
+    let max_runs = expect(ctx, "positive number", verify(parse_number, |n| *n > 0));
+    alt((
+        preceded(kw(ctx, "UNTIL"), |i| parse_condition_expr(ctx, i)).map(Clause::Until),
+        preceded(kw(ctx, "MAX_RUNS"), ws(max_runs)).map(Clause::MaxRuns),
+        meta.map(|(key, value): (&str, String)| Clause::Meta(key.to_string(), value)),
+    ))
+    .parse(input)
+}
+
+fn parse_located_clause<'a>(
+    ctx: &ParseContext,
+    input: &'a str,
+) -> IResult<&'a str, (&'a str, Clause)> {
+    let start = skip_trivia(input);
+    let (rest, clause) = parse_clause(ctx, start)?;
+    Ok((rest, (start, clause)))
+}
+
+// "LET <name> = <condition>;", binding `name` for the conditions that follow. returns the
+// name, and whether it was still unbound
+fn parse_let<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, (&'a str, bool)> {
+    let (input, name) =
+        preceded(kw(ctx, "LET"), ws(expect(ctx, "name", identifier))).parse(input)?;
+    let (input, condition) =
+        preceded(sym(ctx, '='), |i| parse_condition_expr(ctx, i)).parse(input)?;
+    let (input, _) = sym(ctx, ';').parse(input)?;
+    Ok((input, (name, ctx.bind(name, condition))))
+}
+
+// clauses keep the input left where they start, to point at a repeated one; so does the
+// first name bound twice
+type StrategyParts<'a> = (
+    Option<&'a str>,
+    ConditionBuilder,
+    ActionBuilder,
+    u64,
+    Vec<(&'a str, Clause)>,
+);
+
+// [LET <name> = <condition>;]* WHEN <condition> THEN <actions> EVERY <duration>, then
+// optional clauses in any order
+fn parse_strategy<'a>(ctx: &ParseContext, input: &'a str) -> IResult<&'a str, StrategyParts<'a>> {
+    let (input, lets) = many0(|i| parse_let(ctx, i)).parse(input)?;
+    let rebound = lets
+        .into_iter()
+        .find(|(_, fresh)| !fresh)
+        .map(|(name, _)| name);
+    let (input, condition) =
+        preceded(kw(ctx, "WHEN"), |i| parse_condition_expr(ctx, i)).parse(input)?;
+    let (input, action) =
+        preceded(kw(ctx, "THEN"), |i| parse_action_sequence(ctx, i)).parse(input)?;
+    let duration = expect(
+        ctx,
+        "duration",
+        verify(|i| parse_duration(ctx, i), |d| *d > 0),
+    );
+    let (input, every) = preceded(kw(ctx, "EVERY"), ws(duration)).parse(input)?;
+    let (input, clauses) = many0(|i| parse_located_clause(ctx, i)).parse(input)?;
+    Ok((input, (rebound, condition, action, every, clauses)))
+}
+
+// `LET` names are bound in `ctx` only while this runs
+pub fn parse_strategy_string_with(
+    ctx: &ParseContext,
+    input: &str,
+) -> std::result::Result<Strategy, Diagnostic> {
+    ctx.clear_bindings();
+    let parsed = parse_complete(ctx, input, parse_strategy);
+    ctx.clear_bindings();
+    let (rebound, condition, action, every, clauses) = parsed?;
+    if let Some(name) = rebound {
+        let offset = name.as_ptr() as usize - input.as_ptr() as usize;
+        return Err(Diagnostic::spanning(
+            input,
+            offset..offset + name.len(),
+            format!("`{}` is bound more than once", name),
+        ));
+    }
+    let whole = |e: Error| Diagnostic::spanning(input, 0..input.len(), e.to_string());
+
+    let mut strategy = Strategy::new(
+        condition.build().map_err(whole)?,
+        action.build().map_err(whole)?,
+        every,
+    );
+    for (start, clause) in clauses {
+        let repeated = |keyword: &str| {
+            let offset = input.len() - start.len();
+            Diagnostic::spanning(
+                input,
+                offset..offset + keyword.len(),
+                format!("{} is given more than once", keyword),
+            )
+        };
+        match clause {
+            Clause::Until(until) => {
+                if strategy.until.is_some() {
+                    return Err(repeated("UNTIL"));
+                }
+                strategy.until = Some(until.build().map_err(whole)?);
+            }
+            Clause::MaxRuns(max_runs) => {
+                if strategy.max_runs.is_some() {
+                    return Err(repeated("MAX_RUNS"));
+                }
+                strategy.max_runs = Some(max_runs);
+            }
+            Clause::Meta(key, value) => strategy.metadata.push((key, value)),
+        }
+    }
+    Ok(strategy)
+}
+
+// tokens as raw pubkeys only
+pub fn parse_strategy_string(input: &str) -> std::result::Result<Strategy, Diagnostic> {
+    parse_strategy_string_with(&ParseContext::new(), input)
+}
+
+pub fn translate_strategy_string(input: &str) -> Result<Strategy> {
+    parse_strategy_string(input).map_err(|_| error!(ErrorCode::ParseError))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::actions::Amount;
+    use crate::logic::conditions::EvaluationContext;
+    use crate::logic::price::Price;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_translate_strategy_string() {
+        let sol = Pubkey::new_unique();
+        let input = format!(
+            "WHEN NOT(PRICE_ABOVE({},100)) THEN SELL({}, 50%) EVERY 5m",
+            sol, sol
+        );
+
+        let strategy = translate_strategy_string(&input).unwrap();
+        assert_eq!(strategy.execute_every_seconds, 300);
+        assert_eq!(
+            strategy.action,
+            ActionBuilder::sell(sol, Amount::Percent(5000))
+                .build()
+                .unwrap()
+        );
+        let ctx = EvaluationContext {
+            token_prices: HashMap::from([(sol, Price::from(90))]),
+            ..Default::default()
+        };
+        assert!(strategy.condition.evaluate(&ctx));
+        assert!(!strategy.is_finished(&ctx, 1_000));
+    }
+
+    #[test]
+    fn test_optional_clauses_round_trip() {
+        let sol = Pubkey::new_unique();
+        let usdc = Pubkey::new_unique();
+        // clauses in any order, spread over several lines as in a file
+        let input = format!(
+            "WHEN PRICE_BELOW({sol}, 90)\n\
+             THEN BUY({sol}, 100) THEN LEND({usdc}, 10%)\n\
+             EVERY 1h30m\n\
+             META(name, \"dip \\\"buyer\\\"\")\n\
+             MAX_RUNS 3\n\
+             UNTIL PRICE_ABOVE({sol}, 200)\n",
+        );
+
+        let strategy = translate_strategy_string(&input).unwrap();
+        assert_eq!(strategy.execute_every_seconds, 5_400);
+        assert_eq!(strategy.max_runs, Some(3));
+        assert_eq!(
+            strategy.metadata,
+            vec![("name".to_string(), "dip \"buyer\"".to_string())]
+        );
+
+        let canonical = strategy.to_string_expr();
+        assert_eq!(
+            canonical,
+            format!(
+                "WHEN PRICE_BELOW({sol}, 90) THEN BUY({sol}, 100) THEN LEND({usdc}, 10%) EVERY 1h30m \
+                 UNTIL PRICE_ABOVE({sol}, 200) MAX_RUNS 3 META(name, \"dip \\\"buyer\\\"\")",
+            )
+        );
+        assert_eq!(translate_strategy_string(&canonical).unwrap(), strategy);
+
+        let ctx = EvaluationContext {
+            token_prices: HashMap::from([(sol, Price::from(250))]),
+            ..Default::default()
+        };
+        assert!(strategy.is_finished(&ctx, 0));
+        let ctx = EvaluationContext {
+            token_prices: HashMap::from([(sol, Price::from(100))]),
+            ..Default::default()
+        };
+        assert!(!strategy.is_finished(&ctx, 2));
+        assert!(strategy.is_finished(&ctx, 3));
+    }
+
+    #[test]
+    fn test_comments_and_number_formats() {
+        let sol = Pubkey::new_unique();
+        let input = format!(
+            "# buy the dip\n\
+             when price({sol}) < 1_000.50 // a decimal with a separator\n\
+             then buy({sol}, 2_500) then sell({sol}, 12.5%)\n\
+             every 1h30m # and a trailing comment",
+        );
+        let strategy = translate_strategy_string(&input).unwrap();
+        assert_eq!(strategy.execute_every_seconds, 5_400);
+        assert_eq!(
+            strategy.to_string_expr(),
+            format!(
+                "WHEN PRICE_BELOW({sol}, 1000.50) THEN BUY({sol}, 2500) THEN SELL({sol}, 12.5%) EVERY 1h30m"
+            )
+        );
+        // keywords need a word boundary now, and can't be LET names
+        assert!(translate_strategy_string(&input.replace("when ", "whenx ")).is_err());
+        let shadowing = format!("LET every = TRUE; WHEN every THEN BUY({sol}, 1) EVERY 1m");
+        assert!(translate_strategy_string(&shadowing).is_err());
+    }
+
+    #[test]
+    fn test_invalid_strategies() {
+        let sol = Pubkey::new_unique();
+        let body = format!("WHEN PRICE_BELOW({sol}, 90) THEN BUY({sol}, 100)");
+        for bad in [
+            body.clone(),
+            format!("{body} EVERY 0s"),
+            format!("{body} EVERY 5 minutes"),
+            format!("{body} EVERY 5m MAX_RUNS 1 MAX_RUNS 2"),
+            format!("{body} EVERY 5m MAX_RUNS 0"),
+            format!("{body} EVERY 5m META(name, \"unterminated)"),
+        ] {
+            assert!(translate_strategy_string(&bad).is_err(), "{}", bad);
+        }
+        let repeated = format!("{body} EVERY 5m MAX_RUNS 1 MAX_RUNS 2");
+        let diagnostic = parse_strategy_string(&repeated).unwrap_err();
+        assert_eq!(diagnostic.found, "MAX_RUNS");
+        assert_eq!(diagnostic.span.start, repeated.rfind("MAX_RUNS").unwrap());
+        assert_eq!(
+            parse_duration(&ParseContext::new(), "1d2h3m4s").unwrap().1,
+            93_784
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use super::context::ParseContext;
+use super::diagnostics::Diagnostic;
+use super::lexer::{lex, skip_trivia, Spanned, Token};
+use super::strategyParser::parse_strategy_string_with;
+use crate::logic::registry::TokenRegistry;
+use crate::logic::strategy::Strategy;
+use nom::{IResult, Parser};
+
+type Error<'a> = nom::error::Error<&'a str>;
+
+// `inner`, or a `$name` placeholder whose value `inner` parses in full
+pub fn or_parameter<'a, 'c, O>(
+    ctx: &'c ParseContext,
+    inner: fn(&str) -> IResult<&str, O>,
+) -> impl Parser<&'a str, Output = O, Error = Error<'a>> + use<'a, 'c, O> {
+    move |input: &'a str| {
+        let Ok((rest, Token::Placeholder(name))) = lex(input) else {
+            return inner(input);
+        };
+        let parsed = ctx
+            .parameter(name)
+            .and_then(|value| match inner(value.trim()) {
+                Ok(("", output)) => Some(output),
+                _ => None,
+            });
+        match parsed {
+            Some(output) => Ok((rest, output)),
+            None => {
+                ctx.record(input, &["parameter with a value"]);
+                Err(nom::Err::Error(Error::new(
+                    input,
+                    nom::error::ErrorKind::Verify,
+                )))
+            }
+        }
+    }
+}
+
+// A strategy with `$name` placeholders, e.g. `... PRICE_BELOW(SOL, $entry) ... EVERY $every`,
+// turned into a `Strategy` once every placeholder has a value. Placeholders can stand for
+// prices, amounts and durations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StrategyTemplate {
+    pub source: String,
+    // placeholder names without the `$`, in order of first use
+    pub parameters: Vec<String>,
+}
+
+// the `$name` placeholders in `source`, as names without the `$` and the span of the whole
+// placeholder. up to the first thing the lexer can't read, which parsing will report anyway
+fn placeholders(source: &str) -> Vec<Spanned<&str>> {
+    let mut found = vec![];
+    let mut input = source;
+    while let Ok((rest, token)) = lex(input) {
+        if let Token::Placeholder(name) = token {
+            let start = source.len() - skip_trivia(input).len();
+            found.push(Spanned {
+                token: name,
+                span: start..source.len() - rest.len(),
+            });
+        }
+        input = rest;
+    }
+    found
+}
+
+impl StrategyTemplate {
+    pub fn new(source: &str) -> Self {
+        let mut parameters: Vec<String> = vec![];
+        for placeholder in placeholders(source) {
+            if !parameters.iter().any(|p| p == placeholder.token) {
+                parameters.push(placeholder.token.to_string());
+            }
+        }
+        Self {
+            source: source.to_string(),
+            parameters,
+        }
+    }
+
+    // symbols in the template resolve through `registry`
+    pub fn instantiate(
+        &self,
+        values: &[(&str, &str)],
+        registry: &TokenRegistry,
+    ) -> Result<Strategy, Diagnostic> {
+        let source = self.source.as_str();
+        for (name, _) in values {
+            if !self.parameters.iter().any(|p| p == name) {
+                return Err(Diagnostic::spanning(
+                    source,
+                    0..0,
+                    format!("the template has no parameter `${}`", name),
+                ));
+            }
+        }
+        for name in &self.parameters {
+            if !values.iter().any(|(n, _)| n == name) {
+                let span = placeholders(source)
+                    .into_iter()
+                    .find(|p| p.token == name)
+                    .map_or(0..0, |p| p.span);
+                return Err(Diagnostic::spanning(
+                    source,
+                    span,
+                    format!("no value given for `${}`", name),
+                ));
+            }
+        }
+        let values = values
+            .iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect();
+        let ctx = ParseContext::with_registry(registry).with_parameters(values);
+        parse_strategy_string_with(&ctx, source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::actions::Amount;
+    use crate::logic::price::Price;
+    use std::collections::HashMap;
+
+    const REGISTRY: &str = r#"
+        [tokens.SOL]
+        mint = "So11111111111111111111111111111111111111112"
+        decimals = 9
+        oracle = "H6ARHf6YXhGYeQfUzQNGk6rDNnLBQKrenN712K4AQJEG"
+    "#;
+
+    const DIP_BUYER: &str = "
+        LET dip = PRICE_BELOW(SOL, $entry) AND price(SOL) > $floor;
+        LET spike = price(SOL) >= $exit;
+        WHEN dip OR (spike AND NOT dip)
+        THEN BUY(SOL, $size)
+        EVERY $every
+        UNTIL spike
+    ";
+
+    #[test]
+    fn test_instantiate_template() {
+        let registry = TokenRegistry::from_toml(REGISTRY).unwrap();
+        let template = StrategyTemplate::new(DIP_BUYER);
+        assert_eq!(
+            template.parameters,
+            vec!["entry", "floor", "exit", "size", "every"]
+        );
+
+        let values = [
+            ("entry", "90.5"),
+            ("floor", "50"),
+            ("exit", "200"),
+            ("size", "10%"),
+            ("every", "1h"),
+        ];
+        let strategy = template.instantiate(&values, &registry).unwrap();
+        assert_eq!(strategy.execute_every_seconds, 3_600);
+        // `dip` is used twice but stored once: its two atoms and their AND, `spike`, NOT, AND, OR
+        assert_eq!(strategy.condition.nodes.len(), 7);
+        assert_eq!(
+            strategy.to_string_expr_with(&registry),
+            "WHEN PRICE_BELOW(SOL, 90.5) AND PRICE_ABOVE(SOL, 50) OR \
+             PRICE_AT_OR_ABOVE(SOL, 200) AND NOT(PRICE_BELOW(SOL, 90.5) AND PRICE_ABOVE(SOL, 50)) \
+             THEN BUY(SOL, 10%) EVERY 1h UNTIL PRICE_AT_OR_ABOVE(SOL, 200)"
+        );
+
+        // the same template with other thresholds
+        let values = [
+            ("entry", "20"),
+            ("floor", "5"),
+            ("exit", "40"),
+            ("size", "1000"),
+            ("every", "5m"),
+        ];
+        let other = template.instantiate(&values, &registry).unwrap();
+        assert_eq!(other.execute_every_seconds, 300);
+        let sol = registry.by_symbol("SOL").unwrap().mint;
+        let ctx = crate::logic::conditions::EvaluationContext {
+            token_prices: HashMap::from([(sol, Price::from(10))]),
+            ..Default::default()
+        };
+        assert!(other.condition.evaluate(&ctx));
+        assert_eq!(
+            other.action,
+            crate::logic::actions::ActionBuilder::buy(sol, Amount::Units(1000))
+                .build()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_template_errors() {
+        let registry = TokenRegistry::from_toml(REGISTRY).unwrap();
+        let template = StrategyTemplate::new(DIP_BUYER);
+        let instantiate =
+            |values: &[(&str, &str)]| template.instantiate(values, &registry).unwrap_err();
+        let complete = [
+            ("entry", "90"),
+            ("floor", "50"),
+            ("exit", "200"),
+            ("size", "10%"),
+            ("every", "1h"),
+        ];
+
+        let missing = instantiate(&complete[1..]);
+        assert_eq!(missing.found, "$entry");
+        assert_eq!(missing.message, "no value given for `$entry`");
+
+        // a placeholder that is a prefix of another is reported where it is used
+        let template =
+            StrategyTemplate::new("WHEN PRICE_BELOW(SOL, $size) THEN BUY(SOL, $s) EVERY 1m");
+        let missing = template
+            .instantiate(&[("size", "10")], &registry)
+            .unwrap_err();
+        assert_eq!(missing.found, "$s");
+        assert_eq!(missing.span.start, template.source.find("$s)").unwrap());
+
+        let mut extra = complete.to_vec();
+        extra.push(("stop", "1"));
+        assert!(instantiate(&extra).message.contains("`$stop`"));
+
+        // a value of the wrong kind is reported at its placeholder
+        let mut wrong = complete.to_vec();
+        wrong[3] = ("size", "lots");
+        let diagnostic = instantiate(&wrong);
+        assert_eq!(diagnostic.found, "$size");
+
+        // bindings must be defined before use, and only once
+        let source = "LET a = TRUE; LET a = FALSE; WHEN a THEN BUY(SOL, 1) EVERY 1m";
+        let ctx = ParseContext::with_registry(&registry);
+        let diagnostic = parse_strategy_string_with(&ctx, source).unwrap_err();
+        assert_eq!(diagnostic.span.start, source.rfind("a =").unwrap());
+        let source = "WHEN b THEN BUY(SOL, 1) EVERY 1m";
+        assert!(parse_strategy_string_with(&ctx, source).is_err());
+    }
+}
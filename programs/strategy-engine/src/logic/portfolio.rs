@@ -0,0 +1,862 @@
+use crate::logic::actions::{ActionTree, ActionType, Amount, AtomicAction};
+use crate::logic::conditions::{EvaluationContext, NodeIndex};
+use crate::logic::metrics::SECONDS_PER_YEAR;
+use crate::logic::parser::conditionParser::parse_token;
+use crate::logic::parser::context::ParseContext;
+use crate::logic::parser::diagnostics::parse_complete;
+use crate::logic::price::Price;
+use crate::logic::registry::TokenRegistry;
+use anchor_lang::prelude::*;
+use std::collections::HashMap;
+
+// Paper trading: what a vault holds off-chain and what every `AtomicAction` does to it, for
+// everything that plays a strategy forward without a cluster. `ActionTree::execute` only logs
+// the actions; `Portfolio::execute` applies them to balances, lending supply and debt, charges
+// fees and price impact, and rejects what the vault could not do on-chain. Applying the same
+// actions to the same portfolio at the same prices always gives the same result, so the
+// backtester, the simulator and the CLI agree on outcomes
+
+// Interest and borrowing power of one token in the lending market. rates are yearly and
+// compound continuously
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LendingTerms {
+    // earned on supplied tokens
+    pub supply_rate: f64,
+    // owed on debt
+    pub borrow_rate: f64,
+    // share of the value of supplied tokens that can be borrowed against
+    pub collateral_factor: f64,
+}
+
+impl Default for LendingTerms {
+    fn default() -> Self {
+        Self {
+            supply_rate: 0.0,
+            borrow_rate: 0.0,
+            collateral_factor: 0.75,
+        }
+    }
+}
+
+// a problem with a market file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MarketError(pub String);
+
+impl std::fmt::Display for MarketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn market_error(message: impl Into<String>) -> MarketError {
+    MarketError(message.into())
+}
+
+// false for NaN and infinity too
+fn non_negative(value: f64) -> bool {
+    value.is_finite() && value >= 0.0
+}
+
+// Where the actions trade and lend
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Market {
+    // decimals of each token; raw units of a token not listed here are whole tokens
+    pub decimals: HashMap<Pubkey, u8>,
+    // charged on the quote value of every BUY and SELL
+    pub fee_bps: u16,
+    // BUYs fill this much above the price, SELLs this much below
+    pub slippage_bps: u16,
+    // quote value of the liquidity of a token: a trade worth `value` at the price fills a
+    // further `value / depth` away from it. tokens not listed here trade without impact
+    pub depth: HashMap<Pubkey, f64>,
+    // tokens not listed here have `LendingTerms::default()`
+    pub lending: HashMap<Pubkey, LendingTerms>,
+}
+
+impl Market {
+    // decimals of every token in `registry`
+    pub fn with_registry(mut self, registry: &TokenRegistry) -> Self {
+        for token in &registry.tokens {
+            self.decimals.insert(token.mint, token.decimals);
+        }
+        self
+    }
+
+    // fees, then one table per token with any of its depth and lending terms:
+    //
+    //   fee_bps = 10
+    //   slippage_bps = 5
+    //
+    //   [[tokens]]
+    //   token = "SOL"
+    //   depth = 2000000
+    //   supply_rate = 0.05
+    //   borrow_rate = 0.08
+    //   collateral_factor = 0.7
+    //
+    // tokens are pubkeys or symbols of `registry`
+    pub fn from_toml(
+        input: &str,
+        registry: &TokenRegistry,
+    ) -> std::result::Result<Self, MarketError> {
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct File {
+            #[serde(default)]
+            fee_bps: u16,
+            #[serde(default)]
+            slippage_bps: u16,
+            #[serde(default)]
+            tokens: Vec<TokenEntry>,
+        }
+
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct TokenEntry {
+            token: String,
+            depth: Option<f64>,
+            supply_rate: Option<f64>,
+            borrow_rate: Option<f64>,
+            collateral_factor: Option<f64>,
+        }
+
+        let file: File =
+            toml::from_str(input).map_err(|e| market_error(e.to_string().trim_end()))?;
+        let mut market = Self {
+            fee_bps: file.fee_bps,
+            slippage_bps: file.slippage_bps,
+            ..Default::default()
+        };
+        let ctx = ParseContext::with_registry(registry);
+        for entry in file.tokens {
+            let token = parse_complete(&ctx, &entry.token, parse_token).map_err(|_| {
+                market_error(format!(
+                    "`{}` is neither a pubkey nor a registered symbol",
+                    entry.token
+                ))
+            })?;
+            if let Some(depth) = entry.depth {
+                market.depth.insert(token, depth);
+            }
+            let defaults = LendingTerms::default();
+            market.lending.insert(
+                token,
+                LendingTerms {
+                    supply_rate: entry.supply_rate.unwrap_or(defaults.supply_rate),
+                    borrow_rate: entry.borrow_rate.unwrap_or(defaults.borrow_rate),
+                    collateral_factor: entry
+                        .collateral_factor
+                        .unwrap_or(defaults.collateral_factor),
+                },
+            );
+        }
+        market.validate()?;
+        Ok(market)
+    }
+
+    pub fn validate(&self) -> std::result::Result<(), MarketError> {
+        for (token, depth) in &self.depth {
+            if !(non_negative(*depth) && *depth > 0.0) {
+                return Err(market_error(format!(
+                    "the depth of {} must be positive",
+                    token
+                )));
+            }
+        }
+        for (token, terms) in &self.lending {
+            if !non_negative(terms.supply_rate) || !non_negative(terms.borrow_rate) {
+                return Err(market_error(format!(
+                    "the rates of {} must not be negative",
+                    token
+                )));
+            }
+            if !(0.0..=1.0).contains(&terms.collateral_factor) {
+                return Err(market_error(format!(
+                    "the collateral factor of {} must be between 0 and 1",
+                    token
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn terms(&self, token: &Pubkey) -> LendingTerms {
+        self.lending.get(token).copied().unwrap_or_default()
+    }
+
+    fn scale(&self, token: &Pubkey) -> f64 {
+        10f64.powi(self.decimals.get(token).copied().unwrap_or(0) as i32)
+    }
+
+    // how much further than the slippage a trade worth `value` at the price fills, as a fraction
+    fn impact(&self, token: &Pubkey, value: f64) -> f64 {
+        self.depth.get(token).map_or(0.0, |depth| value / depth)
+    }
+}
+
+// share of `of` an amount stands for; raw units are taken as they are
+fn share(amount: Amount, of: u64) -> u64 {
+    match amount {
+        Amount::Units(units) => units,
+        Amount::Percent(bps) => (of as u128 * bps as u128 / 10_000) as u64,
+    }
+}
+
+// what `balances` holds of `token` after `quantity` more, unless that no longer fits a `u64`
+fn added(
+    balances: &HashMap<Pubkey, u64>,
+    token: &Pubkey,
+    quantity: u64,
+    what: &str,
+) -> std::result::Result<u64, String> {
+    let current = balances.get(token).copied().unwrap_or(0);
+    current
+        .checked_add(quantity)
+        .ok_or_else(|| format!("takes the {} of {} past {} units", what, token, u64::MAX))
+}
+
+// What an action that went through moved
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Fill {
+    // raw units of the action's token
+    pub quantity: u64,
+    // for BUY and SELL, the price filled at after slippage and impact
+    pub price: Option<f64>,
+    // for BUY and SELL, the quote value of `quantity` at `price`, before the fee
+    pub value: f64,
+    pub fee: f64,
+}
+
+// What one atomic action did
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trade {
+    pub timestamp: u64,
+    pub action: AtomicAction,
+    // raw units of the action's token that moved
+    pub quantity: u64,
+    // for BUY and SELL, the price filled at after slippage and impact
+    pub price: Option<f64>,
+    // for BUY and SELL, the quote value of `quantity` at `price`, before the fee
+    pub value: f64,
+    pub fee: f64,
+    // why the action had no effect; the actions after it in the sequence did not run
+    pub rejected: Option<String>,
+}
+
+// What the vault holds. token amounts are raw units, like the token accounts they stand for
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Portfolio {
+    // quote currency (e.g. USD); BUY pays and SELL receives it
+    pub cash: f64,
+    pub holdings: HashMap<Pubkey, u64>,
+    // lent out with LEND, back with REDEEM, growing with the supply rate
+    pub supplied: HashMap<Pubkey, u64>,
+    // taken with BORROW, paid back with REPAY, growing with the borrow rate
+    pub debt: HashMap<Pubkey, u64>,
+    // interest earned and owed that does not make a whole unit yet
+    supply_interest: HashMap<Pubkey, f64>,
+    debt_interest: HashMap<Pubkey, f64>,
+    accrued_at: Option<u64>,
+}
+
+// adds the interest on every entry of `units` for `years`, keeping what falls short of a whole
+// unit in `carry` for the next time
+fn compound(
+    units: &mut HashMap<Pubkey, u64>,
+    carry: &mut HashMap<Pubkey, f64>,
+    rate: impl Fn(&Pubkey) -> f64,
+    years: f64,
+) {
+    for (token, amount) in units.iter_mut() {
+        let rate = rate(token);
+        if *amount == 0 || rate == 0.0 {
+            continue;
+        }
+        let pending = carry.entry(*token).or_default();
+        *pending += *amount as f64 * (rate * years).exp_m1();
+        let whole = pending.floor();
+        *amount = amount.saturating_add(whole as u64);
+        *pending -= whole;
+    }
+}
+
+impl Portfolio {
+    pub fn new(cash: f64, holdings: HashMap<Pubkey, u64>) -> Self {
+        Self {
+            cash,
+            holdings,
+            ..Default::default()
+        }
+    }
+
+    // interest on the supplied tokens and the debt from the last accrual to `timestamp`; the
+    // first call only starts the clock
+    pub fn accrue(&mut self, market: &Market, timestamp: u64) {
+        let since = self.accrued_at.replace(timestamp).unwrap_or(timestamp);
+        let years = timestamp.saturating_sub(since) as f64 / SECONDS_PER_YEAR;
+        if years == 0.0 {
+            return;
+        }
+        compound(
+            &mut self.supplied,
+            &mut self.supply_interest,
+            |token| market.terms(token).supply_rate,
+            years,
+        );
+        compound(
+            &mut self.debt,
+            &mut self.debt_interest,
+            |token| market.terms(token).borrow_rate,
+            years,
+        );
+    }
+
+    fn value(
+        &self,
+        market: &Market,
+        prices: &HashMap<Pubkey, Price>,
+        token: &Pubkey,
+        units: u64,
+    ) -> f64 {
+        let price = prices.get(token).map_or(0.0, Price::to_f64);
+        units as f64 / market.scale(token) * price
+    }
+
+    fn total(
+        &self,
+        market: &Market,
+        prices: &HashMap<Pubkey, Price>,
+        units: &HashMap<Pubkey, u64>,
+    ) -> f64 {
+        units
+            .iter()
+            .map(|(token, amount)| self.value(market, prices, token, *amount))
+            .fold(0.0, |sum, value| sum + value)
+    }
+
+    // quote value that can be borrowed against: the supplied tokens, each at its collateral
+    // factor. tokens without a price count for nothing
+    pub fn collateral(&self, market: &Market, prices: &HashMap<Pubkey, Price>) -> f64 {
+        self.supplied
+            .iter()
+            .map(|(token, units)| {
+                self.value(market, prices, token, *units) * market.terms(token).collateral_factor
+            })
+            .fold(0.0, |sum, value| sum + value)
+    }
+
+    pub fn debt_value(&self, market: &Market, prices: &HashMap<Pubkey, Price>) -> f64 {
+        self.total(market, prices, &self.debt)
+    }
+
+    // (equity, positions): cash plus holdings and supplied tokens less debt, and the value of
+    // the holdings and supplied tokens alone
+    pub fn valuation(&self, market: &Market, prices: &HashMap<Pubkey, Price>) -> (f64, f64) {
+        let positions =
+            self.total(market, prices, &self.holdings) + self.total(market, prices, &self.supplied);
+        (
+            self.cash + positions - self.debt_value(market, prices),
+            positions,
+        )
+    }
+
+    // what the conditions see: the prices and the holdings in whole tokens
+    pub fn context(&self, market: &Market, prices: &HashMap<Pubkey, Price>) -> EvaluationContext {
+        let decimals = |token: &Pubkey| market.decimals.get(token).copied().unwrap_or(0);
+        EvaluationContext {
+            token_prices: prices.clone(),
+            token_balances: self
+                .holdings
+                .iter()
+                .map(|(token, units)| (*token, Price::from_token_amount(*units, decimals(token))))
+                .collect(),
+        }
+    }
+
+    // runs the sequence left to right, stopping at the first rejected action; one trade for
+    // every action that ran, the rejected one included
+    pub fn execute(
+        &mut self,
+        tree: &ActionTree,
+        market: &Market,
+        prices: &HashMap<Pubkey, Price>,
+        timestamp: u64,
+    ) -> Vec<Trade> {
+        let mut trades = vec![];
+        self.execute_node(
+            tree,
+            tree.root_index,
+            market,
+            prices,
+            timestamp,
+            &mut trades,
+        );
+        trades
+    }
+
+    fn execute_node(
+        &mut self,
+        tree: &ActionTree,
+        index: NodeIndex,
+        market: &Market,
+        prices: &HashMap<Pubkey, Price>,
+        timestamp: u64,
+        trades: &mut Vec<Trade>,
+    ) -> bool {
+        match &tree.nodes[index as usize].action_type {
+            ActionType::And { left, right } => {
+                self.execute_node(tree, *left, market, prices, timestamp, trades)
+                    && self.execute_node(tree, *right, market, prices, timestamp, trades)
+            }
+            ActionType::Atomic(action) => {
+                let (fill, rejected) = match self.apply(action, market, prices) {
+                    Ok(fill) => (fill, None),
+                    Err(reason) => (Fill::default(), Some(reason)),
+                };
+                let filled = rejected.is_none();
+                trades.push(Trade {
+                    timestamp,
+                    action: action.clone(),
+                    quantity: fill.quantity,
+                    price: fill.price,
+                    value: fill.value,
+                    fee: fill.fee,
+                    rejected,
+                });
+                filled
+            }
+        }
+    }
+
+    // one action, or why it cannot go through, in which case nothing changes. a percentage is
+    // of the cash for BUY, of what the collateral still allows for BORROW, of the debt for
+    // REPAY, of the supplied tokens for REDEEM and of the holding otherwise
+    pub fn apply(
+        &mut self,
+        action: &AtomicAction,
+        market: &Market,
+        prices: &HashMap<Pubkey, Price>,
+    ) -> std::result::Result<Fill, String> {
+        let (token, amount) = (action.token(), action.amount());
+        let scale = market.scale(&token);
+        let fee_rate = market.fee_bps as f64 / 10_000.0;
+        let slippage = market.slippage_bps as f64 / 10_000.0;
+        let price = prices.get(&token).map(Price::to_f64);
+        let no_price = || format!("no price for {}", token);
+        let held = self.holdings.get(&token).copied().unwrap_or(0);
+        let moved = |quantity| Fill {
+            quantity,
+            ..Default::default()
+        };
+        match action {
+            AtomicAction::Buy { .. } => {
+                let price = price.ok_or_else(no_price)?;
+                // anything at all would be free
+                if price <= 0.0 {
+                    return Err(format!("{} has a price of zero", token));
+                }
+                let quantity = match amount {
+                    Amount::Units(units) => units,
+                    Amount::Percent(bps) => {
+                        // the value at the price `x` that the budget buys solves
+                        // x * (1 + slippage + x / depth) * (1 + fee) = budget
+                        let budget = self.cash * bps as f64 / 10_000.0 / (1.0 + fee_rate);
+                        let spread = 1.0 + slippage;
+                        let x = match market.depth.get(&token) {
+                            Some(depth) => {
+                                2.0 * budget
+                                    / (spread + (spread * spread + 4.0 * budget / depth).sqrt())
+                            }
+                            None => budget / spread,
+                        };
+                        (x / price * scale).floor() as u64
+                    }
+                };
+                let at_price = quantity as f64 / scale * price;
+                let fill = price * (1.0 + slippage + market.impact(&token, at_price));
+                let value = quantity as f64 / scale * fill;
+                let fee = value * fee_rate;
+                if value + fee > self.cash + 1e-9 {
+                    return Err(format!(
+                        "costs {:.2} with fees, the cash is {:.2}",
+                        value + fee,
+                        self.cash
+                    ));
+                }
+                let holding = added(&self.holdings, &token, quantity, "holding")?;
+                self.cash = (self.cash - value - fee).max(0.0);
+                self.holdings.insert(token, holding);
+                Ok(Fill {
+                    quantity,
+                    price: Some(fill),
+                    value,
+                    fee,
+                })
+            }
+            AtomicAction::Sell { .. } => {
+                let price = price.ok_or_else(no_price)?;
+                let quantity = share(amount, held);
+                if quantity > held {
+                    return Err(format!("sells {} units, holds {}", quantity, held));
+                }
+                let at_price = quantity as f64 / scale * price;
+                let fill = (price * (1.0 - slippage - market.impact(&token, at_price))).max(0.0);
+                let value = quantity as f64 / scale * fill;
+                let fee = value * fee_rate;
+                self.cash += value - fee;
+                *self.holdings.entry(token).or_default() -= quantity;
+                Ok(Fill {
+                    quantity,
+                    price: Some(fill),
+                    value,
+                    fee,
+                })
+            }
+            AtomicAction::Borrow { .. } => {
+                let price = price.ok_or_else(no_price)?;
+                let collateral = self.collateral(market, prices);
+                let debt = self.debt_value(market, prices);
+                let quantity = match amount {
+                    Amount::Units(units) => units,
+                    Amount::Percent(bps) => {
+                        let room = (collateral - debt).max(0.0) * bps as f64 / 10_000.0;
+                        (room / price * scale).floor() as u64
+                    }
+                };
+                let after = debt + quantity as f64 / scale * price;
+                if after > collateral + 1e-9 {
+                    return Err(format!(
+                        "takes the debt to {:.2}, the collateral allows {:.2}",
+                        after, collateral
+                    ));
+                }
+                let holding = added(&self.holdings, &token, quantity, "holding")?;
+                let owed = added(&self.debt, &token, quantity, "debt")?;
+                self.holdings.insert(token, holding);
+                self.debt.insert(token, owed);
+                Ok(moved(quantity))
+            }
+            AtomicAction::Repay { .. } => {
+                let owed = self.debt.get(&token).copied().unwrap_or(0);
+                let quantity = share(amount, owed);
+                if quantity > owed || quantity > held {
+                    return Err(format!(
+                        "repays {} units, owes {} and holds {}",
+                        quantity, owed, held
+                    ));
+                }
+                *self.holdings.entry(token).or_default() -= quantity;
+                *self.debt.entry(token).or_default() -= quantity;
+                if quantity == owed {
+                    self.debt_interest.remove(&token);
+                }
+                Ok(moved(quantity))
+            }
+            AtomicAction::Lend { .. } => {
+                let quantity = share(amount, held);
+                if quantity > held {
+                    return Err(format!("lends {} units, holds {}", quantity, held));
+                }
+                let supply = added(&self.supplied, &token, quantity, "supply")?;
+                *self.holdings.entry(token).or_default() -= quantity;
+                self.supplied.insert(token, supply);
+                Ok(moved(quantity))
+            }
+            AtomicAction::Redeem { .. } => {
+                let supplied = self.supplied.get(&token).copied().unwrap_or(0);
+                let quantity = share(amount, supplied);
+                if quantity > supplied {
+                    return Err(format!(
+                        "redeems {} units, {} are supplied",
+                        quantity, supplied
+                    ));
+                }
+                let debt = self.debt_value(market, prices);
+                if debt > 0.0 {
+                    let freed = price.unwrap_or(0.0) * quantity as f64 / scale
+                        * market.terms(&token).collateral_factor;
+                    let left = self.collateral(market, prices) - freed;
+                    if debt > left + 1e-9 {
+                        return Err(format!(
+                            "leaves {:.2} of collateral for {:.2} of debt",
+                            left.max(0.0),
+                            debt
+                        ));
+                    }
+                }
+                let holding = added(&self.holdings, &token, quantity, "holding")?;
+                *self.supplied.entry(token).or_default() -= quantity;
+                self.holdings.insert(token, holding);
+                if quantity == supplied {
+                    self.supply_interest.remove(&token);
+                }
+                Ok(moved(quantity))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::parser::actionParser::translate_action_string;
+
+    fn prices(entries: &[(Pubkey, u64)]) -> HashMap<Pubkey, Price> {
+        entries
+            .iter()
+            .map(|(token, price)| (*token, Price::from(*price)))
+            .collect()
+    }
+
+    fn run(
+        portfolio: &mut Portfolio,
+        actions: &str,
+        market: &Market,
+        prices: &HashMap<Pubkey, Price>,
+    ) -> Vec<Trade> {
+        let tree = translate_action_string(actions).unwrap();
+        portfolio.execute(&tree, market, prices, 0)
+    }
+
+    #[test]
+    fn test_collateral_limits() {
+        let (sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let market = Market {
+            lending: HashMap::from([(
+                sol,
+                LendingTerms {
+                    collateral_factor: 0.5,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let at = prices(&[(sol, 100), (usdc, 1)]);
+        let mut portfolio = Portfolio::new(0.0, HashMap::from([(sol, 10)]));
+
+        // nothing supplied, nothing to borrow against
+        let trades = run(&mut portfolio, &format!("BORROW({usdc}, 1)"), &market, &at);
+        assert_eq!(
+            trades[0].rejected.as_deref(),
+            Some("takes the debt to 1.00, the collateral allows 0.00")
+        );
+
+        // 1000 supplied at a factor of one half
+        let trades = run(
+            &mut portfolio,
+            &format!("LEND({sol}, 100%) THEN BORROW({usdc}, 80%) THEN BORROW({usdc}, 101)"),
+            &market,
+            &at,
+        );
+        assert_eq!(trades[1].quantity, 400);
+        assert!(trades[2].rejected.is_some());
+        assert_eq!(portfolio.debt[&usdc], 400);
+        assert_eq!(portfolio.valuation(&market, &at), (1000.0, 1400.0));
+
+        // 2 SOL back would leave 400 against 400, 3 would not
+        let trades = run(&mut portfolio, &format!("REDEEM({sol}, 3)"), &market, &at);
+        assert_eq!(
+            trades[0].rejected.as_deref(),
+            Some("leaves 350.00 of collateral for 400.00 of debt")
+        );
+        let trades = run(
+            &mut portfolio,
+            &format!("REDEEM({sol}, 2) THEN REPAY({usdc}, 100%) THEN REDEEM({sol}, 100%)"),
+            &market,
+            &at,
+        );
+        assert!(trades.iter().all(|t| t.rejected.is_none()), "{:?}", trades);
+        assert_eq!(portfolio.holdings[&sol], 10);
+        assert_eq!(portfolio.debt[&usdc], 0);
+    }
+
+    #[test]
+    fn test_overflow_and_zero_price() {
+        let sol = Pubkey::new_unique();
+        let market = Market::default();
+        let mut portfolio = Portfolio::new(1.0, HashMap::new());
+
+        let free = prices(&[(sol, 0)]);
+        let trades = run(&mut portfolio, &format!("BUY({sol}, 1)"), &market, &free);
+        assert_eq!(
+            trades[0].rejected,
+            Some(format!("{} has a price of zero", sol))
+        );
+
+        // cheap enough that the cash covers it, but the holding can't grow past u64::MAX
+        let cheap = HashMap::from([(sol, Price::new(1, -30))]);
+        let trades = run(
+            &mut portfolio,
+            &format!("BUY({sol}, {max}) THEN BUY({sol}, 1)", max = u64::MAX),
+            &market,
+            &cheap,
+        );
+        assert!(trades[0].rejected.is_none());
+        assert!(trades[1].rejected.as_ref().unwrap().contains("holding"));
+        assert_eq!(portfolio.holdings[&sol], u64::MAX);
+
+        // nor can what is supplied
+        portfolio.supplied.insert(sol, 1);
+        let trades = run(
+            &mut portfolio,
+            &format!("LEND({sol}, 100%)"),
+            &market,
+            &cheap,
+        );
+        assert!(trades[0].rejected.as_ref().unwrap().contains("supply"));
+        assert_eq!(portfolio.holdings[&sol], u64::MAX);
+        assert_eq!(portfolio.supplied[&sol], 1);
+    }
+
+    #[test]
+    fn test_interest() {
+        let (sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let market = Market {
+            lending: HashMap::from([
+                (
+                    sol,
+                    LendingTerms {
+                        supply_rate: 0.05,
+                        ..Default::default()
+                    },
+                ),
+                (
+                    usdc,
+                    LendingTerms {
+                        borrow_rate: 0.1,
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        let mut portfolio = Portfolio {
+            supplied: HashMap::from([(sol, 1_000_000)]),
+            debt: HashMap::from([(usdc, 1_000_000)]),
+            ..Default::default()
+        };
+        // hourly for a year: the carried fractions add up to the continuous rate
+        let hour = 3_600;
+        let hours = (SECONDS_PER_YEAR / hour as f64) as u64;
+        for i in 0..=hours {
+            portfolio.accrue(&market, i * hour);
+        }
+        let years = (hours * hour) as f64 / SECONDS_PER_YEAR;
+        let expected = |rate: f64| 1e6 * (rate * years).exp();
+        assert!((portfolio.supplied[&sol] as f64 - expected(0.05)).abs() <= 1.0);
+        assert!((portfolio.debt[&usdc] as f64 - expected(0.1)).abs() <= 1.0);
+
+        // the same accruals give the same portfolio
+        let mut again = Portfolio {
+            supplied: HashMap::from([(sol, 1_000_000)]),
+            debt: HashMap::from([(usdc, 1_000_000)]),
+            ..Default::default()
+        };
+        for i in 0..=hours {
+            again.accrue(&market, i * hour);
+        }
+        assert_eq!(again, portfolio);
+    }
+
+    #[test]
+    fn test_price_impact() {
+        let sol = Pubkey::new_unique();
+        let market = Market {
+            fee_bps: 100,
+            depth: HashMap::from([(sol, 100_000.0)]),
+            ..Default::default()
+        };
+        let at = prices(&[(sol, 100)]);
+        let mut portfolio = Portfolio::new(10_000.0, HashMap::new());
+
+        // 10 SOL are worth 1000, 1% of the depth
+        let fill = portfolio
+            .apply(
+                &AtomicAction::Buy {
+                    token: sol,
+                    amount: Amount::Units(10),
+                },
+                &market,
+                &at,
+            )
+            .unwrap();
+        assert_eq!(fill.price, Some(101.0));
+        assert!((fill.fee - 10.1).abs() < 1e-9);
+        let fill = portfolio
+            .apply(
+                &AtomicAction::Sell {
+                    token: sol,
+                    amount: Amount::Units(10),
+                },
+                &market,
+                &at,
+            )
+            .unwrap();
+        assert_eq!(fill.price, Some(99.0));
+        assert!((portfolio.cash - (10_000.0 - 1010.0 - 10.1 + 990.0 - 9.9)).abs() < 1e-9);
+
+        // as many whole SOL as the cash pays for with the impact and the fee
+        let buy = |amount| AtomicAction::Buy { token: sol, amount };
+        let fill = portfolio
+            .apply(&buy(Amount::Percent(10_000)), &market, &at)
+            .unwrap();
+        assert_eq!(fill.quantity, 90);
+        assert!(fill.price.unwrap() > 100.0);
+        assert!(portfolio
+            .apply(&buy(Amount::Units(1)), &market, &at)
+            .is_err());
+
+        let unknown = Pubkey::new_unique();
+        let before = portfolio.clone();
+        assert_eq!(
+            portfolio.apply(
+                &AtomicAction::Buy {
+                    token: unknown,
+                    amount: Amount::Units(1)
+                },
+                &market,
+                &at
+            ),
+            Err(format!("no price for {}", unknown))
+        );
+        assert_eq!(portfolio, before);
+    }
+
+    #[test]
+    fn test_market_from_toml() {
+        let sol = Pubkey::new_unique();
+        let market = Market::from_toml(
+            &format!(
+                "fee_bps = 10\n\n[[tokens]]\ntoken = \"{sol}\"\ndepth = 5e6\nborrow_rate = 0.08\n"
+            ),
+            &TokenRegistry::default(),
+        )
+        .unwrap();
+        assert_eq!(market.fee_bps, 10);
+        assert_eq!(market.depth[&sol], 5e6);
+        assert_eq!(
+            market.terms(&sol),
+            LendingTerms {
+                borrow_rate: 0.08,
+                ..Default::default()
+            }
+        );
+
+        let error = |toml: &str| {
+            Market::from_toml(toml, &TokenRegistry::default())
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error("fees = 1").contains("fees"));
+        assert_eq!(
+            error(&format!(
+                "[[tokens]]\ntoken = \"{sol}\"\ncollateral_factor = 2\n"
+            )),
+            format!("the collateral factor of {} must be between 0 and 1", sol)
+        );
+        assert_eq!(
+            error("[[tokens]]\ntoken = \"NOT_A_TOKEN\"\n"),
+            "`NOT_A_TOKEN` is neither a pubkey nor a registered symbol"
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+// Fixed-point decimal: `mantissa * 10^expo`.
+// The same value can be written several ways (`100e0` == `1000e-1`); equality, ordering and
+// hashing all compare the value exactly, so prices from feeds with different exponents and
+// thresholds written with different precision can be compared directly.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct Price {
+    pub mantissa: u64,
+    pub expo: i32,
+}
+
+impl Price {
+    pub const ZERO: Price = Price {
+        mantissa: 0,
+        expo: 0,
+    };
+
+    // exponents `try_new` accepts; far past any feed or token, and small enough that nothing
+    // derived from a price comes near the ends of an `i32`
+    pub const MAX_EXPO: i32 = 64;
+
+    pub fn new(mantissa: u64, expo: i32) -> Self {
+        Self { mantissa, expo }
+    }
+
+    // for a price from outside the program, whose exponent may be anything
+    pub fn try_new(mantissa: u64, expo: i32) -> Result<Self> {
+        require!(
+            (-Self::MAX_EXPO..=Self::MAX_EXPO).contains(&expo),
+            ErrorCode::PriceOutOfRange
+        );
+        Ok(Self::new(mantissa, expo))
+    }
+
+    // oracle-style `(price, expo)` pair, as published by Pyth and Switchboard
+    pub fn from_oracle(price: i64, expo: i32) -> Result<Self> {
+        let mantissa = u64::try_from(price).map_err(|_| error!(ErrorCode::NegativePrice))?;
+        Self::try_new(mantissa, expo)
+    }
+
+    // a raw SPL token amount, e.g. `1_500_000` with 6 decimals is 1.5
+    pub fn from_token_amount(amount: u64, decimals: u8) -> Self {
+        Self::new(amount, -(decimals as i32))
+    }
+
+    // the mantissa this value has at exponent `expo`, if it is representable exactly
+    pub fn rescale(&self, expo: i32) -> Option<u64> {
+        if self.mantissa == 0 {
+            return Some(0);
+        }
+        let shift = self.expo.checked_sub(expo)?;
+        if shift >= 0 {
+            10u64
+                .checked_pow(shift as u32)
+                .and_then(|f| self.mantissa.checked_mul(f))
+        } else {
+            let f = 10u64.checked_pow(shift.unsigned_abs())?;
+            self.mantissa.is_multiple_of(f).then_some(self.mantissa / f)
+        }
+    }
+
+    // amount of a token with `decimals` decimals worth exactly this value
+    pub fn to_token_amount(&self, decimals: u8) -> Option<u64> {
+        self.rescale(-(decimals as i32))
+    }
+
+    // same value with trailing zeros moved into the exponent; the canonical form used for hashing.
+    // the exponent stops at `i32::MAX`, where equal values still end up the same
+    pub fn normalized(&self) -> Self {
+        if self.mantissa == 0 {
+            return Self::ZERO;
+        }
+        let mut p = *self;
+        while p.mantissa.is_multiple_of(10) && p.expo < i32::MAX {
+            p.mantissa /= 10;
+            p.expo += 1;
+        }
+        p
+    }
+
+    // nearest f64; for simulations and reports, never for comparisons
+    pub fn to_f64(&self) -> f64 {
+        self.mantissa as f64 * 10f64.powi(self.expo)
+    }
+
+    // `value` to 9 significant digits, for simulated prices; zero for anything not positive
+    pub fn from_f64(value: f64) -> Self {
+        if !(value.is_finite() && value > 0.0) {
+            return Self::ZERO;
+        }
+        let expo = value.log10().floor() as i32 - 8;
+        Self::new((value / 10f64.powi(expo)).round() as u64, expo).normalized()
+    }
+
+    fn digits(mantissa: u64) -> i64 {
+        mantissa.checked_ilog10().map_or(0, |d| d as i64 + 1)
+    }
+}
+
+impl From<u64> for Price {
+    fn from(units: u64) -> Self {
+        Self::new(units, 0)
+    }
+}
+
+impl Ord for Price {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.mantissa, other.mantissa) {
+            (0, 0) => return Ordering::Equal,
+            (0, _) => return Ordering::Less,
+            (_, 0) => return Ordering::Greater,
+            _ => {}
+        }
+        // position of the leading digit decides unless it is the same for both
+        let magnitude = |p: &Price| Self::digits(p.mantissa) + p.expo as i64;
+        match magnitude(self).cmp(&magnitude(other)) {
+            Ordering::Equal => {}
+            unequal => return unequal,
+        }
+        // same magnitude, so the exponents differ by less than 20 digits and the
+        // rescaled mantissa fits in a u128
+        let (a, b) = (self.mantissa as u128, other.mantissa as u128);
+        match self.expo.cmp(&other.expo) {
+            Ordering::Equal => a.cmp(&b),
+            Ordering::Greater => (a * 10u128.pow((self.expo - other.expo) as u32)).cmp(&b),
+            Ordering::Less => a.cmp(&(b * 10u128.pow((other.expo - self.expo) as u32))),
+        }
+    }
+}
+
+impl PartialOrd for Price {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Price {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Price {}
+
+impl Hash for Price {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let n = self.normalized();
+        n.mantissa.hash(state);
+        n.expo.hash(state);
+    }
+}
+
+// plain decimal notation with exactly the precision the price carries: 10025e-2 is "100.25",
+// 5e3 is "5000". `parse_price` reads it back to the same value, and for a negative exponent
+// to the same mantissa and exponent as well. a price whose plain form would not fit a `u64`
+// or need more than `MAX_PADDING` zeros after the point is written as e.g. "5e40" instead,
+// which keeps the output short for any exponent but does not parse
+const MAX_PADDING: usize = 20;
+
+impl std::fmt::Display for Price {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = self.mantissa.to_string();
+        if self.mantissa == 0 && self.expo >= 0 {
+            return write!(f, "0");
+        }
+        if self.expo >= 0 {
+            return match self.rescale(0) {
+                Some(units) => write!(f, "{}", units),
+                None => write!(f, "{}e{}", digits, self.expo),
+            };
+        }
+        let scale = self.expo.unsigned_abs() as usize;
+        if digits.len() > scale {
+            let (int, frac) = digits.split_at(digits.len() - scale);
+            write!(f, "{}.{}", int, frac)
+        } else if scale - digits.len() <= MAX_PADDING {
+            write!(f, "0.{}{}", "0".repeat(scale - digits.len()), digits)
+        } else {
+            write!(f, "{}e{}", digits, self.expo)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_compare_across_exponents() {
+        // 100 USD from a feed with expo -8 vs a threshold written as 100
+        let feed = Price::from_oracle(10_000_000_000, -8).unwrap();
+        assert_eq!(feed, Price::from(100));
+        assert!(Price::new(100_000_001, -6) > Price::from(100));
+        assert!(Price::new(99_999_999, -6) < Price::from(100));
+        assert!(Price::new(1, 30) > Price::new(u64::MAX, 0));
+        assert!(Price::new(u64::MAX, -30) < Price::new(1, 0));
+        assert!(Price::ZERO < Price::new(1, -50));
+        assert!(Price::from_oracle(-1, 0).is_err());
+        assert!(Price::from_oracle(1, -Price::MAX_EXPO).is_ok());
+        assert!(Price::from_oracle(1, Price::MAX_EXPO + 1).is_err());
+        assert!(Price::try_new(1, i32::MIN).is_err());
+
+        let set: HashSet<Price> = [Price::new(15, -1), Price::new(150, -2)].into();
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn test_normalized_at_the_largest_exponent() {
+        assert_eq!(
+            Price::new(100, i32::MAX).normalized(),
+            Price::new(100, i32::MAX)
+        );
+        let set: HashSet<Price> = [Price::new(10, i32::MAX), Price::new(100, i32::MAX - 1)].into();
+        assert_eq!(set.len(), 1);
+        assert_eq!(Price::new(1000, i32::MAX - 2).normalized().mantissa, 10);
+    }
+
+    #[test]
+    fn test_token_amounts() {
+        let p = Price::from_token_amount(1_500_000, 6);
+        assert_eq!(p, Price::new(15, -1));
+        assert_eq!(p.to_token_amount(9), Some(1_500_000_000));
+        assert_eq!(p.to_token_amount(0), None);
+        assert_eq!(Price::from(7).to_token_amount(2), Some(700));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Price::new(10025, -2).to_string(), "100.25");
+        assert_eq!(Price::new(5, 3).to_string(), "5000");
+        assert_eq!(Price::new(5, -3).to_string(), "0.005");
+        assert_eq!(Price::new(100, 0).to_string(), "100");
+        assert_eq!(Price::new(10050, -2).to_string(), "100.50");
+        assert_eq!(Price::new(0, -3).to_string(), "0.000");
+
+        // bounded whatever the exponent, e.g. from a decoded account
+        assert_eq!(Price::new(5, i32::MAX).to_string(), "5e2147483647");
+        assert_eq!(Price::new(5, i32::MIN).to_string(), "5e-2147483648");
+        assert_eq!(Price::new(2, 19).to_string(), "2e19");
+        assert_eq!(Price::new(1, 19).to_string(), "10000000000000000000");
+        assert_eq!(Price::new(5, -21).to_string(), "0.000000000000000000005");
+        assert_eq!(Price::new(5, -22).to_string(), "5e-22");
+    }
+
+    #[test]
+    fn test_f64() {
+        assert_eq!(Price::from_f64(101.5), Price::new(1015, -1));
+        assert_eq!(Price::from_f64(1.0 / 3.0), Price::new(333_333_333, -9));
+        assert_eq!(Price::from_f64(2.5e12), Price::new(25, 11));
+        assert_eq!(Price::from_f64(-1.0), Price::ZERO);
+        assert_eq!(Price::from_f64(f64::NAN), Price::ZERO);
+        // back to the price it came from
+        let price = Price::new(12345, -4);
+        assert_eq!(Price::from_f64(price.to_f64()), price);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::logic::conditions::{
+    AtomicCondition, ConditionTree, ConditionType, ExprStyle, NodeIndex,
+};
+use anchor_lang::prelude::*;
+
+const INDENT: &str = "    ";
+
+// How `ConditionTree::to_string_pretty` lays out a condition
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PrintOptions {
+    pub style: ExprStyle,
+    // one operand per line, with parenthesized groups and ALL/ANY lists indented
+    pub multiline: bool,
+}
+
+// how tightly an operator binds, following the levels of `conditionParser`; terms (atoms,
+// NOT, ALL, ...) bind tightest
+fn precedence(condition: &ConditionType) -> u8 {
+    match condition {
+        ConditionType::Implies { .. } => 1,
+        ConditionType::Or { .. } => 2,
+        ConditionType::Xor { .. } => 3,
+        ConditionType::And { .. } => 4,
+        _ => 5,
+    }
+}
+
+// Prints the fewest parentheses that parse back to the same tree: a child is wrapped when it
+// binds looser than its parent, or equally loose on the side its operator doesn't associate to
+// (AND, XOR and OR group to the left, IMPLIES to the right)
+struct Printer<'a> {
+    tree: &'a ConditionTree,
+    style: ExprStyle,
+    label: &'a dyn Fn(&Pubkey) -> String,
+}
+
+impl Printer<'_> {
+    fn condition(&self, index: NodeIndex) -> &ConditionType {
+        &self.tree.nodes[index as usize].condition_type
+    }
+
+    // operator, operands and the precedence each operand must have to go unwrapped
+    fn binary(&self, index: NodeIndex) -> Option<(&'static str, [(NodeIndex, u8); 2])> {
+        let symbols = self.style == ExprStyle::Symbols;
+        let (op, left, right) = match *self.condition(index) {
+            ConditionType::And { left, right } => (if symbols { "&&" } else { "AND" }, left, right),
+            ConditionType::Or { left, right } => (if symbols { "||" } else { "OR" }, left, right),
+            ConditionType::Xor { left, right } => ("XOR", left, right),
+            ConditionType::Implies { left, right } => {
+                return Some(("IMPLIES", [(left, 2), (right, 1)]));
+            }
+            _ => return None,
+        };
+        let p = precedence(self.condition(index));
+        Some((op, [(left, p), (right, p + 1)]))
+    }
+
+    fn not(&self) -> &'static str {
+        match self.style {
+            ExprStyle::Keywords => "NOT",
+            ExprStyle::Symbols => "!",
+        }
+    }
+
+    // `NOT(..)` and `!..` need no space, `NOT PRICE_ABOVE(..)` does
+    fn not_separator(&self, wraps: bool) -> &'static str {
+        if wraps || self.style == ExprStyle::Symbols {
+            ""
+        } else {
+            " "
+        }
+    }
+
+    // NOT takes a term; infix atoms are wrapped as well, since `NOT price(t) > 1` reads as if
+    // it negated `price(t)`
+    fn not_wraps(&self, child: NodeIndex) -> bool {
+        match self.condition(child) {
+            ConditionType::Atomic(AtomicCondition::Compare { .. }) => true,
+            ConditionType::Atomic(AtomicCondition::Custom { .. }) => false,
+            ConditionType::Atomic(_) => self.style == ExprStyle::Symbols,
+            c => precedence(c) < 5,
+        }
+    }
+
+    fn list(&self, index: NodeIndex) -> Option<(String, &[NodeIndex])> {
+        match self.condition(index) {
+            ConditionType::All { children } => Some(("ALL(".to_string(), children)),
+            ConditionType::Any { children } => Some(("ANY(".to_string(), children)),
+            ConditionType::AtLeast { k, children } => {
+                Some((format!("AT_LEAST {} OF (", k), children))
+            }
+            _ => None,
+        }
+    }
+
+    fn leaf(&self, index: NodeIndex) -> String {
+        match self.condition(index) {
+            ConditionType::Atomic(atomic) => atomic.to_string_styled(self.style, self.label),
+            ConditionType::Const(true) => "TRUE".to_string(),
+            ConditionType::Const(false) => "FALSE".to_string(),
+            _ => unreachable!("operators are printed by `inline` and `lines`"),
+        }
+    }
+
+    fn inline(&self, index: NodeIndex, min: u8) -> String {
+        let s = if let Some((op, [(l, lp), (r, rp)])) = self.binary(index) {
+            format!("{} {} {}", self.inline(l, lp), op, self.inline(r, rp))
+        } else if let ConditionType::Not { child } = *self.condition(index) {
+            let wraps = self.not_wraps(child);
+            let c = if wraps {
+                format!("({})", self.inline(child, 0))
+            } else {
+                self.inline(child, 5)
+            };
+            format!("{}{}{}", self.not(), self.not_separator(wraps), c)
+        } else if let Some((open, children)) = self.list(index) {
+            let children: Vec<_> = children.iter().map(|c| self.inline(*c, 0)).collect();
+            format!("{}{})", open, children.join(", "))
+        } else {
+            self.leaf(index)
+        };
+        if precedence(self.condition(index)) < min {
+            format!("({})", s)
+        } else {
+            s
+        }
+    }
+
+    fn lines(&self, index: NodeIndex, min: u8) -> Vec<String> {
+        let lines = if let Some((op, [(l, lp), (r, rp)])) = self.binary(index) {
+            let mut lines = self.lines(l, lp);
+            let mut right = self.lines(r, rp).into_iter();
+            lines.extend(right.next().map(|first| format!("{} {}", op, first)));
+            lines.extend(right);
+            lines
+        } else if let ConditionType::Not { child } = *self.condition(index) {
+            let wraps = self.not_wraps(child);
+            let mut lines = if wraps && precedence(self.condition(child)) == 5 {
+                vec![format!("({})", self.inline(child, 0))]
+            } else {
+                self.lines(child, 5)
+            };
+            lines[0] = format!("{}{}{}", self.not(), self.not_separator(wraps), lines[0]);
+            lines
+        } else if let Some((open, children)) = self.list(index) {
+            let mut lines = vec![open];
+            for (i, child) in children.iter().enumerate() {
+                let mut child_lines = self.lines(*child, 0);
+                if i + 1 < children.len() {
+                    if let Some(last) = child_lines.last_mut() {
+                        last.push(',');
+                    }
+                }
+                lines.extend(child_lines.into_iter().map(|l| INDENT.to_string() + &l));
+            }
+            lines.push(")".to_string());
+            lines
+        } else {
+            vec![self.leaf(index)]
+        };
+        if precedence(self.condition(index)) < min {
+            let mut wrapped = vec!["(".to_string()];
+            wrapped.extend(lines.into_iter().map(|l| INDENT.to_string() + &l));
+            wrapped.push(")".to_string());
+            wrapped
+        } else {
+            lines
+        }
+    }
+}
+
+impl ConditionTree {
+    // canonical form. a subtree used more than once is printed at every use, and parsing stores
+    // equal subtrees once (see `ConditionBuilder::graft`), so a tree made by the builder parses
+    // back to an equal tree; one stored some other way, e.g. with a subtree copied, parses back
+    // to the tree the builder would have made
+    pub fn to_string_pretty(&self, options: PrintOptions) -> String {
+        self.print_with(options, &|token| token.to_string())
+    }
+
+    pub(crate) fn print_with(
+        &self,
+        options: PrintOptions,
+        label: &dyn Fn(&Pubkey) -> String,
+    ) -> String {
+        let printer = Printer {
+            tree: self,
+            style: options.style,
+            label,
+        };
+        if options.multiline {
+            printer.lines(self.root_index, 0).join("\n")
+        } else {
+            printer.inline(self.root_index, 0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::conditions::{CompareOp, ConditionBuilder, ConditionNode, EvaluationContext};
+    use crate::logic::kinds::{condition_kind, register_condition_kind, ConditionKind};
+    use crate::logic::parser::conditionParser::{parse_number, translate_condition_string, ws};
+    use crate::logic::price::Price;
+    use crate::logic::value::ValueExpr;
+    use nom::character::complete::char;
+    use nom::{IResult, Parser};
+    use proptest::prelude::*;
+
+    const TOKENS: [Pubkey; 3] = [
+        Pubkey::new_from_array([1; 32]),
+        Pubkey::new_from_array([2; 32]),
+        Pubkey::new_from_array([3; 32]),
+    ];
+
+    // FLAG(n): true for even n
+    struct Flag;
+
+    impl ConditionKind for Flag {
+        fn id(&self) -> u16 {
+            2000
+        }
+
+        fn keyword(&self) -> &'static str {
+            "FLAG"
+        }
+
+        fn parse<'a>(&self, input: &'a str) -> IResult<&'a str, Vec<u8>> {
+            let (input, _) = ws(char('(')).parse(input)?;
+            let (input, n) = ws(parse_number).parse(input)?;
+            let (input, _) = ws(char(')')).parse(input)?;
+            Ok((input, vec![n as u8]))
+        }
+
+        fn display(&self, data: &[u8]) -> String {
+            format!("({})", data[0])
+        }
+
+        fn evaluate(&self, data: &[u8], _ctx: &EvaluationContext) -> bool {
+            data[0].is_multiple_of(2)
+        }
+
+        fn data_len(&self) -> usize {
+            1
+        }
+    }
+
+    fn register() {
+        if condition_kind(2000).is_none() {
+            let _ = register_condition_kind(&Flag);
+        }
+    }
+
+    // `tree` with every subtree copied at each use, as no parse would store it
+    fn unshare(tree: &ConditionTree) -> ConditionTree {
+        fn copy(
+            tree: &ConditionTree,
+            index: NodeIndex,
+            nodes: &mut Vec<ConditionNode>,
+        ) -> NodeIndex {
+            let mut node = tree.nodes[index as usize].clone();
+            node.condition_type
+                .map_children(|child| copy(tree, child, nodes));
+            nodes.push(node);
+            (nodes.len() - 1) as NodeIndex
+        }
+        let mut nodes = vec![];
+        let root_index = copy(tree, tree.root_index, &mut nodes);
+        ConditionTree { nodes, root_index }
+    }
+
+    fn price() -> impl Strategy<Value = Price> {
+        (0u64..100_000, -4i32..=2).prop_map(|(mantissa, expo)| Price::new(mantissa, expo))
+    }
+
+    fn token() -> impl Strategy<Value = Pubkey> {
+        (0..TOKENS.len()).prop_map(|i| TOKENS[i])
+    }
+
+    fn value() -> impl Strategy<Value = ValueExpr> {
+        let leaf = prop_oneof![
+            price().prop_map(ValueExpr::constant),
+            token().prop_map(ValueExpr::price),
+            token().prop_map(ValueExpr::balance),
+        ];
+        leaf.prop_recursive(3, 12, 2, |inner| {
+            prop_oneof![
+                inner.clone().prop_map(ValueExpr::neg),
+                inner.clone().prop_map(ValueExpr::abs),
+                (inner.clone(), inner.clone(), 0..6usize).prop_map(|(l, r, op)| match op {
+                    0 => l.add(r),
+                    1 => l.sub(r),
+                    2 => l.mul(r),
+                    3 => l.div(r),
+                    4 => l.min(r),
+                    _ => l.max(r),
+                }),
+            ]
+        })
+    }
+
+    fn condition() -> impl Strategy<Value = ConditionBuilder> {
+        let op = prop_oneof![
+            Just(CompareOp::Above),
+            Just(CompareOp::Below),
+            Just(CompareOp::AtOrAbove),
+            Just(CompareOp::AtOrBelow),
+        ];
+        let leaf = prop_oneof![
+            (token(), price()).prop_map(|(t, p)| ConditionBuilder::price_above(t, p)),
+            (token(), price()).prop_map(|(t, p)| ConditionBuilder::price_below(t, p)),
+            (token(), price()).prop_map(|(t, p)| ConditionBuilder::price_at_or_above(t, p)),
+            (token(), price()).prop_map(|(t, p)| ConditionBuilder::price_at_or_below(t, p)),
+            any::<bool>().prop_map(ConditionBuilder::constant),
+            (0u8..4).prop_map(|n| ConditionBuilder::custom(2000, vec![n])),
+            (value(), op, value()).prop_map(|(l, op, r)| ConditionBuilder::compare(l, op, r)),
+        ];
+        leaf.prop_recursive(5, 48, 4, |inner| {
+            prop_oneof![
+                inner.clone().prop_map(ConditionBuilder::not),
+                // the same subtree on both sides, stored once
+                (inner.clone(), inner.clone())
+                    .prop_map(|(shared, other)| shared.clone().and(other).or(shared.not())),
+                (inner.clone(), inner.clone(), 0..4usize).prop_map(|(l, r, op)| match op {
+                    0 => l.and(r),
+                    1 => l.or(r),
+                    2 => l.xor(r),
+                    _ => l.implies(r),
+                }),
+                prop::collection::vec(inner.clone(), 1..4).prop_map(ConditionBuilder::all),
+                prop::collection::vec(inner.clone(), 1..4).prop_map(ConditionBuilder::any),
+                prop::collection::vec(inner, 1..4).prop_flat_map(|children| {
+                    (0..=children.len() as NodeIndex)
+                        .prop_map(move |k| ConditionBuilder::at_least(k, children.clone()))
+                }),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_print_parse_round_trip(builder in condition()) {
+            register();
+            let tree = builder.build().unwrap();
+            // the same tree with nothing shared prints the same and parses back to the
+            // builder's tree
+            let unshared = unshare(&tree);
+            prop_assert!(unshared.nodes.len() >= tree.nodes.len());
+            for style in [ExprStyle::Keywords, ExprStyle::Symbols] {
+                for multiline in [false, true] {
+                    let options = PrintOptions { style, multiline };
+                    let printed = tree.to_string_pretty(options);
+                    prop_assert_eq!(unshared.to_string_pretty(options), printed.clone());
+                    let parsed = translate_condition_string(&printed);
+                    prop_assert!(parsed.is_ok(), "{}", printed);
+                    prop_assert_eq!(parsed.unwrap(), tree.clone(), "{}", printed);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_shared_subtrees_round_trip() {
+        let [a, b, _] = TOKENS;
+        let shared = ConditionBuilder::price_above(a, Price::from(1))
+            .or(ConditionBuilder::price_below(b, Price::from(2)));
+        let tree = shared
+            .clone()
+            .and(shared.clone().not())
+            .implies(shared)
+            .build()
+            .unwrap();
+        assert_eq!(tree.nodes.len(), 6);
+
+        let unshared = unshare(&tree);
+        assert_eq!(unshared.nodes.len(), 12);
+        let printed = unshared.to_string_expr();
+        assert_eq!(printed, tree.to_string_expr());
+        assert_eq!(translate_condition_string(&printed).unwrap(), tree);
+    }
+
+    #[test]
+    fn test_minimal_parentheses() {
+        let [a, b, _] = TOKENS;
+        let above = |t, p: u64| ConditionBuilder::price_above(t, Price::from(p));
+        let tree = above(a, 1)
+            .and(above(b, 2).or(above(a, 3)))
+            .or(above(b, 4).and(above(a, 5).and(above(b, 6))))
+            .implies(above(a, 7).implies(above(b, 8)).not())
+            .build()
+            .unwrap();
+        assert_eq!(
+            tree.to_string_expr(),
+            format!(
+                "PRICE_ABOVE({a}, 1) AND (PRICE_ABOVE({b}, 2) OR PRICE_ABOVE({a}, 3)) OR \
+                 PRICE_ABOVE({b}, 4) AND (PRICE_ABOVE({a}, 5) AND PRICE_ABOVE({b}, 6)) IMPLIES \
+                 NOT(PRICE_ABOVE({a}, 7) IMPLIES PRICE_ABOVE({b}, 8))"
+            )
+        );
+
+        let tree = above(a, 1)
+            .not()
+            .and(ConditionBuilder::any(vec![
+                above(b, 2),
+                above(a, 3).or(above(b, 4)),
+            ]))
+            .build()
+            .unwrap();
+        assert_eq!(
+            tree.to_string_pretty(PrintOptions {
+                style: ExprStyle::Symbols,
+                multiline: true
+            }),
+            format!(
+                "!(price({a}) > 1)\n\
+                 && ANY(\n    price({b}) > 2,\n    price({a}) > 3\n    || price({b}) > 4\n)"
+            )
+        );
+        assert_eq!(
+            tree.to_string_pretty(PrintOptions {
+                style: ExprStyle::Keywords,
+                multiline: true
+            }),
+            format!(
+                "NOT PRICE_ABOVE({a}, 1)\n\
+                 AND ANY(\n    PRICE_ABOVE({b}, 2),\n    PRICE_ABOVE({a}, 3)\n    OR PRICE_ABOVE({b}, 4)\n)"
+            )
+        );
+    }
+}
@@ -0,0 +1,111 @@
+// Deterministic pseudo-random numbers (splitmix64), so that a search or a simulation run again
+// with the same seed gives the same result. not for anything that has to be unpredictable
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    // generator for the `index`-th of many runs that share `seed`, e.g. one per simulated path.
+    // the seed goes through splitmix before the index is added, so (seed + 1, index) is not the
+    // run (seed, index + 1) and the runs of neighbouring seeds don't overlap
+    pub fn stream(seed: u64, index: u64) -> Self {
+        let base = Rng::new(seed).next_u64();
+        Self::new(Rng::new(base.wrapping_add(index)).next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // standard normal, by Box-Muller
+    pub fn normal(&mut self) -> f64 {
+        // 1 - u is in (0, 1], so the logarithm is finite
+        let u = 1.0 - self.next_f64();
+        let v = self.next_f64();
+        (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+    }
+
+    // Poisson with the given mean, by counting uniform draws (Knuth); for small means
+    pub fn poisson(&mut self, mean: f64) -> u64 {
+        let limit = (-mean).exp();
+        let mut count = 0;
+        let mut product = self.next_f64();
+        while product > limit {
+            count += 1;
+            product *= self.next_f64();
+        }
+        count
+    }
+
+    // uniform in 0..n, for n > 0
+    pub fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_rng() {
+        // the reference splitmix64 sequence
+        let mut rng = Rng::new(0);
+        assert_eq!(rng.next_u64(), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(rng.next_u64(), 0x6e78_9e6a_a1b9_65f4);
+
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..1_000 {
+            let x = a.next_f64();
+            assert!((0.0..1.0).contains(&x));
+            assert_eq!(x, b.next_f64());
+            assert!(a.below(7) < 7);
+            b.below(7);
+        }
+        assert_eq!(Rng::new(1).below(1), 0);
+    }
+
+    #[test]
+    fn test_streams() {
+        let first = |seed: u64| {
+            (0..1_000)
+                .map(|i| Rng::stream(seed, i).next_u64())
+                .collect::<HashSet<_>>()
+        };
+        let (zero, one) = (first(0), first(1));
+        assert_eq!((zero.len(), one.len()), (1_000, 1_000));
+        assert!(zero.is_disjoint(&one));
+    }
+
+    #[test]
+    fn test_distributions() {
+        let mut rng = Rng::new(7);
+        let n = 20_000;
+        let normals = (0..n).map(|_| rng.normal()).collect::<Vec<_>>();
+        let mean = normals.iter().sum::<f64>() / n as f64;
+        let variance = normals.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n as f64;
+        assert!(mean.abs() < 0.03, "{}", mean);
+        assert!((variance - 1.0).abs() < 0.05, "{}", variance);
+
+        let counts = (0..n).map(|_| rng.poisson(0.5)).collect::<Vec<_>>();
+        let mean = counts.iter().sum::<u64>() as f64 / n as f64;
+        assert!((mean - 0.5).abs() < 0.02, "{}", mean);
+        assert_eq!(rng.poisson(0.0), 0);
+    }
+}
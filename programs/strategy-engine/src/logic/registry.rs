@@ -0,0 +1,234 @@
+use crate::logic::actions::ActionTree;
+use crate::logic::conditions::{AtomicCondition, ConditionTree, ConditionType};
+use crate::logic::printer::PrintOptions;
+use crate::logic::strategy::Strategy;
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+
+pub const MAX_SYMBOL_LEN: usize = 10;
+
+// What a symbol in the DSL stands for
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TokenInfo {
+    pub symbol: String,
+    pub mint: Pubkey,
+    pub decimals: u8,
+    // price feed the keeper reads this token's price from
+    pub oracle: Pubkey,
+}
+
+impl TokenInfo {
+    pub const MAX_SIZE: usize = 4 + MAX_SYMBOL_LEN + 32 + 1 + 32;
+}
+
+// Symbols such as SOL or USDC, resolved to mints while parsing. Lives on chain inside a
+// `TokenRegistryAccount`, or is loaded off chain from a TOML file:
+//
+//   [tokens.SOL]
+//   mint = "So11111111111111111111111111111111111111112"
+//   decimals = 9
+//   oracle = "H6ARHf6YXhGYeQfUzQNGk6rDNnLBQKrenN712K4AQJEG"
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TokenRegistry {
+    pub tokens: Vec<TokenInfo>,
+}
+
+impl TokenRegistry {
+    pub fn new(tokens: Vec<TokenInfo>) -> Result<Self> {
+        let mut registry = Self::default();
+        for token in tokens {
+            registry.add(token)?;
+        }
+        Ok(registry)
+    }
+
+    pub fn add(&mut self, token: TokenInfo) -> Result<()> {
+        let mut chars = token.symbol.chars();
+        let well_formed = token.symbol.len() <= MAX_SYMBOL_LEN
+            && chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        require!(well_formed, ErrorCode::InvalidTokenSymbol);
+        require!(
+            self.by_symbol(&token.symbol).is_none() && self.by_mint(&token.mint).is_none(),
+            ErrorCode::TokenConflict
+        );
+        self.tokens.push(token);
+        Ok(())
+    }
+
+    // symbols are matched exactly: `sol` is not `SOL`
+    pub fn by_symbol(&self, symbol: &str) -> Option<&TokenInfo> {
+        self.tokens.iter().find(|t| t.symbol == symbol)
+    }
+
+    pub fn by_mint(&self, mint: &Pubkey) -> Option<&TokenInfo> {
+        self.tokens.iter().find(|t| t.mint == *mint)
+    }
+
+    // the symbol for `mint`, or the mint itself if it isn't registered
+    pub fn label(&self, mint: &Pubkey) -> String {
+        self.by_mint(mint)
+            .map_or_else(|| mint.to_string(), |t| t.symbol.clone())
+    }
+
+    // tokens `tree` refers to that have no entry here, e.g. a mistyped raw pubkey
+    pub fn unknown_tokens(&self, tree: &ConditionTree) -> Vec<Pubkey> {
+        let mut unknown = vec![];
+        for node in &tree.nodes {
+            let tokens = match &node.condition_type {
+                ConditionType::Atomic(AtomicCondition::Compare { left, right, .. }) => {
+                    [left.tokens(), right.tokens()].concat()
+                }
+                ConditionType::Atomic(atomic) => atomic.token().into_iter().collect(),
+                _ => vec![],
+            };
+            for token in tokens {
+                if self.by_mint(&token).is_none() && !unknown.contains(&token) {
+                    unknown.push(token);
+                }
+            }
+        }
+        unknown
+    }
+
+    #[cfg(not(target_os = "solana"))]
+    pub fn from_toml(input: &str) -> Result<Self> {
+        use std::collections::BTreeMap;
+        use std::str::FromStr;
+
+        #[derive(serde::Deserialize)]
+        struct File {
+            tokens: BTreeMap<String, Entry>,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct Entry {
+            mint: String,
+            decimals: u8,
+            oracle: String,
+        }
+
+        let file: File = toml::from_str(input).map_err(|e| {
+            msg!("invalid token registry: {}", e);
+            error!(ErrorCode::InvalidRegistryFile)
+        })?;
+        let key = |s: &str| Pubkey::from_str(s).map_err(|_| error!(ErrorCode::InvalidRegistryFile));
+        let mut registry = Self::default();
+        for (symbol, entry) in file.tokens {
+            registry.add(TokenInfo {
+                symbol,
+                mint: key(&entry.mint)?,
+                decimals: entry.decimals,
+                oracle: key(&entry.oracle)?,
+            })?;
+        }
+        Ok(registry)
+    }
+}
+
+impl ConditionTree {
+    // like `to_string_expr`, but registered tokens are printed as their symbols
+    pub fn to_string_expr_with(&self, registry: &TokenRegistry) -> String {
+        self.to_string_pretty_with(PrintOptions::default(), registry)
+    }
+
+    pub fn to_string_pretty_with(&self, options: PrintOptions, registry: &TokenRegistry) -> String {
+        self.print_with(options, &|t| registry.label(t))
+    }
+}
+
+impl ActionTree {
+    // like `to_string_expr`, but registered tokens are printed as their symbols
+    pub fn to_string_expr_with(&self, registry: &TokenRegistry) -> String {
+        self.string_node_with(self.root_index, &|t| registry.label(t))
+    }
+}
+
+impl Strategy {
+    // like `to_string_expr`, but registered tokens are printed as their symbols
+    pub fn to_string_expr_with(&self, registry: &TokenRegistry) -> String {
+        self.render(&|c| c.to_string_expr_with(registry), &|a| {
+            a.to_string_expr_with(registry)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::parser::actionParser::parse_action_string_with;
+    use crate::logic::parser::conditionParser::{
+        parse_condition_string_with, translate_condition_string,
+    };
+    use crate::logic::parser::context::ParseContext;
+    use crate::logic::parser::strategyParser::parse_strategy_string_with;
+
+    const REGISTRY: &str = r#"
+        [tokens.SOL]
+        mint = "So11111111111111111111111111111111111111112"
+        decimals = 9
+        oracle = "H6ARHf6YXhGYeQfUzQNGk6rDNnLBQKrenN712K4AQJEG"
+
+        [tokens.USDC]
+        mint = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v"
+        decimals = 6
+        oracle = "Gnt27xtC473ZT2Mw5u8wZ68Z3gULkSTb5DuxJy7eJotD"
+    "#;
+
+    #[test]
+    fn test_symbols_round_trip() {
+        let registry = TokenRegistry::from_toml(REGISTRY).unwrap();
+        let sol = registry.by_symbol("SOL").unwrap().mint;
+        assert_eq!(registry.by_symbol("USDC").unwrap().decimals, 6);
+        let ctx = ParseContext::with_registry(&registry);
+
+        let input = "PRICE_ABOVE(SOL, 100) AND PRICE_BELOW(USDC, 1.01)";
+        let tree = parse_condition_string_with(&ctx, input).unwrap();
+        assert_eq!(tree.to_string_expr_with(&registry), input);
+        assert!(tree.to_string_expr().contains(&sol.to_string()));
+        assert!(registry.unknown_tokens(&tree).is_empty());
+
+        // raw pubkeys still parse, and are reported if they aren't registered
+        let stray = Pubkey::new_unique();
+        let input = format!("PRICE_ABOVE(SOL, 100) OR price(SOL) / price({}) > 5", stray);
+        let tree = parse_condition_string_with(&ctx, &input).unwrap();
+        assert_eq!(registry.unknown_tokens(&tree), vec![stray]);
+
+        let input = "BUY(SOL, 1000000000) THEN SELL(USDC, 50000000)";
+        let actions = parse_action_string_with(&ctx, input).unwrap();
+        assert_eq!(actions.to_string_expr_with(&registry), input);
+
+        let input = "WHEN PRICE_BELOW(SOL, 90) THEN BUY(SOL, 10%) EVERY 1h";
+        let strategy = parse_strategy_string_with(&ctx, input).unwrap();
+        assert_eq!(strategy.to_string_expr_with(&registry), input);
+    }
+
+    #[test]
+    fn test_unknown_symbols_are_rejected() {
+        let registry = TokenRegistry::from_toml(REGISTRY).unwrap();
+        let typo = "PRICE_ABOVE(SOLL, 100)";
+        let ctx = ParseContext::with_registry(&registry);
+        assert!(parse_condition_string_with(&ctx, typo).is_err());
+        // symbols only resolve through the context's registry
+        assert!(translate_condition_string("PRICE_ABOVE(SOL, 100)").is_err());
+    }
+
+    #[test]
+    fn test_registry_conflicts() {
+        let mut registry = TokenRegistry::from_toml(REGISTRY).unwrap();
+        let sol = registry.by_symbol("SOL").unwrap().clone();
+        let token = |symbol: &str, mint| TokenInfo {
+            symbol: symbol.to_string(),
+            mint,
+            decimals: 9,
+            oracle: Pubkey::new_unique(),
+        };
+        assert!(registry.add(token("SOL", Pubkey::new_unique())).is_err());
+        assert!(registry.add(token("WSOL", sol.mint)).is_err());
+        assert!(registry.add(token("9LIVES", Pubkey::new_unique())).is_err());
+        assert!(registry
+            .add(token("JITO_SOL", Pubkey::new_unique()))
+            .is_ok());
+        assert!(TokenRegistry::from_toml("[tokens.SOL]\nmint = \"nope\"").is_err());
+    }
+}
@@ -0,0 +1,316 @@
+use crate::logic::backtest::{BacktestReport, EquityPoint};
+use crate::logic::metrics::Metrics;
+use crate::logic::printer::PrintOptions;
+use crate::logic::strategy::Strategy;
+use anchor_lang::prelude::*;
+
+// Backtest results as a document a reviewer can read on its own: the strategy, the metrics, an
+// ASCII chart of the equity curve and the trade log, as markdown or as a standalone HTML page
+
+// The equity curve in `height` rows, one column per point after sampling it down to `width`
+// points, with the highest and lowest equity marked on the axis:
+//
+//   10291.00 |      ***
+//            |     *
+//    9991.00 |*****
+//            +---------
+//             0     180
+pub fn ascii_chart(curve: &[EquityPoint], width: usize, height: usize) -> String {
+    if curve.is_empty() || width == 0 || height == 0 {
+        return String::new();
+    }
+    let columns = width.min(curve.len());
+    let sampled = (0..columns)
+        .map(|c| match columns {
+            1 => curve[curve.len() - 1],
+            _ => curve[c * (curve.len() - 1) / (columns - 1)],
+        })
+        .collect::<Vec<_>>();
+    let (low, high) = sampled.iter().fold((f64::MAX, f64::MIN), |(lo, hi), p| {
+        (lo.min(p.equity), hi.max(p.equity))
+    });
+    let row_of = |equity: f64| match high - low {
+        range if range > 0.0 => ((equity - low) / range * (height - 1) as f64).round() as usize,
+        _ => (height - 1) / 2,
+    };
+    let mut grid = vec![vec![' '; columns]; height];
+    for (column, point) in sampled.iter().enumerate() {
+        grid[height - 1 - row_of(point.equity)][column] = '*';
+    }
+
+    let top = format!("{:.2}", high);
+    let bottom = format!("{:.2}", low);
+    let gutter = top.len().max(bottom.len());
+    let mut out = String::new();
+    for (i, row) in grid.iter().enumerate() {
+        let label = match i {
+            0 => &top,
+            _ if i == height - 1 => &bottom,
+            _ => "",
+        };
+        let line = row.iter().collect::<String>();
+        out += &format!("{:>gutter$} |{}\n", label, line.trim_end());
+    }
+    out += &format!("{:gutter$} +{}\n", "", "-".repeat(columns));
+    let first = sampled[0].timestamp.to_string();
+    let last = sampled[columns - 1].timestamp.to_string();
+    let space = columns.saturating_sub(first.len() + last.len()).max(1);
+    out += &format!("{:gutter$}  {}{:space$}{}\n", "", first, "", last);
+    out
+}
+
+fn percent(value: f64) -> String {
+    format!("{:+.2}%", value * 100.0)
+}
+
+fn optional(value: Option<f64>, show: fn(f64) -> String) -> String {
+    value.map_or_else(|| "n/a".to_string(), show)
+}
+
+// the metrics as the reports show them, in the order they show them
+pub fn metric_rows(metrics: &Metrics) -> Vec<(&'static str, String)> {
+    let ratio = |value: f64| format!("{:.2}", value);
+    vec![
+        ("Initial equity", format!("{:.2}", metrics.initial_equity)),
+        ("Final equity", format!("{:.2}", metrics.final_equity)),
+        ("Total return", percent(metrics.total_return)),
+        ("CAGR", optional(metrics.cagr, percent)),
+        (
+            "Max drawdown",
+            format!("{:.2}%", metrics.max_drawdown * 100.0),
+        ),
+        ("Sharpe ratio", optional(metrics.sharpe, ratio)),
+        ("Sortino ratio", optional(metrics.sortino, ratio)),
+        (
+            "Win rate",
+            optional(metrics.win_rate, |w| format!("{:.1}%", w * 100.0)),
+        ),
+        ("Turnover", format!("{:.2}x", metrics.turnover)),
+        ("Exposure", format!("{:.1}%", metrics.exposure * 100.0)),
+        ("Triggers", metrics.triggers.to_string()),
+        (
+            "Trades",
+            format!("{} ({} rejected)", metrics.trades, metrics.rejected),
+        ),
+        ("Fees", format!("{:.2}", metrics.fees)),
+    ]
+}
+
+const TRADE_HEADER: [&str; 6] = ["Time", "Action", "Quantity", "Price", "Fee", "Result"];
+
+fn trade_rows(report: &BacktestReport, label: &dyn Fn(&Pubkey) -> String) -> Vec<[String; 6]> {
+    report
+        .trades
+        .iter()
+        .map(|trade| {
+            [
+                trade.timestamp.to_string(),
+                trade.action.to_string_with(label),
+                trade.quantity.to_string(),
+                trade
+                    .price
+                    .map_or_else(String::new, |price| format!("{:.4}", price)),
+                format!("{:.4}", trade.fee),
+                trade
+                    .rejected
+                    .as_ref()
+                    .map_or_else(|| "filled".to_string(), |r| format!("rejected: {}", r)),
+            ]
+        })
+        .collect()
+}
+
+fn strategy_text(strategy: &Strategy, label: &dyn Fn(&Pubkey) -> String) -> String {
+    strategy.render(
+        &|condition| condition.print_with(PrintOptions::default(), label),
+        &|action| action.string_node_with(action.root_index, label),
+    )
+}
+
+const CHART_WIDTH: usize = 72;
+const CHART_HEIGHT: usize = 16;
+
+// `|` would end a markdown table cell
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+pub fn render_markdown(
+    strategy: &Strategy,
+    report: &BacktestReport,
+    metrics: &Metrics,
+    label: &dyn Fn(&Pubkey) -> String,
+) -> String {
+    let mut out = String::from("# Backtest report\n\n");
+    out += &format!("```\n{}\n```\n\n", strategy_text(strategy, label));
+    out += "## Metrics\n\n| Metric | Value |\n| --- | ---: |\n";
+    for (name, value) in metric_rows(metrics) {
+        out += &format!("| {} | {} |\n", name, value);
+    }
+    out += &format!(
+        "\n## Equity\n\n```text\n{}```\n\n## Trades\n\n",
+        ascii_chart(&report.equity_curve, CHART_WIDTH, CHART_HEIGHT)
+    );
+    let rows = trade_rows(report, label);
+    if rows.is_empty() {
+        return out + "No trades.\n";
+    }
+    out += &format!("| {} |\n", TRADE_HEADER.join(" | "));
+    out += "| ---: | --- | ---: | ---: | ---: | --- |\n";
+    for row in rows {
+        let cells = row.iter().map(|c| cell(c)).collect::<Vec<_>>();
+        out += &format!("| {} |\n", cells.join(" | "));
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em auto;max-width:60em;color:#222}\
+pre{background:#f6f6f6;padding:1em;overflow-x:auto}\
+table{border-collapse:collapse;margin-bottom:1em}\
+th,td{border:1px solid #ccc;padding:.3em .6em;text-align:left}\
+td.n{text-align:right;font-variant-numeric:tabular-nums}";
+
+// a single page with its styles inline, so it can be attached or archived as it is
+pub fn render_html(
+    strategy: &Strategy,
+    report: &BacktestReport,
+    metrics: &Metrics,
+    label: &dyn Fn(&Pubkey) -> String,
+) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Backtest report</title>\n<style>{}</style>\n</head>\n<body>\n\
+         <h1>Backtest report</h1>\n<pre>{}</pre>\n<h2>Metrics</h2>\n<table>\n",
+        STYLE,
+        escape(&strategy_text(strategy, label))
+    );
+    for (name, value) in metric_rows(metrics) {
+        out += &format!(
+            "<tr><th>{}</th><td class=\"n\">{}</td></tr>\n",
+            name,
+            escape(&value)
+        );
+    }
+    out += &format!(
+        "</table>\n<h2>Equity</h2>\n<pre>{}</pre>\n<h2>Trades</h2>\n",
+        escape(&ascii_chart(
+            &report.equity_curve,
+            CHART_WIDTH,
+            CHART_HEIGHT
+        ))
+    );
+    let rows = trade_rows(report, label);
+    if rows.is_empty() {
+        out += "<p>No trades.</p>\n";
+    } else {
+        out += "<table>\n<tr>";
+        for name in TRADE_HEADER {
+            out += &format!("<th>{}</th>", name);
+        }
+        out += "</tr>\n";
+        for row in rows {
+            out += "<tr>";
+            for (i, text) in row.iter().enumerate() {
+                let class = if matches!(i, 1 | 5) {
+                    ""
+                } else {
+                    " class=\"n\""
+                };
+                out += &format!("<td{}>{}</td>", class, escape(text));
+            }
+            out += "</tr>\n";
+        }
+        out += "</table>\n";
+    }
+    out + "</body>\n</html>\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::backtest::{backtest, BacktestConfig, PriceHistory};
+    use crate::logic::parser::strategyParser::translate_strategy_string;
+    use crate::logic::registry::TokenRegistry;
+
+    fn point(timestamp: u64, equity: f64) -> EquityPoint {
+        EquityPoint {
+            timestamp,
+            equity,
+            positions: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_ascii_chart() {
+        let curve = [point(0, 100.0), point(60, 90.0), point(120, 120.0)];
+        assert_eq!(
+            ascii_chart(&curve, 10, 4),
+            "120.00 |  *\n       |\n       |*\n 90.00 | *\n       +---\n        0 120\n"
+        );
+        // sampled down to the width, keeping both ends
+        let long = (0..100).map(|i| point(i, i as f64)).collect::<Vec<_>>();
+        assert_eq!(
+            ascii_chart(&long, 10, 3),
+            "99.00 |       ***\n      |   ****\n 0.00 |***\n      +----------\n       0       99\n"
+        );
+        // a flat curve sits in the middle
+        assert_eq!(
+            ascii_chart(&[point(5, 1.0)], 10, 3),
+            "1.00 |\n     |*\n1.00 |\n     +-\n      5 5\n"
+        );
+        assert_eq!(ascii_chart(&[], 10, 3), "");
+    }
+
+    #[test]
+    fn test_documents() {
+        let sol = Pubkey::new_unique();
+        let strategy = translate_strategy_string(&format!(
+            "WHEN PRICE_BELOW({sol}, 100) THEN BUY({sol}, 10) THEN SELL({sol}, 99) EVERY 1m"
+        ))
+        .unwrap();
+        let history = PriceHistory::from_csv(
+            &format!("timestamp,{sol}\n0,100\n60,90\n120,110\n"),
+            &TokenRegistry::default(),
+        )
+        .unwrap();
+        let report = backtest(&strategy, &history, &BacktestConfig::default());
+        let metrics = Metrics::from_report(&report);
+        let label = |token: &Pubkey| {
+            if *token == sol {
+                "SOL".to_string()
+            } else {
+                token.to_string()
+            }
+        };
+
+        let markdown = render_markdown(&strategy, &report, &metrics, &label);
+        assert!(markdown.contains("WHEN PRICE_BELOW(SOL, 100) THEN BUY(SOL, 10)"));
+        assert!(
+            markdown.contains("| Total return | +2.00% |\n"),
+            "{}",
+            markdown
+        );
+        assert!(markdown.contains("| 60 | BUY(SOL, 10) | 10 | 90.0000 | 0.0000 | filled |\n"));
+        assert!(markdown.contains("rejected: sells 99 units, holds 10 |\n"));
+        assert!(
+            markdown.contains("```text\n10200.00 |  *\n"),
+            "{}",
+            markdown
+        );
+        // a two minute run has no meaningful yearly rate
+        assert!(markdown.contains("| CAGR | n/a |\n"));
+
+        let html = render_html(&strategy, &report, &metrics, &label);
+        assert!(html.starts_with("<!DOCTYPE html>") && html.ends_with("</html>\n"));
+        assert!(html.contains("<tr><th>Win rate</th><td class=\"n\">n/a</td></tr>"));
+        assert!(html.contains("<td>BUY(SOL, 10)</td>"));
+        assert!(!html.contains("<script"));
+    }
+}
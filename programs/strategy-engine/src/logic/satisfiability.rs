@@ -0,0 +1,374 @@
+use crate::logic::conditions::{AtomicCondition, ConditionTree, ConditionType, NodeIndex};
+use crate::logic::price::Price;
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+// Range of prices between two bounds. the lower bound is never `Unbounded`, since prices
+// can't go below zero; prices are exact decimals, so `(a, b)` is non-empty whenever `a < b`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PriceInterval {
+    pub lo: Bound<Price>,
+    pub hi: Bound<Price>,
+}
+
+impl PriceInterval {
+    pub const FULL: PriceInterval = PriceInterval {
+        lo: Bound::Included(Price::ZERO),
+        hi: Bound::Unbounded,
+    };
+
+    // prices for which `atomic` holds, or `None` if there are none (e.g. PRICE_BELOW(t, 0))
+    // or `atomic` is a custom condition or a comparison of value expressions, which don't
+    // constrain a single price
+    pub fn of_atom(atomic: &AtomicCondition) -> Option<Self> {
+        let interval = match *atomic {
+            AtomicCondition::PriceAbove { price, .. } => Self {
+                lo: Bound::Excluded(price),
+                hi: Bound::Unbounded,
+            },
+            AtomicCondition::PriceBelow { price, .. } => Self {
+                lo: Bound::Included(Price::ZERO),
+                hi: Bound::Excluded(price),
+            },
+            AtomicCondition::PriceAtOrAbove { price, .. } => Self {
+                lo: Bound::Included(price),
+                hi: Bound::Unbounded,
+            },
+            AtomicCondition::PriceAtOrBelow { price, .. } => Self {
+                lo: Bound::Included(Price::ZERO),
+                hi: Bound::Included(price),
+            },
+            AtomicCondition::Custom { .. } | AtomicCondition::Compare { .. } => return None,
+        };
+        (!interval.is_empty()).then_some(interval)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match (self.lo, self.hi) {
+            (_, Bound::Unbounded) | (Bound::Unbounded, _) => false,
+            (Bound::Included(lo), Bound::Included(hi)) => lo > hi,
+            (
+                Bound::Included(lo) | Bound::Excluded(lo),
+                Bound::Included(hi) | Bound::Excluded(hi),
+            ) => lo >= hi,
+        }
+    }
+
+    pub fn contains(&self, price: Price) -> bool {
+        let above = match self.lo {
+            Bound::Included(lo) => price >= lo,
+            Bound::Excluded(lo) => price > lo,
+            Bound::Unbounded => true,
+        };
+        let below = match self.hi {
+            Bound::Included(hi) => price <= hi,
+            Bound::Excluded(hi) => price < hi,
+            Bound::Unbounded => true,
+        };
+        above && below
+    }
+
+    pub fn intersect(&self, other: &Self) -> Option<Self> {
+        let interval = Self {
+            lo: tighter(self.lo, other.lo, Ordering::Greater),
+            hi: tighter(self.hi, other.hi, Ordering::Less),
+        };
+        (!interval.is_empty()).then_some(interval)
+    }
+}
+
+// of two lower (`keep` = Greater) or upper (`keep` = Less) bounds, the one that excludes more
+fn tighter(a: Bound<Price>, b: Bound<Price>, keep: Ordering) -> Bound<Price> {
+    match (a, b) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            match x.cmp(&y) {
+                Ordering::Equal if matches!(a, Bound::Excluded(_)) => a,
+                Ordering::Equal => b,
+                order if order == keep => a,
+                _ => b,
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for PriceInterval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.lo {
+            Bound::Included(lo) => write!(f, "[{}, ", lo)?,
+            Bound::Excluded(lo) => write!(f, "({}, ", lo)?,
+            Bound::Unbounded => write!(f, "(-inf, ")?,
+        }
+        match self.hi {
+            Bound::Included(hi) => write!(f, "{}]", hi),
+            Bound::Excluded(hi) => write!(f, "{})", hi),
+            Bound::Unbounded => write!(f, "inf)"),
+        }
+    }
+}
+
+// Caps on the check `create_vault` runs. it expands the tree into an OR of ANDs of atoms,
+// which can grow exponentially, so past these it gives up instead of running out of compute.
+// the node cap also bounds the recursion depth
+pub const MAX_CHECKED_NODES: usize = 32;
+pub const MAX_CHECKED_ATOMS: usize = 16;
+pub const MAX_CHECKED_TERMS: usize = 64;
+
+// an atom, and whether it appears un-negated
+type Literal<'a> = (&'a AtomicCondition, bool);
+// a conjunction of literals; a list of terms is their disjunction
+type Term<'a> = Vec<Literal<'a>>;
+
+impl ConditionTree {
+    // whether some context makes the tree true, or `None` if the tree is past the caps above.
+    // a token may have no price, which makes every price atom on it false: a term that only
+    // negates atoms on a token is satisfied by leaving its price out. custom conditions and
+    // comparisons of value expressions are opaque and only conflict with their own negation.
+    // expects a tree that passed `validate`
+    pub fn check_satisfiable(&self) -> Option<bool> {
+        let atoms = self
+            .nodes
+            .iter()
+            .filter(|n| matches!(n.condition_type, ConditionType::Atomic(_)))
+            .count();
+        if self.nodes.len() > MAX_CHECKED_NODES || atoms > MAX_CHECKED_ATOMS {
+            return None;
+        }
+        let terms = self.terms(self.root_index, false)?;
+        Some(terms.iter().any(|term| is_consistent(term)))
+    }
+
+    // the terms of the node (or of its negation), or `None` once there are too many
+    fn terms(&self, index: NodeIndex, negated: bool) -> Option<Vec<Term<'_>>> {
+        let terms = |child: &NodeIndex, negated: bool| self.terms(*child, negated);
+        match &self.nodes[index as usize].condition_type {
+            ConditionType::Atomic(atomic) => Some(vec![vec![(atomic, !negated)]]),
+            ConditionType::Const(value) if *value != negated => Some(vec![vec![]]),
+            ConditionType::Const(_) => Some(vec![]),
+            ConditionType::Not { child } => terms(child, !negated),
+            // De Morgan: a negated AND is an OR of negations and the other way around
+            ConditionType::And { left, right } | ConditionType::Or { left, right } => {
+                let is_and = matches!(
+                    self.nodes[index as usize].condition_type,
+                    ConditionType::And { .. }
+                );
+                let parts = vec![terms(left, negated)?, terms(right, negated)?];
+                if is_and != negated {
+                    and(parts)
+                } else {
+                    or(parts)
+                }
+            }
+            ConditionType::All { children } | ConditionType::Any { children } => {
+                let is_all = matches!(
+                    self.nodes[index as usize].condition_type,
+                    ConditionType::All { .. }
+                );
+                let parts = children
+                    .iter()
+                    .map(|c| terms(c, negated))
+                    .collect::<Option<Vec<_>>>()?;
+                if is_all != negated {
+                    and(parts)
+                } else {
+                    or(parts)
+                }
+            }
+            // XOR is `(l AND NOT r) OR (NOT l AND r)`, its negation `(l AND r) OR (NOT l AND NOT r)`
+            ConditionType::Xor { left, right } => or(vec![
+                and(vec![terms(left, false)?, terms(right, !negated)?])?,
+                and(vec![terms(left, true)?, terms(right, negated)?])?,
+            ]),
+            ConditionType::Implies { left, right } if negated => {
+                and(vec![terms(left, false)?, terms(right, true)?])
+            }
+            ConditionType::Implies { left, right } => {
+                or(vec![terms(left, true)?, terms(right, false)?])
+            }
+            // fewer than `k` true children means more than `n - k` false ones
+            ConditionType::AtLeast { k, children } => {
+                let k = if negated {
+                    children.len() + 1 - *k as usize
+                } else {
+                    *k as usize
+                };
+                let parts = children
+                    .iter()
+                    .map(|c| terms(c, negated))
+                    .collect::<Option<Vec<_>>>()?;
+                at_least(parts, k)
+            }
+        }
+    }
+}
+
+// every combination of one term from each part, dropping combinations that contain an atom
+// and its negation
+fn and(parts: Vec<Vec<Term<'_>>>) -> Option<Vec<Term<'_>>> {
+    let mut product: Vec<Term> = vec![vec![]];
+    for part in parts {
+        if product.len() * part.len() > MAX_CHECKED_TERMS * MAX_CHECKED_TERMS {
+            return None;
+        }
+        let mut next = vec![];
+        for acc in &product {
+            'terms: for term in &part {
+                let mut merged = acc.clone();
+                for &(atomic, positive) in term {
+                    if merged.contains(&(atomic, !positive)) {
+                        continue 'terms;
+                    }
+                    if !merged.contains(&(atomic, positive)) {
+                        merged.push((atomic, positive));
+                    }
+                }
+                next.push(merged);
+            }
+        }
+        if next.len() > MAX_CHECKED_TERMS {
+            return None;
+        }
+        product = next;
+    }
+    Some(product)
+}
+
+fn or(parts: Vec<Vec<Term<'_>>>) -> Option<Vec<Term<'_>>> {
+    let terms: Vec<Term> = parts.into_iter().flatten().collect();
+    (terms.len() <= MAX_CHECKED_TERMS).then_some(terms)
+}
+
+// at least `k` of the parts hold. built from the last part to the first: after each step,
+// `suffix[j]` holds the terms for "at least `j` of the parts seen so far"
+fn at_least(parts: Vec<Vec<Term<'_>>>, k: usize) -> Option<Vec<Term<'_>>> {
+    let mut suffix: Vec<Vec<Term>> = (0..=k)
+        .map(|j| if j == 0 { vec![vec![]] } else { vec![] })
+        .collect();
+    for part in parts.into_iter().rev() {
+        for j in (1..=k).rev() {
+            let with_part = and(vec![part.clone(), suffix[j - 1].clone()])?;
+            suffix[j] = or(vec![with_part, std::mem::take(&mut suffix[j])])?;
+        }
+    }
+    suffix.pop()
+}
+
+// whether some context makes every literal of the term true
+fn is_consistent(term: &Term<'_>) -> bool {
+    // `and` already dropped terms with an atom and its negation; what is left to check is
+    // whether the price atoms on each token leave any price for it
+    for &(atomic, positive) in term {
+        let Some(token) = atomic.token() else {
+            continue;
+        };
+        // a token with no un-negated atom can go without a price
+        if !positive {
+            continue;
+        }
+        let mut interval = PriceInterval::FULL;
+        for &(other, other_positive) in term {
+            if other.token() != Some(token) {
+                continue;
+            }
+            let allowed = if other_positive {
+                PriceInterval::of_atom(other)
+            } else {
+                other.negate().as_ref().and_then(PriceInterval::of_atom)
+            };
+            match allowed.and_then(|allowed| interval.intersect(&allowed)) {
+                Some(narrowed) => interval = narrowed,
+                None => return false,
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::conditions::ConditionBuilder;
+    use anchor_lang::prelude::Pubkey;
+
+    #[test]
+    fn test_missing_prices() {
+        let token = Pubkey::new_unique();
+        let above = || ConditionBuilder::price_above(token, Price::from(100));
+        let at_or_below = || ConditionBuilder::price_at_or_below(token, Price::from(100));
+
+        // true when the token has no price
+        let tree = above().not().and(at_or_below().not()).build().unwrap();
+        assert_eq!(tree.check_satisfiable(), Some(true));
+
+        // a price is needed, and no price is both
+        let tree = above().and(at_or_below()).build().unwrap();
+        assert_eq!(tree.check_satisfiable(), Some(false));
+        let tree = above().and(above().not()).build().unwrap();
+        assert_eq!(tree.check_satisfiable(), Some(false));
+        let tree = above().and(at_or_below().not()).build().unwrap();
+        assert_eq!(tree.check_satisfiable(), Some(true));
+        let tree = ConditionBuilder::price_below(token, Price::ZERO)
+            .build()
+            .unwrap();
+        assert_eq!(tree.check_satisfiable(), Some(false));
+    }
+
+    #[test]
+    fn test_operators() {
+        let t1 = Pubkey::new_unique();
+        let t2 = Pubkey::new_unique();
+        let a = || ConditionBuilder::price_above(t1, Price::from(400));
+        let b = || ConditionBuilder::price_below(t1, Price::from(10));
+        let c = || ConditionBuilder::price_below(t2, Price::from(50));
+
+        // a and b never hold together, so at least two of three needs c
+        let tree = ConditionBuilder::at_least(2, vec![a(), b(), c()])
+            .and(c().not())
+            .build()
+            .unwrap();
+        assert_eq!(tree.check_satisfiable(), Some(false));
+        let tree = ConditionBuilder::at_least(2, vec![a(), b(), c()])
+            .build()
+            .unwrap();
+        assert_eq!(tree.check_satisfiable(), Some(true));
+
+        // NOT (a OR b) holds when t1 has no price or sits in [10, 400]
+        let tree = a().or(b()).not().build().unwrap();
+        assert_eq!(tree.check_satisfiable(), Some(true));
+        let tree = a().xor(a()).build().unwrap();
+        assert_eq!(tree.check_satisfiable(), Some(false));
+        let tree = a().implies(b()).and(a()).build().unwrap();
+        assert_eq!(tree.check_satisfiable(), Some(false));
+        let tree = ConditionBuilder::constant(false).not().build().unwrap();
+        assert_eq!(tree.check_satisfiable(), Some(true));
+
+        // custom conditions only conflict with their own negation
+        let custom = || ConditionBuilder::custom(7, vec![1]);
+        let tree = custom().and(custom().not()).build().unwrap();
+        assert_eq!(tree.check_satisfiable(), Some(false));
+        let tree = custom()
+            .and(ConditionBuilder::custom(7, vec![2]).not())
+            .build()
+            .unwrap();
+        assert_eq!(tree.check_satisfiable(), Some(true));
+    }
+
+    #[test]
+    fn test_caps() {
+        let token = Pubkey::new_unique();
+        let atom = |p: u64| ConditionBuilder::price_above(token, Price::from(p));
+
+        let wide = ConditionBuilder::any((0..=MAX_CHECKED_ATOMS as u64).map(atom).collect());
+        assert_eq!(wide.build().unwrap().check_satisfiable(), None);
+
+        // an AND of seven two-way ORs has 2^7 terms
+        let product = (0..7)
+            .map(|i| atom(2 * i).or(atom(2 * i + 1)))
+            .reduce(|acc, or| acc.and(or))
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(product.nodes.len() <= MAX_CHECKED_NODES);
+        assert_eq!(product.check_satisfiable(), None);
+    }
+}
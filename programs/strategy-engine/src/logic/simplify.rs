@@ -0,0 +1,683 @@
+use crate::logic::conditions::{
+    AtomicCondition, ConditionNode, ConditionTree, ConditionType, NodeIndex, MAX_NODES,
+};
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+use std::collections::HashMap;
+
+// Boxed, n-ary view of a condition tree. The arena form is awkward to rewrite in place,
+// so every pass lifts the tree into an `Expr`, rewrites it and lowers it back.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Expr {
+    Const(bool),
+    Atom(AtomicCondition),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+// Cap on the `Expr` that `simplify` lifts a tree into. the lift writes out a node shared by
+// several parents at every use and expands AT_LEAST into all of its k-sized subsets, so the
+// `Expr` can be exponentially larger than the tree. past this, `simplify` keeps the tree as it
+// is instead of running `create_vault` out of heap or compute
+pub const MAX_SIMPLIFIED_NODES: usize = 256;
+
+impl Expr {
+    // the tree as an `Expr`, or `None` if that would take more than `max_nodes` nodes. the size
+    // is worked out before anything is lifted, so a tree past the cap costs one pass over it
+    pub(crate) fn from_tree(tree: &ConditionTree, max_nodes: usize) -> Option<Expr> {
+        let mut sizes = vec![None; tree.nodes.len()];
+        if Self::lifted_size(tree, tree.root_index, &mut sizes) > max_nodes {
+            return None;
+        }
+        Some(Self::from_node(
+            tree,
+            tree.root_index,
+            &mut vec![None; tree.nodes.len()],
+        ))
+    }
+
+    // nodes `from_node` turns `index` into, saturating at `usize::MAX`. `sizes` keeps the
+    // size of every node counted so far, so a shared node is counted once
+    fn lifted_size(tree: &ConditionTree, index: NodeIndex, sizes: &mut [Option<usize>]) -> usize {
+        if let Some(size) = sizes[index as usize] {
+            return size;
+        }
+        let mut size_of = |c: &NodeIndex| Self::lifted_size(tree, *c, sizes);
+        let size = match &tree.nodes[index as usize].condition_type {
+            ConditionType::Atomic(_) | ConditionType::Const(_) => 1,
+            ConditionType::Not { child } => size_of(child).saturating_add(1),
+            ConditionType::And { left, right } | ConditionType::Or { left, right } => size_of(left)
+                .saturating_add(size_of(right))
+                .saturating_add(1),
+            ConditionType::All { children } | ConditionType::Any { children } => children
+                .iter()
+                .fold(1, |acc: usize, c| acc.saturating_add(size_of(c))),
+            // OR of two ANDs, each with one side negated
+            ConditionType::Xor { left, right } => size_of(left)
+                .saturating_add(size_of(right))
+                .saturating_mul(2)
+                .saturating_add(5),
+            // OR(NOT left, right)
+            ConditionType::Implies { left, right } => size_of(left)
+                .saturating_add(size_of(right))
+                .saturating_add(2),
+            // an OR of C(n, k) ANDs, with every child in C(n - 1, k - 1) of them
+            ConditionType::AtLeast { k, children } => {
+                let (n, k) = (children.len(), *k as usize);
+                let children = children
+                    .iter()
+                    .fold(0, |acc: usize, c| acc.saturating_add(size_of(c)));
+                let uses = match k {
+                    0 => 0,
+                    _ => binomial(n - 1, k - 1),
+                };
+                binomial(n, k)
+                    .saturating_add(uses.saturating_mul(children))
+                    .saturating_add(1)
+            }
+        };
+        sizes[index as usize] = Some(size);
+        size
+    }
+
+    // `lifted` keeps every node lifted so far, so a shared node is lifted once and cloned
+    // into its other parents
+    fn from_node(tree: &ConditionTree, index: NodeIndex, lifted: &mut [Option<Expr>]) -> Expr {
+        if let Some(expr) = &lifted[index as usize] {
+            return expr.clone();
+        }
+        let mut lift = |c: &NodeIndex| Self::from_node(tree, *c, lifted);
+        let expr = match &tree.nodes[index as usize].condition_type {
+            ConditionType::Atomic(atomic) => Expr::Atom(atomic.clone()),
+            ConditionType::And { left, right } => Expr::And(vec![lift(left), lift(right)]),
+            ConditionType::Or { left, right } => Expr::Or(vec![lift(left), lift(right)]),
+            ConditionType::Not { child } => Expr::Not(Box::new(lift(child))),
+            ConditionType::Const(value) => Expr::Const(*value),
+            ConditionType::All { children } => Expr::And(children.iter().map(lift).collect()),
+            ConditionType::Any { children } => Expr::Or(children.iter().map(lift).collect()),
+            // expanded into an OR over every k-sized subset of the children, which grows
+            // combinatorially; `lifted_size` counts the subsets against the cap
+            ConditionType::AtLeast { k, children } => {
+                let children: Vec<Expr> = children.iter().map(lift).collect();
+                Expr::Or(
+                    combinations(children.len(), *k as usize)
+                        .into_iter()
+                        .map(|subset| {
+                            Expr::And(subset.iter().map(|i| children[*i].clone()).collect())
+                        })
+                        .collect(),
+                )
+            }
+            ConditionType::Xor { left, right } => {
+                let l = lift(left);
+                let r = lift(right);
+                Expr::Or(vec![
+                    Expr::And(vec![l.clone(), Expr::Not(Box::new(r.clone()))]),
+                    Expr::And(vec![Expr::Not(Box::new(l)), r]),
+                ])
+            }
+            ConditionType::Implies { left, right } => {
+                Expr::Or(vec![Expr::Not(Box::new(lift(left))), lift(right)])
+            }
+        };
+        lifted[index as usize] = Some(expr.clone());
+        expr
+    }
+
+    // lowers back into the arena form. identical subtrees are emitted once and shared (a DAG).
+    // like `ConditionBuilder::build`, the arena only grows, so checking the final length is enough
+    pub(crate) fn into_tree(self) -> Result<ConditionTree> {
+        let mut nodes = vec![];
+        let mut seen = HashMap::new();
+        let root_index = self.lower(&mut nodes, &mut seen);
+        require!(nodes.len() <= MAX_NODES, ErrorCode::TooManyNodes);
+        Ok(ConditionTree { nodes, root_index })
+    }
+
+    fn lower(
+        self,
+        nodes: &mut Vec<ConditionNode>,
+        seen: &mut HashMap<ConditionType, NodeIndex>,
+    ) -> NodeIndex {
+        let condition_type = match self {
+            Expr::Const(value) => ConditionType::Const(value),
+            Expr::Atom(atomic) => ConditionType::Atomic(atomic),
+            Expr::Not(child) => ConditionType::Not {
+                child: child.lower(nodes, seen),
+            },
+            Expr::And(children) | Expr::Or(children) if children.len() == 1 => {
+                return children.into_iter().next().unwrap().lower(nodes, seen);
+            }
+            // more than two operands fit in a single ALL/ANY node
+            Expr::And(children) if children.len() > 2 => ConditionType::All {
+                children: children.into_iter().map(|c| c.lower(nodes, seen)).collect(),
+            },
+            Expr::Or(children) if children.len() > 2 => ConditionType::Any {
+                children: children.into_iter().map(|c| c.lower(nodes, seen)).collect(),
+            },
+            Expr::And(children) => return Self::lower_chain(children, true, nodes, seen),
+            Expr::Or(children) => return Self::lower_chain(children, false, nodes, seen),
+        };
+        Self::intern(condition_type, nodes, seen)
+    }
+
+    fn lower_chain(
+        children: Vec<Expr>,
+        is_and: bool,
+        nodes: &mut Vec<ConditionNode>,
+        seen: &mut HashMap<ConditionType, NodeIndex>,
+    ) -> NodeIndex {
+        if children.is_empty() {
+            return Self::intern(ConditionType::Const(is_and), nodes, seen);
+        }
+        let mut children = children.into_iter();
+        let mut acc = children.next().unwrap().lower(nodes, seen);
+        for child in children {
+            let right = child.lower(nodes, seen);
+            let condition_type = if is_and {
+                ConditionType::And { left: acc, right }
+            } else {
+                ConditionType::Or { left: acc, right }
+            };
+            acc = Self::intern(condition_type, nodes, seen);
+        }
+        acc
+    }
+
+    fn intern(
+        condition_type: ConditionType,
+        nodes: &mut Vec<ConditionNode>,
+        seen: &mut HashMap<ConditionType, NodeIndex>,
+    ) -> NodeIndex {
+        if let Some(index) = seen.get(&condition_type) {
+            return *index;
+        }
+        let index = nodes.len() as NodeIndex;
+        seen.insert(condition_type.clone(), index);
+        nodes.push(ConditionNode { condition_type });
+        index
+    }
+
+    // negation normal form: pushes every `Not` down to the atoms (De Morgan). a negated atom
+    // keeps its `Not`: an atom is false when its price is missing, so NOT PRICE_ABOVE is not
+    // the same condition as PRICE_AT_OR_BELOW
+    pub(crate) fn nnf(self, negated: bool) -> Expr {
+        match self {
+            Expr::Const(value) => Expr::Const(value != negated),
+            Expr::Atom(atomic) if negated => Expr::Not(Box::new(Expr::Atom(atomic))),
+            Expr::Atom(atomic) => Expr::Atom(atomic),
+            Expr::Not(child) => child.nnf(!negated),
+            Expr::And(children) => {
+                let children = children.into_iter().map(|c| c.nnf(negated)).collect();
+                if negated {
+                    Expr::Or(children)
+                } else {
+                    Expr::And(children)
+                }
+            }
+            Expr::Or(children) => {
+                let children = children.into_iter().map(|c| c.nnf(negated)).collect();
+                if negated {
+                    Expr::And(children)
+                } else {
+                    Expr::Or(children)
+                }
+            }
+        }
+    }
+
+    // bottom-up constant folding, flattening of nested AND/OR, removal of duplicate operands
+    // and detection of `a AND NOT a` / `a OR NOT a`
+    pub(crate) fn fold(self) -> Expr {
+        match self {
+            Expr::Not(child) => match child.fold() {
+                Expr::Const(value) => Expr::Const(!value),
+                Expr::Not(inner) => *inner,
+                other => Expr::Not(Box::new(other)),
+            },
+            Expr::And(children) => Self::fold_nary(children, true),
+            Expr::Or(children) => Self::fold_nary(children, false),
+            other => other,
+        }
+    }
+
+    fn fold_nary(children: Vec<Expr>, is_and: bool) -> Expr {
+        // for AND, `true` is the identity and `false` absorbs everything; the reverse for OR
+        let identity = is_and;
+        let mut operands: Vec<Expr> = vec![];
+        let mut flat = vec![];
+        for child in children {
+            match child.fold() {
+                Expr::And(inner) if is_and => flat.extend(inner),
+                Expr::Or(inner) if !is_and => flat.extend(inner),
+                other => flat.push(other),
+            }
+        }
+        for operand in flat {
+            match operand {
+                Expr::Const(value) if value == identity => continue,
+                Expr::Const(_) => return Expr::Const(!identity),
+                _ => {}
+            }
+            if operands.iter().any(|o| o.is_complement_of(&operand)) {
+                return Expr::Const(!identity);
+            }
+            if !operands.contains(&operand) {
+                operands.push(operand);
+            }
+        }
+        match operands.len() {
+            0 => Expr::Const(identity),
+            1 => operands.pop().unwrap(),
+            _ if is_and => Expr::And(operands),
+            _ => Expr::Or(operands),
+        }
+    }
+
+    // only `x` and `NOT x` are complements. PRICE_ABOVE(t, 100) and PRICE_AT_OR_BELOW(t, 100)
+    // are both false when t has no price, so they are not
+    fn is_complement_of(&self, other: &Expr) -> bool {
+        match (self, other) {
+            (Expr::Not(a), b) | (b, Expr::Not(a)) => **a == *b,
+            _ => false,
+        }
+    }
+
+    // expects NNF input. returns the clauses of an AND of ORs (`clauses` = true)
+    // or the terms of an OR of ANDs (`clauses` = false). the result can grow exponentially,
+    // so this gives up with `None` as soon as it has more than `max_parts` of them
+    pub(crate) fn normal_form(self, clauses: bool, max_parts: usize) -> Option<Vec<Vec<Expr>>> {
+        // the outer operator distributes over the inner one; for CNF the outer operator is AND
+        let outer = matches!(self, Expr::And(_)) == clauses;
+        let parts = match self {
+            Expr::And(children) | Expr::Or(children) if outer => {
+                let mut parts = vec![];
+                for child in children {
+                    parts.extend(child.normal_form(clauses, max_parts)?);
+                    if parts.len() > max_parts {
+                        return None;
+                    }
+                }
+                parts
+            }
+            Expr::And(children) | Expr::Or(children) => {
+                let mut product: Vec<Vec<Expr>> = vec![vec![]];
+                for child in children {
+                    let parts = child.normal_form(clauses, max_parts)?;
+                    if product.len().saturating_mul(parts.len()) > max_parts {
+                        return None;
+                    }
+                    product = product
+                        .iter()
+                        .flat_map(|acc| {
+                            parts.iter().map(move |part| {
+                                let mut merged = acc.clone();
+                                merged.extend(part.iter().cloned());
+                                merged
+                            })
+                        })
+                        .collect();
+                }
+                product
+            }
+            literal => vec![vec![literal]],
+        };
+        Some(parts)
+    }
+
+    fn from_normal_form(parts: Vec<Vec<Expr>>, clauses: bool) -> Expr {
+        let inner = parts
+            .into_iter()
+            .map(|p| if clauses { Expr::Or(p) } else { Expr::And(p) })
+            .collect();
+        if clauses {
+            Expr::And(inner)
+        } else {
+            Expr::Or(inner)
+        }
+    }
+}
+
+// C(n, k), saturating at `usize::MAX`
+fn binomial(n: usize, k: usize) -> usize {
+    if k > n {
+        return 0;
+    }
+    // C(n, i) grows with i up to n / 2, so once past `usize::MAX` it stays there
+    let k = k.min(n - k);
+    let mut acc: u128 = 1;
+    for i in 0..k {
+        // C(n, i) * (n - i) is C(n, i + 1) * (i + 1), so this divides exactly
+        acc = acc * (n - i) as u128 / (i + 1) as u128;
+        if acc > usize::MAX as u128 {
+            return usize::MAX;
+        }
+    }
+    acc as usize
+}
+
+// every `k`-sized subset of `0..n`, as sorted index lists
+fn combinations(n: usize, k: usize) -> Vec<Vec<usize>> {
+    if k == 0 {
+        return vec![vec![]];
+    }
+    if k > n {
+        return vec![];
+    }
+    // subsets containing n - 1, then subsets without it
+    let mut with_last: Vec<Vec<usize>> = combinations(n - 1, k - 1)
+        .into_iter()
+        .map(|mut subset| {
+            subset.push(n - 1);
+            subset
+        })
+        .collect();
+    with_last.extend(combinations(n - 1, k));
+    with_last
+}
+
+// none of these rewrites assume a price is present: every result evaluates like the input,
+// including for tokens the `EvaluationContext` has no price for
+impl ConditionTree {
+    // NNF + constant folding + duplicate removal. the result never has more nodes than the input
+    // and shares identical subtrees instead of repeating them. a tree past
+    // `MAX_SIMPLIFIED_NODES` once unrolled comes back as it is
+    pub fn simplify(&self) -> ConditionTree {
+        let simplified = Expr::from_tree(self, MAX_SIMPLIFIED_NODES)
+            .map(|expr| expr.nnf(false).fold().into_tree());
+        match simplified {
+            Some(Ok(simplified)) if simplified.nodes.len() <= self.nodes.len() => simplified,
+            _ => self.clone(),
+        }
+    }
+
+    // with shared subtrees and AT_LEAST written out, which fails with `TooManyNodes` past
+    // `MAX_NODES`
+    fn to_expr(&self) -> Result<Expr> {
+        Ok(Expr::from_tree(self, MAX_NODES).ok_or(ErrorCode::TooManyNodes)?)
+    }
+
+    pub fn to_nnf(&self) -> Result<ConditionTree> {
+        self.to_expr()?.nnf(false).into_tree()
+    }
+
+    // CNF and DNF can be exponentially larger than the input and fail with `TooManyNodes`
+    pub fn to_cnf(&self) -> Result<ConditionTree> {
+        self.to_normal_form(true)
+    }
+
+    pub fn to_dnf(&self) -> Result<ConditionTree> {
+        self.to_normal_form(false)
+    }
+
+    fn to_normal_form(&self, clauses: bool) -> Result<ConditionTree> {
+        let nnf = self.to_expr()?.nnf(false).fold();
+        let parts = nnf
+            .normal_form(clauses, MAX_NODES)
+            .ok_or(ErrorCode::TooManyNodes)?;
+        Expr::from_normal_form(parts, clauses).fold().into_tree()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::conditions::{ConditionBuilder, EvaluationContext};
+    use crate::logic::price::Price;
+
+    // compares both trees at prices around every threshold used in the tests below, and with
+    // either token missing from the context (`None`)
+    fn assert_equivalent(a: &ConditionTree, b: &ConditionTree, tokens: &[Pubkey]) {
+        let samples = [0, 50, 99, 100, 101, 150, 199, 200, 201, 400, 1000]
+            .map(|p| Some(Price::from(p)))
+            .into_iter()
+            .chain([None])
+            .collect::<Vec<_>>();
+        for &p in &samples {
+            for &q in &samples {
+                let mut token_prices = HashMap::new();
+                if let Some(p) = p {
+                    token_prices.insert(tokens[0], p);
+                }
+                if let (Some(other), Some(q)) = (tokens.get(1), q) {
+                    token_prices.insert(*other, q);
+                }
+                let ctx = EvaluationContext {
+                    token_prices,
+                    ..Default::default()
+                };
+                assert_eq!(
+                    a.evaluate(&ctx),
+                    b.evaluate(&ctx),
+                    "{} vs {} at ({:?}, {:?})",
+                    a.to_string_expr(),
+                    b.to_string_expr(),
+                    p,
+                    q
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_double_not_and_negated_atom() {
+        let token = Pubkey::new_unique();
+        let tree = ConditionBuilder::price_above(token, Price::from(100))
+            .not()
+            .not()
+            .not()
+            .build()
+            .unwrap();
+        let simplified = tree.simplify();
+
+        assert_eq!(
+            simplified.to_string_expr(),
+            format!("NOT PRICE_ABOVE({}, 100)", token)
+        );
+        assert_equivalent(&tree, &simplified, &[token]);
+    }
+
+    #[test]
+    fn test_missing_price_is_not_a_complement() {
+        let token = Pubkey::new_unique();
+        let above = || ConditionBuilder::price_above(token, Price::from(100));
+        let at_or_below = || ConditionBuilder::price_at_or_below(token, Price::from(100));
+
+        // both atoms are false without a price, so neither folds to a constant
+        let either = above().or(at_or_below()).build().unwrap();
+        let both_negated = above().not().and(at_or_below().not()).build().unwrap();
+        for tree in [&either, &both_negated] {
+            let simplified = tree.simplify();
+            assert!(!matches!(
+                simplified.nodes[simplified.root_index as usize].condition_type,
+                ConditionType::Const(_)
+            ));
+            assert_equivalent(tree, &simplified, &[token]);
+            assert_equivalent(tree, &tree.to_cnf().unwrap(), &[token]);
+        }
+
+        // `x OR NOT x` still folds, missing price or not
+        let always = above().or(above().not()).build().unwrap().simplify();
+        assert_eq!(always.to_string_expr(), "TRUE");
+    }
+
+    #[test]
+    fn test_dedup_and_contradiction() {
+        let token = Pubkey::new_unique();
+        // (a AND a) OR (b AND NOT b)  ->  a
+        let a = || ConditionBuilder::price_above(token, Price::from(100));
+        let b = || ConditionBuilder::price_below(token, Price::from(200));
+        let tree = a().and(a()).or(b().and(b().not())).build().unwrap();
+
+        let simplified = tree.simplify();
+        assert_eq!(simplified.nodes.len(), 1);
+        assert_equivalent(&tree, &simplified, &[token]);
+
+        let never = a().and(a().not()).build().unwrap().simplify();
+        assert_eq!(never.to_string_expr(), "FALSE");
+    }
+
+    #[test]
+    fn test_shared_subtrees() {
+        let t1 = Pubkey::new_unique();
+        let t2 = Pubkey::new_unique();
+        let shared = || {
+            ConditionBuilder::price_above(t1, Price::from(100))
+                .or(ConditionBuilder::price_below(t2, Price::from(50)))
+        };
+        let built = shared()
+            .and(ConditionBuilder::price_below(t1, Price::from(400)))
+            .or(shared().and(ConditionBuilder::price_above(t2, Price::from(10))))
+            .build()
+            .unwrap();
+        // the builder already stores the OR subtree once
+        assert_eq!(built.nodes.len(), 8);
+
+        // the same condition with the subtree copied, as a client may send it
+        let node = |condition_type: ConditionType| ConditionNode { condition_type };
+        let [above_100, below_50, _, below_400, _, above_10, _, _]: [ConditionNode; 8] =
+            built.nodes.clone().try_into().unwrap();
+        let tree = ConditionTree {
+            nodes: vec![
+                above_100.clone(),
+                below_50.clone(),
+                node(ConditionType::Or { left: 0, right: 1 }),
+                below_400,
+                node(ConditionType::And { left: 2, right: 3 }),
+                above_100,
+                below_50,
+                node(ConditionType::Or { left: 5, right: 6 }),
+                above_10,
+                node(ConditionType::And { left: 7, right: 8 }),
+                node(ConditionType::Or { left: 4, right: 9 }),
+            ],
+            root_index: 10,
+        };
+        tree.validate().unwrap();
+
+        let simplified = tree.simplify();
+        // the OR subtree is emitted once and referenced twice
+        assert!(simplified.nodes.len() < tree.nodes.len());
+        assert_equivalent(&tree, &simplified, &[t1, t2]);
+        assert_equivalent(&built, &simplified, &[t1, t2]);
+    }
+
+    #[test]
+    fn test_lifted_size() {
+        fn count(expr: &Expr) -> usize {
+            match expr {
+                Expr::Const(_) | Expr::Atom(_) => 1,
+                Expr::Not(child) => 1 + count(child),
+                Expr::And(children) | Expr::Or(children) => {
+                    1 + children.iter().map(count).sum::<usize>()
+                }
+            }
+        }
+        let token = Pubkey::new_unique();
+        let above = |p: u64| ConditionBuilder::price_above(token, Price::from(p));
+        let shared = above(1).xor(above(2).implies(above(3)));
+        let tree = ConditionBuilder::at_least(
+            2,
+            vec![shared.clone(), above(4), shared.clone().not(), above(5)],
+        )
+        .or(shared)
+        .build()
+        .unwrap();
+
+        let expr = Expr::from_tree(&tree, usize::MAX).unwrap();
+        let size = count(&expr);
+        assert_eq!(Expr::from_tree(&tree, size), Some(expr));
+        assert_eq!(Expr::from_tree(&tree, size - 1), None);
+        assert_eq!(binomial(20, 10), 184_756);
+        assert_eq!(binomial(65_535, 32_767), usize::MAX);
+    }
+
+    #[test]
+    fn test_simplify_gives_up_past_the_cap() {
+        let token = Pubkey::new_unique();
+        let above = |p: u64| ConditionBuilder::price_above(token, Price::from(p));
+
+        // 184756 subsets of 10 atoms each
+        let wide = ConditionBuilder::at_least(10, (0..20).map(above).collect())
+            .build()
+            .unwrap();
+        assert_eq!(wide.simplify(), wide);
+
+        // each level uses the one below twice: 2^30 copies of the first atom unrolled
+        let mut chain = above(0);
+        for i in 1..=30 {
+            chain = chain.clone().and(above(i)).or(chain.and(above(100 + i)));
+        }
+        let deep = chain.build().unwrap();
+        assert_eq!(deep.nodes.len(), 151);
+        assert_eq!(deep.simplify(), deep);
+        assert!(deep.to_dnf().is_err());
+
+        // under the cap it still simplifies
+        let shared = above(1).not().not();
+        let small = shared.clone().and(shared.not()).build().unwrap();
+        assert_eq!(small.simplify().to_string_expr(), "FALSE");
+    }
+
+    #[test]
+    fn test_n_ary_operators() {
+        let t1 = Pubkey::new_unique();
+        let t2 = Pubkey::new_unique();
+        let tree = ConditionBuilder::at_least(
+            2,
+            vec![
+                ConditionBuilder::price_below(t1, Price::from(100)),
+                ConditionBuilder::price_below(t2, Price::from(200)),
+                ConditionBuilder::price_above(t1, Price::from(150)),
+            ],
+        )
+        .xor(ConditionBuilder::price_above(t2, Price::from(400)))
+        .implies(ConditionBuilder::all(vec![
+            ConditionBuilder::price_above(t1, Price::from(50)),
+            ConditionBuilder::price_below(t1, Price::from(1000)),
+            ConditionBuilder::price_below(t2, Price::from(1000)),
+        ]))
+        .build()
+        .unwrap();
+
+        for converted in [
+            tree.simplify(),
+            tree.to_nnf().unwrap(),
+            tree.to_cnf().unwrap(),
+            tree.to_dnf().unwrap(),
+        ] {
+            assert_equivalent(&tree, &converted, &[t1, t2]);
+        }
+    }
+
+    #[test]
+    fn test_normal_forms() {
+        let t1 = Pubkey::new_unique();
+        let t2 = Pubkey::new_unique();
+        // NOT((a OR b) AND c) OR d
+        let tree = ConditionBuilder::price_above(t1, Price::from(100))
+            .or(ConditionBuilder::price_below(t2, Price::from(200)))
+            .and(ConditionBuilder::price_below(t1, Price::from(400)))
+            .not()
+            .or(ConditionBuilder::price_above(t2, Price::from(150)))
+            .build()
+            .unwrap();
+
+        // the only NOTs left sit directly on atoms
+        let nnf = tree.to_nnf().unwrap();
+        assert!(nnf.nodes.iter().all(|n| match n.condition_type {
+            ConditionType::Not { child } => matches!(
+                nnf.nodes[child as usize].condition_type,
+                ConditionType::Atomic(_)
+            ),
+            _ => true,
+        }));
+
+        let cnf = tree.to_cnf().unwrap();
+        let dnf = tree.to_dnf().unwrap();
+        for normal in [&nnf, &cnf, &dnf] {
+            assert_equivalent(&tree, normal, &[t1, t2]);
+        }
+    }
+}
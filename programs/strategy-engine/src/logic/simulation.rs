@@ -0,0 +1,863 @@
+use crate::logic::backtest::{backtest, in_parallel, BacktestConfig, PriceHistory, Tick};
+use crate::logic::metrics::{Metrics, SECONDS_PER_YEAR};
+use crate::logic::parser::conditionParser::parse_token;
+use crate::logic::parser::context::ParseContext;
+use crate::logic::parser::diagnostics::parse_complete;
+use crate::logic::parser::strategyParser::parse_duration;
+use crate::logic::price::Price;
+use crate::logic::random::Rng;
+use crate::logic::registry::TokenRegistry;
+use crate::logic::strategy::Strategy;
+use anchor_lang::prelude::*;
+use std::collections::HashMap;
+
+// Monte Carlo stress tests: a `Strategy` backtested over many seeded random price paths rather
+// than the one history that happened. Each asset's log price moves as a geometric Brownian
+// motion, optionally with
+//  - jumps: sudden moves that hit every asset at once, like a crash
+//  - regimes: market states (calm, crash, ...) that replace the assets' drift and scale their
+//    volatility, switching at random
+//  - correlation between the assets' moves
+// Path `i` depends only on the seed and `i`, not on the number of paths or threads.
+
+// one asset's price process; drift and volatility are yearly, of the log price
+#[derive(Clone, Debug, PartialEq)]
+pub struct Asset {
+    pub token: Pubkey,
+    // at the start of every path
+    pub price: f64,
+    pub drift: f64,
+    pub volatility: f64,
+}
+
+// Merton-style jumps. they arrive `intensity` times a year on average and move every asset at
+// once, each by a log return drawn from a normal distribution of `mean` and `volatility`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Jumps {
+    pub intensity: f64,
+    pub mean: f64,
+    pub volatility: f64,
+}
+
+// A state of the market. the market leaves it after `mean_duration` seconds on average, for one
+// of the other regimes at random
+#[derive(Clone, Debug, PartialEq)]
+pub struct Regime {
+    pub name: String,
+    // instead of the assets' own drift
+    pub drift: Option<f64>,
+    // times the assets' own volatility
+    pub volatility_scale: f64,
+    pub mean_duration: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathModel {
+    pub assets: Vec<Asset>,
+    // of the assets' random moves, in the order of `assets`; empty for none
+    pub correlation: Vec<Vec<f64>>,
+    pub jumps: Option<Jumps>,
+    // paths start in the first; with none the assets keep their own drift and volatility
+    pub regimes: Vec<Regime>,
+    // timestamp of the first tick
+    pub start: u64,
+    pub step_seconds: u64,
+    // ticks after the first
+    pub steps: usize,
+}
+
+// a model that can't generate paths
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelError(pub String);
+
+impl std::fmt::Display for ModelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn model_error(message: impl Into<String>) -> ModelError {
+    ModelError(message.into())
+}
+
+// false for NaN and infinity too
+fn non_negative(value: f64) -> bool {
+    value.is_finite() && value >= 0.0
+}
+
+// lower triangular `l` with `l * l^T = matrix`; positive semidefinite matrices only, so that
+// perfectly correlated assets are allowed
+fn cholesky(matrix: &[Vec<f64>]) -> std::result::Result<Vec<Vec<f64>>, ModelError> {
+    let n = matrix.len();
+    let mut l = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in 0..=i {
+            let sum = matrix[i][j] - (0..j).map(|k| l[i][k] * l[j][k]).sum::<f64>();
+            if i == j {
+                if sum < -1e-9 {
+                    return Err(model_error(
+                        "the correlation matrix is not positive semidefinite",
+                    ));
+                }
+                l[i][i] = sum.max(0.0).sqrt();
+            } else if l[j][j] > 0.0 {
+                l[i][j] = sum / l[j][j];
+            }
+        }
+    }
+    Ok(l)
+}
+
+impl PathModel {
+    // The model as TOML, with tokens as pubkeys or symbols of `registry` and durations
+    // as in the strategy DSL:
+    //
+    //   step = "1h"
+    //   steps = 720
+    //   correlation = [[1, 0.6], [0.6, 1]]
+    //
+    //   [[assets]]
+    //   token = "SOL"
+    //   price = 150
+    //   drift = 0.1
+    //   volatility = 0.8
+    //
+    //   [[assets]]
+    //   token = "BTC"
+    //   price = 60000
+    //   volatility = 0.5
+    //
+    //   [jumps]
+    //   intensity = 2
+    //   mean = -0.3
+    //   volatility = 0.1
+    //
+    //   [[regimes]]
+    //   name = "crash"
+    //   drift = -3
+    //   volatility_scale = 3
+    //   mean_duration = "5d"
+    //
+    // `start` and an asset's `drift` are 0, a regime's `volatility_scale` 1, and `correlation`,
+    // `jumps` and `regimes` empty, unless given
+    pub fn from_toml(
+        input: &str,
+        registry: &TokenRegistry,
+    ) -> std::result::Result<Self, ModelError> {
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct File {
+            #[serde(default)]
+            start: u64,
+            step: String,
+            steps: usize,
+            #[serde(default)]
+            correlation: Vec<Vec<f64>>,
+            assets: Vec<AssetEntry>,
+            jumps: Option<JumpsEntry>,
+            #[serde(default)]
+            regimes: Vec<RegimeEntry>,
+        }
+
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct AssetEntry {
+            token: String,
+            price: f64,
+            #[serde(default)]
+            drift: f64,
+            volatility: f64,
+        }
+
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct JumpsEntry {
+            intensity: f64,
+            mean: f64,
+            #[serde(default)]
+            volatility: f64,
+        }
+
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct RegimeEntry {
+            name: String,
+            drift: Option<f64>,
+            #[serde(default = "one")]
+            volatility_scale: f64,
+            mean_duration: String,
+        }
+
+        fn one() -> f64 {
+            1.0
+        }
+
+        let ctx = ParseContext::with_registry(registry);
+        let duration = |text: &str| {
+            parse_complete(&ctx, text, parse_duration)
+                .map_err(|_| model_error(format!("`{}` is not a duration", text)))
+        };
+        let file: File =
+            toml::from_str(input).map_err(|e| model_error(e.to_string().trim_end()))?;
+        let assets = file
+            .assets
+            .into_iter()
+            .map(|entry| {
+                Ok(Asset {
+                    token: parse_complete(&ctx, &entry.token, parse_token).map_err(|_| {
+                        model_error(format!(
+                            "`{}` is neither a pubkey nor a registered symbol",
+                            entry.token
+                        ))
+                    })?,
+                    price: entry.price,
+                    drift: entry.drift,
+                    volatility: entry.volatility,
+                })
+            })
+            .collect::<std::result::Result<Vec<_>, ModelError>>()?;
+        let regimes = file
+            .regimes
+            .into_iter()
+            .map(|entry| {
+                Ok(Regime {
+                    name: entry.name,
+                    drift: entry.drift,
+                    volatility_scale: entry.volatility_scale,
+                    mean_duration: duration(&entry.mean_duration)?,
+                })
+            })
+            .collect::<std::result::Result<Vec<_>, ModelError>>()?;
+        let model = Self {
+            assets,
+            correlation: file.correlation,
+            jumps: file.jumps.map(|j| Jumps {
+                intensity: j.intensity,
+                mean: j.mean,
+                volatility: j.volatility,
+            }),
+            regimes,
+            start: file.start,
+            step_seconds: duration(&file.step)?,
+            steps: file.steps,
+        };
+        model.validate()?;
+        Ok(model)
+    }
+
+    pub fn validate(&self) -> std::result::Result<(), ModelError> {
+        self.sampler().map(|_| ())
+    }
+
+    fn sampler(&self) -> std::result::Result<Sampler<'_>, ModelError> {
+        if self.assets.is_empty() {
+            return Err(model_error("the model has no assets"));
+        }
+        if self.step_seconds == 0 {
+            return Err(model_error("the step must be longer than 0s"));
+        }
+        for asset in &self.assets {
+            if !(non_negative(asset.price) && asset.price > 0.0) {
+                return Err(model_error(format!(
+                    "the price of {} must be more than 0",
+                    asset.token
+                )));
+            }
+            if !(non_negative(asset.volatility) && asset.drift.is_finite()) {
+                return Err(model_error(format!(
+                    "{} needs a finite drift and a volatility of at least 0",
+                    asset.token
+                )));
+            }
+        }
+        if let Some(jumps) = &self.jumps {
+            if !(non_negative(jumps.intensity)
+                && non_negative(jumps.volatility)
+                && jumps.mean.is_finite())
+            {
+                return Err(model_error(
+                    "jumps need an intensity and a volatility of at least 0",
+                ));
+            }
+        }
+        for regime in &self.regimes {
+            if regime.mean_duration == 0 || !non_negative(regime.volatility_scale) {
+                return Err(model_error(format!(
+                    "regime `{}` needs a mean duration and a volatility scale of at least 0",
+                    regime.name
+                )));
+            }
+        }
+        let n = self.assets.len();
+        let correlation = match self.correlation.len() {
+            0 => (0..n)
+                .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+                .collect(),
+            _ => {
+                let square = self.correlation.len() == n
+                    && self.correlation.iter().all(|row| row.len() == n);
+                if !square {
+                    return Err(model_error(format!(
+                        "the correlation matrix must be {} by {}, one row and column per asset",
+                        n, n
+                    )));
+                }
+                for i in 0..n {
+                    for j in 0..n {
+                        let c = self.correlation[i][j];
+                        let valid = if i == j {
+                            c == 1.0
+                        } else {
+                            (-1.0..=1.0).contains(&c) && c == self.correlation[j][i]
+                        };
+                        if !valid {
+                            return Err(model_error(
+                                "correlations must be symmetric, between -1 and 1, and 1 on the diagonal",
+                            ));
+                        }
+                    }
+                }
+                self.correlation.clone()
+            }
+        };
+        Ok(Sampler {
+            model: self,
+            cholesky: cholesky(&correlation)?,
+        })
+    }
+
+    // one path: the starting prices, then a tick of every asset's price per step
+    pub fn path(&self, rng: &mut Rng) -> std::result::Result<PriceHistory, ModelError> {
+        Ok(self.sampler()?.path(rng))
+    }
+}
+
+struct Sampler<'a> {
+    model: &'a PathModel,
+    cholesky: Vec<Vec<f64>>,
+}
+
+impl Sampler<'_> {
+    fn path(&self, rng: &mut Rng) -> PriceHistory {
+        let model = self.model;
+        let dt = model.step_seconds as f64 / SECONDS_PER_YEAR;
+        let mut log_prices = model
+            .assets
+            .iter()
+            .map(|a| a.price.ln())
+            .collect::<Vec<_>>();
+        let mut regime = 0;
+        // chance of leaving the current regime within a step
+        let leave = |regime: &Regime| {
+            1.0 - (-(model.step_seconds as f64) / regime.mean_duration as f64).exp()
+        };
+        let tick = |step: usize, log_prices: &[f64]| Tick {
+            timestamp: model.start + step as u64 * model.step_seconds,
+            prices: model
+                .assets
+                .iter()
+                .zip(log_prices)
+                .map(|(asset, log_price)| (asset.token, Price::from_f64(log_price.exp())))
+                .collect::<HashMap<_, _>>(),
+        };
+        let mut ticks = Vec::with_capacity(model.steps + 1);
+        ticks.push(tick(0, &log_prices));
+        for step in 1..=model.steps {
+            let shocks = (0..log_prices.len())
+                .map(|_| rng.normal())
+                .collect::<Vec<_>>();
+            let jumps = match &model.jumps {
+                Some(jumps) => rng.poisson(jumps.intensity * dt),
+                None => 0,
+            };
+            let current = model.regimes.get(regime);
+            for (i, asset) in model.assets.iter().enumerate() {
+                let drift = current.and_then(|r| r.drift).unwrap_or(asset.drift);
+                let volatility = asset.volatility * current.map_or(1.0, |r| r.volatility_scale);
+                let shock = (0..=i)
+                    .map(|k| self.cholesky[i][k] * shocks[k])
+                    .sum::<f64>();
+                let mut change =
+                    (drift - volatility * volatility / 2.0) * dt + volatility * dt.sqrt() * shock;
+                if let Some(j) = model.jumps.as_ref().filter(|_| jumps > 0) {
+                    let n = jumps as f64;
+                    change += n * j.mean + n.sqrt() * j.volatility * rng.normal();
+                }
+                log_prices[i] += change;
+            }
+            if let Some(current) = current {
+                let others = model.regimes.len() - 1;
+                if others > 0 && rng.next_f64() < leave(current) {
+                    // any regime but the current one
+                    let next = rng.below(others);
+                    regime = if next >= regime { next + 1 } else { next };
+                }
+            }
+            ticks.push(tick(step, &log_prices));
+        }
+        PriceHistory { ticks }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulationConfig {
+    pub paths: usize,
+    pub seed: u64,
+    // paths run at once; 0 runs one per available core
+    pub threads: usize,
+    pub backtest: BacktestConfig,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            paths: 1_000,
+            seed: 0,
+            threads: 0,
+            backtest: BacktestConfig::default(),
+        }
+    }
+}
+
+// how the strategy did on one path
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct PathOutcome {
+    pub total_return: f64,
+    pub max_drawdown: f64,
+    pub triggers: u64,
+    pub trades: usize,
+    pub rejected: usize,
+    // UNTIL or MAX_RUNS ended the strategy
+    pub finished: bool,
+}
+
+// Summary of a sample. percentiles interpolate between the nearest values
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct Distribution {
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub p1: f64,
+    pub p5: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+// the `p`th percentile of sorted values
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f64)
+}
+
+impl Distribution {
+    // all zero for no values
+    pub fn of(values: &[f64]) -> Self {
+        if values.is_empty() {
+            return Self::of(&[0.0]);
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+        Self {
+            mean,
+            std_dev: variance.sqrt(),
+            min: sorted[0],
+            p1: percentile(&sorted, 1.0),
+            p5: percentile(&sorted, 5.0),
+            p25: percentile(&sorted, 25.0),
+            median: percentile(&sorted, 50.0),
+            p75: percentile(&sorted, 75.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+// Outcomes over all the paths. losses are positive fractions of the initial equity
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct SimulationReport {
+    pub paths: usize,
+    pub returns: Distribution,
+    pub drawdowns: Distribution,
+    pub triggers: Distribution,
+    // share of paths that ended with less than they started with
+    pub loss_probability: f64,
+    // the loss that 95% (99%) of paths did not exceed
+    pub value_at_risk_95: f64,
+    pub value_at_risk_99: f64,
+    // mean loss over the worst 5% (1%) of paths
+    pub expected_shortfall_95: f64,
+    pub expected_shortfall_99: f64,
+    // share of paths on which the actions ran at least once
+    pub fired: f64,
+    // share of paths UNTIL or MAX_RUNS ended
+    pub finished: f64,
+    // in the order of the paths
+    pub outcomes: Vec<PathOutcome>,
+}
+
+// mean loss over the worst `share` of the sorted returns
+fn shortfall(sorted: &[f64], share: f64) -> f64 {
+    let count = ((sorted.len() as f64 * share).ceil() as usize).clamp(1, sorted.len());
+    -sorted[..count].iter().sum::<f64>() / count as f64
+}
+
+pub fn simulate(
+    strategy: &Strategy,
+    model: &PathModel,
+    config: &SimulationConfig,
+) -> std::result::Result<SimulationReport, ModelError> {
+    let sampler = model.sampler()?;
+    let outcomes = in_parallel(config.paths, config.threads, |i| {
+        let mut rng = Rng::stream(config.seed, i as u64);
+        let report = backtest(strategy, &sampler.path(&mut rng), &config.backtest);
+        let metrics = Metrics::from_report(&report);
+        PathOutcome {
+            total_return: metrics.total_return,
+            max_drawdown: metrics.max_drawdown,
+            triggers: metrics.triggers,
+            trades: metrics.trades,
+            rejected: metrics.rejected,
+            finished: report.finished_at.is_some(),
+        }
+    });
+
+    let returns = outcomes.iter().map(|o| o.total_return).collect::<Vec<_>>();
+    let mut sorted = returns.clone();
+    sorted.sort_by(f64::total_cmp);
+    let share = |count: usize| match outcomes.len() {
+        0 => 0.0,
+        n => count as f64 / n as f64,
+    };
+    let (value_at_risk_95, value_at_risk_99, expected_shortfall_95, expected_shortfall_99) =
+        if sorted.is_empty() {
+            (0.0, 0.0, 0.0, 0.0)
+        } else {
+            (
+                -percentile(&sorted, 5.0),
+                -percentile(&sorted, 1.0),
+                shortfall(&sorted, 0.05),
+                shortfall(&sorted, 0.01),
+            )
+        };
+    Ok(SimulationReport {
+        paths: outcomes.len(),
+        returns: Distribution::of(&returns),
+        drawdowns: Distribution::of(&outcomes.iter().map(|o| o.max_drawdown).collect::<Vec<_>>()),
+        triggers: Distribution::of(
+            &outcomes
+                .iter()
+                .map(|o| o.triggers as f64)
+                .collect::<Vec<_>>(),
+        ),
+        loss_probability: share(returns.iter().filter(|r| **r < 0.0).count()),
+        value_at_risk_95,
+        value_at_risk_99,
+        expected_shortfall_95,
+        expected_shortfall_99,
+        fired: share(outcomes.iter().filter(|o| o.triggers > 0).count()),
+        finished: share(outcomes.iter().filter(|o| o.finished).count()),
+        outcomes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::parser::strategyParser::translate_strategy_string;
+
+    const DAY: u64 = 86_400;
+
+    fn asset(token: Pubkey, drift: f64, volatility: f64) -> Asset {
+        Asset {
+            token,
+            price: 100.0,
+            drift,
+            volatility,
+        }
+    }
+
+    fn model(assets: Vec<Asset>, step_seconds: u64, steps: usize) -> PathModel {
+        PathModel {
+            assets,
+            correlation: vec![],
+            jumps: None,
+            regimes: vec![],
+            start: 0,
+            step_seconds,
+            steps,
+        }
+    }
+
+    // log returns of `token` from tick to tick
+    fn log_returns(path: &PriceHistory, token: &Pubkey) -> Vec<f64> {
+        path.ticks
+            .windows(2)
+            .map(|w| (w[1].prices[token].to_f64() / w[0].prices[token].to_f64()).ln())
+            .collect()
+    }
+
+    fn mean_and_std_dev(values: &[f64]) -> (f64, f64) {
+        let distribution = Distribution::of(values);
+        (distribution.mean, distribution.std_dev)
+    }
+
+    #[test]
+    fn test_geometric_brownian_motion() {
+        let sol = Pubkey::new_unique();
+        // doubles every year, in four steps
+        let quarter = SECONDS_PER_YEAR as u64 / 4;
+        let steady = model(vec![asset(sol, 2f64.ln(), 0.0)], quarter, 4);
+        let path = steady.path(&mut Rng::new(1)).unwrap();
+        assert_eq!(
+            path.ticks.iter().map(|t| t.timestamp).collect::<Vec<_>>(),
+            (0..5).map(|i| i * quarter).collect::<Vec<_>>()
+        );
+        assert_eq!(path.ticks[4].prices[&sol], Price::from(200));
+
+        let volatile = model(vec![asset(sol, 0.0, 0.5)], DAY, 10_000);
+        let path = volatile.path(&mut Rng::new(1)).unwrap();
+        let (mean, std_dev) = mean_and_std_dev(&log_returns(&path, &sol));
+        let expected = 0.5 * (DAY as f64 / SECONDS_PER_YEAR).sqrt();
+        assert!((std_dev / expected - 1.0).abs() < 0.05, "{}", std_dev);
+        assert!(mean.abs() < 0.002, "{}", mean);
+
+        assert_eq!(volatile.path(&mut Rng::new(1)).unwrap(), path);
+        assert_ne!(volatile.path(&mut Rng::new(2)).unwrap(), path);
+    }
+
+    #[test]
+    fn test_correlation() {
+        let (sol, btc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut correlated = model(vec![asset(sol, 0.0, 0.5), asset(btc, 0.0, 0.5)], DAY, 5_000);
+        correlated.correlation = vec![vec![1.0, 0.8], vec![0.8, 1.0]];
+        let path = correlated.path(&mut Rng::new(3)).unwrap();
+        let (a, b) = (log_returns(&path, &sol), log_returns(&path, &btc));
+        let ((mean_a, sd_a), (mean_b, sd_b)) = (mean_and_std_dev(&a), mean_and_std_dev(&b));
+        let covariance = a
+            .iter()
+            .zip(&b)
+            .map(|(x, y)| (x - mean_a) * (y - mean_b))
+            .sum::<f64>()
+            / a.len() as f64;
+        let correlation = covariance / (sd_a * sd_b);
+        assert!((correlation - 0.8).abs() < 0.05, "{}", correlation);
+
+        // perfectly correlated assets move together
+        correlated.correlation = vec![vec![1.0, 1.0], vec![1.0, 1.0]];
+        let path = correlated.path(&mut Rng::new(3)).unwrap();
+        assert_eq!(path.ticks[100].prices[&sol], path.ticks[100].prices[&btc]);
+
+        let invalid = |correlation: Vec<Vec<f64>>| {
+            let mut model = correlated.clone();
+            model.correlation = correlation;
+            model.validate().unwrap_err().to_string()
+        };
+        assert!(invalid(vec![vec![1.0]]).starts_with("the correlation matrix must be 2 by 2"));
+        assert!(invalid(vec![vec![1.0, 0.5], vec![0.4, 1.0]]).contains("symmetric"));
+        assert!(invalid(vec![vec![0.5, 0.0], vec![0.0, 1.0]]).contains("1 on the diagonal"));
+        let mut three = model(
+            vec![
+                asset(sol, 0.0, 0.1),
+                asset(btc, 0.0, 0.1),
+                asset(Pubkey::new_unique(), 0.0, 0.1),
+            ],
+            DAY,
+            1,
+        );
+        three.correlation = vec![
+            vec![1.0, 0.9, -0.9],
+            vec![0.9, 1.0, 0.9],
+            vec![-0.9, 0.9, 1.0],
+        ];
+        assert_eq!(
+            three.validate().unwrap_err().to_string(),
+            "the correlation matrix is not positive semidefinite"
+        );
+    }
+
+    #[test]
+    fn test_jumps_and_regimes() {
+        let sol = Pubkey::new_unique();
+        // on average a 10% fall every 10 days, and nothing else
+        let mut crashes = model(vec![asset(sol, 0.0, 0.0)], DAY, 1_000);
+        crashes.jumps = Some(Jumps {
+            intensity: SECONDS_PER_YEAR / DAY as f64 / 10.0,
+            mean: -0.1,
+            volatility: 0.0,
+        });
+        let path = crashes.path(&mut Rng::new(5)).unwrap();
+        let falls = log_returns(&path, &sol)
+            .iter()
+            .map(|r| (-r / 0.1).round() as u64)
+            .sum::<u64>();
+        assert!((80..=120).contains(&falls), "{}", falls);
+
+        // a single regime that stills the market
+        let mut still = model(vec![asset(sol, 0.5, 0.8)], DAY, 100);
+        still.regimes = vec![Regime {
+            name: "still".to_string(),
+            drift: Some(0.0),
+            volatility_scale: 0.0,
+            mean_duration: 30 * DAY,
+        }];
+        let path = still.path(&mut Rng::new(5)).unwrap();
+        assert!(path
+            .ticks
+            .iter()
+            .all(|t| t.prices[&sol] == Price::from(100)));
+
+        // rises in one regime, falls in the other, switching every month or so
+        let mut switching = model(vec![asset(sol, 0.0, 0.0)], DAY, 2_000);
+        switching.regimes = [("bull", 1.0), ("bear", -1.0)]
+            .map(|(name, drift)| Regime {
+                name: name.to_string(),
+                drift: Some(drift),
+                volatility_scale: 1.0,
+                mean_duration: 30 * DAY,
+            })
+            .to_vec();
+        let returns = log_returns(&switching.path(&mut Rng::new(5)).unwrap(), &sol);
+        let switches = returns
+            .windows(2)
+            .filter(|w| (w[0] > 0.0) != (w[1] > 0.0))
+            .count();
+        assert!((40..=100).contains(&switches), "{}", switches);
+    }
+
+    #[test]
+    fn test_simulate() {
+        let sol = Pubkey::new_unique();
+        let strategy = translate_strategy_string(&format!(
+            "WHEN PRICE_BELOW({sol}, 90) THEN BUY({sol}, 10) EVERY 1h MAX_RUNS 1"
+        ))
+        .unwrap();
+        let model = model(vec![asset(sol, 0.0, 0.8)], 3_600, 24 * 30);
+        let config = SimulationConfig {
+            paths: 200,
+            seed: 11,
+            threads: 1,
+            ..Default::default()
+        };
+        let report = simulate(&strategy, &model, &config).unwrap();
+        assert_eq!((report.paths, report.outcomes.len()), (200, 200));
+        assert!(report.fired > 0.0 && report.fired < 1.0, "{}", report.fired);
+        assert!(report.finished <= report.fired);
+        assert_eq!(report.triggers.max, 1.0);
+        // only paths that bought can gain or lose
+        assert!(report.loss_probability < report.fired);
+        let r = &report.returns;
+        let ordered = [
+            r.min, r.p1, r.p5, r.p25, r.median, r.p75, r.p95, r.p99, r.max,
+        ];
+        assert!(ordered.windows(2).all(|w| w[0] <= w[1]));
+        assert!(report.value_at_risk_99 >= report.value_at_risk_95);
+        assert!(report.expected_shortfall_95 >= report.value_at_risk_95);
+        assert!(report.expected_shortfall_99 >= report.expected_shortfall_95);
+
+        // the same paths on any number of threads
+        let parallel = SimulationConfig {
+            threads: 4,
+            ..config.clone()
+        };
+        assert_eq!(simulate(&strategy, &model, &parallel).unwrap(), report);
+        let reseeded = SimulationConfig { seed: 12, ..config };
+        assert_ne!(simulate(&strategy, &model, &reseeded).unwrap(), report);
+    }
+
+    #[test]
+    fn test_neighbouring_seeds_share_no_paths() {
+        let sol = Pubkey::new_unique();
+        let model = model(vec![asset(sol, 0.0, 0.5)], DAY, 30);
+        let paths = |seed: u64| {
+            (0..50)
+                .map(|i| model.path(&mut Rng::stream(seed, i)).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let (zero, one) = (paths(0), paths(1));
+        assert!(zero.iter().all(|path| !one.contains(path)));
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let (sol, btc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let toml = format!(
+            r#"
+            step = "1h"
+            steps = 720
+            correlation = [[1, 0.6], [0.6, 1]]
+
+            [[assets]]
+            token = "{sol}"
+            price = 150
+            drift = 0.1
+            volatility = 0.8
+
+            [[assets]]
+            token = "{btc}"
+            price = 60000
+            volatility = 0.5
+
+            [jumps]
+            intensity = 2
+            mean = -0.3
+
+            [[regimes]]
+            name = "calm"
+            mean_duration = "60d"
+
+            [[regimes]]
+            name = "crash"
+            drift = -3
+            volatility_scale = 3
+            mean_duration = "5d"
+            "#
+        );
+        let model = PathModel::from_toml(&toml, &TokenRegistry::default()).unwrap();
+        assert_eq!(model.step_seconds, 3_600);
+        assert_eq!(
+            model.assets[1],
+            Asset {
+                token: btc,
+                price: 60_000.0,
+                drift: 0.0,
+                volatility: 0.5,
+            }
+        );
+        assert_eq!(model.jumps.as_ref().unwrap().volatility, 0.0);
+        assert_eq!(model.regimes[0].volatility_scale, 1.0);
+        assert_eq!(model.regimes[1].drift, Some(-3.0));
+        assert_eq!(model.regimes[1].mean_duration, 5 * DAY);
+        assert_eq!(model.path(&mut Rng::new(0)).unwrap().ticks.len(), 721);
+
+        let error = |toml: &str| {
+            PathModel::from_toml(toml, &TokenRegistry::default())
+                .unwrap_err()
+                .to_string()
+        };
+        let asset = format!("[[assets]]\ntoken = \"{sol}\"\nprice = 1\nvolatility = 0.1\n");
+        assert!(
+            error(&format!("step = \"1h\"\nsteps = 1\ncolour = 1\n{asset}")).contains("colour")
+        );
+        assert_eq!(
+            error(&format!("step = \"soon\"\nsteps = 1\n{asset}")),
+            "`soon` is not a duration"
+        );
+        assert_eq!(
+            error("step = \"1h\"\nsteps = 1\nassets = []\n"),
+            "the model has no assets"
+        );
+        assert_eq!(
+            error("step = \"1h\"\nsteps = 1\n[[assets]]\ntoken = \"SOL\"\nprice = 1\nvolatility = 0\n"),
+            "`SOL` is neither a pubkey nor a registered symbol"
+        );
+    }
+}
@@ -1,10 +1,6 @@
 use crate::logic::actions::ActionTree;
 use crate::logic::conditions::ConditionTree;
 
-use anchor_lang::prelude::*;
-
-use std::collections::HashMap;
-
 use super::conditions::EvaluationContext;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -12,6 +8,12 @@ pub struct Strategy {
     pub condition: ConditionTree,
     pub action: ActionTree,
     pub execute_every_seconds: u64,
+    // the strategy stops for good once this is true
+    pub until: Option<ConditionTree>,
+    // the strategy stops for good after its actions ran this many times
+    pub max_runs: Option<u64>,
+    // free-form `META(key, "value")` clauses, in the order they were written
+    pub metadata: Vec<(String, String)>,
 }
 
 impl Strategy {
@@ -20,14 +22,77 @@ impl Strategy {
             condition,
             action,
             execute_every_seconds,
+            until: None,
+            max_runs: None,
+            metadata: vec![],
+        }
+    }
+
+    pub fn is_finished(&self, ctx: &EvaluationContext, runs: u64) -> bool {
+        self.until.as_ref().is_some_and(|until| until.evaluate(ctx))
+            || self.max_runs.is_some_and(|max| runs >= max)
+    }
+
+    // canonical form of the strategy DSL; `translate_strategy_string` parses it back
+    pub fn to_string_expr(&self) -> String {
+        self.render(&|c| c.to_string_expr(), &|a| a.to_string_expr())
+    }
+
+    pub(crate) fn render(
+        &self,
+        condition: &dyn Fn(&ConditionTree) -> String,
+        action: &dyn Fn(&ActionTree) -> String,
+    ) -> String {
+        let mut s = format!(
+            "WHEN {} THEN {} EVERY {}",
+            condition(&self.condition),
+            action(&self.action),
+            format_duration(self.execute_every_seconds)
+        );
+        if let Some(until) = &self.until {
+            s += &format!(" UNTIL {}", condition(until));
+        }
+        if let Some(max_runs) = self.max_runs {
+            s += &format!(" MAX_RUNS {}", max_runs);
+        }
+        for (key, value) in &self.metadata {
+            s += &format!(" META({}, {})", key, quote(value));
         }
+        s
     }
 }
 
+// string literal as `parse_string` reads it: only `"`, `\` and newlines are escaped
+fn quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+// largest units first, e.g. 5400 -> "1h30m"
+pub fn format_duration(seconds: u64) -> String {
+    if seconds == 0 {
+        return "0s".to_string();
+    }
+    let mut rest = seconds;
+    let mut s = String::new();
+    for (unit, size) in [("d", 86_400), ("h", 3_600), ("m", 60), ("s", 1)] {
+        if rest >= size {
+            s += &format!("{}{}", rest / size, unit);
+            rest %= size;
+        }
+    }
+    s
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Vault {
     pub strategy: Strategy,
     pub balance: u64,
+    // times the strategy's actions ran, counted against `MAX_RUNS`
+    pub runs: u64,
 }
 
 impl Vault {
@@ -35,39 +100,66 @@ impl Vault {
         Self {
             strategy,
             balance: 0,
+            runs: 0,
         }
     }
     // deposits amount into the vault
     pub fn deposit(self, amount: u64) -> Self {
         Self {
-            strategy: self.strategy,
             balance: self.balance + amount,
+            ..self
         }
     }
     // withdraws amount from the vault
     pub fn withdraw(self, amount: u64) -> Self {
         Self {
-            strategy: self.strategy,
             balance: self.balance - amount,
+            ..self
         }
     }
 
-    pub fn execute(self, ctx: &EvaluationContext) -> bool {
-        if self.strategy.condition.evaluate(ctx) {
-            let success = self.strategy.action.execute();
-            success
-        } else {
-            false
+    // runs the actions if the condition holds and the strategy isn't finished, i.e. `UNTIL`
+    // isn't true and the actions ran fewer than `MAX_RUNS` times. true if they ran and succeeded
+    pub fn execute(&mut self, ctx: &EvaluationContext) -> bool {
+        if self.strategy.is_finished(ctx, self.runs) || !self.strategy.condition.evaluate(ctx) {
+            return false;
         }
+        self.runs += 1;
+        self.strategy.action.execute()
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::parser::strategyParser::translate_strategy_string;
+    use crate::logic::price::Price;
+    use anchor_lang::prelude::Pubkey;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_vault_stops_after_max_runs() {
+        let sol = Pubkey::new_unique();
+        let strategy = translate_strategy_string(&format!(
+            "WHEN PRICE_BELOW({sol}, 100) THEN BUY({sol}, 1) EVERY 1m \
+             UNTIL PRICE_ABOVE({sol}, 200) MAX_RUNS 2"
+        ))
+        .unwrap();
+        let at = |price: u64| EvaluationContext {
+            token_prices: HashMap::from([(sol, Price::from(price))]),
+            ..Default::default()
+        };
+
+        let mut vault = Vault::new(strategy.clone()).deposit(10);
+        assert!(!vault.execute(&at(150)));
+        assert!(vault.execute(&at(90)));
+        assert!(vault.execute(&at(90)));
+        assert!(!vault.execute(&at(90)));
+        assert_eq!(vault.runs, 2);
+        assert_eq!(vault.clone().withdraw(4).runs, 2);
 
-//     #[test]
-//     fn test_strategy_execute() {
-//         let token = Pubkey::default();
-//     }
-// }
+        let mut vault = Vault::new(strategy);
+        assert!(!vault.execute(&at(250)));
+        assert_eq!(vault.runs, 0);
+    }
+}
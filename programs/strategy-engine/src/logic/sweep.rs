@@ -0,0 +1,848 @@
+use crate::logic::backtest::{backtest, in_parallel, BacktestConfig, PriceHistory};
+use crate::logic::metrics::Metrics;
+use crate::logic::parser::diagnostics::Diagnostic;
+use crate::logic::parser::lexer::{lex, skip_trivia, Token};
+use crate::logic::parser::template::StrategyTemplate;
+use crate::logic::random::Rng;
+use crate::logic::registry::TokenRegistry;
+use crate::logic::strategy::Strategy;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::str::FromStr;
+
+// Tuning a strategy's thresholds against a price history. The numbers left open are written in
+// braces, as ranges or lists of values:
+//
+//   WHEN PRICE_BELOW(SOL, {80..120 step 5}) THEN BUY(SOL, {size = 1, 2, 5}) EVERY {1h, 4h}
+//
+// `{A..B}` counts from A up to B by 1 and `{A..B step S}` by S; a `%` after the numbers makes
+// them percentages. `{A, B, C}` takes the values as written, so it can hold durations too.
+// `name =` in front names the parameter, and `$name` then uses the same value elsewhere in the
+// strategy; ranges without a name are named by position, `1`, `2`, ...
+//
+// Every combination of values, or a random sample of them, is backtested across threads and the
+// results ranked by an `Objective`. `walk_forward` checks that the best values found on one part
+// of the history still hold up on the part after it.
+
+// most values a single range expands to
+pub const MAX_VALUES: usize = 10_000;
+// most strategies a single search backtests
+pub const MAX_CANDIDATES: usize = 1_000_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Parameter {
+    pub name: String,
+    // as they are put into the strategy text
+    pub values: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SweepTemplate {
+    pub source: String,
+    // in the order the ranges appear in `source`
+    pub parameters: Vec<Parameter>,
+    // `source` with every range replaced by a `$name` placeholder
+    template: StrategyTemplate,
+    // where each range is in `source`, and the length of the placeholder that replaced it
+    replaced: Vec<(Range<usize>, usize)>,
+}
+
+// a number as written, split at the decimal point
+fn decimal(text: &str) -> std::result::Result<(&str, &str), String> {
+    let (int, frac) = text.split_once('.').unwrap_or((text, ""));
+    let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if int.is_empty() || !digits(int) || !digits(frac) || (text.contains('.') && frac.is_empty()) {
+        return Err(format!("`{}` is not a number", text));
+    }
+    Ok((int, frac))
+}
+
+// `start` to `end` by `step`, counted in integers scaled to the most decimals any of the three
+// has, so that steps like 0.1 don't drift
+fn stepped(start: &str, end: &str, step: Option<&str>) -> std::result::Result<Vec<String>, String> {
+    let percent = start.ends_with('%');
+    let unit = if percent { "%" } else { "" };
+    let strip = |text: &str| match text.strip_suffix('%') {
+        Some(number) if percent => Ok(number.to_string()),
+        None if !percent => Ok(text.to_string()),
+        _ => Err("either all or none of the numbers of a range are percentages".to_string()),
+    };
+    let start = strip(start)?;
+    let end = strip(end)?;
+    let step = match step {
+        Some(step) => strip(step)?,
+        None => "1".to_string(),
+    };
+    let numbers = [decimal(&start)?, decimal(&end)?, decimal(&step)?];
+    let decimals = numbers
+        .iter()
+        .map(|(_, frac)| frac.len())
+        .max()
+        .unwrap_or(0);
+    if decimals > 18 {
+        return Err("numbers of a range have at most 18 decimals".to_string());
+    }
+    let scale = 10u128.pow(decimals as u32);
+    let mut scaled = numbers.iter().map(|(int, frac)| {
+        format!("{}{:0<decimals$}", int, frac)
+            .parse::<u128>()
+            .map_err(|_| format!("`{}.{}` is too large", int, frac))
+    });
+    let (start, end, step) = (
+        scaled.next().unwrap()?,
+        scaled.next().unwrap()?,
+        scaled.next().unwrap()?,
+    );
+    if step == 0 {
+        return Err("the step must be more than 0".to_string());
+    }
+    if start > end {
+        return Err("the range ends before it starts".to_string());
+    }
+    let count = (end - start) / step + 1;
+    if count > MAX_VALUES as u128 {
+        return Err(format!(
+            "the range has {} values, more than {}",
+            count, MAX_VALUES
+        ));
+    }
+    Ok((0..count)
+        .map(|i| {
+            let value = start + i * step;
+            let text = match value % scale {
+                0 => (value / scale).to_string(),
+                frac => {
+                    let frac = format!("{:0>decimals$}", frac);
+                    format!("{}.{}", value / scale, frac.trim_end_matches('0'))
+                }
+            };
+            text + unit
+        })
+        .collect())
+}
+
+// what is between the braces: an optional `name =`, then a range or a list
+fn range_values(spec: &str) -> std::result::Result<(Option<String>, Vec<String>), String> {
+    let (name, spec) = match spec.split_once('=') {
+        Some((name, rest)) => {
+            let name = name.trim();
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(format!("`{}` is not a parameter name", name));
+            }
+            (Some(name.to_string()), rest)
+        }
+        None => (None, spec),
+    };
+    let values = match spec.split_once("..") {
+        Some((start, rest)) => {
+            let (end, step) = match rest.to_ascii_lowercase().find("step") {
+                Some(i) => (&rest[..i], Some(rest[i + 4..].trim())),
+                None => (rest, None),
+            };
+            stepped(start.trim(), end.trim(), step)?
+        }
+        None => spec.split(',').map(|v| v.trim().to_string()).collect(),
+    };
+    if values.iter().any(String::is_empty) {
+        return Err("a parameter needs a value between every pair of commas".to_string());
+    }
+    Ok((name, values))
+}
+
+impl SweepTemplate {
+    pub fn parse(source: &str) -> std::result::Result<Self, Diagnostic> {
+        // span, name and values of each range, and the first span of each placeholder written out
+        let mut ranges: Vec<(Range<usize>, Option<String>, Vec<String>)> = vec![];
+        let mut used: HashMap<String, Range<usize>> = HashMap::new();
+        let mut input = source;
+        loop {
+            if let Ok((rest, token)) = lex(input) {
+                if let Token::Placeholder(name) = token {
+                    let start = source.len() - skip_trivia(input).len();
+                    used.entry(name.to_string())
+                        .or_insert(start..source.len() - rest.len());
+                }
+                input = rest;
+                continue;
+            }
+            // anything else the lexer can't read is left for the parser to report
+            let at = skip_trivia(input);
+            if !at.starts_with('{') {
+                break;
+            }
+            let start = source.len() - at.len();
+            let end = match at.find('}') {
+                Some(i) => start + i + 1,
+                None => {
+                    return Err(Diagnostic::spanning(
+                        source,
+                        start..start + 1,
+                        "`{` is never closed",
+                    ))
+                }
+            };
+            let (name, values) = range_values(&source[start + 1..end - 1])
+                .map_err(|message| Diagnostic::spanning(source, start..end, message))?;
+            if let Some(name) = &name {
+                if ranges.iter().any(|(_, n, _)| n.as_ref() == Some(name)) {
+                    return Err(Diagnostic::spanning(
+                        source,
+                        start..end,
+                        format!("`{}` is defined more than once", name),
+                    ));
+                }
+            }
+            ranges.push((start..end, name, values));
+            input = &source[end..];
+        }
+
+        let mut rewritten = String::new();
+        let mut parameters = vec![];
+        let mut replaced = vec![];
+        let mut position = 0;
+        let mut next = 1;
+        let named = ranges
+            .iter()
+            .filter_map(|(_, name, _)| name.clone())
+            .collect::<HashSet<_>>();
+        for (span, name, values) in ranges {
+            let name = name.unwrap_or_else(|| loop {
+                let candidate = next.to_string();
+                next += 1;
+                if !used.contains_key(&candidate) && !named.contains(&candidate) {
+                    break candidate;
+                }
+            });
+            let placeholder = format!("${}", name);
+            rewritten += &source[position..span.start];
+            rewritten += &placeholder;
+            position = span.end;
+            replaced.push((span, placeholder.len()));
+            parameters.push(Parameter { name, values });
+        }
+        rewritten += &source[position..];
+
+        let template = StrategyTemplate::new(&rewritten);
+        if let Some(name) = template
+            .parameters
+            .iter()
+            .find(|name| !parameters.iter().any(|p| &p.name == *name))
+        {
+            return Err(Diagnostic::spanning(
+                source,
+                used.get(name).cloned().unwrap_or(0..0),
+                format!("no values given for `${}`", name),
+            ));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            parameters,
+            template,
+            replaced,
+        })
+    }
+
+    // number of strategies the ranges make between them
+    pub fn combinations(&self) -> usize {
+        self.parameters
+            .iter()
+            .fold(1, |n, p| n.saturating_mul(p.values.len()))
+    }
+
+    // name and value of every parameter, for `choice` holding an index into each one's values
+    pub fn values(&self, choice: &[usize]) -> Vec<(&str, &str)> {
+        self.parameters
+            .iter()
+            .zip(choice)
+            .map(|(p, &i)| (p.name.as_str(), p.values[i].as_str()))
+            .collect()
+    }
+
+    pub fn instantiate(
+        &self,
+        choice: &[usize],
+        registry: &TokenRegistry,
+    ) -> std::result::Result<Strategy, Diagnostic> {
+        self.template
+            .instantiate(&self.values(choice), registry)
+            .map_err(|diagnostic| self.locate(diagnostic))
+    }
+
+    // a diagnostic about the rewritten source, pointed back at `source`
+    fn locate(&self, diagnostic: Diagnostic) -> Diagnostic {
+        let original = |offset: usize| {
+            // how much longer `source` is than the rewritten text, up to the current range
+            let mut longer = 0;
+            for (span, len) in &self.replaced {
+                let start = span.start - longer;
+                if offset < start {
+                    break;
+                }
+                if offset < start + len {
+                    return span.start;
+                }
+                longer += span.len() - len;
+            }
+            offset + longer
+        };
+        let span = original(diagnostic.span.start)..original(diagnostic.span.end);
+        let mut located = Diagnostic::spanning(&self.source, span, diagnostic.message.clone());
+        if diagnostic.span.is_empty() {
+            located.found = diagnostic.found;
+        } else {
+            located.message = diagnostic.message.replace(
+                &format!("`{}`", diagnostic.found),
+                &format!("`{}`", located.found),
+            );
+        }
+        located.expected = diagnostic.expected;
+        located.suggestion = diagnostic.suggestion;
+        located
+    }
+}
+
+// What a search ranks the strategies by
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Objective {
+    TotalReturn,
+    Cagr,
+    Sharpe,
+    Sortino,
+    MaxDrawdown,
+    WinRate,
+}
+
+impl Objective {
+    pub const ALL: [Objective; 6] = [
+        Objective::TotalReturn,
+        Objective::Cagr,
+        Objective::Sharpe,
+        Objective::Sortino,
+        Objective::MaxDrawdown,
+        Objective::WinRate,
+    ];
+
+    // as the field of `Metrics` it reads
+    pub fn name(self) -> &'static str {
+        match self {
+            Objective::TotalReturn => "total_return",
+            Objective::Cagr => "cagr",
+            Objective::Sharpe => "sharpe",
+            Objective::Sortino => "sortino",
+            Objective::MaxDrawdown => "max_drawdown",
+            Objective::WinRate => "win_rate",
+        }
+    }
+
+    // higher is better, so a drawdown counts negatively
+    pub fn score(self, metrics: &Metrics) -> Option<f64> {
+        match self {
+            Objective::TotalReturn => Some(metrics.total_return),
+            Objective::Cagr => metrics.cagr,
+            Objective::Sharpe => metrics.sharpe,
+            Objective::Sortino => metrics.sortino,
+            Objective::MaxDrawdown => Some(-metrics.max_drawdown),
+            Objective::WinRate => metrics.win_rate,
+        }
+    }
+}
+
+impl FromStr for Objective {
+    type Err = String;
+
+    fn from_str(text: &str) -> std::result::Result<Self, String> {
+        Objective::ALL
+            .into_iter()
+            .find(|o| o.name() == text)
+            .ok_or_else(|| {
+                let names = Objective::ALL.map(Objective::name);
+                format!(
+                    "unknown objective `{}`, expected one of {}",
+                    text,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Search {
+    // every combination
+    Grid,
+    // `samples` different combinations, or all of them if there are no more than that
+    Random { samples: usize, seed: u64 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepConfig {
+    pub search: Search,
+    pub objective: Objective,
+    // backtests run at once; 0 runs one per available core
+    pub threads: usize,
+    pub backtest: BacktestConfig,
+}
+
+impl Default for SweepConfig {
+    fn default() -> Self {
+        Self {
+            search: Search::Grid,
+            objective: Objective::Sharpe,
+            threads: 0,
+            backtest: BacktestConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    // index into the values of each parameter; `SweepTemplate::values` names them
+    pub choice: Vec<usize>,
+    pub strategy: Strategy,
+    pub metrics: Metrics,
+    // the objective's score; candidates without one rank last
+    pub score: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SweepError {
+    // the values of a combination don't make a valid strategy
+    Template(Diagnostic),
+    Config(String),
+}
+
+impl std::fmt::Display for SweepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SweepError::Template(diagnostic) => write!(f, "{}", diagnostic),
+            SweepError::Config(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+fn choices(
+    template: &SweepTemplate,
+    search: Search,
+) -> std::result::Result<Vec<Vec<usize>>, SweepError> {
+    let sizes = template
+        .parameters
+        .iter()
+        .map(|p| p.values.len())
+        .collect::<Vec<_>>();
+    let total = template.combinations();
+    if let Search::Random { samples, seed } = search {
+        if samples < total {
+            if samples > MAX_CANDIDATES {
+                return Err(SweepError::Config(format!(
+                    "{} samples are more than {}",
+                    samples, MAX_CANDIDATES
+                )));
+            }
+            let mut rng = Rng::new(seed);
+            let mut seen = HashSet::new();
+            let mut sampled = vec![];
+            while sampled.len() < samples {
+                let choice = sizes.iter().map(|&n| rng.below(n)).collect::<Vec<_>>();
+                if seen.insert(choice.clone()) {
+                    sampled.push(choice);
+                }
+            }
+            return Ok(sampled);
+        }
+    }
+    if total > MAX_CANDIDATES {
+        return Err(SweepError::Config(format!(
+            "the ranges make {} strategies, more than {}; sample some with a random search",
+            total, MAX_CANDIDATES
+        )));
+    }
+    // the last parameter changes fastest
+    Ok((0..total)
+        .map(|mut n| {
+            let mut choice = vec![0; sizes.len()];
+            for (i, size) in sizes.iter().enumerate().rev() {
+                choice[i] = n % size;
+                n /= size;
+            }
+            choice
+        })
+        .collect())
+}
+
+// Backtests the strategies `config.search` picks from the template and ranks them, best first.
+// symbols in the template resolve through `registry`
+pub fn sweep(
+    template: &SweepTemplate,
+    registry: &TokenRegistry,
+    history: &PriceHistory,
+    config: &SweepConfig,
+) -> std::result::Result<Vec<Candidate>, SweepError> {
+    let choices = choices(template, config.search)?;
+    let strategies = choices
+        .iter()
+        .map(|choice| template.instantiate(choice, registry))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(SweepError::Template)?;
+    let metrics = in_parallel(strategies.len(), config.threads, |i| {
+        Metrics::from_report(&backtest(&strategies[i], history, &config.backtest))
+    });
+    let mut candidates = choices
+        .into_iter()
+        .zip(strategies)
+        .zip(metrics)
+        .map(|((choice, strategy), metrics)| Candidate {
+            choice,
+            strategy,
+            score: config.objective.score(&metrics),
+            metrics,
+        })
+        .collect::<Vec<_>>();
+    // stable, so equal scores keep the order they were searched in
+    candidates.sort_by(|a, b| match (a.score, b.score) {
+        (Some(a), Some(b)) => b.total_cmp(&a),
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    });
+    Ok(candidates)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WalkForward {
+    pub folds: usize,
+    // train on everything before the test window, rather than on the window just before it
+    pub anchored: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fold {
+    // first and last timestamps of the windows
+    pub train: (u64, u64),
+    pub test: (u64, u64),
+    // the best candidate on the training window, with its metrics there
+    pub best: Candidate,
+    // the best candidate's strategy on the test window, which the search never saw
+    pub test_metrics: Metrics,
+    pub test_score: Option<f64>,
+}
+
+fn bounds(history: &PriceHistory) -> (u64, u64) {
+    match (history.ticks.first(), history.ticks.last()) {
+        (Some(first), Some(last)) => (first.timestamp, last.timestamp),
+        _ => (0, 0),
+    }
+}
+
+// The history cut into `folds + 1` windows with as many ticks each; every fold searches on one
+// window (or all of them so far, anchored) and tests the winner on the next. scores that hold up
+// out of sample suggest the parameters weren't fit to noise
+pub fn walk_forward(
+    template: &SweepTemplate,
+    registry: &TokenRegistry,
+    history: &PriceHistory,
+    config: &SweepConfig,
+    walk: WalkForward,
+) -> std::result::Result<Vec<Fold>, SweepError> {
+    let windows = walk.folds + 1;
+    if walk.folds == 0 || history.ticks.len() < 2 * windows {
+        return Err(SweepError::Config(format!(
+            "{} folds need at least {} ticks, the history has {}",
+            walk.folds,
+            2 * windows,
+            history.ticks.len()
+        )));
+    }
+    let bound = |window: usize| window * history.ticks.len() / windows;
+    (0..walk.folds)
+        .map(|fold| {
+            let start = if walk.anchored { 0 } else { bound(fold) };
+            let train = history.window(start..bound(fold + 1));
+            let test = history.window(bound(fold + 1)..bound(fold + 2));
+            let best = sweep(template, registry, &train, config)?
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    SweepError::Config("the search has no strategy to run".to_string())
+                })?;
+            let test_metrics =
+                Metrics::from_report(&backtest(&best.strategy, &test, &config.backtest));
+            Ok(Fold {
+                train: bounds(&train),
+                test: bounds(&test),
+                test_score: config.objective.score(&test_metrics),
+                best,
+                test_metrics,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::prelude::Pubkey;
+
+    fn values(template: &SweepTemplate) -> Vec<(&str, Vec<&str>)> {
+        template
+            .parameters
+            .iter()
+            .map(|p| {
+                (
+                    p.name.as_str(),
+                    p.values.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn history(token: Pubkey, prices: &[u64]) -> PriceHistory {
+        let mut csv = format!("timestamp,{}\n", token);
+        for (i, price) in prices.iter().enumerate() {
+            csv += &format!("{},{}\n", i * 60, price);
+        }
+        PriceHistory::from_csv(&csv, &TokenRegistry::default()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        let sol = Pubkey::new_unique();
+        let template = SweepTemplate::parse(&format!(
+            "WHEN PRICE_BELOW({sol}, {{80..120 step 10}}) // {{not a range}}\n\
+             THEN BUY({sol}, {{size = 1, 2, 5}}) THEN SELL({sol}, $size) EVERY {{1h, 4h}}"
+        ))
+        .unwrap();
+        assert_eq!(
+            values(&template),
+            vec![
+                ("1", vec!["80", "90", "100", "110", "120"]),
+                ("size", vec!["1", "2", "5"]),
+                ("2", vec!["1h", "4h"]),
+            ]
+        );
+        assert_eq!(template.combinations(), 30);
+        let strategy = template
+            .instantiate(&[4, 2, 1], &TokenRegistry::default())
+            .unwrap();
+        assert_eq!(
+            strategy.to_string_expr(),
+            format!("WHEN PRICE_BELOW({sol}, 120) THEN BUY({sol}, 5) THEN SELL({sol}, 5) EVERY 4h")
+        );
+
+        let parameter = |spec: &str| {
+            let template = SweepTemplate::parse(&format!(
+                "WHEN PRICE_BELOW({sol}, 1) THEN BUY({sol}, {}) EVERY 1m",
+                spec
+            ))
+            .unwrap();
+            template.parameters[0].values.clone()
+        };
+        assert_eq!(parameter("{1..3}"), ["1", "2", "3"]);
+        assert_eq!(parameter("{0.1..0.35 step 0.1}"), ["0.1", "0.2", "0.3"]);
+        assert_eq!(parameter("{5%..15% STEP 5%}"), ["5%", "10%", "15%"]);
+        assert_eq!(parameter("{ n = 2 }"), ["2"]);
+
+        let error = |spec: &str| {
+            SweepTemplate::parse(&format!(
+                "WHEN PRICE_BELOW({sol}, 1) THEN BUY({sol}, {}) EVERY 1m",
+                spec
+            ))
+            .unwrap_err()
+            .message
+        };
+        assert_eq!(error("{1..3"), "`{` is never closed");
+        assert_eq!(error("{1..3 step 0}"), "the step must be more than 0");
+        assert_eq!(error("{3..1}"), "the range ends before it starts");
+        assert_eq!(
+            error("{1..5% step 1}"),
+            "either all or none of the numbers of a range are percentages"
+        );
+        assert_eq!(error("{1..x}"), "`x` is not a number");
+        assert_eq!(
+            error("{1,,2}"),
+            "a parameter needs a value between every pair of commas"
+        );
+        assert_eq!(
+            error("{0..100000}"),
+            "the range has 100001 values, more than 10000"
+        );
+        assert_eq!(error("$size"), "no values given for `$size`");
+        assert_eq!(
+            error("{a = 1} THEN SELL(SOL, {a = 2})"),
+            "`a` is defined more than once"
+        );
+    }
+
+    #[test]
+    fn test_errors_point_at_the_range() {
+        let source = "WHEN PRICE_BELOW({1, 2}, 100) THEN BUY(SOL, 1) EVERY 1m";
+        let template = SweepTemplate::parse(source).unwrap();
+        let diagnostic = template
+            .instantiate(&[0], &TokenRegistry::default())
+            .unwrap_err();
+        assert_eq!((diagnostic.line, diagnostic.column), (1, 18));
+        assert_eq!(&source[diagnostic.span.clone()], "{1, 2}");
+        assert_eq!(diagnostic.found, "{1, 2}");
+        assert!(
+            diagnostic.message.ends_with("found `{1, 2}`"),
+            "{}",
+            diagnostic.message
+        );
+
+        // after a range, offsets move by the difference in length
+        let source = "WHEN PRICE_BELOW(SOL, {10..20 step 5}) THEN BUY(SOL, 1) EVERY 1m";
+        let diagnostic = SweepTemplate::parse(source)
+            .unwrap()
+            .instantiate(&[0], &TokenRegistry::default())
+            .unwrap_err();
+        assert_eq!(&source[diagnostic.span.clone()], "SOL");
+
+        // a placeholder with no range is reported where it is used, even when it is a prefix
+        // of another one
+        let source = "WHEN PRICE_BELOW(SOL, {size = 1, 2}) OR PRICE_ABOVE(SOL, $size) \
+                      THEN BUY(SOL, $s) EVERY 1m";
+        let diagnostic = SweepTemplate::parse(source).unwrap_err();
+        assert_eq!(&source[diagnostic.span.clone()], "$s");
+        assert_eq!(diagnostic.message, "no values given for `$s`");
+    }
+
+    #[test]
+    fn test_grid_search() {
+        let sol = Pubkey::new_unique();
+        let template = SweepTemplate::parse(&format!(
+            "WHEN PRICE_BELOW({sol}, {{80..120 step 10}}) THEN BUY({sol}, {{1, 2}}) EVERY 1m"
+        ))
+        .unwrap();
+        // bought at 90 by thresholds above it, worth 130 after
+        let history = history(sol, &[100, 90, 130]);
+        let config = SweepConfig {
+            objective: Objective::TotalReturn,
+            threads: 1,
+            ..Default::default()
+        };
+        let candidates = sweep(&template, &TokenRegistry::default(), &history, &config).unwrap();
+        assert_eq!(candidates.len(), 10);
+        let ranked = candidates
+            .iter()
+            .map(|c| c.choice.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ranked[..6],
+            [[2, 1], [3, 1], [4, 1], [2, 0], [3, 0], [4, 0]].map(Vec::from)
+        );
+        assert!((candidates[0].score.unwrap() - 0.008).abs() < 1e-12);
+        assert_eq!(
+            template.values(&candidates[0].choice),
+            [("1", "100"), ("2", "2")]
+        );
+        assert_eq!(candidates[9].metrics.trades, 0);
+
+        // the threads share the work, not the result
+        let parallel = SweepConfig {
+            threads: 4,
+            ..config
+        };
+        assert_eq!(
+            sweep(&template, &TokenRegistry::default(), &history, &parallel).unwrap(),
+            candidates
+        );
+
+        assert_eq!("sharpe".parse::<Objective>(), Ok(Objective::Sharpe));
+        assert!("profit".parse::<Objective>().is_err());
+    }
+
+    #[test]
+    fn test_random_search() {
+        let sol = Pubkey::new_unique();
+        let template = SweepTemplate::parse(&format!(
+            "WHEN PRICE_BELOW({sol}, {{1..20}}) THEN BUY({sol}, {{1..10}}) EVERY 1m"
+        ))
+        .unwrap();
+        let history = history(sol, &[10, 5, 10]);
+        let config = |samples, seed| SweepConfig {
+            search: Search::Random { samples, seed },
+            ..Default::default()
+        };
+        let picked = |config| {
+            let mut choices = sweep(&template, &TokenRegistry::default(), &history, &config)
+                .unwrap()
+                .into_iter()
+                .map(|c| c.choice)
+                .collect::<Vec<_>>();
+            choices.sort();
+            choices
+        };
+        let sample = picked(config(25, 7));
+        assert_eq!(sample.len(), 25);
+        assert!(sample.windows(2).all(|w| w[0] != w[1]));
+        assert_eq!(picked(config(25, 7)), sample);
+        assert_ne!(picked(config(25, 8)), sample);
+        // asking for more than there are runs them all
+        assert_eq!(picked(config(1_000, 7)).len(), 200);
+    }
+
+    #[test]
+    fn test_walk_forward() {
+        let sol = Pubkey::new_unique();
+        let template = SweepTemplate::parse(&format!(
+            "WHEN PRICE_BELOW({sol}, {{50..150 step 50}}) THEN BUY({sol}, 1) EVERY 1m"
+        ))
+        .unwrap();
+        let history = history(
+            sol,
+            &[100, 120, 130, 100, 120, 140, 100, 90, 80, 100, 120, 130],
+        );
+        let config = SweepConfig {
+            objective: Objective::TotalReturn,
+            ..Default::default()
+        };
+        let walk = WalkForward {
+            folds: 3,
+            anchored: false,
+        };
+        let folds = walk_forward(
+            &template,
+            &TokenRegistry::default(),
+            &history,
+            &config,
+            walk,
+        )
+        .unwrap();
+        assert_eq!(
+            folds.iter().map(|f| (f.train, f.test)).collect::<Vec<_>>(),
+            [
+                ((0, 120), (180, 300)),
+                ((180, 300), (360, 480)),
+                ((360, 480), (540, 660))
+            ]
+        );
+        // only a threshold above 120 buys in the first window, and it pays off in the next
+        assert_eq!(template.values(&folds[0].best.choice), [("1", "150")]);
+        assert!(folds[0].test_score.unwrap() > 0.0);
+
+        let anchored = WalkForward {
+            anchored: true,
+            ..walk
+        };
+        let folds = walk_forward(
+            &template,
+            &TokenRegistry::default(),
+            &history,
+            &config,
+            anchored,
+        )
+        .unwrap();
+        assert!(folds.iter().all(|f| f.train.0 == 0));
+        assert_eq!(folds[2].train, (0, 480));
+
+        let too_many = WalkForward { folds: 6, ..walk };
+        assert_eq!(
+            walk_forward(
+                &template,
+                &TokenRegistry::default(),
+                &history,
+                &config,
+                too_many
+            )
+            .unwrap_err()
+            .to_string(),
+            "6 folds need at least 14 ticks, the history has 12"
+        );
+    }
+}
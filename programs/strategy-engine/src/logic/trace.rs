@@ -0,0 +1,217 @@
+use crate::logic::conditions::{
+    ConditionTree, ConditionType, EvaluationContext, ExprStyle, NodeIndex,
+};
+use crate::logic::price::Price;
+use anchor_lang::prelude::*;
+
+// What happened to a single node during `evaluate_with_trace`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NodeTrace {
+    // `None` when the node was never reached because a parent short-circuited
+    pub value: Option<bool>,
+    // for atomic nodes: the token that was looked up and the price found for it (if any)
+    pub price_read: Option<(Pubkey, Option<Price>)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvaluationTrace {
+    pub result: bool,
+    // indexed the same way as `ConditionTree::nodes`
+    pub nodes: Vec<NodeTrace>,
+}
+
+impl EvaluationTrace {
+    // indices of nodes that were skipped by short-circuiting
+    pub fn short_circuited(&self) -> Vec<NodeIndex> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.value.is_none())
+            .map(|(i, _)| i as NodeIndex)
+            .collect()
+    }
+
+    // every (token, price) pair read from the context, in node order
+    pub fn prices_read(&self) -> Vec<(Pubkey, Option<Price>)> {
+        self.nodes.iter().filter_map(|n| n.price_read).collect()
+    }
+}
+
+impl ConditionTree {
+    // same semantics as `evaluate`, but records the value of every visited node. like
+    // `evaluate`, a node shared by several parents is evaluated once, and its entry holds that
+    // one value
+    pub fn evaluate_with_trace(&self, ctx: &EvaluationContext) -> EvaluationTrace {
+        let mut nodes = vec![NodeTrace::default(); self.nodes.len()];
+        let result = self.trace_node(self.root_index, ctx, &mut nodes);
+        EvaluationTrace { result, nodes }
+    }
+
+    fn trace_node(
+        &self,
+        index: NodeIndex,
+        ctx: &EvaluationContext,
+        trace: &mut Vec<NodeTrace>,
+    ) -> bool {
+        if let Some(value) = trace[index as usize].value {
+            return value;
+        }
+        let node = &self.nodes[index as usize];
+        let value = match &node.condition_type {
+            ConditionType::Atomic(atomic) => match atomic.token() {
+                Some(token) => {
+                    let observed = ctx.token_prices.get(&token).copied();
+                    trace[index as usize].price_read = Some((token, observed));
+                    atomic.evaluate_price(observed)
+                }
+                None => atomic.evaluate(ctx),
+            },
+            ConditionType::And { left, right } => {
+                self.trace_node(*left, ctx, trace) && self.trace_node(*right, ctx, trace)
+            }
+            ConditionType::Or { left, right } => {
+                self.trace_node(*left, ctx, trace) || self.trace_node(*right, ctx, trace)
+            }
+            ConditionType::Not { child } => !self.trace_node(*child, ctx, trace),
+            ConditionType::Const(value) => *value,
+            ConditionType::All { children } => {
+                children.iter().all(|c| self.trace_node(*c, ctx, trace))
+            }
+            ConditionType::Any { children } => {
+                children.iter().any(|c| self.trace_node(*c, ctx, trace))
+            }
+            ConditionType::AtLeast { k, children } => {
+                let mut count = 0;
+                for c in children {
+                    if count >= *k {
+                        break;
+                    }
+                    if self.trace_node(*c, ctx, trace) {
+                        count += 1;
+                    }
+                }
+                count >= *k
+            }
+            ConditionType::Xor { left, right } => {
+                self.trace_node(*left, ctx, trace) != self.trace_node(*right, ctx, trace)
+            }
+            ConditionType::Implies { left, right } => {
+                !self.trace_node(*left, ctx, trace) || self.trace_node(*right, ctx, trace)
+            }
+        };
+        trace[index as usize].value = Some(value);
+        value
+    }
+
+    // renders the tree like `to_string_expr`, with each node followed by its traced result, e.g.
+    // (PRICE_ABOVE(.., 100)[150 => true] AND PRICE_BELOW(.., 120)[150 => false])[false]
+    pub fn explain(&self, trace: &EvaluationTrace) -> String {
+        self.string_node_with(self.root_index, ExprStyle::Keywords, &|index, s| {
+            let node = &trace.nodes[index as usize];
+            match (node.value, node.price_read) {
+                (None, _) => format!("{}[skipped]", s),
+                (Some(v), Some((_, Some(p)))) => format!("{}[{} => {}]", s, p, v),
+                (Some(v), Some((_, None))) => format!("{}[no price => {}]", s, v),
+                (Some(v), None) => format!("{}[{}]", s, v),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::conditions::ConditionBuilder;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_evaluate_with_trace_short_circuit() {
+        let token = Pubkey::new_unique();
+        // (price > 400 AND price < 500) OR price < 200
+        let condition = ConditionBuilder::price_above(token, Price::from(400))
+            .and(ConditionBuilder::price_below(token, Price::from(500)))
+            .or(ConditionBuilder::price_below(token, Price::from(200)))
+            .build()
+            .unwrap();
+
+        let context = EvaluationContext {
+            token_prices: HashMap::from([(token, Price::from(150))]),
+            ..Default::default()
+        };
+
+        let trace = condition.evaluate_with_trace(&context);
+        assert_eq!(trace.result, condition.evaluate(&context));
+        assert!(trace.result);
+        // PRICE_BELOW(500) is skipped because PRICE_ABOVE(400) already failed
+        assert_eq!(trace.short_circuited(), vec![1]);
+        assert_eq!(trace.nodes[0].value, Some(false));
+        assert_eq!(
+            trace.nodes[0].price_read,
+            Some((token, Some(Price::from(150))))
+        );
+        assert_eq!(trace.prices_read().len(), 2);
+    }
+
+    #[test]
+    fn test_trace_shared_nodes() {
+        let token = Pubkey::new_unique();
+        let mut condition = ConditionBuilder::price_above(token, Price::from(100));
+        for _ in 0..40 {
+            condition = condition.clone().xor(condition.not());
+        }
+        let condition = condition.build().unwrap();
+
+        let context = EvaluationContext {
+            token_prices: HashMap::from([(token, Price::from(150))]),
+            ..Default::default()
+        };
+        let trace = condition.evaluate_with_trace(&context);
+        assert_eq!(trace.result, condition.evaluate(&context));
+        assert!(trace.short_circuited().is_empty());
+        // the atom is read once, however many paths lead to it
+        assert_eq!(trace.prices_read(), vec![(token, Some(Price::from(150)))]);
+
+        // every use of a shared node shows the value recorded for it
+        let shared = ConditionBuilder::price_above(token, Price::from(100));
+        let condition = shared
+            .clone()
+            .and(ConditionBuilder::constant(false))
+            .or(shared.not())
+            .build()
+            .unwrap();
+        let trace = condition.evaluate_with_trace(&context);
+        assert_eq!(
+            condition.explain(&trace),
+            format!(
+                "((PRICE_ABOVE({token}, 100)[150 => true] AND FALSE[false])[false] OR \
+                 NOTPRICE_ABOVE({token}, 100)[150 => true][false])[false]"
+            )
+        );
+    }
+
+    #[test]
+    fn test_explain() {
+        let token = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let condition =
+            ConditionBuilder::not(ConditionBuilder::price_above(token, Price::from(100)))
+                .or(ConditionBuilder::price_below(other, Price::from(10)))
+                .build()
+                .unwrap();
+
+        let context = EvaluationContext {
+            token_prices: HashMap::from([(token, Price::from(150))]),
+            ..Default::default()
+        };
+
+        let trace = condition.evaluate_with_trace(&context);
+        let explained = condition.explain(&trace);
+        assert_eq!(
+            explained,
+            format!(
+                "(NOTPRICE_ABOVE({}, 100)[150 => true][false] OR PRICE_BELOW({}, 10)[no price => false])[false]",
+                token, other
+            )
+        );
+    }
+}
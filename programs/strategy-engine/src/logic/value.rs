@@ -0,0 +1,391 @@
+use crate::logic::conditions::{EvaluationContext, NodeIndex, MAX_NODES};
+use crate::logic::price::Price;
+use crate::ErrorCode;
+use anchor_lang::prelude::*;
+
+// Signed fixed-point number with `Fixed::DECIMALS` decimals, what value expressions evaluate
+// to. Every operation is checked: an overflow or a division by zero gives `None`, and a
+// comparison that can't be evaluated is false, like one on a missing price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fixed(pub i128);
+
+impl Fixed {
+    pub const DECIMALS: u32 = 9;
+    const SCALE: i128 = 10i128.pow(Self::DECIMALS);
+
+    // digits past `DECIMALS` are truncated
+    pub fn from_price(price: Price) -> Option<Self> {
+        let mantissa = price.mantissa as i128;
+        let shift = price.expo.checked_add(Self::DECIMALS as i32)?;
+        if shift >= 0 {
+            let f = 10i128.checked_pow(shift as u32)?;
+            mantissa.checked_mul(f).map(Fixed)
+        } else {
+            // 10^39 is past i128, and every u64 mantissa is below it anyway
+            let f = 10i128
+                .checked_pow(shift.unsigned_abs())
+                .unwrap_or(i128::MAX);
+            Some(Fixed(mantissa / f))
+        }
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Fixed)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Fixed)
+    }
+
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        self.0.checked_mul(other.0).map(|v| Fixed(v / Self::SCALE))
+    }
+
+    pub fn checked_div(self, other: Self) -> Option<Self> {
+        self.0
+            .checked_mul(Self::SCALE)
+            .and_then(|v| v.checked_div(other.0))
+            .map(Fixed)
+    }
+
+    pub fn checked_neg(self) -> Option<Self> {
+        self.0.checked_neg().map(Fixed)
+    }
+
+    pub fn checked_abs(self) -> Option<Self> {
+        self.0.checked_abs().map(Fixed)
+    }
+}
+
+impl std::fmt::Display for Fixed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let scale = Self::SCALE as u128;
+        let frac = format!("{:0width$}", abs % scale, width = Self::DECIMALS as usize);
+        let frac = frac.trim_end_matches('0');
+        if frac.is_empty() {
+            write!(f, "{}{}", sign, abs / scale)
+        } else {
+            write!(f, "{}{}.{}", sign, abs / scale, frac)
+        }
+    }
+}
+
+// One node of a value expression. Like condition nodes, children come before their parents
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ValueNode {
+    Const(Price),
+    // the token's current price
+    Price(Pubkey),
+    // the vault's holding of the token, in whole tokens
+    Balance(Pubkey),
+    Neg { child: u16 },
+    Abs { child: u16 },
+    Add { left: u16, right: u16 },
+    Sub { left: u16, right: u16 },
+    Mul { left: u16, right: u16 },
+    Div { left: u16, right: u16 },
+    Min { left: u16, right: u16 },
+    Max { left: u16, right: u16 },
+}
+
+impl ValueNode {
+    fn children(&self) -> Vec<NodeIndex> {
+        match self {
+            ValueNode::Const(_) | ValueNode::Price(_) | ValueNode::Balance(_) => vec![],
+            ValueNode::Neg { child } | ValueNode::Abs { child } => vec![*child],
+            ValueNode::Add { left, right }
+            | ValueNode::Sub { left, right }
+            | ValueNode::Mul { left, right }
+            | ValueNode::Div { left, right }
+            | ValueNode::Min { left, right }
+            | ValueNode::Max { left, right } => vec![*left, *right],
+        }
+    }
+
+    fn map_children(&mut self, f: impl Fn(NodeIndex) -> NodeIndex) {
+        match self {
+            ValueNode::Const(_) | ValueNode::Price(_) | ValueNode::Balance(_) => {}
+            ValueNode::Neg { child } | ValueNode::Abs { child } => *child = f(*child),
+            ValueNode::Add { left, right }
+            | ValueNode::Sub { left, right }
+            | ValueNode::Mul { left, right }
+            | ValueNode::Div { left, right }
+            | ValueNode::Min { left, right }
+            | ValueNode::Max { left, right } => {
+                *left = f(*left);
+                *right = f(*right);
+            }
+        }
+    }
+}
+
+// An arithmetic expression over prices and balances, e.g. `(price(A) - price(B)) / price(B)`,
+// stored as its own small arena inside the comparison that uses it
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ValueExpr {
+    pub nodes: Vec<ValueNode>,
+    pub root: u16,
+}
+
+impl ValueExpr {
+    fn leaf(node: ValueNode) -> Self {
+        Self {
+            nodes: vec![node],
+            root: 0,
+        }
+    }
+
+    pub fn constant(value: Price) -> Self {
+        Self::leaf(ValueNode::Const(value))
+    }
+
+    pub fn price(token: Pubkey) -> Self {
+        Self::leaf(ValueNode::Price(token))
+    }
+
+    pub fn balance(token: Pubkey) -> Self {
+        Self::leaf(ValueNode::Balance(token))
+    }
+
+    fn unary(mut self, make: impl FnOnce(NodeIndex) -> ValueNode) -> Self {
+        let node = make(self.root);
+        self.root = self.nodes.len() as NodeIndex;
+        self.nodes.push(node);
+        self
+    }
+
+    // appends `other`'s arena after this one's, shifting its indices, like
+    // `ConditionBuilder::graft`
+    fn binary(mut self, other: Self, make: impl FnOnce(NodeIndex, NodeIndex) -> ValueNode) -> Self {
+        let offset = self.nodes.len();
+        let left = self.root;
+        let right = (other.root as usize + offset) as NodeIndex;
+        for mut node in other.nodes {
+            node.map_children(|c| (c as usize + offset) as NodeIndex);
+            self.nodes.push(node);
+        }
+        self.root = self.nodes.len() as NodeIndex;
+        self.nodes.push(make(left, right));
+        self
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn neg(self) -> Self {
+        self.unary(|child| ValueNode::Neg { child })
+    }
+
+    pub fn abs(self) -> Self {
+        self.unary(|child| ValueNode::Abs { child })
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn add(self, other: Self) -> Self {
+        self.binary(other, |left, right| ValueNode::Add { left, right })
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn sub(self, other: Self) -> Self {
+        self.binary(other, |left, right| ValueNode::Sub { left, right })
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn mul(self, other: Self) -> Self {
+        self.binary(other, |left, right| ValueNode::Mul { left, right })
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn div(self, other: Self) -> Self {
+        self.binary(other, |left, right| ValueNode::Div { left, right })
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        self.binary(other, |left, right| ValueNode::Min { left, right })
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        self.binary(other, |left, right| ValueNode::Max { left, right })
+    }
+
+    // the token when the whole expression is `price(token)`
+    pub fn as_price(&self) -> Option<Pubkey> {
+        match self.nodes.as_slice() {
+            [ValueNode::Price(token)] => Some(*token),
+            _ => None,
+        }
+    }
+
+    // the value when the whole expression is a constant
+    pub fn as_constant(&self) -> Option<Price> {
+        match self.nodes.as_slice() {
+            [ValueNode::Const(value)] => Some(*value),
+            _ => None,
+        }
+    }
+
+    // tokens whose price or balance the expression reads
+    pub fn tokens(&self) -> Vec<Pubkey> {
+        let mut tokens = vec![];
+        for node in &self.nodes {
+            if let ValueNode::Price(t) | ValueNode::Balance(t) = node {
+                if !tokens.contains(t) {
+                    tokens.push(*t);
+                }
+            }
+        }
+        tokens
+    }
+
+    // same structural rules as `ConditionTree::validate`
+    pub fn validate(&self) -> Result<()> {
+        require!(self.nodes.len() <= MAX_NODES, ErrorCode::TooManyNodes);
+        require!(
+            (self.root as usize) < self.nodes.len(),
+            ErrorCode::InvalidTree
+        );
+        for (index, node) in self.nodes.iter().enumerate() {
+            require!(
+                node.children().iter().all(|c| (*c as usize) < index),
+                ErrorCode::InvalidTree
+            );
+        }
+        Ok(())
+    }
+
+    // `None` if a price or balance is missing, or the arithmetic overflows or divides by zero
+    pub fn evaluate(&self, ctx: &EvaluationContext) -> Option<Fixed> {
+        self.evaluate_node(self.root, ctx)
+    }
+
+    fn evaluate_node(&self, index: NodeIndex, ctx: &EvaluationContext) -> Option<Fixed> {
+        let eval = |i: &NodeIndex| self.evaluate_node(*i, ctx);
+        match &self.nodes[index as usize] {
+            ValueNode::Const(value) => Fixed::from_price(*value),
+            ValueNode::Price(token) => Fixed::from_price(*ctx.token_prices.get(token)?),
+            ValueNode::Balance(token) => Fixed::from_price(*ctx.token_balances.get(token)?),
+            ValueNode::Neg { child } => eval(child)?.checked_neg(),
+            ValueNode::Abs { child } => eval(child)?.checked_abs(),
+            ValueNode::Add { left, right } => eval(left)?.checked_add(eval(right)?),
+            ValueNode::Sub { left, right } => eval(left)?.checked_sub(eval(right)?),
+            ValueNode::Mul { left, right } => eval(left)?.checked_mul(eval(right)?),
+            ValueNode::Div { left, right } => eval(left)?.checked_div(eval(right)?),
+            ValueNode::Min { left, right } => Some(eval(left)?.min(eval(right)?)),
+            ValueNode::Max { left, right } => Some(eval(left)?.max(eval(right)?)),
+        }
+    }
+
+    // infix form with only the parentheses precedence needs; tokens are written by `label`
+    pub fn to_string_with(&self, label: &dyn Fn(&Pubkey) -> String) -> String {
+        self.string_node_with(self.root, label).0
+    }
+
+    // the printed node together with its precedence: 1 for + and -, 2 for * and /, 3 otherwise
+    fn string_node_with(
+        &self,
+        index: NodeIndex,
+        label: &dyn Fn(&Pubkey) -> String,
+    ) -> (String, u8) {
+        let operand = |i: &NodeIndex, min: u8| {
+            let (s, precedence) = self.string_node_with(*i, label);
+            if precedence < min {
+                format!("({})", s)
+            } else {
+                s
+            }
+        };
+        let call = |name: &str, args: &[&NodeIndex]| {
+            let args: Vec<_> = args.iter().map(|a| operand(a, 0)).collect();
+            (format!("{}({})", name, args.join(", ")), 3)
+        };
+        // operators are left-associative, so a right operand of equal precedence needs parentheses
+        let infix = |left: &NodeIndex, op: &str, right: &NodeIndex, precedence: u8| {
+            let s = format!(
+                "{} {} {}",
+                operand(left, precedence),
+                op,
+                operand(right, precedence + 1)
+            );
+            (s, precedence)
+        };
+        match &self.nodes[index as usize] {
+            ValueNode::Const(value) => (value.to_string(), 3),
+            ValueNode::Price(token) => (format!("price({})", label(token)), 3),
+            ValueNode::Balance(token) => (format!("balance({})", label(token)), 3),
+            ValueNode::Neg { child } => (format!("-{}", operand(child, 3)), 3),
+            ValueNode::Abs { child } => call("abs", &[child]),
+            ValueNode::Min { left, right } => call("min", &[left, right]),
+            ValueNode::Max { left, right } => call("max", &[left, right]),
+            ValueNode::Add { left, right } => infix(left, "+", right, 1),
+            ValueNode::Sub { left, right } => infix(left, "-", right, 1),
+            ValueNode::Mul { left, right } => infix(left, "*", right, 2),
+            ValueNode::Div { left, right } => infix(left, "/", right, 2),
+        }
+    }
+}
+
+impl std::fmt::Display for ValueExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_string_with(&|token| token.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_fixed_point_arithmetic() {
+        let fixed = |mantissa, expo| Fixed::from_price(Price::new(mantissa, expo)).unwrap();
+        assert_eq!(fixed(1005, -1).to_string(), "100.5");
+        assert_eq!(fixed(1, -12).to_string(), "0");
+        let ratio = fixed(2, 0).checked_div(fixed(3, 0)).unwrap();
+        assert_eq!(ratio.to_string(), "0.666666666");
+        assert_eq!(
+            fixed(1, 0).checked_sub(fixed(3, 0)).unwrap().to_string(),
+            "-2"
+        );
+        assert_eq!(fixed(1, 0).checked_div(fixed(0, 0)), None);
+        assert_eq!(fixed(u64::MAX, 0).checked_mul(fixed(u64::MAX, 0)), None);
+        assert_eq!(Fixed::from_price(Price::new(1, 40)), None);
+    }
+
+    #[test]
+    fn test_evaluate_and_print() {
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        // (price(A) - price(B)) / price(B) - -min(balance(A), 2)
+        let spread = ValueExpr::price(a)
+            .sub(ValueExpr::price(b))
+            .div(ValueExpr::price(b));
+        let expr = spread.clone().sub(
+            ValueExpr::balance(a)
+                .min(ValueExpr::constant(Price::from(2)))
+                .neg(),
+        );
+        expr.validate().unwrap();
+        assert_eq!(
+            expr.to_string(),
+            format!("(price({a}) - price({b})) / price({b}) - -min(balance({a}), 2)")
+        );
+        assert_eq!(expr.tokens(), vec![a, b]);
+
+        let mut ctx = EvaluationContext {
+            token_prices: HashMap::from([(a, Price::from(102)), (b, Price::from(100))]),
+            ..Default::default()
+        };
+        assert_eq!(spread.evaluate(&ctx).unwrap().to_string(), "0.02");
+        // no balance for A
+        assert_eq!(expr.evaluate(&ctx), None);
+        ctx.token_balances.insert(a, Price::new(15, -1));
+        assert_eq!(expr.evaluate(&ctx).unwrap().to_string(), "1.52");
+
+        let right_nested =
+            ValueExpr::constant(Price::from(1)).sub(ValueExpr::price(a).sub(ValueExpr::price(b)));
+        assert_eq!(
+            right_nested.to_string(),
+            format!("1 - (price({a}) - price({b}))")
+        );
+    }
+}